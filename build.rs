@@ -0,0 +1,34 @@
+//! Build info for `lair self --version-info`, passed to the compiler as environment variables.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let output = |program: &str, args: &[&str]| Command::new(program).args(args).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .filter(|output| !output.is_empty());
+
+    let commit = output("git", &["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_owned());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_owned());
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| Some(key.strip_prefix("CARGO_FEATURE_")?.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=LAIR_COMMIT={}", commit);
+    println!("cargo:rustc-env=LAIR_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=LAIR_TARGET={}", std::env::var("TARGET").unwrap_or_default());
+    println!("cargo:rustc-env=LAIR_PROFILE={}", std::env::var("PROFILE").unwrap_or_default());
+    println!("cargo:rustc-env=LAIR_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-changed=build.rs");
+    // Without a repository, there's no commit to follow. Naming a missing file would rerun this on
+    // every build.
+    for file in [".git/HEAD", ".git/index"] {
+        if Path::new(file).exists() {
+            println!("cargo:rerun-if-changed={}", file);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Downloading and unpacking archive (`.tar.gz`, `.zip`) dependencies.
+
+use std::fs::create_dir_all;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+
+use crate::error::SourceFetchError;
+use crate::paths;
+use crate::proxy;
+
+/// Archive formats we know how to unpack. Determined from the url.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    TarGz,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// Guess the archive format from the url, for example `https://.../pkg-1.0.tar.gz`.
+    pub fn from_url(url: &str) -> Option<Self> {
+        // Ignore query strings and fragments, like in `.../archive.zip?raw=true`.
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if path.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Download the whole archive into memory. Blocking.
+pub fn download(url: &str) -> Result<Vec<u8>, SourceFetchError> {
+    let response = proxy::agent(url)?.get(url).call()?;
+    let mut bytes = Vec::new();
+    response.into_reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Hex-encoded sha256 of `bytes`.
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Make sure the downloaded archive is the one we expect.
+pub fn verify(url: &str, bytes: &[u8], expected: &str) -> Result<(), SourceFetchError> {
+    let actual = sha256(bytes);
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(SourceFetchError::ChecksumMismatch {
+            url: url.to_owned(),
+            expected: expected.to_owned(),
+            actual,
+        })
+    }
+}
+
+/// Unpack the archive into `dest`, so that `{dest}/Egg.toml` exists.
+///
+/// Release tarballs usually wrap everything in a single top-level directory (`pkg-1.0/`), so if
+/// that is the case, we strip it.
+///
+/// We first unpack into a temporary sibling directory and only rename it to `dest` once
+/// everything went well, so that an interrupted extraction doesn't look like it's already
+/// downloaded next time.
+pub fn extract(bytes: &[u8], kind: ArchiveKind, dest: &Path) -> Result<(), SourceFetchError> {
+    let tmp = dest.with_extension("tmp");
+    if tmp.exists() {
+        std::fs::remove_dir_all(paths::long(&tmp))?;
+    }
+    create_dir_all(&tmp)?;
+
+    match kind {
+        ArchiveKind::TarGz => tar::Archive::new(GzDecoder::new(bytes)).unpack(&tmp)?,
+        ArchiveKind::Zip => zip::ZipArchive::new(Cursor::new(bytes))?.extract(&tmp)?,
+    }
+
+    // Is there exactly one directory at the top level? Then that's the actual package root.
+    let mut entries = std::fs::read_dir(&tmp)?.collect::<Result<Vec<_>, _>>()?;
+    let root = match entries.as_slice() {
+        [single] if single.file_type()?.is_dir() => entries.remove(0).path(),
+        _ => tmp.clone(),
+    };
+
+    std::fs::rename(&root, dest)?;
+    if tmp.exists() {
+        std::fs::remove_dir_all(paths::long(&tmp))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! `lair bench`: compiling and timing the programs listed in `[bench]`.
+//!
+//! ```toml
+//! [bench]
+//! parse = "src/Bench/Parse.idr"
+//! ```
+//!
+//! Each entry is a main module, in the package's `src` like everything else, with a `main`. It's
+//! compiled in the release profile (see [`release_env`]) to `build/bench/{name}`, then run as
+//! often as asked, one run after the other, timing each by the wall clock. What the program prints
+//! to stdout is thrown away, so that the results can go there, as a table or as JSON to compare
+//! with earlier runs.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::compiler::Backend;
+use crate::layout;
+use crate::paths;
+
+/// `build/bench`, where the benchmark programs are compiled to.
+pub fn dir() -> PathBuf {
+    layout::build_dir().join("bench")
+}
+
+/// Environment variables making idris2 compile with optimizations: `-O2` for the C compiler refc
+/// hands its output to. idris2 has no such switch for the other backends.
+pub fn release_env(backend: Backend) -> Vec<(&'static str, String)> {
+    match backend {
+        Backend::RefC => {
+            let cflags = std::env::var("IDRIS2_CFLAGS").unwrap_or_default();
+            vec![("IDRIS2_CFLAGS", format!("{} -O2", cflags).trim().to_owned())]
+        },
+        Backend::Chez | Backend::Racket | Backend::Node => Vec::new(),
+    }
+}
+
+/// How a benchmark went, with the times in milliseconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct Measurement {
+    pub name: String,
+    pub backend: Backend,
+    pub runs: usize,
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    /// Each run, in order.
+    pub times_ms: Vec<f64>,
+}
+
+impl Measurement {
+    fn new(name: &str, backend: Backend, times: &[Duration]) -> Self {
+        // Microseconds are plenty.
+        let round = |ms: f64| (ms * 1000.0).round() / 1000.0;
+        let times_ms: Vec<f64> = times.iter().map(|time| round(time.as_secs_f64() * 1000.0)).collect();
+        Self {
+            name: name.to_owned(),
+            backend,
+            runs: times_ms.len(),
+            mean_ms: round(times_ms.iter().sum::<f64>() / times_ms.len().max(1) as f64),
+            min_ms: times_ms.iter().copied().reduce(f64::min).unwrap_or_default(),
+            max_ms: times_ms.iter().copied().reduce(f64::max).unwrap_or_default(),
+            times_ms,
+        }
+    }
+}
+
+/// Run the benchmark `name`, compiled with `backend` into `dir`, `runs` times in the current
+/// directory. Fails if any run does. Blocking.
+pub fn run(dir: &Path, name: &str, backend: Backend, runs: usize) -> Result<Measurement, anyhow::Error> {
+    let mut command = match backend {
+        Backend::Node => {
+            let mut command = Command::new("node");
+            command.arg(dir.join(name));
+            command
+        },
+        Backend::Chez | Backend::Racket | Backend::RefC => match paths::executable(dir, name) {
+            Some(program) => Command::new(program),
+            None => anyhow::bail!("idris2 didn't produce `{}`", dir.join(name).display()),
+        },
+    };
+    command.stdin(Stdio::null()).stdout(Stdio::null());
+
+    let mut times = Vec::with_capacity(runs);
+    for run in 1..=runs {
+        let started = Instant::now();
+        let status = command.status().with_context(|| format!("Failed to run the benchmark `{}`", name))?;
+        times.push(started.elapsed());
+        if !status.success() {
+            anyhow::bail!("The benchmark `{}` failed in run {} of {} ({}).", name, run, runs, status);
+        }
+    }
+    Ok(Measurement::new(name, backend, &times))
+}
@@ -0,0 +1,64 @@
+//! `lair bundle`: everything needed to run a program, in one directory which can be copied
+//! elsewhere.
+//!
+//! ```text
+//! build/bundle/AmazingTool/
+//!     AmazingTool             -- Launcher script (chez, racket), executable (refc),
+//!                                or AmazingTool.js (node).
+//!     AmazingTool_app/        -- Compiled code the launcher runs (chez, racket).
+//!     licenses/
+//!         NotJson/LICENSE     -- License files of every package which has one.
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use crate::compiler::Backend;
+use crate::files;
+use crate::layout;
+use crate::paths;
+
+/// `build/bundle/{name}`.
+pub fn default_dest(name: &str) -> PathBuf {
+    layout::build_dir().join("bundle").join(name)
+}
+
+/// Copy idris2's output for program `name` from `exec_dir` (usually `build/exec`) to `dest`,
+/// replacing whatever was there, together with the license files of each package, given as
+/// (package name, license files). Returns the path of the program to run. Blocking.
+pub fn assemble(exec_dir: &Path, name: &str, backend: Backend, dest: &Path, licenses: &[(String, Vec<PathBuf>)]) -> Result<PathBuf, anyhow::Error> {
+    if dest.exists() {
+        std::fs::remove_dir_all(paths::long(dest))?;
+    }
+    std::fs::create_dir_all(dest)?;
+
+    // On Windows, `AmazingTool.bat` or `AmazingTool.exe`, except for node.
+    let program = match backend {
+        Backend::Node => Some(exec_dir.join(name)).filter(|program| program.is_file()),
+        Backend::Chez | Backend::Racket | Backend::RefC => paths::executable(exec_dir, name),
+    };
+    let Some(program) = program else {
+        anyhow::bail!("idris2 didn't produce `{}`", exec_dir.join(name).display());
+    };
+    let entry = match backend {
+        Backend::Node => dest.join(format!("{}.js", name)),
+        Backend::Chez | Backend::Racket | Backend::RefC => dest.join(program.file_name().expect("found in `exec_dir`")),
+    };
+    std::fs::copy(&program, &entry)?; // keeps the executable bit
+
+    let app = exec_dir.join(format!("{}_app", name));
+    if app.is_dir() {
+        files::copy_dir(&paths::long(&app), &paths::long(&dest.join(format!("{}_app", name))))?;
+    }
+
+    for (package, license_files) in licenses {
+        for file in license_files {
+            if let Some(file_name) = file.file_name() {
+                let dir = dest.join("licenses").join(package);
+                std::fs::create_dir_all(&dir)?;
+                std::fs::copy(file, dir.join(file_name))?;
+            }
+        }
+    }
+
+    Ok(entry)
+}
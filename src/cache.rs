@@ -0,0 +1,289 @@
+//! Keeping `build/deps` from growing forever.
+//!
+//! Since dependency directories are keyed by descriptor hash, every change of a dependency's url,
+//! revision, etc. leaves the old checkout behind. We record when each checkout was last part of
+//! the dependency graph in `build/.lair/cache-index.json`, so that `lair cache gc` can delete
+//! the ones which haven't been used in a while.
+//!
+//! More coarsely, `lair clean` reports the size of, and deletes, whole [`Area`]s.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::descriptor::Descriptor;
+use crate::graph::Graph;
+use crate::layout;
+use crate::paths;
+
+/// Where the index lives.
+pub fn index_path() -> PathBuf {
+    layout::lair_dir().join("cache-index.json")
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// What is checked out there.
+    pub descriptor: Descriptor,
+    pub last_used: SystemTime,
+}
+
+/// Directory name in `build/deps` (for example `NotJson-3f2a9c01`) --> usage.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CacheIndex {
+    pub entries: BTreeMap<String, CacheEntry>,
+}
+
+impl CacheIndex {
+    /// Load the index, or start with an empty one if there is none (or it's unreadable; it's only
+    /// a cache after all).
+    pub fn load() -> Self {
+        std::fs::read(index_path()).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let path = index_path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Record that every package in `graph` was just used.
+    pub fn touch(&mut self, graph: &Graph) {
+        let now = SystemTime::now();
+        for package in &graph.packages {
+            if let Some(dir) = dir_name(&package.descriptor) {
+                self.entries.insert(dir, CacheEntry { descriptor: package.descriptor.clone(), last_used: now });
+            }
+        }
+    }
+}
+
+/// Name of the directory in `build/deps` a descriptor is downloaded to, if any.
+fn dir_name(desc: &Descriptor) -> Option<String> {
+    match desc {
+        Descriptor::Git { .. } | Descriptor::Archive { .. } | Descriptor::Registry { .. } =>
+            layout::source_dir(desc).file_name().map(|name| name.to_string_lossy().into_owned()),
+        Descriptor::Root { .. } | Descriptor::Local { .. } => None,
+    }
+}
+
+/// Which checkouts may go.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GcPolicy {
+    /// Delete checkouts not used for this long. If neither this nor `max_size` is set, delete
+    /// everything not used by the current dependency graph.
+    pub max_age: Option<Duration>,
+    /// Delete the least recently used checkouts until all of them together take at most this
+    /// many bytes.
+    pub max_size: Option<u64>,
+}
+
+/// A checkout deleted by [`gc`].
+#[derive(Clone, Debug)]
+pub struct Removed {
+    pub path: PathBuf,
+    pub size: u64,
+    /// `None` if we had no record of it ever being used.
+    pub last_used: Option<SystemTime>,
+}
+
+/// Delete checkouts in `build/deps` according to `policy`. Checkouts used by `graph` are always
+/// kept. Blocking.
+pub fn gc(index: &mut CacheIndex, graph: &Graph, policy: GcPolicy) -> Result<Vec<Removed>, anyhow::Error> {
+    let in_use: BTreeSet<String> = graph.packages.iter().filter_map(|package| dir_name(&package.descriptor)).collect();
+    let now = SystemTime::now();
+
+    let mut candidates = Vec::new();
+    let mut total = 0;
+    let entries = match std::fs::read_dir(layout::deps_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let size = dir_size(&entry.path())?;
+        total += size;
+        if !in_use.contains(&name) {
+            let last_used = index.entries.get(&name).map(|entry| entry.last_used);
+            candidates.push((last_used, name, entry.path(), size));
+        }
+    }
+    // Least recently used first, never used before anything else.
+    candidates.sort();
+
+    let mut removed = Vec::new();
+    for (last_used, name, path, size) in candidates {
+        let too_old = match (policy.max_age, last_used) {
+            (Some(max_age), Some(last_used)) => now.duration_since(last_used).unwrap_or_default() > max_age,
+            (Some(_), None) => true,
+            (None, _) => policy.max_size.is_none(),
+        };
+        let too_big = policy.max_size.is_some_and(|max_size| total > max_size);
+        if too_old || too_big {
+            std::fs::remove_dir_all(paths::long(&path))?;
+            index.entries.remove(&name);
+            total -= size;
+            removed.push(Removed { path, size, last_used });
+        }
+    }
+    Ok(removed)
+}
+
+/// A part of what Lair keeps on disk, which `lair clean` reports and deletes on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Area {
+    /// `build/deps`, the downloaded dependencies, along with their TTC files.
+    Deps,
+    /// Everything idris2 built: the `build/idris2-*` directories of the root package and of
+    /// every dependency.
+    Ttc,
+    /// `build/logs`.
+    Logs,
+    /// The cache shared by all projects, see [`layout::user_cache_dir`].
+    Cache,
+}
+
+impl Area {
+    pub const ALL: [Area; 4] = [Area::Deps, Area::Ttc, Area::Logs, Area::Cache];
+
+    /// For example "build/deps (downloaded dependencies)".
+    pub fn describe(self) -> String {
+        match self {
+            Area::Deps => format!("{} (downloaded dependencies)", layout::deps_dir().display()),
+            Area::Ttc => "TTC files (of the package and its dependencies)".to_owned(),
+            Area::Logs => format!("{} (build output)", layout::logs_dir().display()),
+            Area::Cache => format!("{} (registry indexes, shared by all projects)", layout::user_cache_dir().display()),
+        }
+    }
+
+    /// The directories this area consists of right now, those which exist.
+    pub fn dirs(self) -> std::io::Result<Vec<PathBuf>> {
+        let dirs = match self {
+            Area::Deps => vec![layout::deps_dir()],
+            Area::Ttc => {
+                let mut dirs = Vec::new();
+                toolchain_dirs(&layout::build_dir(), &mut dirs)?;
+                find_toolchain_dirs(&layout::deps_dir(), &mut dirs)?;
+                dirs
+            },
+            Area::Logs => vec![layout::logs_dir()],
+            Area::Cache => vec![layout::user_cache_dir()],
+        };
+        Ok(dirs.into_iter().filter(|dir| dir.is_dir()).collect())
+    }
+
+    /// Total size of [`Area::dirs`].
+    pub fn size(self) -> std::io::Result<u64> {
+        self.dirs()?.iter().map(|dir| dir_size(dir)).sum()
+    }
+}
+
+/// The `idris2-*` directories in the build directory `build`, like `build/idris2-0.6.0`.
+fn toolchain_dirs(build: &Path, dirs: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let entries = match std::fs::read_dir(build) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.file_name().to_string_lossy().starts_with("idris2-") {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// [`toolchain_dirs`] of every package somewhere below `dir`, say in subdirectories of a
+/// monorepo checkout.
+fn find_toolchain_dirs(dir: &Path, dirs: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() || entry.file_name() == ".git" {
+            continue;
+        }
+        if entry.file_name() == "build" {
+            toolchain_dirs(&entry.path(), dirs)?;
+        } else {
+            find_toolchain_dirs(&entry.path(), dirs)?;
+        }
+    }
+    Ok(())
+}
+
+/// Total size of all files in `path`, recursively. Symlinks aren't followed.
+pub fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// Parse durations like `30d`, `12h`, `2w` or `90m`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("`{}` is not a duration like `30d`", s))?;
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit in `{}`, expected one of s, m, h, d, w", s)),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// Parse sizes like `500M`, `2G`, `1.5GiB` or `10000` (bytes). Units are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("`{}` is not a size like `500M`", s))?;
+    let factor: u64 = match unit.trim().trim_end_matches("iB").trim_end_matches('B') {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("Unknown unit in `{}`, expected one of K, M, G, T", s)),
+    };
+    Ok((number * factor as f64) as u64)
+}
+
+/// For example `12.3 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
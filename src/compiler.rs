@@ -0,0 +1,265 @@
+//! Which idris2 we're talking to, and how it wants to be told about dependencies.
+
+use std::fmt::Display;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+/// For example `0.5.1`. Pre-release and commit suffixes (`0.5.1-6ee6bbd0b`) are dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Idris2Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Idris2Version {
+    /// The first version with `IDRIS2_PACKAGE_PATH` and `{name}-{version}` package directories.
+    pub const PACKAGE_PATH: Idris2Version = Idris2Version { major: 0, minor: 5, patch: 0 };
+
+    /// Parse the output of `idris2 --version`, for example `Idris 2, version 0.5.1-6ee6bbd0b`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let version = output.split("version").nth(1)?.trim();
+        let version = version.split(|c: char| !c.is_ascii_digit() && c != '.').next()?;
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        Some(Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next().flatten().unwrap_or(0),
+        })
+    }
+
+    /// Parse just a version number, like `0.6.0` or `0.6` (meaning `0.6.0`).
+    fn parse_number(s: &str) -> Option<Self> {
+        let mut parts = s.trim().split('.').map(|part| part.parse().ok());
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next().unwrap_or(Some(0))?,
+            patch: parts.next().unwrap_or(Some(0))?,
+        };
+        parts.next().is_none().then_some(version)
+    }
+
+    /// The smallest version after this one.
+    fn next(self) -> Self {
+        Self { patch: self.patch + 1, ..self }
+    }
+
+    /// Ask the `idris2` on PATH. `None` if it isn't there, or says something we don't understand.
+    /// Blocking.
+    pub fn detect() -> Option<Self> {
+        Some(Toolchain::detect()?.version)
+    }
+}
+
+/// One particular build of idris2. TTC files written by one can't be loaded by another, not even
+/// by one with the same version number but built from a different commit, so each gets its own
+/// build artifacts.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Toolchain {
+    pub version: Idris2Version,
+    /// What `idris2 --version` says after `version`, for example `0.5.1-6ee6bbd0b`.
+    pub full: String,
+}
+
+impl Toolchain {
+    /// Parse the output of `idris2 --version`, see [`Idris2Version::parse`].
+    pub fn parse(output: &str) -> Option<Self> {
+        Some(Self {
+            version: Idris2Version::parse(output)?,
+            full: output.split("version").nth(1)?.split_whitespace().next()?.to_owned(),
+        })
+    }
+
+    /// Ask the `idris2` on PATH. `None` if it isn't there, or says something we don't understand.
+    /// Blocking.
+    pub fn detect() -> Option<Self> {
+        let output = Command::new(paths::idris2())
+            .arg("--version")
+            .stdin(Stdio::null())
+            .output()
+            .ok()?;
+        Self::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Name of the directory its build artifacts go into, for example `idris2-0.5.1-6ee6bbd0b`.
+    /// `idris2-unknown` if we couldn't tell which idris2 it is.
+    pub fn dir_name(toolchain: Option<&Self>) -> String {
+        match toolchain {
+            Some(toolchain) => format!("idris2-{}", toolchain.full.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+                .collect::<String>()),
+            None => "idris2-unknown".to_owned(),
+        }
+    }
+}
+
+impl Display for Idris2Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Which idris2 versions a package works with, for example `>=0.6.0, <0.8`: comma-separated
+/// comparisons (`>=`, `>`, `<=`, `<`, `=`), all of which have to hold. A version without a
+/// comparison means `=`, and missing minor or patch numbers mean `0`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Idris2Requirement {
+    /// As written, for messages.
+    text: String,
+    comparisons: Vec<(Comparison, Idris2Version)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Eq,
+}
+
+impl Idris2Requirement {
+    pub fn matches(&self, version: Idris2Version) -> bool {
+        self.comparisons.iter().all(|(comparison, bound)| match comparison {
+            Comparison::Greater => version > *bound,
+            Comparison::GreaterEq => version >= *bound,
+            Comparison::Less => version < *bound,
+            Comparison::LessEq => version <= *bound,
+            Comparison::Eq => version == *bound,
+        })
+    }
+
+    /// Is there any version all of `requirements` allow?
+    pub fn satisfiable<'a>(requirements: impl IntoIterator<Item = &'a Idris2Requirement>) -> bool {
+        // Allowed are the versions in `lowest..below`.
+        let mut lowest = Idris2Version { major: 0, minor: 0, patch: 0 };
+        let mut below: Option<Idris2Version> = None;
+        for (comparison, bound) in requirements.into_iter().flat_map(|requirement| &requirement.comparisons) {
+            let (low, high) = match comparison {
+                Comparison::Greater => (Some(bound.next()), None),
+                Comparison::GreaterEq => (Some(*bound), None),
+                Comparison::Less => (None, Some(*bound)),
+                Comparison::LessEq => (None, Some(bound.next())),
+                Comparison::Eq => (Some(*bound), Some(bound.next())),
+            };
+            lowest = lowest.max(low.unwrap_or(lowest));
+            below = match (below, high) {
+                (Some(below), Some(high)) => Some(below.min(high)),
+                (below, high) => below.or(high),
+            };
+        }
+        below.is_none_or(|below| lowest < below)
+    }
+}
+
+impl FromStr for Idris2Requirement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let comparisons = s.split(',')
+            .map(|part| {
+                let part = part.trim();
+                // Two-character operators first, so that `>=` isn't read as `>`.
+                let (comparison, version) = [
+                    (">=", Comparison::GreaterEq),
+                    ("<=", Comparison::LessEq),
+                    (">", Comparison::Greater),
+                    ("<", Comparison::Less),
+                    ("=", Comparison::Eq),
+                ].into_iter()
+                    .find_map(|(op, comparison)| Some((comparison, part.strip_prefix(op)?)))
+                    .unwrap_or((Comparison::Eq, part));
+                let version = Idris2Version::parse_number(version)
+                    .ok_or_else(|| format!("`{}` is not a valid idris2 version requirement, expected something like `>=0.6.0, <0.8`", s))?;
+                Ok((comparison, version))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { text: s.trim().to_owned(), comparisons })
+    }
+}
+
+impl TryFrom<String> for Idris2Requirement {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Idris2Requirement> for String {
+    fn from(requirement: Idris2Requirement) -> Self {
+        requirement.text
+    }
+}
+
+impl Display for Idris2Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// How dependencies are made visible to idris2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepsMode {
+    /// Put the TTC directory of every dependency on `IDRIS2_PATH`. Works with every idris2.
+    Idris2Path,
+    /// Install every dependency as `build/{toolchain}/idris2-packages/{name}-{version}`, put that directory on
+    /// `IDRIS2_PACKAGE_PATH`, and pass `-p {name}` for each. What newer idris2 versions expect.
+    PackagePath,
+}
+
+impl DepsMode {
+    /// [`DepsMode::PackagePath`] if the compiler is new enough, otherwise (or if we don't know
+    /// the compiler) [`DepsMode::Idris2Path`].
+    pub fn for_version(version: Option<Idris2Version>) -> Self {
+        match version {
+            Some(version) if version >= Idris2Version::PACKAGE_PATH => DepsMode::PackagePath,
+            _ => DepsMode::Idris2Path,
+        }
+    }
+}
+
+/// Code generators, passed to idris2 as `--cg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A launcher script, plus a `{name}_app` directory with the compiled Chez Scheme code.
+    Chez,
+    /// Like Chez.
+    Racket,
+    /// A single JavaScript file.
+    Node,
+    /// A native executable, via C.
+    RefC,
+}
+
+impl Backend {
+    /// What to pass to `--cg`.
+    pub fn cg(self) -> &'static str {
+        match self {
+            Backend::Chez => "chez",
+            Backend::Racket => "racket",
+            Backend::Node => "node",
+            Backend::RefC => "refc",
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chez" => Ok(Backend::Chez),
+            "racket" => Ok(Backend::Racket),
+            "node" => Ok(Backend::Node),
+            "refc" => Ok(Backend::RefC),
+            _ => Err(format!("Unknown backend `{}`, expected one of chez, racket, node, refc", s)),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Settings which belong to the user or the machine rather than to a package, like a proxy.
+//!
+//! They come from two TOML files, the project's overriding the global one key by key:
+//! - global: `config.toml` in [`layout::user_config_dir`], usually `~/.config/lair/config.toml`,
+//! - project: `.lair/config.toml` next to the root package's `Egg.toml`.
+//!
+//! ```toml
+//! [network]
+//! proxy = "http://proxy.example.com:3128"
+//!
+//! [trust]
+//! keys = ["3AA5C34371567BD2"]
+//!
+//! [build]
+//! sandbox = "env"
+//! link-local = true
+//!
+//! [updates]
+//! check = true
+//!
+//! [manifest]
+//! env = ["CORP_GIT_HOST"]
+//!
+//! [stats]
+//! record = true
+//! ```
+//!
+//! `lair config` reads and writes them key by key (see [`KEYS`]), checking values before writing
+//! them. It rewrites the whole file, so comments in it don't survive.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+
+use crate::interpolate;
+use crate::layout;
+use crate::sandbox::Sandbox;
+use crate::signature;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub network: Network,
+    #[serde(default)]
+    pub trust: Trust,
+    #[serde(default)]
+    pub build: Build,
+    #[serde(default)]
+    pub updates: Updates,
+    #[serde(default)]
+    pub manifest: ManifestSettings,
+    #[serde(default)]
+    pub stats: StatsSettings,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    /// Proxy for all git and HTTP traffic, for example `http://proxy.example.com:3128`. Takes
+    /// precedence over `https_proxy` and friends, see [`crate::proxy`].
+    pub proxy: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Trust {
+    /// Keys whose signatures on dependencies we accept, see [`crate::signature`].
+    pub keys: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Build {
+    /// How to build third-party packages, see [`crate::sandbox`]. `--sandbox` takes precedence.
+    #[serde(default, deserialize_with = "from_str")]
+    pub sandbox: Option<Sandbox>,
+    /// Symlink `path` dependencies instead of copying them, see [`crate::Lair::with_link_local`].
+    /// Off unless set.
+    #[serde(rename = "link-local")]
+    pub link_local: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Updates {
+    /// Tell when there's a newer Lair or idris2, see [`crate::updates`]. Off unless set.
+    pub check: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestSettings {
+    /// Environment variables manifests can use as `${env:VAR}`, see [`crate::interpolate`].
+    pub env: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatsSettings {
+    /// Record how long each command took and what it reused, see [`crate::stats`]. Off unless set.
+    pub record: Option<bool>,
+}
+
+/// A key `lair config` knows, see [`KEYS`].
+#[derive(Debug)]
+pub struct Key {
+    /// `section.key`, as in the files.
+    pub name: &'static str,
+    pub kind: Kind,
+    /// Says what's wrong with a value.
+    check: fn(&str) -> Result<(), String>,
+}
+
+/// What TOML a [`Key`] holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    String,
+    /// Of strings.
+    List,
+    Bool,
+}
+
+/// Every key there is.
+pub const KEYS: &[Key] = &[
+    Key { name: "network.proxy", kind: Kind::String, check: check_proxy },
+    Key { name: "trust.keys", kind: Kind::List, check: signature::check_key },
+    Key { name: "build.sandbox", kind: Kind::String, check: |s| s.parse::<Sandbox>().map(|_| ()) },
+    Key { name: "build.link-local", kind: Kind::Bool, check: check_bool },
+    Key { name: "updates.check", kind: Kind::Bool, check: check_bool },
+    Key { name: "manifest.env", kind: Kind::List, check: interpolate::check_name },
+    Key { name: "stats.record", kind: Kind::Bool, check: check_bool },
+];
+
+impl Key {
+    pub fn find(name: &str) -> Result<&'static Key, String> {
+        KEYS.iter().find(|key| key.name == name).ok_or_else(|| format!("Unknown config key `{}`, expected one of {}",
+            name, KEYS.iter().map(|key| format!("`{}`", key.name)).collect::<Vec<_>>().join(", ")))
+    }
+
+    fn section_and_name(&self) -> (&'static str, &'static str) {
+        self.name.split_once('.').expect("Keys are always `section.key`.")
+    }
+}
+
+fn check_bool(s: &str) -> Result<(), String> {
+    s.parse::<bool>().map(|_| ()).map_err(|_| format!("`{}` is neither `true` nor `false`", s))
+}
+
+fn check_proxy(proxy: &str) -> Result<(), String> {
+    match proxy.split_once("://") {
+        Some(("http" | "https" | "socks4" | "socks4a" | "socks5", host)) if !host.is_empty() => Ok(()),
+        _ => Err(format!("`{}` is not a proxy url, expected for example `http://proxy.example.com:3128`", proxy)),
+    }
+}
+
+/// Which file `lair config` reads or writes, see the module docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Project,
+}
+
+/// One of the files, as plain TOML, for `lair config`.
+#[derive(Clone, Debug)]
+pub struct File {
+    pub path: PathBuf,
+    table: toml::value::Table,
+}
+
+impl File {
+    /// The file for `scope`, empty if there is none yet.
+    pub fn open(scope: Scope, project_dir: &Path) -> Result<Self, anyhow::Error> {
+        let path = match scope {
+            Scope::Global => Config::global_path().context("Don't know where the global config goes, neither HOME nor APPDATA is set.")?,
+            Scope::Project => Config::project_path(project_dir),
+        };
+        let table = match std::fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).with_context(|| format!("Failed to read `{}`", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => toml::value::Table::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read `{}`", path.display())),
+        };
+        Ok(Self { path, table })
+    }
+
+    /// The value of `key`, as it is in the file. `None` if the file doesn't set it.
+    pub fn value(&self, key: &Key) -> Option<&toml::Value> {
+        let (section, name) = key.section_and_name();
+        self.table.get(section)?.get(name)
+    }
+
+    /// The value of `key`, one string per list element. `None` if the file doesn't set it.
+    pub fn get(&self, key: &Key) -> Option<Vec<String>> {
+        match self.value(key)? {
+            toml::Value::Array(values) => Some(values.iter().map(|value| match value {
+                toml::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            }).collect()),
+            toml::Value::String(s) => Some(vec![s.clone()]),
+            value => Some(vec![value.to_string()]),
+        }
+    }
+
+    /// Set `key` to `values`, after checking them. Only lists take more than one.
+    pub fn set(&mut self, key: &Key, values: Vec<String>) -> Result<(), anyhow::Error> {
+        if key.kind != Kind::List && values.len() != 1 {
+            anyhow::bail!("`{}` takes exactly one value, not {}.", key.name, values.len());
+        }
+        for value in &values {
+            (key.check)(value).map_err(|e| anyhow::anyhow!("{}.", e))?;
+        }
+        let mut values = values.into_iter();
+        let value = match key.kind {
+            Kind::String => toml::Value::String(values.next().expect("Checked above.")),
+            Kind::List => toml::Value::Array(values.map(toml::Value::String).collect()),
+            Kind::Bool => toml::Value::Boolean(values.next().expect("Checked above.") == "true"),
+        };
+        let (section, name) = key.section_and_name();
+        match self.table.entry(section).or_insert_with(|| toml::Value::Table(Default::default())) {
+            toml::Value::Table(section) => section.insert(name.to_owned(), value),
+            _ => anyhow::bail!("`{}` in `{}` isn't a section, fix or remove it first.", section, self.path.display()),
+        };
+        Ok(())
+    }
+
+    /// Remove `key`, and its section if that's empty then. `false` if the file didn't set it.
+    pub fn unset(&mut self, key: &Key) -> bool {
+        let (section, name) = key.section_and_name();
+        let Some(toml::Value::Table(table)) = self.table.get_mut(section) else {
+            return false;
+        };
+        let removed = table.remove(name).is_some();
+        if table.is_empty() {
+            self.table.remove(section);
+        }
+        removed
+    }
+
+    /// Write the file back, or delete it if nothing is left, unless Lair couldn't read it then.
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let s = toml::to_string(&self.table).expect("TOML tables are always serializable.");
+        toml::from_str::<Config>(&s).with_context(|| format!("`{}` wouldn't be a valid config file anymore", self.path.display()))?;
+        if self.table.is_empty() {
+            return match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).with_context(|| format!("Failed to remove `{}`", self.path.display())),
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create `{}`", dir.display()))?;
+        }
+        std::fs::write(&self.path, s).with_context(|| format!("Failed to write `{}`", self.path.display()))
+    }
+}
+
+/// Deserialize a `T` from a string through its `FromStr`.
+fn from_str<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl Config {
+    /// The global config file, if we know where home is.
+    pub fn global_path() -> Option<PathBuf> {
+        layout::user_config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// The config file of the project whose root package is in `project_dir`.
+    pub fn project_path(project_dir: &Path) -> PathBuf {
+        project_dir.join(".lair").join("config.toml")
+    }
+
+    /// Both files layered, see the module docs. Missing files are fine, invalid ones aren't.
+    pub fn load(project_dir: &Path) -> Result<Self, anyhow::Error> {
+        let mut config = Self::default();
+        for path in Self::global_path().into_iter().chain([Self::project_path(project_dir)]) {
+            if let Some(layer) = Self::read(&path)? {
+                config.overlay(layer);
+            }
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        let s = match std::fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read `{}`", path.display())),
+        };
+        let layer = toml::from_str(&s).with_context(|| format!("Failed to read `{}`", path.display()))?;
+        Ok(Some(layer))
+    }
+
+    /// Take every key `other` sets from it.
+    fn overlay(&mut self, other: Self) {
+        if other.network.proxy.is_some() {
+            self.network.proxy = other.network.proxy;
+        }
+        if other.trust.keys.is_some() {
+            self.trust.keys = other.trust.keys;
+        }
+        if other.build.sandbox.is_some() {
+            self.build.sandbox = other.build.sandbox;
+        }
+        if other.build.link_local.is_some() {
+            self.build.link_local = other.build.link_local;
+        }
+        if other.updates.check.is_some() {
+            self.updates.check = other.updates.check;
+        }
+        if other.manifest.env.is_some() {
+            self.manifest.env = other.manifest.env;
+        }
+        if other.stats.record.is_some() {
+            self.stats.record = other.stats.record;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Registry tokens, from `lair login`, for talking to a registry's API (see
+//! [`crate::registry_api`]).
+//!
+//! Tokens go into the OS keychain where we can reach one: the login keychain through `security` on
+//! macOS, and the Secret Service (GNOME Keyring, KWallet) through `secret-tool` elsewhere.
+//! Otherwise they go into `credentials.toml` next to the global config (see
+//! [`layout::user_config_dir`]), which only the user may read:
+//!
+//! ```toml
+//! [registries."https://github.com/Kiiyya/lair-index"]
+//! token = "..."
+//! ```
+//!
+//! `LAIR_REGISTRY_TOKEN`, if set, is used instead of either, for CI.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::fetch;
+use crate::layout;
+
+/// Where a token ended up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Storage {
+    Keychain,
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    registries: BTreeMap<String, Entry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    token: String,
+}
+
+/// Remember `token` for the registry whose index is at `registry`, replacing any earlier one.
+/// Blocking.
+pub fn store(registry: &str, token: &str) -> Result<Storage, anyhow::Error> {
+    let registry = fetch::canonical_url(registry);
+    if keychain_store(&registry, token) {
+        // Don't leave an older token behind in the file, where it would outlive a logout.
+        remove_from_file(&registry)?;
+        return Ok(Storage::Keychain);
+    }
+    let path = path()?;
+    let mut file = read_file()?;
+    file.registries.insert(registry, Entry { token: token.to_owned() });
+    write_file(&file)?;
+    Ok(Storage::File(path))
+}
+
+/// The token for `registry`, if we have one. Blocking.
+pub fn load(registry: &str) -> Result<Option<String>, anyhow::Error> {
+    if let Some(token) = std::env::var("LAIR_REGISTRY_TOKEN").ok().filter(|token| !token.is_empty()) {
+        return Ok(Some(token));
+    }
+    let registry = fetch::canonical_url(registry);
+    if let Some(token) = keychain_load(&registry) {
+        return Ok(Some(token));
+    }
+    Ok(read_file()?.registries.remove(&registry).map(|entry| entry.token))
+}
+
+/// Forget the token for `registry`, wherever it is. `false` if there was none. Blocking.
+pub fn remove(registry: &str) -> Result<bool, anyhow::Error> {
+    let registry = fetch::canonical_url(registry);
+    let from_keychain = keychain_remove(&registry);
+    let from_file = remove_from_file(&registry)?;
+    Ok(from_keychain || from_file)
+}
+
+/// `false` if there is no keychain we can use, or it refused.
+fn keychain_store(registry: &str, token: &str) -> bool {
+    if cfg!(target_os = "macos") {
+        // `-w` last asks for the password, twice, rather than taking it on the command line, where
+        // anybody could see it in `ps`.
+        let input = format!("{}\n{}\n", token, token);
+        run(Command::new("security").args(["add-generic-password", "-U", "-s", "lair", "-a", registry, "-w"]), Some(&input)).is_some()
+    } else if cfg!(windows) {
+        false
+    } else {
+        let label = format!("Lair token for {}", registry);
+        run(Command::new("secret-tool").args(["store", "--label", &label, "service", "lair", "registry", registry]), Some(token)).is_some()
+    }
+}
+
+fn keychain_load(registry: &str) -> Option<String> {
+    let output = if cfg!(target_os = "macos") {
+        run(Command::new("security").args(["find-generic-password", "-s", "lair", "-a", registry, "-w"]), None)?
+    } else if cfg!(windows) {
+        return None;
+    } else {
+        run(Command::new("secret-tool").args(["lookup", "service", "lair", "registry", registry]), None)?
+    };
+    let token = output.trim();
+    (!token.is_empty()).then(|| token.to_owned())
+}
+
+fn keychain_remove(registry: &str) -> bool {
+    if cfg!(target_os = "macos") {
+        run(Command::new("security").args(["delete-generic-password", "-s", "lair", "-a", registry]), None).is_some()
+    } else if cfg!(windows) {
+        false
+    } else {
+        // `secret-tool clear` succeeds whether or not there was anything, so look first.
+        keychain_load(registry).is_some()
+            && run(Command::new("secret-tool").args(["clear", "service", "lair", "registry", registry]), None).is_some()
+    }
+}
+
+/// Run `command`, writing `input` to its stdin. Its stdout if it succeeded, `None` if it couldn't
+/// be run or failed.
+fn run(command: &mut Command, input: Option<&str>) -> Option<String> {
+    let mut child = command
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    if let Some(input) = input {
+        child.stdin.take()?.write_all(input.as_bytes()).ok()?;
+    }
+    let output = child.wait_with_output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn path() -> Result<PathBuf, anyhow::Error> {
+    Ok(layout::user_config_dir().context("Don't know where to keep the token, since neither HOME nor XDG_CONFIG_HOME is set")?
+        .join("credentials.toml"))
+}
+
+fn read_file() -> Result<File, anyhow::Error> {
+    // Without a home there can't be a file either.
+    let Ok(path) = path() else { return Ok(File::default()) };
+    match std::fs::read_to_string(&path) {
+        Ok(s) => toml::from_str(&s).with_context(|| format!("Failed to read `{}`", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(File::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read `{}`", path.display())),
+    }
+}
+
+fn write_file(file: &File) -> Result<(), anyhow::Error> {
+    let path = path()?;
+    if file.registries.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&path)
+        .and_then(|mut f| f.write_all(toml::to_string(file).expect("Credentials are always serializable.").as_bytes()))
+        .with_context(|| format!("Failed to write `{}`", path.display()))
+}
+
+fn remove_from_file(registry: &str) -> Result<bool, anyhow::Error> {
+    let mut file = read_file()?;
+    if file.registries.remove(registry).is_none() {
+        return Ok(false);
+    }
+    write_file(&file)?;
+    Ok(true)
+}
@@ -0,0 +1,91 @@
+//! The nodes of a run, by [`ResolvedDescriptor`], looked up by every task, often all at once.
+//!
+//! Split into [`SHARDS`] maps, each behind its own `RwLock`, picked by the key's hash. Looking up
+//! a node only takes its shard's read lock, so lookups never wait for each other, and only wait
+//! for a new node being added to the same shard. Nobody holds a lock across an `.await`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::descriptor::ResolvedDescriptor;
+use crate::profile::Profiler;
+
+/// Plenty for the number of tasks running at once, which is what contends.
+const SHARDS: usize = 16;
+
+type Shard<V> = RwLock<BTreeMap<ResolvedDescriptor, V>>;
+
+#[derive(Debug)]
+pub struct Db<V> {
+    shards: Vec<Shard<V>>,
+}
+
+impl<V: Clone> Db<V> {
+    pub fn new() -> Self {
+        Self { shards: (0..SHARDS).map(|_| RwLock::new(BTreeMap::new())).collect() }
+    }
+
+    fn shard(&self, key: &ResolvedDescriptor) -> &Shard<V> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    fn read<'a>(
+        shard: &'a Shard<V>,
+        profiler: Option<&Profiler>,
+    ) -> RwLockReadGuard<'a, BTreeMap<ResolvedDescriptor, V>> {
+        match profiler {
+            Some(profiler) => profiler.wait("db", || shard.try_read().ok(), || shard.read().unwrap()),
+            None => shard.read().unwrap(),
+        }
+    }
+
+    fn write<'a>(
+        shard: &'a Shard<V>,
+        profiler: Option<&Profiler>,
+    ) -> RwLockWriteGuard<'a, BTreeMap<ResolvedDescriptor, V>> {
+        match profiler {
+            Some(profiler) => profiler.wait("db", || shard.try_write().ok(), || shard.write().unwrap()),
+            None => shard.write().unwrap(),
+        }
+    }
+
+    /// Waits for locks are recorded in `profiler`, if given.
+    pub fn get(&self, key: &ResolvedDescriptor, profiler: Option<&Profiler>) -> Option<V> {
+        Self::read(self.shard(key), profiler).get(key).cloned()
+    }
+
+    pub fn insert(&self, key: ResolvedDescriptor, value: V) {
+        Self::write(self.shard(&key), None).insert(key, value);
+    }
+
+    /// The value for `key`, made by `make` if there is none yet. `make` runs without holding any
+    /// lock, so two tasks may both make one, and the first to insert it wins.
+    pub fn get_or_insert_with(&self, key: ResolvedDescriptor, profiler: Option<&Profiler>, make: impl FnOnce() -> V) -> V {
+        let shard = self.shard(&key);
+        if let Some(value) = Self::read(shard, profiler).get(&key) {
+            return value.clone();
+        }
+        let value = make();
+        // Someone may have added it meanwhile.
+        Self::write(shard, profiler).entry(key).or_insert(value).clone()
+    }
+
+    /// Everything in here, by key.
+    pub fn entries(&self) -> Vec<(ResolvedDescriptor, V)> {
+        let mut entries: Vec<_> = self.shards.iter()
+            .flat_map(|shard| shard.read().unwrap().iter().map(|(key, value)| (key.clone(), value.clone())).collect::<Vec<_>>())
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+}
+
+impl<V: Clone> Default for Db<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,16 +1,28 @@
+use std::fmt::Display;
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
+use crate::error::SourceFetchError;
+use crate::package_name::PackageName;
 
 /// A git repository alone isn't enough to determine the source code version to use.
 /// We may want a specific branch or tag to be used instead.
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum GitVersion {
     /// E.g. `main`.
     Branch(String),
-    /// Full commit hash.
+    /// Commit hash, possibly abbreviated.
     Rev(String),
     /// Some git tag.
     Tag(String),
+    /// Whichever branch the remote's `HEAD` points to, usually `main` or `master`. What a
+    /// dependency without `branch`, `tag` or `rev` follows.
+    DefaultBranch,
+    /// The newest tag `{prefix}{version}` whose version matches `requirement` (see
+    /// [`crate::registry::matches`]). For monorepos tagging each package on its own, like
+    /// `collections-v1.2.0` and `json-v0.4.1`.
+    TagPrefix { prefix: String, requirement: String },
 }
 
 /// *Dependency descriptor*: package name together with version. Enough to info to find and download
@@ -19,18 +31,58 @@ pub enum GitVersion {
 /// This should determine the exact source code
 /// Ideally (loc1 == loc2 ==> hash(loc1.download()) == hash(loc2.download())), assuming same point
 /// in time.
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Descriptor {
     Root {
-        name: String,
+        name: PackageName,
     },
 
     Git {
         /// Package name, for example `CoolCollections`.
-        name: String,
+        /// If `package` is set, this is the alias we refer to the package by instead.
+        name: PackageName,
+        /// Actual package name, if the package is depended upon under a different `name`.
+        package: Option<PackageName>,
         url: String,
         /// Do we refer to a branch, commit hash, or tag?
         version: GitVersion,
+        /// Where the package lives inside the repository, if not at its root.
+        subdir: Option<PathBuf>,
+    },
+
+    /// Source code comes packed in a `.tar.gz` or `.zip` file, for example a release tarball.
+    Archive {
+        /// Package name, for example `CoolCollections`.
+        /// If `package` is set, this is the alias we refer to the package by instead.
+        name: PackageName,
+        /// Actual package name, if the package is depended upon under a different `name`.
+        package: Option<PackageName>,
+        /// For example `https://example.com/CoolCollections-0.1.0.tar.gz`.
+        url: String,
+        /// Hex-encoded sha256 of the archive. If given, the download is rejected on mismatch.
+        checksum: Option<String>,
+        /// Url of a detached GPG or SSH signature of the archive, see [`crate::signature`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        /// Where the package lives inside the archive, if not at its root.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdir: Option<PathBuf>,
+    },
+
+    /// A version of a package published in a registry, see [`crate::registry`]. Which version
+    /// (and so which git commit) this is gets decided when it is first fetched, and is then
+    /// recorded in the lockfile.
+    Registry {
+        /// Package name, for example `CoolCollections`.
+        /// If `package` is set, this is the alias we refer to the package by instead.
+        name: PackageName,
+        /// Actual package name, if the package is depended upon under a different `name`.
+        package: Option<PackageName>,
+        /// Url of the registry's index.
+        registry: String,
+        /// Which versions will do, for example `0.2` for any `0.2.x`. See
+        /// [`crate::registry::matches`].
+        version: String,
     },
 
     /// Origin of source code is somewhere on the local computer.
@@ -39,18 +91,238 @@ pub enum Descriptor {
     /// arbitrary absolute path?
     Local {
         /// Package name, for example `CoolCollections`.
-        name: String,
+        /// If `package` is set, this is the alias we refer to the package by instead.
+        name: PackageName,
+        /// Actual package name, if the package is depended upon under a different `name`.
+        package: Option<PackageName>,
         path: PathBuf,
     },
 }
 
 impl Descriptor {
     /// Get the package name, for example `CoolCollections`.
+    ///
+    /// For aliased dependencies (`CoolFork = { git = "...", package = "CoolCollections" }`), this
+    /// is the alias (`CoolFork`), which is what on-disk paths and collision checks use.
+    /// See [`Descriptor::package_name`] for the actual package name.
     pub fn name(&self) -> &str {
         match self {
             Descriptor::Git { name, .. } => name,
+            Descriptor::Archive { name, .. } => name,
+            Descriptor::Registry { name, .. } => name,
             Descriptor::Local { name, .. } => name,
             Descriptor::Root { name } => name,
         }
     }
+
+    /// The package's actual name, even if it is depended upon under an alias. Determines the
+    /// main module, for example `CoolCollections` means `src/CoolCollections.idr`.
+    pub fn package_name(&self) -> &str {
+        match self {
+            Descriptor::Git { package: Some(package), .. } => package,
+            Descriptor::Archive { package: Some(package), .. } => package,
+            Descriptor::Registry { package: Some(package), .. } => package,
+            Descriptor::Local { package: Some(package), .. } => package,
+            _ => self.name(),
+        }
+    }
+
+    /// The actual package name, if the package is depended upon under an alias.
+    pub fn package(&self) -> Option<&PackageName> {
+        match self {
+            Descriptor::Git { package, .. }
+            | Descriptor::Archive { package, .. }
+            | Descriptor::Registry { package, .. }
+            | Descriptor::Local { package, .. } =>
+                package.as_ref(),
+            Descriptor::Root { .. } => None,
+        }
+    }
+
+    /// The name and, if aliased, the actual package name, for replacing them in place. See
+    /// [`crate::intern::Interner::descriptor`].
+    pub fn names_mut(&mut self) -> impl Iterator<Item = &mut PackageName> {
+        let (name, package) = match self {
+            Descriptor::Git { name, package, .. }
+            | Descriptor::Archive { name, package, .. }
+            | Descriptor::Registry { name, package, .. }
+            | Descriptor::Local { name, package, .. } =>
+                (name, package.as_mut()),
+            Descriptor::Root { name } => (name, None),
+        };
+        std::iter::once(name).chain(package)
+    }
+
+    /// Replace the actual package name, see [`Descriptor::package_name`]. Does nothing for the
+    /// root package.
+    pub fn set_package(&mut self, new: Option<PackageName>) {
+        match self {
+            Descriptor::Git { package, .. }
+            | Descriptor::Archive { package, .. }
+            | Descriptor::Registry { package, .. }
+            | Descriptor::Local { package, .. } =>
+                *package = new,
+            Descriptor::Root { .. } => {},
+        }
+    }
+
+    /// `self` with the `${env:VAR}` in its urls expanded, for downloading it, see
+    /// [`crate::interpolate`]. Everything else, lockfile included, goes by `self`.
+    pub fn expanded(&self) -> Result<Descriptor, SourceFetchError> {
+        let mut expanded = self.clone();
+        let urls = match &mut expanded {
+            Descriptor::Git { url, .. } => vec![url],
+            Descriptor::Archive { url, signature, .. } => std::iter::once(url).chain(signature).collect(),
+            Descriptor::Registry { registry, .. } => vec![registry],
+            Descriptor::Local { .. } | Descriptor::Root { .. } => vec![],
+        };
+        for url in urls {
+            *url = crate::interpolate::expand(url)
+                .map_err(|error| SourceFetchError::Unexpanded { name: self.name().to_owned(), error })?;
+        }
+        Ok(expanded)
+    }
+
+    /// Where the package comes from, for example `https://github.com/Kiiyya/NotJson, branch main`.
+    /// `None` for the root package.
+    pub fn source(&self) -> Option<String> {
+        let origin = self.origin()?;
+        if self.package_name() != self.name() {
+            Some(format!("package {}, {}", self.package_name(), origin))
+        } else {
+            Some(origin)
+        }
+    }
+
+    fn origin(&self) -> Option<String> {
+        match self {
+            Descriptor::Root { .. } => None,
+            Descriptor::Git { url, version, subdir: None, .. } => Some(format!("{}, {}", url, version)),
+            Descriptor::Git { url, version, subdir: Some(subdir), .. } =>
+                Some(format!("{}, {}, in {}", url, version, subdir.display())),
+            Descriptor::Archive { url, subdir: None, .. } => Some(url.clone()),
+            Descriptor::Archive { url, subdir: Some(subdir), .. } => Some(format!("{}, in {}", url, subdir.display())),
+            Descriptor::Registry { registry, version, .. } => Some(format!("version {}, registry {}", version, registry)),
+            Descriptor::Local { path, .. } => Some(path.display().to_string()),
+        }
+    }
+}
+
+/// What a [`Descriptor`] refers to, as far as we know before fetching anything: for git
+/// dependencies, the commit the lockfile pins them to (or their `rev`, if it is a whole commit
+/// hash). Nodes are keyed by this rather than by the descriptor, so that `branch = "main"` and a
+/// `rev` of the same commit share one node and one checkout, and packages are told apart by the
+/// code they contain rather than by how they were asked for. Likewise, a dependency following the
+/// default branch shares its node with one naming that branch, once we know which one it is (see
+/// [`ResolvedDescriptor::following`]).
+///
+/// Each descriptor is resolved once, when its node is first wanted, and keeps that resolution for
+/// the rest of the run, even once the lockfile moves on.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResolvedDescriptor {
+    /// A git dependency at a known commit.
+    Git {
+        name: PackageName,
+        package: Option<PackageName>,
+        /// Canonical, see [`crate::fetch::canonical_url`].
+        url: String,
+        commit: String,
+        subdir: Option<PathBuf>,
+    },
+    /// A git dependency following a branch, which isn't locked yet.
+    Branch {
+        name: PackageName,
+        package: Option<PackageName>,
+        /// Canonical, see [`crate::fetch::canonical_url`].
+        url: String,
+        branch: String,
+        subdir: Option<PathBuf>,
+    },
+    /// Everything else, including git dependencies on tags which aren't locked yet.
+    Unresolved(Descriptor),
+}
+
+impl ResolvedDescriptor {
+    /// Resolve `desc`, given the commit the lockfile has for it, if any.
+    pub fn new(desc: &Descriptor, locked: Option<&str>) -> Self {
+        let Descriptor::Git { name, package, url, version, subdir } = desc else {
+            return ResolvedDescriptor::Unresolved(desc.clone());
+        };
+        let commit = locked.map(str::to_lowercase).or_else(|| match version {
+            GitVersion::Rev(rev) if rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit()) => Some(rev.to_lowercase()),
+            _ => None,
+        });
+        match commit {
+            Some(commit) => ResolvedDescriptor::Git {
+                name: name.clone(),
+                package: package.clone(),
+                url: crate::fetch::canonical_url(url),
+                commit,
+                subdir: subdir.clone(),
+            },
+            None => match version {
+                GitVersion::Branch(branch) => ResolvedDescriptor::Branch {
+                    name: name.clone(),
+                    package: package.clone(),
+                    url: crate::fetch::canonical_url(url),
+                    branch: branch.clone(),
+                    subdir: subdir.clone(),
+                },
+                _ => ResolvedDescriptor::Unresolved(desc.clone()),
+            },
+        }
+    }
+
+    /// `desc`, a git dependency following the default branch, as if it named `branch`, which the
+    /// default branch is. `None` for other descriptors.
+    pub fn following(desc: &Descriptor, branch: &str) -> Option<Descriptor> {
+        let mut named = desc.clone();
+        match &mut named {
+            Descriptor::Git { version: version @ GitVersion::DefaultBranch, .. } => *version = GitVersion::Branch(branch.to_owned()),
+            _ => return None,
+        }
+        Some(named)
+    }
+}
+
+impl Display for GitVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GitVersion::Branch(branch) => write!(f, "branch {}", branch),
+            GitVersion::Rev(rev) => write!(f, "rev {}", rev),
+            GitVersion::Tag(tag) => write!(f, "tag {}", tag),
+            GitVersion::DefaultBranch => write!(f, "default branch"),
+            GitVersion::TagPrefix { prefix, requirement } if requirement == "*" => write!(f, "newest tag {}<version>", prefix),
+            GitVersion::TagPrefix { prefix, requirement } => write!(f, "newest tag {}<version> matching {}", prefix, requirement),
+        }
+    }
+}
+
+/// For example `NotJson (https://github.com/Kiiyya/NotJson, branch main)`.
+impl Display for Descriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.source() {
+            Some(source) => write!(f, "{} ({})", self.name(), source),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(url: &str, version: GitVersion) -> Descriptor {
+        Descriptor::Git { name: "B".parse().unwrap(), package: None, url: url.to_owned(), version, subdir: None }
+    }
+
+    #[test]
+    fn default_branch_shares_with_its_name() {
+        let default = git("https://example.com/B.git", GitVersion::DefaultBranch);
+        let named = ResolvedDescriptor::following(&default, "main").unwrap();
+        assert_eq!(named, git("https://example.com/B.git", GitVersion::Branch("main".to_owned())));
+        assert_eq!(ResolvedDescriptor::new(&named, None), ResolvedDescriptor::new(&git("https://example.com/B", GitVersion::Branch("main".to_owned())), None));
+        assert_ne!(ResolvedDescriptor::new(&named, None), ResolvedDescriptor::new(&git("https://example.com/B", GitVersion::Branch("dev".to_owned())), None));
+        assert!(ResolvedDescriptor::following(&named, "dev").is_none());
+    }
 }
@@ -0,0 +1,102 @@
+//! `lair doc`: HTML documentation of the root package, and optionally of its dependencies, in
+//! `build/docs`.
+//!
+//! idris2 only documents packages given as an `.ipkg`, so for each package we write one to
+//! `build/.lair/doc/`, listing every module in its `src` and building to where its TTC files
+//! already are. `idris2 --mkdoc` then writes the package's documentation next to those, which we
+//! copy into `build/docs`:
+//!
+//! ```text
+//! build/docs/
+//!     index.html          -- Lists the packages.
+//!     AmazingTool.html    -- What idris2 made the package's index.html, one per package.
+//!     NotJson.html
+//!     docs/               -- The modules' pages, of all packages together.
+//! ```
+//!
+//! Since all module pages end up in the same directory, idris2's links from one package's pages
+//! to modules of its dependencies work, as long as those were documented too.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::files;
+use crate::layout;
+use crate::paths;
+
+/// `build/docs`.
+pub fn dir() -> PathBuf {
+    layout::build_dir().join("docs")
+}
+
+/// Where the `.ipkg` for documenting `name` goes, `build/.lair/doc/{name}.ipkg`.
+pub fn ipkg_path(name: &str) -> PathBuf {
+    layout::lair_dir().join("doc").join(format!("{}.ipkg", name))
+}
+
+/// An ipkg documenting `modules` from `source_dir`, building into `build_dir`. Both must be
+/// absolute, since idris2 reads them relative to the ipkg.
+pub fn ipkg(name: &str, version: &str, source_dir: &Path, build_dir: &Path, modules: &[String], depends: &[String]) -> String {
+    let mut ipkg = format!("package {}\nversion = {}\nsourcedir = {:?}\nbuilddir = {:?}\n",
+        name, version, source_dir.display().to_string(), build_dir.display().to_string());
+    if !modules.is_empty() {
+        ipkg.push_str(&format!("modules = {}\n", modules.join(", ")));
+    }
+    if !depends.is_empty() {
+        ipkg.push_str(&format!("depends = {}\n", depends.join(", ")));
+    }
+    ipkg
+}
+
+/// Copy what `idris2 --mkdoc` wrote to `generated` (`{build_dir}/docs`) into `dest`, with its
+/// `index.html` becoming `{name}.html`. Blocking.
+pub fn collect(generated: &Path, dest: &Path, name: &str) -> Result<(), anyhow::Error> {
+    if !generated.join("index.html").is_file() {
+        anyhow::bail!("idris2 didn't write any documentation for `{}` to `{}`", name, generated.display());
+    }
+    std::fs::create_dir_all(dest)?;
+    for entry in std::fs::read_dir(generated)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name() else { continue };
+        if file_name == "index.html" {
+            std::fs::copy(&path, dest.join(format!("{}.html", name)))?;
+        } else if path.is_dir() {
+            files::copy_dir(&paths::long(&path), &paths::long(&dest.join(file_name)))?;
+        } else {
+            std::fs::copy(&path, dest.join(file_name))?;
+        }
+    }
+    Ok(())
+}
+
+/// `{dest}/index.html`, linking to the page of each package, given as (name, version,
+/// description). Blocking.
+pub fn write_index(dest: &Path, packages: &[(String, String, Option<String>)]) -> std::io::Result<()> {
+    let items: String = packages.iter()
+        .map(|(name, version, description)| format!("<li><a href=\"{}.html\">{}</a> {}{}</li>\n",
+            escape(name), escape(name), escape(version),
+            description.as_ref().map(|description| format!(": {}", escape(description))).unwrap_or_default()))
+        .collect();
+    let title = packages.first().map(|(name, _, _)| escape(name)).unwrap_or_default();
+    std::fs::write(dest.join("index.html"), format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<link rel=\"stylesheet\" href=\"styles.css\">\n</head>\n<body>\n<h1>{}</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+        title, title, items))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Open `page` in the default browser, without waiting for it.
+pub fn open(page: &Path) -> std::io::Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(page).spawn().map(drop)
+}
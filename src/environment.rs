@@ -0,0 +1,46 @@
+//! The environment idris2 (or anything else wrapping it, like an LSP server) needs to see a
+//! project's dependencies.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::error::BuildTtcError;
+use crate::paths::Idris2Paths;
+
+#[derive(Clone, Debug, Default)]
+pub struct Idris2Env {
+    /// TTC directories of the dependencies, passed as `IDRIS2_PATH`.
+    pub idris2_path: Vec<PathBuf>,
+
+    /// Directories containing installed `{name}-{version}` packages, passed as
+    /// `IDRIS2_PACKAGE_PATH`. Empty unless dependencies are installed in idris2's package layout.
+    pub package_path: Vec<PathBuf>,
+
+    /// Installed packages to load, passed to idris2 as `-p {name}`.
+    pub packages: Vec<String>,
+}
+
+impl Idris2Env {
+    /// The environment variables to set, for example `("IDRIS2_PATH", "build/deps/NotJson-3f2a9c01/build/idris2-0.6.0/ttc")`.
+    pub fn vars(&self) -> Result<Vec<(&'static str, OsString)>, BuildTtcError> {
+        let mut vars = vec![("IDRIS2_PATH", self.idris2_path.join_idris2()?)];
+        if !self.package_path.is_empty() {
+            vars.push(("IDRIS2_PACKAGE_PATH", self.package_path.join_idris2()?));
+        }
+        Ok(vars)
+    }
+
+    /// Command line arguments for idris2 itself, for example `["-p", "NotJson"]`. Other
+    /// programs can't be expected to understand these.
+    pub fn idris2_args(&self) -> Vec<String> {
+        self.packages.iter()
+            .flat_map(|package| ["-p".to_owned(), package.clone()])
+            .collect()
+    }
+
+    /// Set the environment variables on `command`.
+    pub fn apply<'c>(&self, command: &'c mut Command) -> Result<&'c mut Command, BuildTtcError> {
+        Ok(command.envs(self.vars()?))
+    }
+}
@@ -1,13 +1,411 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
 use std::sync::Arc;
 
+use crate::compiler::{Idris2Requirement, Idris2Version};
+use crate::descriptor::{Descriptor, GitVersion};
+use crate::graph::{Origin, Requirement};
+use crate::modules::Collision;
+
+/// Exit codes of the `lair` command line tool, so that scripts can tell failures apart. Anything
+/// else failing exits with 1.
+pub mod exit_codes {
+    /// A manifest is missing or invalid.
+    pub const MANIFEST: u8 = 101;
+    /// Downloading or checking out a dependency failed.
+    pub const FETCH: u8 = 102;
+    /// idris2 failed, couldn't be run at all, or is a version some package doesn't work with.
+    pub const COMPILE: u8 = 103;
+    /// The dependency graph needs two versions of the same package, or no idris2 version works
+    /// with all of it.
+    pub const CONFLICT: u8 = 104;
+    /// Stopped by Ctrl-C, see [`crate::interrupt`]. What shells use for SIGINT.
+    pub const INTERRUPTED: u8 = 130;
+}
+
+/// The code of `error`, for `lair explain`, looking through whatever was wrapped around it. `None`
+/// for errors Lair doesn't know more about, and for [`Failures`], which have one per failure.
+pub fn code_of(error: &anyhow::Error) -> Option<&'static str> {
+    error.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<BuildTtcError>() {
+            e.code()
+        } else if let Some(e) = cause.downcast_ref::<ManifestFetchError>() {
+            e.code()
+        } else if let Some(e) = cause.downcast_ref::<SourceFetchError>() {
+            e.code()
+        } else {
+            cause.is::<crate::interrupt::Interrupted>().then_some("L0902")
+        }
+    })
+}
+
+/// The package `error` happened in, looking through whatever was wrapped around it.
+pub fn provenance_of(error: &anyhow::Error) -> Option<&Provenance> {
+    error.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<BuildTtcError>() {
+            e.provenance()
+        } else if let Some(e) = cause.downcast_ref::<ManifestFetchError>() {
+            e.provenance()
+        } else {
+            cause.downcast_ref::<SourceFetchError>().and_then(SourceFetchError::provenance)
+        }
+    })
+}
+
+/// The exit code for `error`, see [`exit_codes`].
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        if let Some(failures) = cause.downcast_ref::<Failures>() {
+            return failures.errors.first().map_or(1, exit_code);
+        }
+        if let Some(e) = cause.downcast_ref::<BuildTtcError>() {
+            return e.exit_code();
+        }
+        if let Some(e) = cause.downcast_ref::<ManifestFetchError>() {
+            return e.exit_code();
+        }
+        if let Some(e) = cause.downcast_ref::<SourceFetchError>() {
+            return e.exit_code();
+        }
+        if cause.is::<crate::interrupt::Interrupted>() {
+            return exit_codes::INTERRUPTED;
+        }
+    }
+    1
+}
+
+/// `error`, and each of its causes on a line of its own, the way `lair` prints failures, with its
+/// [code](code_of) if it has one:
+///
+/// ```text
+/// Error[L0401]: Failed to build `CoolCollections` (https://github.com/Kiiyya/CoolCollections, branch main, commit 3f2a9c0)
+///   caused by: idris2 failed (exit status: 1). Its full output is in `build/logs/CoolCollections.log`.
+/// Run `lair explain L0401` for more about this error.
+/// ```
+pub fn render(error: &anyhow::Error) -> String {
+    let code = code_of(error);
+    let mut rendered = match code {
+        Some(code) => format!("Error[{}]: {}", code, error),
+        None => format!("Error: {}", error),
+    };
+    for cause in error.chain().skip(1) {
+        rendered.push_str(&format!("\n  caused by: {}", cause));
+    }
+    let failures = error.downcast_ref::<Failures>();
+    match code {
+        Some(code) => rendered.push_str(&format!("\nRun `lair explain {}` for more about this error.", code)),
+        None if failures.is_some_and(|failures| failures.errors.iter().any(|e| code_of(e).is_some())) => {
+            rendered.push_str("\nRun `lair explain <code>` for more about an error.");
+        },
+        None => {},
+    }
+    rendered
+}
+
+/// Everything which went wrong during a `--keep-going` build, which goes on with whatever doesn't
+/// depend on a failed package:
+///
+/// ```text
+/// Error: 2 packages failed
+///   [L0401] Failed to build `NotJson` (https://github.com/Kiiyya/NotJson, branch main, commit 3f2a9c0)
+///     caused by: idris2 failed (exit status: 1). Its full output is in `build/logs/NotJson.log`.
+///   [L0101] Failed to fetch `CoolCollections` (https://github.com/Kiiyya/CoolCollections, branch main)
+///     caused by: ...
+///   Not built, since something they depend on failed: AmazingTool
+/// ```
+#[derive(Debug)]
+pub struct Failures {
+    pub errors: Vec<anyhow::Error>,
+    /// Packages which weren't built, because they depend on one which failed.
+    pub skipped: Vec<Descriptor>,
+}
+
+impl Display for Failures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} package{} failed", self.errors.len(), if self.errors.len() == 1 { "" } else { "s" })?;
+        for error in &self.errors {
+            match code_of(error) {
+                Some(code) => write!(f, "\n  [{}] {}", code, error)?,
+                None => write!(f, "\n  {}", error)?,
+            }
+            for cause in error.chain().skip(1) {
+                write!(f, "\n    caused by: {}", cause)?;
+            }
+        }
+        if !self.skipped.is_empty() {
+            let names: Vec<&str> = self.skipped.iter().map(Descriptor::name).collect();
+            write!(f, "\n  Not built, since something they depend on failed: {}", names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failures {}
+
+/// What Lair was doing with a package when something went wrong, see [`Provenance`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Fetching,
+    ReadingManifest,
+    Building,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Phase::Fetching => "fetch",
+            Phase::ReadingManifest => "read the manifest of",
+            Phase::Building => "build",
+        })
+    }
+}
+
+/// Which package an error belongs to, and where that package came from. Errors get this attached
+/// once, by the package they happened in, and keep it while they bubble up through the packages
+/// depending on it.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub desc: Descriptor,
+    pub phase: Phase,
+    /// What `Egg.lock` pinned the package to, if anything yet.
+    pub commit: Option<String>,
+    /// The package which first turned out to depend on it, and where it declares that.
+    pub introduced_by: Option<(Descriptor, Origin)>,
+}
+
+/// For example "Failed to build `NotJson` (https://github.com/Kiiyya/NotJson, branch main, commit
+/// 3f2a9c0), required by `AmazingTool` as `dependencies.NotJson` in Egg.toml:7".
+impl Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let details: Vec<String> = self.desc.source().into_iter()
+            .chain(self.commit.as_ref().map(|commit| format!("commit {}", &commit[..commit.len().min(7)])))
+            .collect();
+        write!(f, "Failed to {} `{}`", self.phase, self.desc.name())?;
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        if let Some((dependent, origin)) = &self.introduced_by {
+            write!(f, ", required by `{}` as {}", dependent.name(), origin)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum SourceFetchError {
-    #[error("Dummy")]
-    Dummy(Arc<anyhow::Error>),
+    #[error("{provenance}")]
+    Package {
+        provenance: Box<Provenance>,
+        #[source]
+        error: Box<SourceFetchError>,
+    },
 
-    #[error("Dummy")]
+    #[error("{0:#}")]
+    Other(Arc<anyhow::Error>),
+
+    #[error("git: {0}")]
     GitError(Arc<git2::Error>),
+
+    #[error("git failed: {0}")]
+    GitCommand(String),
+
+    #[error("Commit {commit} doesn't exist in the repository. If it is pinned in Egg.lock, maybe it was force-pushed away; try `lair lock --update <package>`.")]
+    CommitNotFound { commit: String },
+
+    #[error("`{url}` has no {version}. Check the spelling, and whether it was pushed.")]
+    VersionNotFound { url: String, version: GitVersion },
+
+    #[error("HTTP error: {0}")]
+    Http(Arc<ureq::Error>),
+
+    #[error("File IO error: {0}")]
+    Io(Arc<std::io::Error>),
+
+    #[error("Failed to unpack zip archive: {0}")]
+    Zip(Arc<zip::result::ZipError>),
+
+    #[error("Don't know how to unpack `{url}`, expected a `.tar.gz`, `.tgz` or `.zip` file")]
+    UnknownArchiveKind { url: String },
+
+    #[error("`{}` doesn't exist or isn't a directory", .path.display())]
+    LocalPathMissing { path: PathBuf },
+
+    /// An `${env:VAR}` in a url, see [`crate::interpolate`].
+    #[error("Can't download `{name}`: {error}")]
+    Unexpanded { name: String, error: String },
+
+    #[error("Failed to read the index of registry `{url}`: {error}")]
+    Registry {
+        url: String,
+        error: Arc<anyhow::Error>,
+    },
+
+    #[error("Registry `{registry}` has no package `{name}`. {}",
+        if .similar.is_empty() {
+            format!("Try `lair search {} --registry {}`.", .name, .registry)
+        } else {
+            format!("Did you mean {}?", .similar.iter().map(|name| format!("`{}`", name)).collect::<Vec<_>>().join(" or "))
+        })]
+    NotInRegistry {
+        name: String,
+        registry: String,
+        /// Packages whose names only differ in case, `-` or `_`.
+        similar: Vec<String>,
+    },
+
+    #[error("Registry `{registry}` has no version of `{name}` matching `{requirement}`{}.",
+        if .yanked.is_empty() { String::new() } else { format!(", only yanked ones ({})", .yanked.join(", ")) })]
+    NoMatchingVersion {
+        name: String,
+        registry: String,
+        requirement: String,
+        /// Versions which would match, but were yanked.
+        yanked: Vec<String>,
+    },
+
+    #[error("`{name}` has local changes in `{}`, which checking out another commit would overwrite. Commit or stash them, or pass `--yes` to overwrite them.", .path.display())]
+    LocalChanges { name: String, path: PathBuf },
+
+    #[error("`{name}` has local changes in `{}`, and `--frozen` doesn't allow building them: {}. Undo them with `git -C {} checkout .`, or build without `--frozen`.",
+        .path.display(), .files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(", "), .path.display())]
+    Dirty { name: String, path: PathBuf, files: Vec<PathBuf> },
+
+    #[error("`{host}` is not a known SSH host. Check its key with `ssh-keyscan {}{host} | ssh-keygen -lf -` and add it to ~/.ssh/known_hosts, or pass `--yes` to trust it.",
+        .port.map(|port| format!("-p {} ", port)).unwrap_or_default())]
+    UnknownHost { host: String, port: Option<u16> },
+
+    #[error("`{name}` isn't fully downloaded, and `--offline` doesn't allow downloading it. Run `lair fetch` while online first.")]
+    Offline { name: String },
+
+    #[error("Checksum mismatch for `{url}`: expected sha256 {expected}, got {actual}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Refusing to use `{name}`: {reason}.")]
+    Untrusted { name: String, reason: String },
+
+    #[error("Downloading `{url}` already failed {} ago, so Lair won't try again for another {}: {error}. Pass `--refresh` to try anyway.",
+        crate::fetch_failures::describe(*.ago), crate::fetch_failures::describe(crate::fetch_failures::TTL.saturating_sub(*.ago)))]
+    RecentlyFailed { url: String, error: String, ago: Duration },
+
+    #[error("Downloading `{url}` was interrupted.")]
+    Interrupted { url: String },
+}
+
+impl SourceFetchError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            SourceFetchError::Package { error, .. } => error.exit_code(),
+            SourceFetchError::Other(e) => exit_code(e),
+            SourceFetchError::Interrupted { .. } => exit_codes::INTERRUPTED,
+            _ => exit_codes::FETCH,
+        }
+    }
+
+    /// See [`code_of`].
+    pub fn code(&self) -> Option<&'static str> {
+        Some(match self {
+            SourceFetchError::Package { error, .. } => return error.code(),
+            SourceFetchError::Other(e) => return code_of(e),
+            SourceFetchError::GitError(_) => "L0101",
+            SourceFetchError::GitCommand(_) => "L0102",
+            SourceFetchError::CommitNotFound { .. } => "L0103",
+            SourceFetchError::VersionNotFound { .. } => "L0104",
+            SourceFetchError::LocalChanges { .. } => "L0105",
+            SourceFetchError::Dirty { .. } => "L0106",
+            SourceFetchError::UnknownHost { .. } => "L0107",
+            SourceFetchError::Http(_) => "L0201",
+            SourceFetchError::RecentlyFailed { .. } => "L0202",
+            SourceFetchError::ChecksumMismatch { .. } => "L0203",
+            SourceFetchError::Zip(_) => "L0204",
+            SourceFetchError::UnknownArchiveKind { .. } => "L0205",
+            SourceFetchError::Offline { .. } => "L0206",
+            SourceFetchError::Untrusted { .. } => "L0207",
+            SourceFetchError::LocalPathMissing { .. } => "L0004",
+            SourceFetchError::Unexpanded { .. } => "L0001",
+            SourceFetchError::Registry { .. } => "L0301",
+            SourceFetchError::NotInRegistry { .. } => "L0302",
+            SourceFetchError::NoMatchingVersion { .. } => "L0303",
+            SourceFetchError::Io(_) => "L0901",
+            SourceFetchError::Interrupted { .. } => "L0902",
+        })
+    }
+
+    /// The package this error happened in, if it is known yet.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        match self {
+            SourceFetchError::Package { provenance, .. } => Some(provenance),
+            SourceFetchError::Other(e) => provenance_of(e),
+            _ => None,
+        }
+    }
+
+    /// Attribute this error to a package, unless it already is.
+    pub fn in_package(self, provenance: Provenance) -> Self {
+        match self.provenance() {
+            Some(_) => self,
+            None => SourceFetchError::Package { provenance: Box::new(provenance), error: Box::new(self) },
+        }
+    }
+
+    /// Might the same download work if tried again in a moment? True for network trouble:
+    /// timeouts, unreachable or unknown hosts, dropped connections, and servers answering with a
+    /// 5xx or 429. Not for anything saying the url or commit is wrong, or which is our own fault.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SourceFetchError::Package { error, .. } => error.is_transient(),
+            SourceFetchError::Other(e) | SourceFetchError::Registry { error: e, .. } => e.chain().any(|cause| {
+                cause.downcast_ref::<ureq::Error>().is_some_and(transient_http)
+                    || cause.downcast_ref::<std::io::Error>().is_some_and(transient_io)
+            }),
+            SourceFetchError::GitError(e) => e.class() == git2::ErrorClass::Net || transient_git(e.message()),
+            SourceFetchError::GitCommand(stderr) => transient_git(stderr),
+            SourceFetchError::Http(e) => transient_http(e),
+            SourceFetchError::Io(e) => transient_io(e),
+            _ => false,
+        }
+    }
+}
+
+/// What git and libgit2 say (lowercased) when the network, rather than the repository, is the
+/// problem.
+const TRANSIENT_GIT_ERRORS: &[&str] = &[
+    "failed to connect",
+    "failed to resolve address",
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "connection timed out",
+    "operation timed out",
+    "connection reset",
+    "connection refused",
+    "the remote end hung up unexpectedly",
+    "early eof",
+    "the requested url returned error: 5",
+    "the requested url returned error: 429",
+];
+
+fn transient_git(message: &str) -> bool {
+    let message = message.to_lowercase();
+    TRANSIENT_GIT_ERRORS.iter().any(|error| message.contains(error))
+}
+
+fn transient_http(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(code, _) => *code >= 500 || *code == 429,
+        ureq::Error::Transport(transport) => matches!(transport.kind(),
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io | ureq::ErrorKind::ProxyConnect),
+    }
+}
+
+fn transient_io(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(error.kind(),
+        ErrorKind::TimedOut | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::ConnectionRefused | ErrorKind::UnexpectedEof)
 }
 
 impl From<git2::Error> for SourceFetchError {
@@ -16,45 +414,329 @@ impl From<git2::Error> for SourceFetchError {
     }
 }
 
+impl From<ureq::Error> for SourceFetchError {
+    fn from(err: ureq::Error) -> Self {
+        Self::Http(Arc::new(err))
+    }
+}
+
+impl From<std::io::Error> for SourceFetchError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(Arc::new(err))
+    }
+}
+
+impl From<zip::result::ZipError> for SourceFetchError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Zip(Arc::new(err))
+    }
+}
+
 impl From<anyhow::Error> for SourceFetchError {
     fn from(e: anyhow::Error) -> Self {
-        Self::Dummy(Arc::new(e))
+        Self::Other(Arc::new(e))
     }
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum BuildTtcError {
-    #[error("Dummy")]
-    Dummy(Arc<anyhow::Error>),
+    #[error("{provenance}")]
+    Package {
+        provenance: Box<Provenance>,
+        #[source]
+        error: Box<BuildTtcError>,
+    },
+
+    #[error("{0:#}")]
+    Other(Arc<anyhow::Error>),
 
-    #[error("Failed to fetch source: {0}")]
+    #[error(transparent)]
     SourceFetch(#[from] SourceFetchError),
 
-    #[error("Failed to fetch manifest: {0}")]
+    #[error(transparent)]
     ManifestFetch(#[from] ManifestFetchError),
+
+    #[error("`{name}` is required in {} different versions, which can be downloaded side by side, but idris2 can only load one of them: {}. See `lair why {name}`.",
+        .requirements.len(), explain_conflict(.name, .requirements, .satisfying.as_deref()))]
+    Conflict {
+        name: String,
+        requirements: Vec<Requirement>,
+        /// For registry dependencies, the versions which would satisfy every requirement. `None`
+        /// if we can't tell.
+        satisfying: Option<Vec<String>>,
+    },
+
+    #[error("{}, which file systems that ignore case, as macOS' and Windows' do by default, can't tell apart. Rename all but one of them, or depend on only one.", differing_in_case(.names))]
+    CaseCollision {
+        /// Each group differs only in case.
+        names: Vec<Vec<String>>,
+    },
+
+    #[error("Several packages provide the same modules, and idris2 can't tell them apart: {}. Rename the modules in one of them, or depend on only one.", provided_by(.collisions))]
+    ModuleCollision {
+        collisions: Vec<Collision>,
+    },
+
+    #[error("This is idris2 {version}, but {}. Install an idris2 which all of them work with.", needs(.packages))]
+    Idris2Unsupported {
+        version: Idris2Version,
+        /// Package name, and the versions it works with.
+        packages: Vec<(String, Idris2Requirement)>,
+    },
+
+    #[error("No idris2 version works with all packages: {}.", needs(.packages))]
+    Idris2Unsatisfiable {
+        packages: Vec<(String, Idris2Requirement)>,
+    },
+
+    #[error("Failed to run `{program}`: {error}. Is it installed and on PATH?")]
+    Spawn {
+        program: String,
+        error: Arc<std::io::Error>,
+    },
+
+    #[error("idris2 failed ({status}) on `{name}`. Its full output is in `{}`.", .log.display())]
+    Idris2 {
+        name: String,
+        status: ExitStatus,
+        log: PathBuf,
+    },
+
+    #[error("idris2 failed (exit status: {code}) on `{name}` on {worker}. Its full output is in `{}`.", .log.display())]
+    RemoteIdris2 {
+        name: String,
+        worker: String,
+        code: i32,
+        log: PathBuf,
+    },
+
+    #[error("Building `{name}` took longer than {}s, so idris2 was killed. Its output so far is in `{}`.", .timeout.as_secs(), .log.display())]
+    Timeout {
+        name: String,
+        timeout: Duration,
+        log: PathBuf,
+    },
+
+    #[error("Building `{name}` was interrupted, so idris2 was killed. Its output so far is in `{}`.", .log.display())]
+    Interrupted {
+        name: String,
+        log: PathBuf,
+    },
+
+    #[error("Can't tell idris2 about `{}`, since search paths can't contain `{}`. Move it somewhere else.", .path.display(), crate::paths::PATH_SEP)]
+    UnjoinablePath {
+        path: PathBuf,
+    },
+
+    #[error("File IO error: {0}")]
+    Io(Arc<std::io::Error>),
+}
+
+impl BuildTtcError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            BuildTtcError::Package { error, .. } => error.exit_code(),
+            BuildTtcError::Other(e) => exit_code(e),
+            BuildTtcError::SourceFetch(e) => e.exit_code(),
+            BuildTtcError::ManifestFetch(e) => e.exit_code(),
+            BuildTtcError::Conflict { .. } | BuildTtcError::CaseCollision { .. } | BuildTtcError::ModuleCollision { .. } => exit_codes::CONFLICT,
+            BuildTtcError::Idris2Unsatisfiable { .. } => exit_codes::CONFLICT,
+            BuildTtcError::Idris2Unsupported { .. }
+            | BuildTtcError::Spawn { .. }
+            | BuildTtcError::Idris2 { .. }
+            | BuildTtcError::RemoteIdris2 { .. }
+            | BuildTtcError::Timeout { .. } => exit_codes::COMPILE,
+            BuildTtcError::Interrupted { .. } => exit_codes::INTERRUPTED,
+            BuildTtcError::UnjoinablePath { .. } | BuildTtcError::Io(_) => 1,
+        }
+    }
+
+    /// See [`code_of`].
+    pub fn code(&self) -> Option<&'static str> {
+        Some(match self {
+            BuildTtcError::Package { error, .. } => return error.code(),
+            BuildTtcError::Other(e) => return code_of(e),
+            BuildTtcError::SourceFetch(e) => return e.code(),
+            BuildTtcError::ManifestFetch(e) => return e.code(),
+            BuildTtcError::Idris2 { .. } => "L0401",
+            BuildTtcError::RemoteIdris2 { .. } => "L0402",
+            BuildTtcError::Timeout { .. } => "L0403",
+            BuildTtcError::Spawn { .. } => "L0404",
+            BuildTtcError::UnjoinablePath { .. } => "L0405",
+            BuildTtcError::Conflict { .. } => "L0501",
+            BuildTtcError::ModuleCollision { .. } => "L0502",
+            BuildTtcError::Idris2Unsupported { .. } => "L0503",
+            BuildTtcError::Idris2Unsatisfiable { .. } => "L0504",
+            BuildTtcError::CaseCollision { .. } => "L0505",
+            BuildTtcError::Io(_) => "L0901",
+            BuildTtcError::Interrupted { .. } => "L0902",
+        })
+    }
+
+    /// The package this error happened in, if it is known yet. Conflicts between packages, for
+    /// example, don't belong to any single one.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        match self {
+            BuildTtcError::Package { provenance, .. } => Some(provenance),
+            BuildTtcError::Other(e) => provenance_of(e),
+            BuildTtcError::SourceFetch(e) => e.provenance(),
+            BuildTtcError::ManifestFetch(e) => e.provenance(),
+            _ => None,
+        }
+    }
+
+    /// Attribute this error to a package, unless it already is, say because a dependency failed.
+    pub fn in_package(self, provenance: Provenance) -> Self {
+        match self.provenance() {
+            Some(_) => self,
+            None => BuildTtcError::Package { provenance: Box::new(provenance), error: Box::new(self) },
+        }
+    }
+}
+
+/// For example "Packages `JSON` and `Json` differ only in case".
+fn differing_in_case(names: &[Vec<String>]) -> String {
+    let groups: Vec<String> = names.iter()
+        .map(|group| group.iter().map(|name| format!("`{}`", name)).collect::<Vec<_>>().join(" and "))
+        .collect();
+    format!("Packages {} differ only in case", groups.join(", "))
+}
+
+/// For example "`Data.Json` is in `NotJson` and `FastJson`".
+fn provided_by(collisions: &[Collision]) -> String {
+    collisions.iter()
+        .map(|collision| format!("`{}` is in {}", collision.module,
+            collision.packages.iter().map(|desc| format!("`{}`", desc.name())).collect::<Vec<_>>().join(" and ")))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// For example "AmazingTool 0.1.0 requires `Json` (version 0.4, registry https://example.com/index),
+/// which is 0.4.2; AmazingTool 0.1.0 → CoolCollections 1.2.0 requires `Json` (...), which is 0.3.7;
+/// no version of `Json` satisfies all of them".
+fn explain_conflict(name: &str, requirements: &[Requirement], satisfying: Option<&[String]>) -> String {
+    let mut explanation: Vec<String> = requirements.iter().map(ToString::to_string).collect();
+    match satisfying {
+        None => {},
+        Some([]) => explanation.push(format!("no version of `{}` satisfies all of them", name)),
+        Some([.., newest]) => {
+            let registry = match requirements.first().map(|requirement| &requirement.descriptor) {
+                Some(Descriptor::Registry { registry, .. }) => registry.as_str(),
+                _ => "...",
+            };
+            explanation.push(format!(
+                "version {} satisfies all of them, so adding `{} = {{ registry = \"{}\", version = \"{}\" }}` to `[patch]` would use it everywhere",
+                newest, name, registry, newest));
+        },
+    }
+    explanation.join("; ")
+}
+
+/// For example "`NotJson` needs idris2 >=0.6.0; `CoolCollections` needs idris2 <0.6".
+fn needs(packages: &[(String, Idris2Requirement)]) -> String {
+    packages.iter()
+        .map(|(name, requirement)| format!("`{}` needs idris2 {}", name, requirement))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 impl From<anyhow::Error> for BuildTtcError {
     fn from(e: anyhow::Error) -> Self {
-        Self::Dummy(Arc::new(e))
+        Self::Other(Arc::new(e))
+    }
+}
+
+impl From<std::io::Error> for BuildTtcError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(Arc::new(e))
     }
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum ManifestFetchError {
-    #[error("Dummy")]
-    Dummy(Arc<anyhow::Error>),
+    #[error("{provenance}")]
+    Package {
+        provenance: Box<Provenance>,
+        #[source]
+        error: Box<ManifestFetchError>,
+    },
+
+    #[error("{0:#}")]
+    Other(Arc<anyhow::Error>),
 
-    #[error("Failed to fetch source: {0}")]
+    #[error(transparent)]
     SourceFetch(#[from] SourceFetchError),
 
     #[error("File IO error: {0}")]
     Io(Arc<std::io::Error>),
+
+    #[error("Failed to read manifest `{}`: {error}", .path.display())]
+    Invalid {
+        path: PathBuf,
+        error: Arc<anyhow::Error>,
+    },
+
+    #[error("Package `{}` has no manifest: `{}` doesn't exist. If the package lives in a subdirectory of its repository, point to it with `subdir = \"path/to/package\"` in the dependency declaration.",
+        .desc.name(), .searched_path.display())]
+    MissingManifest {
+        desc: Box<Descriptor>,
+        searched_path: PathBuf,
+    },
+
+    #[error("Package `{}` has no Egg.toml, only `{}`, which depends on {}. Lair can't tell where to fetch those from, so the package needs an Egg.toml.",
+        .desc.name(), .ipkg_path.display(), .depends.join(", "))]
+    IpkgDependencies {
+        desc: Box<Descriptor>,
+        ipkg_path: PathBuf,
+        depends: Vec<String>,
+    },
+}
+
+impl ManifestFetchError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ManifestFetchError::Package { error, .. } => error.exit_code(),
+            ManifestFetchError::Other(e) => exit_code(e),
+            ManifestFetchError::SourceFetch(e) => e.exit_code(),
+            _ => exit_codes::MANIFEST,
+        }
+    }
+
+    /// See [`code_of`].
+    pub fn code(&self) -> Option<&'static str> {
+        Some(match self {
+            ManifestFetchError::Package { error, .. } => return error.code(),
+            ManifestFetchError::Other(e) => return code_of(e),
+            ManifestFetchError::SourceFetch(e) => return e.code(),
+            ManifestFetchError::Invalid { .. } => "L0001",
+            ManifestFetchError::MissingManifest { .. } => "L0002",
+            ManifestFetchError::IpkgDependencies { .. } => "L0003",
+            ManifestFetchError::Io(_) => "L0901",
+        })
+    }
+
+    /// The package this error happened in, if it is known yet.
+    pub fn provenance(&self) -> Option<&Provenance> {
+        match self {
+            ManifestFetchError::Package { provenance, .. } => Some(provenance),
+            ManifestFetchError::Other(e) => provenance_of(e),
+            ManifestFetchError::SourceFetch(e) => e.provenance(),
+            _ => None,
+        }
+    }
+
+    /// Attribute this error to a package, unless it already is.
+    pub fn in_package(self, provenance: Provenance) -> Self {
+        match self.provenance() {
+            Some(_) => self,
+            None => ManifestFetchError::Package { provenance: Box::new(provenance), error: Box::new(self) },
+        }
+    }
 }
 
 impl From<anyhow::Error> for ManifestFetchError {
     fn from(e: anyhow::Error) -> Self {
-        Self::Dummy(Arc::new(e))
+        Self::Other(Arc::new(e))
     }
 }
 
@@ -0,0 +1,446 @@
+//! `lair explain <code>`: what the code in front of an error means, and how to fix it.
+//!
+//! Every error Lair knows the cause of has a code (see [`crate::error::code_of`]), printed along
+//! with it as in `Error[L0203]: ...`. Codes are grouped by what went wrong: `L00xx` manifests,
+//! `L01xx` git, `L02xx` downloads, `L03xx` registries, `L04xx` running idris2, `L05xx` the
+//! dependency graph as a whole, and `L09xx` anything else. A code never changes its meaning, and
+//! isn't reused once it's gone, so scripts and search engines can rely on them.
+
+/// One entry of [`CODES`].
+#[derive(Clone, Copy, Debug)]
+pub struct Explanation {
+    /// For example `L0203`.
+    pub code: &'static str,
+    /// One line, for listing all codes.
+    pub title: &'static str,
+    /// What happened, and the usual fixes.
+    pub text: &'static str,
+}
+
+/// Every code, in order.
+pub const CODES: &[Explanation] = &[
+    Explanation {
+        code: "L0001",
+        title: "A manifest is invalid",
+        text: "\
+An Egg.toml couldn't be read or parsed. The message says which file, and where in it TOML or Lair
+stopped making sense of it.
+
+Common causes:
+- A TOML syntax error, such as a missing quote or an unclosed table.
+- A required field missing: `[package]` needs `name` and `version`, and there has to be a
+  `[dependencies]` table, even an empty one.
+- A dependency declaring more than one source, say both `git` and `path`, or none.
+- An `${env:VAR}` in a url whose variable isn't set, or isn't in `[manifest] env` of the config
+  files. Urls are only expanded for downloading, so this shows up then.
+
+If the manifest belongs to a dependency, fix it upstream, or replace the dependency with a fixed
+copy through `[patch]` in the root manifest.",
+    },
+    Explanation {
+        code: "L0002",
+        title: "A package has no manifest",
+        text: "\
+A dependency was downloaded, but there is no Egg.toml (or `.ipkg` file) where Lair looked.
+
+Common causes:
+- The package lives in a subdirectory of its repository. Point to it with
+  `subdir = \"path/to/package\"` in the dependency's declaration.
+- The branch, tag or commit asked for predates the package having a manifest. Pick a newer one.
+- The `path` of a local dependency points at the wrong directory.",
+    },
+    Explanation {
+        code: "L0003",
+        title: "A package only has an .ipkg file with dependencies",
+        text: "\
+A dependency has no Egg.toml, only an idris2 `.ipkg` file, and that file `depends` on other
+packages. Lair reads `.ipkg` files of packages without dependencies, but can't tell where to
+download the dependencies of one that has them from.
+
+Fixes:
+- Add an Egg.toml to the package, `lair migrate` writes one from the `.ipkg` file.
+- Until upstream has one, use a fork with an Egg.toml through `[patch]` in the root manifest.",
+    },
+    Explanation {
+        code: "L0004",
+        title: "A local dependency's path doesn't exist",
+        text: "\
+A dependency declared with `path = \"...\"` points at something which doesn't exist, or isn't a
+directory. Relative paths are relative to the manifest declaring them.
+
+Fixes:
+- Correct the path, or check out the package there.
+- If it's a workspace member, check `[workspace] members` in the root manifest.",
+    },
+    Explanation {
+        code: "L0101",
+        title: "git failed",
+        text: "\
+libgit2, which Lair uses for cloning and fetching, reported an error. The message is git's own.
+
+Common causes:
+- The url is wrong, or the repository is private. For SSH urls, an ssh-agent needs to have the
+  key.
+- The network is down or a proxy is in the way. Lair follows `https_proxy`, `no_proxy` and the
+  like, or `[network] proxy` in a config file, and tries again a few times after network errors
+  before giving up.
+- The checkout in `build/deps` is broken. Deleting it makes Lair clone it again.",
+    },
+    Explanation {
+        code: "L0102",
+        title: "The git command failed",
+        text: "\
+For partial and sparse clones of dependencies in a `subdir`, Lair runs the `git` command, which
+failed. The message is what git printed.
+
+Common causes:
+- `git` isn't installed, or is too old for `--filter` and `--sparse` (they need git 2.25 or
+  newer). Lair then falls back to a full clone, so this usually shows up together with another
+  error.
+- The same network or credential troubles as in L0101.",
+    },
+    Explanation {
+        code: "L0103",
+        title: "A commit doesn't exist",
+        text: "\
+The commit a dependency is pinned to, by `rev` in the manifest or by Egg.lock, isn't in its
+repository.
+
+Common causes:
+- The branch was force-pushed, and the commit is gone. `lair lock --update <package>` pins the
+  branch's current commit instead.
+- A typo in `rev`.
+- The repository moved, and the url points at another one.",
+    },
+    Explanation {
+        code: "L0104",
+        title: "A branch or tag doesn't exist",
+        text: "\
+The `branch` or `tag` a git dependency asks for doesn't exist in its repository.
+
+Fixes:
+- Check the spelling; branch and tag names are case-sensitive.
+- Check that it was pushed, `git ls-remote <url>` lists what the repository has.
+- Without `branch`, `tag` or `rev`, Lair uses the repository's default branch.",
+    },
+    Explanation {
+        code: "L0105",
+        title: "A checkout has local changes",
+        text: "\
+A dependency's checkout in `build/deps` has uncommitted changes, and checking out the commit
+Lair wants would overwrite them.
+
+Fixes:
+- If the changes are worth keeping, commit or stash them in the checkout, or copy the package and
+  use it as a `path` dependency or through `[patch]`.
+- Otherwise pass `--yes`, or answer yes when asked, to overwrite them.",
+    },
+    Explanation {
+        code: "L0106",
+        title: "A checkout has local changes, and --frozen is on",
+        text: "\
+With `--frozen`, Lair builds exactly what Egg.lock says, so it refuses dependencies whose files
+in `build/deps` were edited by hand.
+
+Fixes:
+- Undo the changes, `git -C <checkout> checkout .` as the message says.
+- Build without `--frozen`, in which case Lair builds the edited files and warns about them.",
+    },
+    Explanation {
+        code: "L0107",
+        title: "An SSH host isn't known",
+        text: "\
+A dependency is fetched over SSH from a host which isn't in `~/.ssh/known_hosts`, and Lair
+couldn't ask whether to trust it.
+
+Fixes:
+- Check the host's key out of band, then add it, for example with
+  `ssh-keyscan <host> >> ~/.ssh/known_hosts`.
+- Run Lair in a terminal to be asked, or pass `--yes` to trust the host.",
+    },
+    Explanation {
+        code: "L0201",
+        title: "An HTTP request failed",
+        text: "\
+Downloading an archive or a signature, or talking to a registry's API, failed. The message says what the server
+answered, or why there was no answer.
+
+Common causes:
+- A 404: the url is wrong, or the release was deleted.
+- Network trouble or a 5xx from the server, after which Lair tries again a few times first. Try
+  again later, or check the proxy settings, as in L0101.",
+    },
+    Explanation {
+        code: "L0202",
+        title: "A download failed recently",
+        text: "\
+Downloading the same url failed within the last few minutes, so Lair didn't try again, saving
+every run from waiting for the same timeout. The message repeats the earlier error.
+
+Fixes:
+- Fix whatever the earlier error says.
+- Pass `--refresh` to try right away anyway.",
+    },
+    Explanation {
+        code: "L0203",
+        title: "An archive's checksum doesn't match",
+        text: "\
+The archive downloaded for a dependency isn't the one its `sha256` says. Either it changed since
+the checksum was written, or something in between tampered with it.
+
+Fixes:
+- If the archive was re-released on purpose, check the new one and update `sha256` to what the
+  message says it got.
+- If it wasn't, don't use it: the download may have been tampered with.
+- A proxy or captive portal answering with a page of its own also causes this.",
+    },
+    Explanation {
+        code: "L0204",
+        title: "A zip archive is broken",
+        text: "\
+A `.zip` archive of a dependency couldn't be unpacked.
+
+Common causes:
+- The download was cut short, try again.
+- The url doesn't point at the archive itself, but at a page about it.",
+    },
+    Explanation {
+        code: "L0205",
+        title: "Lair can't unpack an archive",
+        text: "\
+An archive dependency's url doesn't end in `.tar.gz`, `.tgz` or `.zip`, which are the kinds Lair
+can unpack.
+
+Fixes:
+- Point the url at one of those.
+- Git hosts offer both kinds for every tag, for example
+  `https://github.com/<owner>/<repo>/archive/refs/tags/<tag>.tar.gz`.",
+    },
+    Explanation {
+        code: "L0206",
+        title: "Something needs downloading, but --offline is on",
+        text: "\
+With `--offline`, Lair never touches the network, and a dependency isn't fully downloaded yet.
+
+Fixes:
+- Run `lair fetch` once while online; it downloads everything a build needs.
+- Build without `--offline`.",
+    },
+    Explanation {
+        code: "L0207",
+        title: "A dependency isn't signed by a trusted key",
+        text: "\
+Signatures are required, by `--require-signatures` or the config, and a dependency's commit, tag
+or archive isn't signed by any of the keys in `[trust] keys`.
+
+Fixes:
+- If the signing key is one you trust, add it to `[trust] keys` in a config file. GPG keys also
+  have to be in gpg's keyring.
+- Ask upstream to sign their releases.
+- Check that `gpg`, or `ssh-keygen` for SSH signatures, is installed. Lair needs them to check
+  signatures.",
+    },
+    Explanation {
+        code: "L0301",
+        title: "A registry index can't be read",
+        text: "\
+The index of a registry, a git repository, couldn't be cloned, updated or parsed.
+
+Common causes:
+- The registry url is wrong, or it's private and no credentials were found.
+- Network trouble, as in L0101.
+- A broken clone in the user cache directory, which deleting makes Lair clone again.",
+    },
+    Explanation {
+        code: "L0302",
+        title: "A registry doesn't have a package",
+        text: "\
+A dependency names a package the registry doesn't have. If the registry has packages spelled
+similarly, the message lists them.
+
+Fixes:
+- Check the spelling; package names are case-sensitive.
+- `lair search <name> --registry <url>` finds packages in a registry.
+- If it was only just published, the registry's index may not have it yet.",
+    },
+    Explanation {
+        code: "L0303",
+        title: "No version of a package matches",
+        text: "\
+The registry has the package, but no version matching the requirement, or only yanked ones.
+
+Fixes:
+- Loosen the requirement, for example `0.3` instead of `=0.3.1`.
+- Yanked versions are never picked for new requirements; if one is locked in Egg.lock already,
+  it stays, otherwise pick another version.",
+    },
+    Explanation {
+        code: "L0401",
+        title: "idris2 failed",
+        text: "\
+idris2 reported errors for a package. They are shown above, and all of idris2's output is in the
+log the message names.
+
+Fixes:
+- For the root package, fix what idris2 says.
+- For a dependency, it may not work with this idris2; check which versions it supports, and
+  consider `idris2 = \"...\"` in its manifest. Otherwise report it upstream, or pin an older
+  version of it.
+- `lair build -v` shows the exact idris2 command, to reproduce it by hand.",
+    },
+    Explanation {
+        code: "L0402",
+        title: "idris2 failed on a build worker",
+        text: "\
+A dependency built with `--remote-exec` failed on the worker. The worker's idris2 output is in
+the log the message names.
+
+Fixes:
+- The same as for L0401.
+- If it only fails on the worker, check that it runs the same idris2, and has enough memory.",
+    },
+    Explanation {
+        code: "L0403",
+        title: "A build took too long",
+        text: "\
+Building a package took longer than `--build-timeout`, so idris2 was killed. What it printed so
+far is in the log the message names.
+
+Fixes:
+- Raise the timeout, or drop it, if the package is simply big.
+- If idris2 hangs, the log shows which module it got stuck in, often one with expensive proof
+  search or elaboration.",
+    },
+    Explanation {
+        code: "L0404",
+        title: "idris2 can't be run",
+        text: "\
+Starting idris2, or another program Lair needed, failed.
+
+Fixes:
+Fix: install idris2, and make sure it's on PATH; `idris2 --version` should work.",
+    },
+    Explanation {
+        code: "L0405",
+        title: "A path can't be passed to idris2",
+        text: "\
+idris2 takes the directories it searches as one list, in `IDRIS2_PATH`, separated by `:` (`;` on
+Windows). A directory whose path contains that separator can't be one of them.
+
+Fix: move the package, or the project, to a path without it.",
+    },
+    Explanation {
+        code: "L0501",
+        title: "A package is required in several versions",
+        text: "\
+Two parts of the dependency graph need different versions of the same package. Lair could
+download both, but idris2 can only load one package by that name. The message lists who needs
+which, and `lair why <package>` shows how each got there.
+
+Fixes:
+- If one version satisfies all requirements, the message suggests a `[patch]` for it.
+- Otherwise update the dependencies asking for the older version, or `[patch]` the package to the
+  newer one and check that they still build.",
+    },
+    Explanation {
+        code: "L0502",
+        title: "Two packages provide the same module",
+        text: "\
+idris2 finds modules by name, so if two packages of a build both have, say, `Data.Json`, it
+would silently use whichever it finds first. Only modules which get built count: a package's main
+module, and whatever it imports from the same package.
+
+Fixes:
+- Depend on only one of them.
+- Rename the modules of one, usually by putting them in a namespace of their own, such as
+  `NotJson.Data.Json`.",
+    },
+    Explanation {
+        code: "L0503",
+        title: "This idris2 is a version a package doesn't work with",
+        text: "\
+A package says, with `idris2 = \"...\"` in its manifest, which idris2 versions it works with, and
+the one on PATH isn't one of them.
+
+Fixes:
+- Install an idris2 they all work with; with a version manager such as pack, switch to it.
+- If the package works anyway, tell upstream to widen its requirement, and `[patch]` in a fork
+  meanwhile.",
+    },
+    Explanation {
+        code: "L0504",
+        title: "No idris2 version works with all packages",
+        text: "\
+The idris2 requirements of the packages in the graph exclude each other, so no idris2 could build
+all of them.
+
+Fix: update or replace the packages with the oldest or newest requirements, which the message
+lists.",
+    },
+    Explanation {
+        code: "L0505",
+        title: "Two package names differ only in case",
+        text: "\
+Packages named, say, `Json` and `JSON` can't be told apart on file systems ignoring case, as
+macOS' and Windows' do by default: their build logs, their installed copies and idris2's `-p`
+would be mistaken for each other. Lair refuses such graphs everywhere, so that a project building
+on Linux doesn't break on a Mac.
+
+Fixes:
+- Depend on only one of them.
+- If only the names they are depended on by collide, rename one dependency and give its actual
+  name with `package = \"...\"`.
+- Otherwise, one of the packages has to be renamed upstream, or in a fork `[patch]`ed in.",
+    },
+    Explanation {
+        code: "L0901",
+        title: "A file couldn't be read or written",
+        text: "\
+An operating system error while reading or writing a file, for example in `build`.
+
+Common causes:
+- Permissions: the directory belongs to another user, say after running Lair with sudo once.
+- A full disk.
+- On Windows, another program, such as a virus scanner or an editor, holding the file open.",
+    },
+    Explanation {
+        code: "L0902",
+        title: "Interrupted",
+        text: "\
+Ctrl-C stopped Lair. Whatever finished before is kept, so the next run goes on from there.",
+    },
+];
+
+/// The entry for `code`, for example `L0203`, `l0203` or `203`.
+pub fn lookup(code: &str) -> Option<&'static Explanation> {
+    let code = code.trim();
+    let digits = code.strip_prefix(['L', 'l']).unwrap_or(code);
+    let code = format!("L{:0>4}", digits);
+    CODES.iter().find(|explanation| explanation.code == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_codes() {
+        assert_eq!(lookup("L0001").unwrap().code, "L0001");
+        assert_eq!(lookup("l0902").unwrap().code, "L0902");
+        assert_eq!(lookup(" 902 ").unwrap().code, "L0902");
+        assert_eq!(lookup("1").unwrap().code, "L0001");
+        assert!(lookup("L9999").is_none());
+        assert!(lookup("").is_none());
+    }
+
+    #[test]
+    fn codes_are_in_order() {
+        for pair in CODES.windows(2) {
+            assert!(pair[0].code < pair[1].code, "{} before {}", pair[0].code, pair[1].code);
+        }
+        for explanation in CODES {
+            assert!(explanation.code.len() == 5 && explanation.code.starts_with('L'), "{}", explanation.code);
+            assert!(!explanation.title.is_empty() && !explanation.text.is_empty(), "{}", explanation.code);
+        }
+    }
+}
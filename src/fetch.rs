@@ -0,0 +1,655 @@
+//! How git repositories end up on disk, and how we move them between commits.
+//!
+//! By default we clone with libgit2. But when we only need a subdirectory of a (possibly huge)
+//! repository, we'd rather not download all of it. libgit2 supports neither partial clones nor
+//! sparse checkouts, so for those we try the `git` command line tool, and fall back to a full
+//! clone if that doesn't work out (no `git` installed, too old, server weirdness, ...).
+//!
+//! libgit2 can't even open such sparse checkouts afterwards, so every operation on a checkout
+//! comes in two flavours: libgit2 for regular checkouts, the `git` command line tool for sparse
+//! ones.
+//!
+//! Everything in here is blocking.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use git2::build::RepoBuilder;
+use git2::{AutotagOption, Direction, FetchOptions, Oid, Remote, RemoteCallbacks, Repository};
+
+use crate::descriptor::GitVersion;
+use crate::error::SourceFetchError;
+use crate::paths;
+use crate::proxy;
+use crate::registry;
+
+/// `url`, spelled the same way however a manifest spells it: without trailing slashes or `.git`,
+/// and with lowercase scheme and host. Used to find dependencies on the same repository.
+pub fn canonical_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            format!("{}://{}{}", scheme.to_lowercase(), host.to_lowercase(), path)
+        },
+        None => url.to_owned(), // `git@github.com:Kiiyya/NotJson`, or a local path
+    }
+}
+
+/// Last part of the path of a url, for example `NotJson` for `https://github.com/Kiiyya/NotJson`.
+pub fn repo_name(url: &str) -> &str {
+    match url.rsplit(['/', ':']).next() {
+        Some(name) if !name.is_empty() => name,
+        _ => "repo",
+    }
+}
+
+/// Clone `url` into `path`. If `subdir` is given, only that part of the repository needs to be
+/// checked out.
+///
+/// `progress` is called with (received objects, total objects, received bytes) as the download
+/// goes on. Sparse clones don't report progress.
+pub fn clone_git(url: &str, path: &Path, subdir: Option<&Path>, progress: &dyn Fn(usize, usize, u64)) -> Result<(), SourceFetchError> {
+    if let Some(subdir) = subdir {
+        match sparse_clone(url, path, subdir) {
+            Ok(()) => return Ok(()),
+            Err(_) if crate::interrupt::requested() => return Err(interrupted(url, path)),
+            Err(_) => {
+                // Whatever went wrong, a full clone may still work, so clean up and try that.
+                if path.exists() {
+                    std::fs::remove_dir_all(paths::long(path))?;
+                }
+            },
+        }
+    }
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(|stats| {
+        progress(stats.received_objects(), stats.total_objects(), stats.received_bytes() as u64);
+        // Abandons the download.
+        !crate::interrupt::requested()
+    });
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks).proxy_options(proxy::git_options(url));
+
+    match RepoBuilder::new().fetch_options(options).clone(url, path) {
+        Ok(_) => Ok(()),
+        Err(_) if crate::interrupt::requested() => Err(interrupted(url, path)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// What [`clone_git`] fails with after Ctrl-C, leaving nothing half downloaded behind at `path`.
+fn interrupted(url: &str, path: &Path) -> SourceFetchError {
+    if path.exists() {
+        let _ = std::fs::remove_dir_all(paths::long(path));
+    }
+    SourceFetchError::Interrupted { url: url.to_owned() }
+}
+
+/// Partial clone (`--filter=blob:none`, only fetching file contents we actually check out), plus a
+/// sparse checkout of just `subdir`.
+///
+/// If the server doesn't support filters, git itself falls back to fetching everything, but we
+/// still only check out `subdir`.
+fn sparse_clone(url: &str, path: &Path, subdir: &Path) -> Result<(), SourceFetchError> {
+    git(proxy::git_command()
+        .args(["clone", "--quiet", "--filter=blob:none", "--sparse", "--end-of-options"])
+        .arg(url)
+        .arg(path))?;
+
+    // Sparse checkout patterns always use forward slashes.
+    let pattern = subdir.to_string_lossy().replace('\\', "/");
+    git(git_in(path)
+        .args(["sparse-checkout", "set", "--cone", "--end-of-options"])
+        .arg(pattern))?;
+
+    Ok(())
+}
+
+/// Make sure `subdir` is checked out too, for checkouts shared by several packages in different
+/// subdirectories. Only sparse checkouts can be missing anything.
+pub fn add_subdir(path: &Path, subdir: &Path) -> Result<(), SourceFetchError> {
+    if is_sparse(path) && !path.join(subdir).exists() {
+        let pattern = subdir.to_string_lossy().replace('\\', "/");
+        git(git_in(path).args(["sparse-checkout", "add", "--end-of-options"]).arg(pattern))?;
+    }
+    Ok(())
+}
+
+/// Was this checkout made by [`sparse_clone`]? Then we have to use the `git` command line tool.
+fn is_sparse(path: &Path) -> bool {
+    path.join(".git").join("info").join("sparse-checkout").exists()
+}
+
+/// `git -C {path} ...`
+fn git_in(path: &Path) -> Command {
+    let mut command = proxy::git_command();
+    command.arg("-C").arg(path);
+    command
+}
+
+/// Run a git command, failing if it doesn't exit successfully. Returns its stdout.
+fn git(command: &mut Command) -> Result<String, SourceFetchError> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| SourceFetchError::GitCommand(format!("Failed to run `git`: {}", e)))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(SourceFetchError::GitCommand(String::from_utf8_lossy(&output.stderr).trim().to_owned()))
+    }
+}
+
+/// The commit currently checked out, as a full hex hash.
+pub fn head_commit(path: &Path) -> Result<String, SourceFetchError> {
+    if is_sparse(path) {
+        Ok(git(git_in(path).args(["rev-parse", "HEAD"]))?.trim().to_owned())
+    } else {
+        let repo = Repository::open(path)?;
+        let commit = repo.head()?.peel_to_commit()?;
+        Ok(commit.id().to_string())
+    }
+}
+
+/// Fetch options going through the proxy for `remote`'s url, see [`proxy`].
+fn fetch_options<'a>(remote: &Remote) -> FetchOptions<'a> {
+    let mut options = FetchOptions::new();
+    options.proxy_options(proxy::git_options(remote.url().unwrap_or_default()));
+    options
+}
+
+/// Connect to `remote` for fetching, through the proxy for its url. Disconnects once dropped.
+fn connect<'r, 'a>(remote: &'r mut Remote<'a>) -> Result<git2::RemoteConnection<'a, 'r, 'static>, git2::Error> {
+    let proxy = proxy::git_options(remote.url().unwrap_or_default());
+    remote.connect_auth(Direction::Fetch, None, Some(proxy))
+}
+
+/// Fetch all branches and tags from `origin`.
+pub fn fetch_origin(path: &Path) -> Result<(), SourceFetchError> {
+    if is_sparse(path) {
+        git(git_in(path).args(["fetch", "--quiet", "--tags", "origin"]))?;
+    } else {
+        let repo = Repository::open(path)?;
+        let mut remote = repo.find_remote("origin")?;
+        let mut options = fetch_options(&remote);
+        options.download_tags(AutotagOption::All);
+        remote.fetch(&[] as &[&str], Some(&mut options), None)?;
+    }
+    Ok(())
+}
+
+/// Which commit does `version` currently refer to, according to what we last fetched?
+/// Branches are looked up on `origin` rather than locally, so that they move after a fetch.
+pub fn resolve_version(path: &Path, version: &GitVersion) -> Result<String, SourceFetchError> {
+    let spec = match version {
+        GitVersion::Branch(branch) => format!("refs/remotes/origin/{}", branch),
+        GitVersion::Tag(tag) => format!("refs/tags/{}", tag),
+        GitVersion::Rev(rev) => rev.clone(),
+        GitVersion::DefaultBranch => "refs/remotes/origin/HEAD".to_owned(),
+        GitVersion::TagPrefix { prefix, requirement } => {
+            let tags = tags(path)?;
+            let tag = pick_tag(tags.iter().map(|(tag, _)| tag.as_str()), prefix, requirement)
+                .ok_or_else(|| git2::Error::from_str(&format!("no tag `{}<version>` matches `{}`", prefix, requirement)))?;
+            format!("refs/tags/{}", tag)
+        },
+    };
+
+    if is_sparse(path) {
+        Ok(git(git_in(path).args(["rev-parse", "--verify", "--quiet", "--end-of-options"]).arg(format!("{}^{{commit}}", spec)))?.trim().to_owned())
+    } else {
+        let repo = Repository::open(path)?;
+        let commit = repo.revparse_single(&spec)?.peel_to_commit()?;
+        Ok(commit.id().to_string())
+    }
+}
+
+/// Every tag in the checkout `path`, as (tag name, commit), for example `("v0.1.0", "3f2a9c01d5e8...")`.
+pub fn tags(path: &Path) -> Result<Vec<(String, String)>, SourceFetchError> {
+    if is_sparse(path) {
+        let out = git(git_in(path).args(["for-each-ref", "--format=%(refname:strip=2) %(objectname) %(*objectname)", "refs/tags"]))?;
+        // Annotated tags point to a tag object, `*objectname` is the commit.
+        Ok(out.lines()
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let (tag, object, peeled) = (fields.next()?, fields.next()?, fields.next().unwrap_or_default());
+                Some((tag.to_owned(), if peeled.is_empty() { object } else { peeled }.to_owned()))
+            })
+            .collect())
+    } else {
+        let repo = Repository::open(path)?;
+        let names = repo.tag_names(None)?;
+        Ok(names.iter().flatten()
+            .filter_map(|tag| {
+                let commit = repo.revparse_single(&format!("refs/tags/{}", tag)).ok()?.peel_to_commit().ok()?;
+                Some((tag.to_owned(), commit.id().to_string()))
+            })
+            .collect())
+    }
+}
+
+/// The tag among `tags` with the highest version after `prefix` which matches `requirement`, like
+/// a registry version would (see [`registry::matches`]), for example `collections-v1.2.3` among
+/// `collections-v1.2.3`, `collections-v1.3.0-rc.1`, `collections-v2.0.0` and `json-v1.4.0` for the
+/// prefix `collections-v` and the requirement `1.2`.
+pub fn pick_tag<'a>(tags: impl IntoIterator<Item = &'a str>, prefix: &str, requirement: &str) -> Option<&'a str> {
+    tags.into_iter()
+        .filter_map(|tag| Some((tag, tag.strip_prefix(prefix)?)))
+        .filter(|(_, version)| version.starts_with(|c: char| c.is_ascii_digit()) && registry::matches(requirement, version))
+        .max_by(|(_, a), (_, b)| registry::compare_versions(a, b))
+        .map(|(tag, _)| tag)
+}
+
+/// Which tag `version` means now that it's checked out at `commit`: the tag itself for `tag`,
+/// the highest matching tag pointing to `commit` for `tag-prefix`, and `None` otherwise.
+pub fn tag_at(path: &Path, version: &GitVersion, commit: &str) -> Option<String> {
+    match version {
+        GitVersion::Tag(tag) => Some(tag.clone()),
+        GitVersion::TagPrefix { prefix, requirement } => {
+            let tags = tags(path).ok()?;
+            let at_commit = tags.iter().filter(|(_, tagged)| tagged == commit).map(|(tag, _)| tag.as_str());
+            pick_tag(at_commit, prefix, requirement).map(str::to_owned)
+        },
+        GitVersion::Branch(_) | GitVersion::Rev(_) | GitVersion::DefaultBranch => None,
+    }
+}
+
+/// Check out what `version` refers to (detached HEAD, like everything we check out), and return
+/// its commit. A `rev` which the clone doesn't know yet, for example one which is on no branch,
+/// is fetched from `origin` by its hash.
+pub fn checkout_version(path: &Path, url: &str, version: &GitVersion) -> Result<String, SourceFetchError> {
+    let not_found = || SourceFetchError::VersionNotFound { url: url.to_owned(), version: version.clone() };
+    let commit = match (resolve_version(path, version), version) {
+        (Ok(commit), _) => commit,
+        (Err(_), GitVersion::Rev(rev)) => {
+            // Not every server lets us fetch arbitrary commits, so failing here is no error yet.
+            let _ = fetch_commit(path, rev);
+            resolve_version(path, version).map_err(|_| not_found())?
+        },
+        (Err(_), GitVersion::DefaultBranch) => {
+            update_default_branch(path).map_err(|_| not_found())?;
+            resolve_version(path, version).map_err(|_| not_found())?
+        },
+        (Err(_), _) => return Err(not_found()),
+    };
+    if head_commit(path)? != commit {
+        checkout(path, &commit)?;
+    }
+    Ok(commit)
+}
+
+/// Fetch what `version` follows from `origin`, so that it doesn't resolve to where the branch (or
+/// the newest tag with its prefix) was when the checkout was last fetched. Nothing to do for tags
+/// and revs, which don't move, and are fetched if missing anyway, see [`checkout_version`].
+pub fn refresh_version(path: &Path, version: &GitVersion) -> Result<(), SourceFetchError> {
+    match version {
+        GitVersion::Branch(_) | GitVersion::TagPrefix { .. } => fetch_origin(path),
+        GitVersion::DefaultBranch => {
+            fetch_origin(path)?;
+            update_default_branch(path)
+        },
+        GitVersion::Tag(_) | GitVersion::Rev(_) => Ok(()),
+    }
+}
+
+/// Ask `origin` which branch its `HEAD` points to, and point our `refs/remotes/origin/HEAD` there
+/// too. Cloning sets it up, but fetching later doesn't notice the default branch changing.
+pub fn update_default_branch(path: &Path) -> Result<(), SourceFetchError> {
+    if is_sparse(path) {
+        git(git_in(path).args(["remote", "set-head", "origin", "--auto"]))?;
+    } else {
+        let repo = Repository::open(path)?;
+        let mut remote = repo.find_remote("origin")?;
+        let head = connect(&mut remote)?.default_branch()?;
+        let branch = head.as_str()
+            .and_then(|head| head.strip_prefix("refs/heads/"))
+            .ok_or_else(|| git2::Error::from_str("origin's HEAD doesn't point to a branch"))?;
+        repo.reference_symbolic("refs/remotes/origin/HEAD", &format!("refs/remotes/origin/{}", branch), true, "lair: default branch")?;
+    }
+    Ok(())
+}
+
+/// Which branch `refs/remotes/origin/HEAD` points to, for example `master`.
+pub fn default_branch(path: &Path) -> Result<String, SourceFetchError> {
+    let target = if is_sparse(path) {
+        git(git_in(path).args(["symbolic-ref", "refs/remotes/origin/HEAD"]))?.trim().to_owned()
+    } else {
+        let repo = Repository::open(path)?;
+        let head = repo.find_reference("refs/remotes/origin/HEAD")?;
+        head.symbolic_target().unwrap_or_default().to_owned()
+    };
+    match target.strip_prefix("refs/remotes/origin/") {
+        Some(branch) => Ok(branch.to_owned()),
+        None => Err(git2::Error::from_str("origin's HEAD doesn't point to a branch").into()),
+    }
+}
+
+/// Fetch a single commit from `origin` by its hash.
+fn fetch_commit(path: &Path, commit: &str) -> Result<(), SourceFetchError> {
+    if is_sparse(path) {
+        git(git_in(path).args(["fetch", "--quiet", "--end-of-options", "origin"]).arg(commit))?;
+    } else {
+        let repo = Repository::open(path)?;
+        let mut remote = repo.find_remote("origin")?;
+        remote.fetch(&[commit], Some(&mut fetch_options(&remote)), None)?;
+    }
+    Ok(())
+}
+
+/// Is this commit present in the local clone?
+pub fn has_commit(path: &Path, commit: &str) -> Result<bool, SourceFetchError> {
+    if is_sparse(path) {
+        Ok(git(git_in(path).args(["cat-file", "-e", "--end-of-options"]).arg(format!("{}^{{commit}}", commit))).is_ok())
+    } else {
+        let repo = Repository::open(path)?;
+        let found = Oid::from_str(commit).ok().is_some_and(|oid| repo.find_commit(oid).is_ok());
+        Ok(found)
+    }
+}
+
+/// Check out `commit` (detached HEAD), overwriting whatever is in the working tree.
+pub fn checkout(path: &Path, commit: &str) -> Result<(), SourceFetchError> {
+    if is_sparse(path) {
+        // `git checkout` takes `--end-of-options` for a second revision, `git switch` doesn't.
+        git(git_in(path).args(["switch", "--quiet", "--discard-changes", "--detach", "--end-of-options", commit]))?;
+    } else {
+        let repo = Repository::open(path)?;
+        let oid = Oid::from_str(commit)?;
+        let object = repo.find_commit(oid)?.into_object();
+        repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))?;
+        repo.set_head_detached(oid)?;
+    }
+    Ok(())
+}
+
+/// Make sure `commit` is checked out, fetching from `origin` first if we don't have it yet.
+pub fn checkout_fetching(path: &Path, commit: &str) -> Result<(), SourceFetchError> {
+    if head_commit(path)? == commit {
+        return Ok(());
+    }
+    if !has_commit(path, commit)? {
+        fetch_origin(path)?;
+        if !has_commit(path, commit)? {
+            return Err(SourceFetchError::CommitNotFound { commit: commit.to_owned() });
+        }
+    }
+    checkout(path, commit)
+}
+
+/// One-line summaries (`8b1d2e4 Add SimpleSet`) of the commits reachable from `new` but not from
+/// `old`, newest first.
+pub fn log_between(path: &Path, old: &str, new: &str) -> Result<Vec<String>, SourceFetchError> {
+    if is_sparse(path) {
+        let out = git(git_in(path).args(["log", "--format=%h %s", "--end-of-options"]).arg(format!("{}..{}", old, new)))?;
+        Ok(out.lines().map(str::to_owned).collect())
+    } else {
+        let repo = Repository::open(path)?;
+        let mut walk = repo.revwalk()?;
+        walk.push(Oid::from_str(new)?)?;
+        walk.hide(Oid::from_str(old)?)?;
+        walk.map(|oid| {
+            let commit = repo.find_commit(oid?)?;
+            let short = commit.as_object().short_id()?;
+            Ok(format!("{} {}", short.as_str().unwrap_or_default(), commit.summary().unwrap_or_default()))
+        }).collect()
+    }
+}
+
+/// Files under `dir` (relative to the repository root) which differ between the commits `old` and
+/// `new`, relative to the repository root, each with git's status letter: `A`dded, `M`odified or
+/// `D`eleted. Renames count as a deletion and an addition. An empty `dir` is the whole repository.
+pub fn files_between(path: &Path, old: &str, new: &str, dir: &Path) -> Result<Vec<(char, PathBuf)>, SourceFetchError> {
+    // git refuses an empty pathspec, but `.` means the same.
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    if is_sparse(path) {
+        let out = git(git_in(path).args(["diff", "--name-status", "--no-renames", "--end-of-options", old, new, "--"]).arg(dir))?;
+        Ok(out.lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(status, file)| Some((status.chars().next()?, PathBuf::from(file))))
+            .collect())
+    } else {
+        let repo = Repository::open(path)?;
+        let tree = |commit: &str| repo.find_commit(Oid::from_str(commit)?)?.tree();
+        let mut options = git2::DiffOptions::new();
+        if dir != Path::new(".") {
+            options.pathspec(dir);
+        }
+        let diff = repo.diff_tree_to_tree(Some(&tree(old)?), Some(&tree(new)?), Some(&mut options))?;
+        Ok(diff.deltas()
+            .filter_map(|delta| {
+                let status = match delta.status() {
+                    git2::Delta::Added => 'A',
+                    git2::Delta::Deleted => 'D',
+                    _ => 'M',
+                };
+                let file = delta.new_file().path().or(delta.old_file().path())?;
+                Some((status, file.to_owned()))
+            })
+            .collect())
+    }
+}
+
+/// Every branch and tag of `url`, as (ref name, commit), for example
+/// `("refs/heads/main", "3f2a9c01d5e8...")`. Annotated tags also come peeled, as
+/// `refs/tags/v0.1.0^{}` with the commit they point to. Doesn't touch any checkout.
+pub fn remote_refs(url: &str) -> Result<Vec<(String, String)>, SourceFetchError> {
+    let mut remote = Remote::create_detached(url)?;
+    let refs = connect(&mut remote)?.list()?.iter()
+        .map(|head| (head.name().to_owned(), head.oid().to_string()))
+        .collect();
+    Ok(refs)
+}
+
+/// Which branch the `HEAD` of `url` points to, for example `master`. Doesn't touch any checkout.
+pub fn remote_default_branch(url: &str) -> Result<String, SourceFetchError> {
+    let mut remote = Remote::create_detached(url)?;
+    let connection = connect(&mut remote)?;
+    let head = connection.default_branch()?;
+    match head.as_str().and_then(|head| head.strip_prefix("refs/heads/")) {
+        Some(branch) => Ok(branch.to_owned()),
+        None => Err(git2::Error::from_str("the remote's HEAD doesn't point to a branch").into()),
+    }
+}
+
+/// Can `commit` still be fetched from `url`, that is, is it reachable from one of its branches or
+/// tags? Doesn't touch any checkout: if `commit` isn't a branch or tag itself, we download the
+/// history into a throwaway repository in the temp directory.
+pub fn is_reachable(url: &str, commit: &str) -> Result<bool, SourceFetchError> {
+    let oid = Oid::from_str(commit)?;
+    let mut remote = Remote::create_detached(url)?;
+    // Includes the peeled `^{}` entries, so annotated tags count with their commit.
+    if connect(&mut remote)?.list()?.iter().any(|head| head.oid() == oid) {
+        return Ok(true);
+    }
+
+    let tmp = std::env::temp_dir().join(format!("lair-{}-{}", std::process::id(), commit));
+    let reachable = (|| {
+        let repo = Repository::init_bare(&tmp)?;
+        let mut remote = repo.remote_anonymous(url)?;
+        let mut options = fetch_options(&remote);
+        remote.fetch(&["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"], Some(&mut options), None)?;
+        // We only fetched what the branches and tags lead to.
+        let found = repo.find_commit(oid).is_ok();
+        Ok(found)
+    })();
+    let _ = std::fs::remove_dir_all(&tmp);
+    reachable
+}
+
+/// What to do about dependencies whose checkouts have local changes, for example from someone
+/// trying out a fix right in `build/deps`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DirtyDeps {
+    /// Build them as they are, but warn.
+    #[default]
+    Warn,
+    /// Fail, so that what's built is exactly what the lockfile says.
+    Deny,
+    /// Build them as they are.
+    Allow,
+}
+
+impl DirtyDeps {
+    pub fn from_flags(frozen: bool, allow_dirty_deps: bool) -> Self {
+        match (frozen, allow_dirty_deps) {
+            (true, _) => DirtyDeps::Deny,
+            (false, true) => DirtyDeps::Allow,
+            (false, false) => DirtyDeps::Warn,
+        }
+    }
+}
+
+/// Are files git tracks changed in the working tree of `path`? New files don't count, since they
+/// survive checkouts, and every package's `build` directory is one.
+pub fn is_dirty(path: &Path) -> Result<bool, SourceFetchError> {
+    Ok(!changed_files(path)?.is_empty())
+}
+
+/// The files git tracks which are changed in the working tree of `path`, relative to it. See
+/// [`is_dirty`].
+pub fn changed_files(path: &Path) -> Result<Vec<PathBuf>, SourceFetchError> {
+    if is_sparse(path) {
+        // `XY file`, or `XY old -> new` for renames.
+        let status = git(git_in(path).args(["status", "--porcelain", "--untracked-files=no"]))?;
+        Ok(status.lines()
+            .filter_map(|line| line.get(3..))
+            .map(|file| PathBuf::from(file.rsplit(" -> ").next().unwrap_or(file).trim_matches('"')))
+            .collect())
+    } else {
+        let repo = Repository::open(path)?;
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(false).include_ignored(false);
+        let files = repo.statuses(Some(&mut options))?.iter()
+            .filter_map(|entry| entry.path().map(PathBuf::from))
+            .collect();
+        Ok(files)
+    }
+}
+
+/// The host (and port, if it isn't 22) `url` connects to over SSH, for example `github.com` for
+/// `git@github.com:Kiiyya/NotJson`. `None` for other protocols.
+pub fn ssh_host(url: &str) -> Option<(String, Option<u16>)> {
+    let authority = match url.split_once("://") {
+        Some(("ssh" | "git+ssh" | "ssh+git", rest)) => rest.split('/').next()?,
+        Some(_) => return None,
+        // scp-like `[user@]host:path`. A single letter before the colon is a Windows drive.
+        None => match url.split_once(':') {
+            Some((host, _)) if host.len() > 1 && !host.contains('/') && !host.contains('\\') => host,
+            _ => return None,
+        },
+    };
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let (host, port) = match url.contains("://").then(|| host.rsplit_once(':')).flatten() {
+        Some((host, port)) => (host, port.parse().ok().filter(|&port| port != 22)),
+        None => (host, None),
+    };
+    (!host.is_empty()).then(|| (host.to_owned(), port))
+}
+
+/// How `ssh` spells the host in `known_hosts`: `github.com`, or `[example.com]:2222`.
+fn known_hosts_name(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) => format!("[{}]:{}", host, port),
+        None => host.to_owned(),
+    }
+}
+
+/// Does `~/.ssh/known_hosts` have a key for `host`? Also `true` if we can't tell, because
+/// `ssh-keygen` isn't installed, so that `ssh` itself gets to decide.
+pub fn is_known_host(host: &str, port: Option<u16>) -> bool {
+    Command::new("ssh-keygen")
+        .arg("-F").arg(known_hosts_name(host, port))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+/// Ask `host` for its public keys, as `known_hosts` lines, along with their fingerprints (for
+/// example `256 SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU github.com (ED25519)`).
+pub fn scan_host_keys(host: &str, port: Option<u16>) -> Result<(Vec<String>, Vec<String>), SourceFetchError> {
+    let mut scan = Command::new("ssh-keyscan");
+    if let Some(port) = port {
+        scan.arg("-p").arg(port.to_string());
+    }
+    let keys: Vec<String> = run_tool("ssh-keyscan", scan.arg(host), None)?.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect();
+    if keys.is_empty() {
+        return Err(SourceFetchError::GitCommand(format!("`ssh-keyscan` got no keys from `{}`", host)));
+    }
+    let fingerprints = run_tool("ssh-keygen", Command::new("ssh-keygen").args(["-l", "-f", "-"]), Some(&keys.join("\n")))?
+        .lines()
+        .map(str::to_owned)
+        .collect();
+    Ok((keys, fingerprints))
+}
+
+/// Append `keys` (from [`scan_host_keys`]) to `~/.ssh/known_hosts`.
+pub fn trust_host_keys(keys: &[String]) -> Result<(), SourceFetchError> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or_else(|| SourceFetchError::GitCommand("Don't know where ~/.ssh is, neither HOME nor USERPROFILE is set".to_owned()))?;
+    let dir = Path::new(&home).join(".ssh");
+    std::fs::create_dir_all(&dir)?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(dir.join("known_hosts"))?;
+    for key in keys {
+        writeln!(file, "{}", key)?;
+    }
+    Ok(())
+}
+
+/// Like [`git`], for the other command line tools we need, optionally feeding them `stdin`.
+fn run_tool(program: &str, command: &mut Command, stdin: Option<&str>) -> Result<String, SourceFetchError> {
+    let mut child = command
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| SourceFetchError::GitCommand(format!("Failed to run `{}`: {}", program, e)))?;
+    if let Some(stdin) = stdin {
+        child.stdin.take().expect("stdin is piped").write_all(stdin.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(SourceFetchError::GitCommand(format!("`{}` failed: {}", program, String::from_utf8_lossy(&output.stderr).trim())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: &[&str] = &["collections-v1.2.0", "collections-v1.2.3", "collections-v1.10.0", "collections-v1.2.4-rc.1", "collections-vnext", "json-v2.0.0"];
+
+    #[test]
+    fn picks_the_highest_matching_tag() {
+        let pick = |requirement| pick_tag(TAGS.iter().copied(), "collections-v", requirement);
+        // Requirements like in registries, with pre-releases only if asked for.
+        assert_eq!(pick("~1.2"), Some("collections-v1.2.3"));
+        assert_eq!(pick("=1.2.3"), Some("collections-v1.2.3"));
+        assert_eq!(pick("1.2"), Some("collections-v1.10.0"));
+        assert_eq!(pick("1"), Some("collections-v1.10.0"));
+        assert_eq!(pick("*"), Some("collections-v1.10.0"));
+        assert_eq!(pick("~1.2.4-rc.1"), Some("collections-v1.2.4-rc.1"));
+        assert_eq!(pick("2"), None);
+        assert_eq!(pick_tag(TAGS.iter().copied(), "json-v", "*"), Some("json-v2.0.0"));
+        assert_eq!(pick_tag(TAGS.iter().copied(), "v", "*"), None);
+    }
+
+    #[test]
+    fn canonicalizes_urls() {
+        assert_eq!(canonical_url("HTTPS://GitHub.com/Kiiyya/NotJson.git/"), "https://github.com/Kiiyya/NotJson");
+        assert_eq!(canonical_url("git@github.com:Kiiyya/NotJson.git"), "git@github.com:Kiiyya/NotJson");
+        assert_eq!(repo_name("https://github.com/Kiiyya/NotJson"), "NotJson");
+        assert_eq!(repo_name("git@github.com:NotJson"), "NotJson");
+        assert_eq!(repo_name("https://example.com/"), "repo");
+    }
+}
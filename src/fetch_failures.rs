@@ -0,0 +1,105 @@
+//! Remembering downloads which failed recently, so that the next run fails right away, instead of
+//! asking for the same bad url again. Only failures which will happen again count: a timeout or
+//! a dropped connection (see [`SourceFetchError::is_transient`]) says nothing about the next try.
+//!
+//! Kept in `build/.lair/fetch-failures.json`, by url: what went wrong, and when. An entry is used
+//! for [`TTL`] after it was recorded, and dropped as soon as the url works again. `--refresh`
+//! ignores them. Within a run this isn't needed, since every package waiting for a failed
+//! download already gets its error (see [`crate::lazy`]).
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SourceFetchError;
+use crate::layout;
+use crate::timestamp;
+
+/// How long a failure is remembered.
+pub const TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Failure {
+    error: String,
+    /// Seconds since 1970-01-01.
+    at: u64,
+}
+
+/// Serializes reading and writing the file between the fetches of one run.
+static FILE: Mutex<()> = Mutex::new(());
+
+/// Fail with the error `url` failed with last time, if that was less than [`TTL`] ago. Blocking.
+pub fn check(url: &str) -> Result<(), SourceFetchError> {
+    let _file = FILE.lock().unwrap();
+    let now = timestamp::now();
+    match load().remove(url) {
+        Some(failure) if now.saturating_sub(failure.at) < TTL.as_secs() => Err(SourceFetchError::RecentlyFailed {
+            url: url.to_owned(),
+            error: failure.error,
+            ago: Duration::from_secs(now.saturating_sub(failure.at)),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Run `fetch`, which downloads `url`, remembering whether it failed. Blocking.
+pub fn recording<T>(url: &str, fetch: impl FnOnce() -> Result<T, SourceFetchError>) -> Result<T, SourceFetchError> {
+    let result = fetch();
+    let _file = FILE.lock().unwrap();
+    let mut failures = load();
+    let changed = match &result {
+        Ok(_) => failures.remove(url).is_some(),
+        // Says nothing about whether it would have worked, or will next time.
+        Err(SourceFetchError::Interrupted { .. }) => false,
+        Err(e) if e.is_transient() => false,
+        Err(e) => {
+            failures.insert(url.to_owned(), Failure { error: e.to_string(), at: timestamp::now() });
+            true
+        },
+    };
+    if changed {
+        let now = timestamp::now();
+        failures.retain(|_, failure| now.saturating_sub(failure.at) < TTL.as_secs());
+        // Only a shortcut, so not being able to write it is no reason to fail.
+        let _ = save(&failures);
+    }
+    result
+}
+
+/// For example `3 minutes`.
+pub fn describe(duration: Duration) -> String {
+    match duration.as_secs() {
+        1 => "1 second".to_owned(),
+        secs @ 0..=59 => format!("{} seconds", secs),
+        60..=119 => "1 minute".to_owned(),
+        secs => format!("{} minutes", secs / 60),
+    }
+}
+
+fn load() -> BTreeMap<String, Failure> {
+    std::fs::read(path()).ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn save(failures: &BTreeMap<String, Failure>) -> std::io::Result<()> {
+    let path = path();
+    if failures.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(failures).expect("Failures are always serializable."))
+}
+
+/// `build/.lair/fetch-failures.json`.
+fn path() -> PathBuf {
+    layout::lair_dir().join("fetch-failures.json")
+}
@@ -0,0 +1,61 @@
+//! Small filesystem helpers std doesn't have.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A new, empty directory in the temporary directory, named `{prefix}{random}`, which only we can
+/// read. Created exclusively, so that nobody can have put it, or a symlink, there first.
+pub fn temp_dir(prefix: &str) -> std::io::Result<PathBuf> {
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    loop {
+        let mut random = [0u8; 8];
+        getrandom::getrandom(&mut random).map_err(|e| std::io::Error::other(e.to_string()))?;
+        let path = std::env::temp_dir().join(format!("{}{:016x}", prefix, u64::from_le_bytes(random)));
+        match builder.create(&path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            result => return result.map(|()| path),
+        }
+    }
+}
+
+/// Make `to` a symlink to the directory `from`, which should be absolute. On Windows, where
+/// symlinks need developer mode, a junction if that fails.
+pub fn link_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(from, to);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_dir(from, to).or_else(|_| {
+        let status = std::process::Command::new("cmd")
+            .args(["/C", "mklink", "/J"]).arg(to).arg(from)
+            .stdout(std::process::Stdio::null())
+            .status()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(std::io::Error::other(format!("mklink /J failed with {}", status))),
+        }
+    });
+    #[cfg(not(any(unix, windows)))]
+    return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no symlinks on this platform"));
+}
+
+/// Whether `path` is a symlink (or junction), without following it.
+pub fn is_link(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Copy the directory `from` to `to`, recursively. `to` is created if necessary; files already
+/// there are overwritten, other files are left alone.
+pub fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+        } else {
+            std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
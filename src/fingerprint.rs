@@ -0,0 +1,314 @@
+//! Deciding whether a package needs to be rebuilt.
+//!
+//! A package's fingerprint consists of two halves:
+//! - `own`: its source tree (paths and contents of all files), and everything else about how
+//!   we'd build it, like its descriptor and the compiler version.
+//! - `deps`: the fingerprints of its direct dependencies, which transitively cover everything
+//!   below them.
+//!
+//! If neither changed since the last successful build, and the TTC files are still there, we
+//! skip the build. If only `own` changed, idris2's own incremental compilation does the rest. But
+//! if `deps` changed, the TTC files may still refer to the old interfaces of the dependencies, so
+//! we delete them and build from scratch.
+//!
+//! The root package, the one being edited, also remembers the modules its last build checked:
+//! `main` and whatever it imports from the package, with hashes of their contents. If only `own`
+//! changed, but not those modules, say because of an edit to a test, a script, the README or a
+//! module `main` doesn't use, idris2 would find nothing to do, so it isn't run at all.
+//!
+//! The fingerprints of the last successful builds live in `build/.lair/fingerprints/{toolchain}/`,
+//! one set per [`Toolchain`](crate::compiler::Toolchain) like the build artifacts themselves.
+//!
+//! So that only files which changed are read again, the hashes of a package's files are kept in
+//! its own `build/.lair/file-hashes.json` along with their sizes and modification times, see
+//! [`FileHashes`]. Touching a file without changing it therefore doesn't rebuild anything.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::descriptor::Descriptor;
+use crate::layout;
+use crate::modules;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub own: String,
+    pub deps: String,
+    /// `own` without the source tree: the descriptor and `extra`. Empty in fingerprints from
+    /// before it existed.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub config: String,
+    /// Module --> hash of its source, for the modules a build checks, see
+    /// [`Fingerprint::with_checked`]. Empty unless asked for.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checked: BTreeMap<String, String>,
+}
+
+impl Fingerprint {
+    /// Fingerprint of the package in `base_path`. `extra` is anything else which should trigger a
+    /// rebuild when it changes, for example the compiler version. Blocking.
+    pub fn compute(desc: &Descriptor, base_path: &Path, extra: &str, dep_fingerprints: &[String]) -> std::io::Result<Self> {
+        let mut own = Sha256::new();
+        own.update(serde_json::to_vec(desc).expect("Descriptors are always serializable."));
+        own.update(extra.as_bytes());
+        let config = hex(own.clone().finalize().as_slice());
+        // The root package's base path is usually empty, meaning the current directory.
+        let base_path = if base_path.as_os_str().is_empty() { Path::new(".") } else { base_path };
+        let mut hashes = FileHashes::load(base_path);
+        hash_tree(&mut own, base_path, base_path, &mut hashes)?;
+        // Only saves reading the files again next time.
+        let _ = hashes.save(base_path);
+
+        let mut deps = Sha256::new();
+        let mut dep_fingerprints = dep_fingerprints.to_vec();
+        dep_fingerprints.sort();
+        for dep in dep_fingerprints {
+            deps.update(dep.as_bytes());
+            deps.update(b"\0");
+        }
+
+        Ok(Self {
+            own: hex(own.finalize().as_slice()),
+            deps: hex(deps.finalize().as_slice()),
+            config,
+            checked: BTreeMap::new(),
+        })
+    }
+
+    /// Also remember the modules `idris2 --check {main}` checks in `source_dir`, see
+    /// [`modules::closure`]. Nothing if `main` isn't in `source_dir`. Blocking.
+    pub fn with_checked(mut self, source_dir: &Path, main: &Path) -> std::io::Result<Self> {
+        let Some(module) = main.strip_prefix(source_dir).ok().and_then(modules::module_of) else { return Ok(self) };
+        for (module, file) in modules::closure(source_dir, &module)? {
+            let hash = hex(Sha256::digest(std::fs::read(file)?).as_slice());
+            self.checked.insert(module, hash);
+        }
+        Ok(self)
+    }
+
+    /// Whether a build with this fingerprint gives the same TTC files as one with `previous`.
+    /// Either nothing changed at all, or only files a build doesn't check.
+    pub fn builds_like(&self, previous: &Fingerprint) -> bool {
+        let same_checked = !self.config.is_empty() && self.config == previous.config
+            && !self.checked.is_empty() && self.checked == previous.checked;
+        self.deps == previous.deps && (self.own == previous.own || same_checked)
+    }
+
+    /// `extra` for a package built with the `idris_flags` from the root manifest. Without any,
+    /// just `extra`, so that fingerprints from before there were flags still match.
+    pub fn extra_with_flags(extra: &str, idris_flags: &[String]) -> String {
+        match idris_flags {
+            [] => extra.to_owned(),
+            flags => format!("{} {:?}", extra, flags),
+        }
+    }
+
+    /// Both halves in one, which is what dependents see.
+    pub fn combined(&self) -> String {
+        hex(Sha256::digest(format!("{}{}", self.own, self.deps).as_bytes()).as_slice())
+    }
+
+    /// The fingerprint of the last successful build of `desc` with `toolchain`, if any.
+    pub fn load(desc: &Descriptor, toolchain: &str) -> Option<Self> {
+        let bytes = std::fs::read(path(desc, toolchain)).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    pub fn save(&self, desc: &Descriptor, toolchain: &str) -> std::io::Result<()> {
+        let path = path(desc, toolchain);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec(self).expect("Fingerprints are always serializable."))
+    }
+}
+
+/// For example `build/.lair/fingerprints/idris2-0.6.0/NotJson-3f2a9c01.json`.
+fn path(desc: &Descriptor, toolchain: &str) -> PathBuf {
+    layout::lair_dir().join("fingerprints").join(toolchain).join(format!("{}-{}.json", desc.name(), layout::short_hash(desc)))
+}
+
+/// Files modified this recently may still change within the same modification time, so their
+/// hashes aren't kept.
+const RACY: Duration = Duration::from_secs(2);
+
+/// Hashes of the files of a package, by path relative to it, and the size and modification time
+/// (in nanoseconds since 1970) they had when hashed. Hashes of files whose size or modification
+/// time differ now are hashed again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FileHashes {
+    files: BTreeMap<PathBuf, (u64, u128, String)>,
+    /// Files seen since loading, the others are gone, and dropped when saving.
+    #[serde(skip)]
+    seen: BTreeMap<PathBuf, (u64, u128, String)>,
+}
+
+impl FileHashes {
+    /// `build/.lair/file-hashes.json` in the package in `base_path`.
+    fn path(base_path: &Path) -> PathBuf {
+        base_path.join("build").join(".lair").join("file-hashes.json")
+    }
+
+    /// Nothing, if there's nothing (readable) yet.
+    fn load(base_path: &Path) -> Self {
+        std::fs::read(Self::path(base_path)).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, base_path: &Path) -> std::io::Result<()> {
+        if self.seen == self.files {
+            return Ok(());
+        }
+        let path = Self::path(base_path);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let saved = FileHashes { files: self.seen.clone(), seen: BTreeMap::new() };
+        std::fs::write(path, serde_json::to_vec(&saved).expect("File hashes are always serializable."))
+    }
+
+    /// The hash of the contents of `path`, at `relative` in the package.
+    fn hash(&mut self, path: &Path, relative: &Path, metadata: &std::fs::Metadata) -> std::io::Result<String> {
+        let modified = metadata.modified()?;
+        let nanos = modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let hash = match self.files.get(relative) {
+            Some((len, cached, hash)) if *len == metadata.len() && *cached == nanos => hash.clone(),
+            _ => hex(Sha256::digest(std::fs::read(path)?).as_slice()),
+        };
+        let racy = SystemTime::now().duration_since(modified).map_or(true, |age| age < RACY);
+        if !racy {
+            self.seen.insert(relative.to_owned(), (metadata.len(), nanos, hash.clone()));
+        }
+        Ok(hash)
+    }
+}
+
+/// Hash paths and contents of every file below `dir`, in a stable order. Skips hidden files (like
+/// `.git`) and the package's own `build` directory.
+fn hash_tree(hasher: &mut Sha256, root: &Path, dir: &Path, hashes: &mut FileHashes) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') || (dir == root && name == "build") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        if metadata.is_dir() {
+            hash_tree(hasher, root, &path, hashes)?;
+        } else if metadata.is_symlink() && !path.is_file() {
+            // Links to directories aren't followed, they may well lead back up. Nor are dangling
+            // ones, which have nothing to read.
+            hasher.update(format!("{}\0-> {}\n", relative.display(), std::fs::read_link(&path)?.display()).as_bytes());
+        } else {
+            let hash = hashes.hash(&path, relative, &std::fs::metadata(&path)?)?;
+            hasher.update(format!("{}\0{}\n", relative.display(), hash).as_bytes());
+        }
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(own: &str, deps: &str, config: &str, checked: &[(&str, &str)]) -> Fingerprint {
+        Fingerprint {
+            own: own.to_owned(),
+            deps: deps.to_owned(),
+            config: config.to_owned(),
+            checked: checked.iter().map(|&(module, hash)| (module.to_owned(), hash.to_owned())).collect(),
+        }
+    }
+
+    #[test]
+    fn builds_like_when_nothing_checked_changed() {
+        let previous = fingerprint("own", "deps", "config", &[("Main", "1")]);
+        assert!(previous.builds_like(&previous));
+        // Only a file the build doesn't check changed.
+        assert!(fingerprint("edited", "deps", "config", &[("Main", "1")]).builds_like(&previous));
+        assert!(!fingerprint("edited", "deps", "config", &[("Main", "2")]).builds_like(&previous));
+        assert!(!fingerprint("edited", "deps", "config", &[("Main", "1"), ("Util", "1")]).builds_like(&previous));
+        assert!(!fingerprint("edited", "deps", "flags", &[("Main", "1")]).builds_like(&previous));
+        assert!(!fingerprint("own", "other deps", "config", &[("Main", "1")]).builds_like(&previous));
+    }
+
+    #[test]
+    fn builds_like_needs_checked_modules() {
+        // From before `config` and `checked` existed, or not the root package.
+        let previous = fingerprint("own", "deps", "", &[]);
+        assert!(previous.builds_like(&previous));
+        assert!(!fingerprint("edited", "deps", "", &[]).builds_like(&previous));
+        let previous = fingerprint("own", "deps", "config", &[]);
+        assert!(!fingerprint("edited", "deps", "config", &[]).builds_like(&previous));
+    }
+
+    #[test]
+    fn compute_sees_edits() {
+        let dir = crate::files::temp_dir("lair-test-").unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("build")).unwrap();
+        std::fs::write(dir.join("src/Main.idr"), "module Main\nimport Util\n").unwrap();
+        std::fs::write(dir.join("src/Util.idr"), "module Util\n").unwrap();
+        std::fs::write(dir.join("README.md"), "Hello").unwrap();
+        let desc = Descriptor::Root { name: "Root".parse().unwrap() };
+        let compute = || Fingerprint::compute(&desc, &dir, "idris2 0.6.0", &["b".to_owned(), "a".to_owned()]).unwrap()
+            .with_checked(&dir.join("src"), &dir.join("src/Main.idr")).unwrap();
+
+        let first = compute();
+        assert_eq!(first.checked.keys().collect::<Vec<_>>(), ["Main", "Util"]);
+        assert_eq!(compute(), first);
+        // Neither `build` nor the order of the dependencies' fingerprints count.
+        std::fs::write(dir.join("build/Main.ttc"), "anything").unwrap();
+        let dep_order = Fingerprint::compute(&desc, &dir, "idris2 0.6.0", &["a".to_owned(), "b".to_owned()]).unwrap();
+        assert_eq!(dep_order.own, first.own);
+        assert_eq!(dep_order.deps, first.deps);
+
+        std::fs::write(dir.join("README.md"), "Hello, world").unwrap();
+        let readme = compute();
+        assert_ne!(readme.own, first.own);
+        assert!(readme.builds_like(&first));
+
+        std::fs::write(dir.join("src/Util.idr"), "module Util\n\nx : Nat\n").unwrap();
+        let util = compute();
+        assert!(!util.builds_like(&first));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compute_hashes_contents() {
+        let dir = crate::files::temp_dir("lair-test-").unwrap();
+        let file = dir.join("Main.idr");
+        let set_modified = |secs| std::fs::File::options().write(true).open(&file).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+        std::fs::write(&file, "module Main\n").unwrap();
+        set_modified(1_000_000);
+        let desc = Descriptor::Root { name: "Root".parse().unwrap() };
+        let compute = || Fingerprint::compute(&desc, &dir, "", &[]).unwrap().own;
+
+        let first = compute();
+        assert!(FileHashes::load(&dir).files.contains_key(Path::new("Main.idr")));
+        // Touched, but the same.
+        set_modified(2_000_000);
+        assert_eq!(compute(), first);
+        // Different, but of the same size and modification time: only hashing afresh can tell.
+        std::fs::write(&file, "module Mai2\n").unwrap();
+        set_modified(2_000_000);
+        assert_eq!(compute(), first);
+        std::fs::remove_file(FileHashes::path(&dir)).unwrap();
+        assert_ne!(compute(), first);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,422 @@
+//! The resolved dependency graph, as plain data.
+//!
+//! Resolving the graph means reading the manifest of every package, which for a large graph is
+//! slow, even when everything is already downloaded. So after resolving, we write a snapshot to
+//! `build/.lair/graph.json`, which metadata-only commands (`list`, `tree`, `why`) reuse as long as
+//! none of the manifests it was computed from changed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compiler::{Idris2Requirement, Idris2Version};
+use crate::descriptor::Descriptor;
+use crate::layout;
+use crate::manifest::Declaration;
+use crate::target::Target;
+
+/// Where the snapshot lives.
+pub fn snapshot_path() -> PathBuf {
+    layout::lair_dir().join("graph.json")
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GraphPackage {
+    pub descriptor: Descriptor,
+
+    /// From the package's manifest, for example `0.1.0`.
+    pub version: String,
+
+    /// Which idris2 versions the package works with, from its manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idris2: Option<Idris2Requirement>,
+
+    /// Direct dependencies.
+    pub dependencies: BTreeSet<Descriptor>,
+
+    /// Where the package's manifest declares each of its direct dependencies. Always written, so
+    /// that snapshots from before it existed don't load.
+    pub origins: Vec<Origin>,
+}
+
+/// Where a dependency comes from: which line of its dependent's manifest asks for it, and which
+/// `[patch]` of the root's replaces that, if any. For finding out who brought an unwanted package
+/// into a deep graph.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Origin {
+    /// As in [`GraphPackage::dependencies`], so after patches.
+    pub dependency: Descriptor,
+    pub declared: Declaration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patched: Option<Declaration>,
+}
+
+/// For example "`dependencies.NotJson` in Egg.toml:7, patched by `patch.NotJson` in Egg.toml:12".
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.declared)?;
+        if let Some(patched) = &self.patched {
+            write!(f, ", patched by {}", patched)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Graph {
+    pub root: Descriptor,
+
+    /// The other packages of the root's workspace, see [`crate::manifest::Manifest::members`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Descriptor>,
+
+    /// Every package reachable from the root or a member, including themselves.
+    pub packages: Vec<GraphPackage>,
+
+    /// Conditional dependencies which were left out, because they're for another target. Their
+    /// own dependencies are unknown, since we never downloaded them.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub inactive: BTreeSet<Descriptor>,
+
+    /// Descriptors which turned out to be the same package at the same commit as another one, say
+    /// `branch = "main"` and the `rev` it is at, with the descriptor of the package they are. See
+    /// [`crate::descriptor::ResolvedDescriptor`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<Alias>,
+}
+
+/// A descriptor that is the same as another one, see [`Graph::aliases`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alias {
+    pub descriptor: Descriptor,
+    /// The descriptor of the package in [`Graph::packages`].
+    pub same_as: Descriptor,
+}
+
+impl Graph {
+    /// Where `dependent` declares its dependency `dependency`, if it's one.
+    pub fn origin(&self, dependent: &Descriptor, dependency: &Descriptor) -> Option<&Origin> {
+        self.package(dependent)?.origins.iter().find(|origin| &origin.dependency == dependency)
+    }
+
+    /// The package `desc` is, following [`Graph::aliases`].
+    pub fn package(&self, desc: &Descriptor) -> Option<&GraphPackage> {
+        let desc = self.aliases.iter().find(|alias| &alias.descriptor == desc).map_or(desc, |alias| &alias.same_as);
+        self.packages.iter().find(|package| &package.descriptor == desc)
+    }
+
+    /// All packages with this name. Usually one, but nothing stops two different descriptors
+    /// from having the same name.
+    pub fn packages_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a GraphPackage> + 'a {
+        self.packages.iter().filter(move |package| package.descriptor.name() == name)
+    }
+
+    /// `desc` and everything it transitively depends on.
+    pub fn closure(&self, desc: &Descriptor) -> BTreeSet<Descriptor> {
+        let mut closure = BTreeSet::new();
+        let mut stack = vec![desc.clone()];
+        while let Some(desc) = stack.pop() {
+            if let Some(package) = self.package(&desc) {
+                stack.extend(package.dependencies.iter().filter(|dep| !closure.contains(*dep)).cloned());
+            }
+            closure.insert(desc);
+        }
+        closure
+    }
+
+    /// Names taken by more than one descriptor, for example the same package pinned to two
+    /// different revisions by two dependents.
+    pub fn conflicts(&self) -> Vec<(&str, Vec<&Descriptor>)> {
+        let mut by_name: BTreeMap<&str, Vec<&Descriptor>> = BTreeMap::new();
+        for package in &self.packages {
+            by_name.entry(package.descriptor.name()).or_default().push(&package.descriptor);
+        }
+        by_name.into_iter()
+            .filter(|(_, descriptors)| descriptors.len() > 1)
+            .collect()
+    }
+
+    /// Names which differ only in case, for example `Json` and `JSON`, each group sorted. Both
+    /// the names packages are depended on by and their actual names count, since logs go by the
+    /// former and installed packages and idris2's `-p` by the latter.
+    pub fn case_collisions(&self) -> Vec<Vec<&str>> {
+        // A package depended on as `json` but called `Json` only collides with others.
+        let mut by_folded: BTreeMap<String, (BTreeSet<&str>, BTreeSet<&Descriptor>)> = BTreeMap::new();
+        for package in &self.packages {
+            for name in [package.descriptor.name(), package.descriptor.package_name()] {
+                let (names, descriptors) = by_folded.entry(name.to_lowercase()).or_default();
+                names.insert(name);
+                descriptors.insert(&package.descriptor);
+            }
+        }
+        by_folded.into_values()
+            .filter(|(names, descriptors)| names.len() > 1 && descriptors.len() > 1)
+            .map(|(names, _)| names.into_iter().collect())
+            .collect()
+    }
+
+    /// Who asks for each of `descriptors`, several versions of the same package, see
+    /// [`Graph::conflicts`].
+    pub fn explain_conflict(&self, descriptors: &[&Descriptor]) -> Vec<Requirement> {
+        let with_version = |desc: &Descriptor| (
+            desc.name().to_owned(),
+            self.package(desc).map(|package| package.version.clone()).unwrap_or_default(),
+        );
+        descriptors.iter()
+            .map(|desc| {
+                let shortest = self.why(desc).into_iter().min_by_key(Vec::len).unwrap_or_default();
+                let dependent = shortest.len().checked_sub(2).map(|i| &shortest[i]);
+                Requirement {
+                    descriptor: (*desc).clone(),
+                    version: with_version(desc).1,
+                    required_by: shortest[..shortest.len().saturating_sub(1)].iter().map(with_version).collect(),
+                    origin: dependent.and_then(|dependent| self.origin(dependent, desc)).cloned(),
+                }
+            })
+            .collect()
+    }
+
+    /// Packages which don't work with idris2 `version`, with their requirements.
+    pub fn unsupported_by(&self, version: Idris2Version) -> Vec<(&Descriptor, &Idris2Requirement)> {
+        self.idris2_requirements().into_iter()
+            .filter(|(_, requirement)| !requirement.matches(version))
+            .collect()
+    }
+
+    /// Every package which says which idris2 versions it works with.
+    pub fn idris2_requirements(&self) -> Vec<(&Descriptor, &Idris2Requirement)> {
+        self.packages.iter()
+            .filter_map(|package| Some((&package.descriptor, package.idris2.as_ref()?)))
+            .collect()
+    }
+
+    /// Render like
+    /// ```text
+    /// AmazingTool v0.1.0
+    /// ├── CoolCollections v0.1.0 (https://github.com/Kiiyya/CoolCollections, branch main)
+    /// └── NotJson v0.2.0 (https://github.com/Kiiyya/NotJson, branch main)
+    ///     └── CoolCollections v0.1.0 (https://github.com/Kiiyya/CoolCollections, branch main) (*)
+    /// ```
+    /// where `(*)` marks packages whose dependencies were already shown further up.
+    pub fn tree(&self) -> String {
+        let mut out = String::new();
+        let mut seen = BTreeSet::new();
+        self.tree_rec(&self.root, "", "", &mut seen, &mut out);
+        // Members not already shown as someone's dependency get their own tree.
+        for member in &self.members {
+            if !seen.contains(member) {
+                out.push('\n');
+                self.tree_rec(member, "", "", &mut seen, &mut out);
+            }
+        }
+        out
+    }
+
+    fn tree_rec(&self, desc: &Descriptor, prefix: &str, child_prefix: &str, seen: &mut BTreeSet<Descriptor>, out: &mut String) {
+        let package = match self.package(desc) {
+            Some(package) => package,
+            None => return,
+        };
+        let first_time = seen.insert(desc.clone());
+        let repeated = if !first_time && !package.dependencies.is_empty() { " (*)" } else { "" };
+        out.push_str(&format!("{}{}{}\n", prefix, self.label(package), repeated));
+
+        if first_time {
+            let count = package.dependencies.len();
+            for (i, dep) in package.dependencies.iter().enumerate() {
+                let (branch, indent) = if i + 1 == count { ("└── ", "    ") } else { ("├── ", "│   ") };
+                self.tree_rec(dep, &format!("{}{}", child_prefix, branch), &format!("{}{}", child_prefix, indent), seen, out);
+            }
+        }
+    }
+
+    /// For example `NotJson v0.2.0 (https://github.com/Kiiyya/NotJson, branch main)`.
+    pub fn label(&self, package: &GraphPackage) -> String {
+        match package.descriptor.source() {
+            Some(source) => format!("{} v{} ({})", package.descriptor.name(), package.version, source),
+            None => format!("{} v{}", package.descriptor.name(), package.version),
+        }
+    }
+
+    /// Every chain of dependencies leading from the root or a member to `target`, each starting
+    /// with one of those and ending with `target`.
+    pub fn why(&self, target: &Descriptor) -> Vec<Vec<Descriptor>> {
+        let mut dependents: BTreeMap<&Descriptor, Vec<&Descriptor>> = BTreeMap::new();
+        for package in &self.packages {
+            for dep in &package.dependencies {
+                dependents.entry(dep).or_default().push(&package.descriptor);
+            }
+        }
+
+        // Walk backwards from the target towards the root.
+        let mut chains = Vec::new();
+        let mut stack = vec![vec![target.clone()]];
+        while let Some(chain) = stack.pop() {
+            let last = chain.last().unwrap();
+            if last == &self.root || self.members.contains(last) {
+                chains.push(chain.iter().rev().cloned().collect());
+            }
+            if last == &self.root {
+                continue;
+            }
+            for dependent in dependents.get(last).into_iter().flatten() {
+                if !chain.contains(dependent) {
+                    let mut longer = chain.clone();
+                    longer.push((*dependent).clone());
+                    stack.push(longer);
+                }
+            }
+        }
+        chains.sort();
+        chains
+    }
+}
+
+/// Why one of several versions of a package is in the graph, see [`Graph::explain_conflict`].
+#[derive(Clone, Debug)]
+pub struct Requirement {
+    pub descriptor: Descriptor,
+    /// Of the package it ended up as, from its manifest, for example `0.3.7`.
+    pub version: String,
+    /// The shortest chain of dependencies from the root (or a workspace member) to the package
+    /// asking for it, as names and versions.
+    pub required_by: Vec<(String, String)>,
+    /// Where the last package of `required_by` asks for it.
+    pub origin: Option<Origin>,
+}
+
+/// For example "AmazingTool 0.1.0 → CoolCollections 1.2.0 requires `Json` (version 0.3, registry
+/// https://example.com/index), which is 0.3.7".
+impl Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let chain: Vec<String> = self.required_by.iter().map(|(name, version)| format!("{} {}", name, version)).collect();
+        write!(f, "{} requires `{}`", chain.join(" → "), self.descriptor.name())?;
+        if let Some(source) = self.descriptor.source() {
+            write!(f, " ({})", source)?;
+        }
+        if let Some(origin) = &self.origin {
+            write!(f, " as {}", origin)?;
+        }
+        write!(f, ", which is {}", self.version)
+    }
+}
+
+/// A file the graph was computed from, and what it looked like back then.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Input {
+    pub path: PathBuf,
+    /// `None` if the file didn't exist.
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+}
+
+impl Input {
+    pub fn of(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let metadata = std::fs::metadata(path).ok();
+        Self {
+            path: path.to_owned(),
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            len: metadata.map_or(0, |m| m.len()),
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        &Input::of(&self.path) == self
+    }
+}
+
+/// What we write to `build/.lair/graph.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub inputs: Vec<Input>,
+    /// What conditional dependencies were chosen for.
+    #[serde(default)]
+    pub target: Target,
+    pub graph: Graph,
+}
+
+impl Snapshot {
+    /// Load the snapshot, if there is one, it was computed for `target`, and all its inputs are
+    /// unchanged.
+    pub fn load_fresh(target: &Target) -> Option<Graph> {
+        let bytes = std::fs::read(snapshot_path()).ok()?;
+        let snapshot: Snapshot = serde_json::from_slice(&bytes).ok()?;
+        if &snapshot.target == target && snapshot.inputs.iter().all(Input::is_fresh) {
+            Some(snapshot.graph)
+        } else {
+            None
+        }
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let path = snapshot_path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Small graphs for unit tests.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    pub fn root() -> Descriptor {
+        Descriptor::Root { name: "Root".parse().unwrap() }
+    }
+
+    /// A path dependency on `name`, which is also its path, with its actual name `package`.
+    pub fn local(name: &str, package: Option<&str>) -> Descriptor {
+        Descriptor::Local { name: name.parse().unwrap(), package: package.map(|package| package.parse().unwrap()), path: name.into() }
+    }
+
+    /// A graph of `packages`, each with what it depends on. The first one is the root.
+    pub fn graph(packages: Vec<(Descriptor, Vec<Descriptor>)>) -> Graph {
+        let root = packages[0].0.clone();
+        let packages = packages.into_iter()
+            .map(|(descriptor, dependencies)| GraphPackage {
+                descriptor,
+                version: "0.1.0".to_owned(),
+                idris2: None,
+                dependencies: dependencies.into_iter().collect(),
+                origins: Vec::new(),
+            })
+            .collect();
+        Graph { root, members: Vec::new(), packages, inactive: BTreeSet::new(), aliases: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::fixtures::local;
+
+    /// The root and `descriptors`, none depending on anything.
+    fn graph(descriptors: Vec<Descriptor>) -> Graph {
+        fixtures::graph(std::iter::once(fixtures::root()).chain(descriptors).map(|descriptor| (descriptor, Vec::new())).collect())
+    }
+
+    #[test]
+    fn finds_names_differing_in_case() {
+        let graph = graph(vec![local("Json", None), local("JSON", None), local("Other", None), local("other", None), local("Fine", None)]);
+        assert_eq!(graph.case_collisions(), [vec!["JSON", "Json"], vec!["Other", "other"]]);
+    }
+
+    #[test]
+    fn counts_actual_names_too() {
+        let graph = graph(vec![local("MyJson", Some("Json")), local("json", None)]);
+        assert_eq!(graph.case_collisions(), [vec!["Json", "json"]]);
+    }
+
+    #[test]
+    fn an_alias_does_not_collide_with_itself() {
+        let graph = graph(vec![local("json", Some("Json")), local("Fine", None)]);
+        assert!(graph.case_collisions().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+//! One copy of what a large graph would otherwise have many of: package names, which every
+//! descriptor pointing at a package repeats, paths of checkouts and TTC files, and manifests of
+//! checkouts which several descriptors lead to (say, a package and an alias of it).
+//!
+//! An [`Interner`] lives as long as its [`crate::Lair`], and never forgets anything, which is
+//! fine for one run.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::descriptor::Descriptor;
+use crate::graph::Graph;
+use crate::manifest::Manifest;
+use crate::package_name::PackageName;
+
+/// The interned path of a package's directory, and how far up its workspace was looked for, see
+/// [`crate::layout::workspace_boundary`], since that can change what it inherits.
+type ManifestKey = (Arc<Path>, Option<Arc<Path>>);
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: Mutex<HashSet<PackageName>>,
+    paths: Mutex<HashSet<Arc<Path>>>,
+    manifests: Mutex<HashMap<ManifestKey, Arc<Manifest>>>,
+}
+
+impl Interner {
+    /// The copy of `name` everybody shares.
+    pub fn name(&self, name: &PackageName) -> PackageName {
+        let mut names = self.names.lock().unwrap();
+        if let Some(interned) = names.get(name) {
+            return interned.clone();
+        }
+        names.insert(name.clone());
+        name.clone()
+    }
+
+    /// Make `desc` use the shared copies of its names.
+    pub fn descriptor(&self, desc: &mut Descriptor) {
+        for name in desc.names_mut() {
+            *name = self.name(name);
+        }
+    }
+
+    /// Make every descriptor in `graph` use the shared copies of its names, for graphs which
+    /// didn't come from this run's nodes, like the snapshot.
+    pub fn graph(&self, graph: &mut Graph) {
+        let descriptors = |descriptors: &mut BTreeSet<Descriptor>| {
+            *descriptors = std::mem::take(descriptors).into_iter()
+                .map(|mut desc| {
+                    self.descriptor(&mut desc);
+                    desc
+                })
+                .collect();
+        };
+        self.descriptor(&mut graph.root);
+        graph.members.iter_mut().for_each(|desc| self.descriptor(desc));
+        for package in &mut graph.packages {
+            self.descriptor(&mut package.descriptor);
+            descriptors(&mut package.dependencies);
+            package.origins.iter_mut().for_each(|origin| self.descriptor(&mut origin.dependency));
+        }
+        descriptors(&mut graph.inactive);
+        for alias in &mut graph.aliases {
+            self.descriptor(&mut alias.descriptor);
+            self.descriptor(&mut alias.same_as);
+        }
+    }
+
+    /// The copy of `path` everybody shares. Spellings of the same path which only differ in `.`
+    /// components and separators are the same, but nothing is looked up on disk, so symlinks and
+    /// `..` are left as they are.
+    pub fn path(&self, path: impl AsRef<Path>) -> Arc<Path> {
+        let path: PathBuf = path.as_ref().components().collect();
+        let mut paths = self.paths.lock().unwrap();
+        if let Some(interned) = paths.get(path.as_path()) {
+            return interned.clone();
+        }
+        let interned: Arc<Path> = Arc::from(path);
+        paths.insert(interned.clone());
+        interned
+    }
+
+    /// The manifest read from `base_path` earlier, looking for its workspace no further than
+    /// `boundary`, if any.
+    pub fn manifest(&self, base_path: &Path, boundary: Option<&Path>) -> Option<Arc<Manifest>> {
+        let key = (self.path(base_path), boundary.map(|boundary| self.path(boundary)));
+        self.manifests.lock().unwrap().get(&key).cloned()
+    }
+
+    /// Remember `manifest` as the one in `base_path` with `boundary`. If another one was
+    /// remembered meanwhile, that one is kept and returned instead.
+    pub fn insert_manifest(&self, base_path: &Path, boundary: Option<&Path>, manifest: Manifest) -> Arc<Manifest> {
+        let key = (self.path(base_path), boundary.map(|boundary| self.path(boundary)));
+        self.manifests.lock().unwrap().entry(key).or_insert_with(|| Arc::new(manifest)).clone()
+    }
+}
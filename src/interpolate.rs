@@ -0,0 +1,133 @@
+//! `${env:VAR}` in the `git`, `archive`, `signature`, `registry` and `path` of dependencies, so
+//! that one manifest works with whichever internal git host or mirror each environment has:
+//!
+//! ```toml
+//! [dependencies]
+//! Ledger = { git = "https://${env:CORP_GIT_HOST}/finance/Ledger" }
+//! ```
+//!
+//! Only variables listed in `[manifest] env` of the config files (see [`crate::config`]) are
+//! expanded, so that a dependency's manifest can't send, say, a token to a host of its choosing.
+//! Anything else, unset variables included, is an error. `--frozen` turns expanding off
+//! altogether, since then the manifests have to say exactly what gets built.
+//!
+//! Urls are expanded only when they're used, see [`crate::descriptor::Descriptor::expanded`]. The
+//! descriptor, and so the lockfile, keep `${env:VAR}`, so that they're the same in every
+//! environment and don't spell out what the variables hold. Local paths are expanded when the
+//! manifest is read, since they're relative to it, and never make it into the lockfile.
+
+use std::sync::OnceLock;
+
+/// From the config files and the command line, see [`configure`].
+#[derive(Clone, Debug, Default)]
+struct Settings {
+    allowed: Vec<String>,
+    frozen: bool,
+}
+
+static CONFIGURED: OnceLock<Settings> = OnceLock::new();
+
+/// Expand the variables in `allowed` from now on, or none at all if `frozen`. Only the first
+/// call counts. Without one, nothing is expanded.
+pub fn configure(allowed: &[String], frozen: bool) {
+    let _ = CONFIGURED.set(Settings { allowed: allowed.to_vec(), frozen });
+}
+
+/// Whether `name` can be an environment variable in `[manifest] env`.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return Err(format!("`{}` is not an environment variable name", name)),
+    }
+    match chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        true => Ok(()),
+        false => Err(format!("`{}` is not an environment variable name", name)),
+    }
+}
+
+/// Whether every `${...}` in `s` is an `${env:VAR}`, without expanding anything. Says what's
+/// wrong otherwise.
+pub fn check(s: &str) -> Result<(), String> {
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let (reference, _) = reference(s, &rest[start..])?;
+        rest = &rest[start + reference.len()..];
+    }
+    Ok(())
+}
+
+/// The `${env:VAR}` `rest` starts with, and `VAR`. `s` is all of it, for the error.
+fn reference<'a>(s: &str, rest: &'a str) -> Result<(&'a str, &'a str), String> {
+    let Some(end) = rest.find('}') else {
+        return Err(format!("`{}` has a `${{` without a `}}`", s));
+    };
+    let reference = &rest[..end + 1];
+    match reference[2..end].strip_prefix("env:") {
+        Some(name) => Ok((reference, name)),
+        None => Err(format!("`{}` isn't expanded, only `${{env:VAR}}` is", reference)),
+    }
+}
+
+/// `s` with each `${env:VAR}` replaced by the value of `VAR`. Says what's wrong otherwise.
+pub fn expand(s: &str) -> Result<String, String> {
+    let settings = CONFIGURED.get().cloned().unwrap_or_default();
+    let mut expanded = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let (reference, name) = reference(s, &rest[start..])?;
+        if settings.frozen {
+            return Err(format!("`{}` isn't expanded with `--frozen`", reference));
+        }
+        if !settings.allowed.iter().any(|allowed| allowed == name) {
+            return Err(format!("`{}` isn't expanded, since `{}` isn't in `[manifest] env` of the config files", reference, name));
+        }
+        match std::env::var(name) {
+            Ok(value) => expanded.push_str(&value),
+            Err(_) => return Err(format!("`{}` can't be expanded, since `{}` isn't set", reference, name)),
+        }
+        rest = &rest[start + reference.len()..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_names() {
+        assert!(check_name("CORP_GIT_HOST").is_ok());
+        assert!(check_name("_x1").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("1X").is_err());
+        assert!(check_name("A-B").is_err());
+    }
+
+    #[test]
+    fn expands_allowed_variables_only() {
+        // The only test to configure, since only the first call counts.
+        configure(&["LAIR_TEST_HOST".to_owned(), "LAIR_TEST_UNSET".to_owned()], false);
+        std::env::set_var("LAIR_TEST_HOST", "git.example.com");
+        std::env::set_var("LAIR_TEST_OTHER", "elsewhere");
+        std::env::remove_var("LAIR_TEST_UNSET");
+
+        assert_eq!(expand("no variables").unwrap(), "no variables");
+        assert_eq!(expand("https://${env:LAIR_TEST_HOST}/a/${env:LAIR_TEST_HOST}").unwrap(),
+            "https://git.example.com/a/git.example.com");
+        assert!(expand("${env:LAIR_TEST_OTHER}").unwrap_err().contains("isn't in `[manifest] env`"));
+        assert!(expand("${env:LAIR_TEST_UNSET}").unwrap_err().contains("isn't set"));
+        assert!(expand("${home}").unwrap_err().contains("only `${env:VAR}`"));
+        assert!(expand("${env:LAIR_TEST_HOST").unwrap_err().contains("without a `}`"));
+    }
+
+    #[test]
+    fn checks_without_expanding() {
+        assert!(check("https://${env:ANYTHING}/a/${env:ELSE}").is_ok());
+        assert!(check("no variables").is_ok());
+        assert!(check("${home}").is_err());
+        assert!(check("a ${env:X").is_err());
+    }
+}
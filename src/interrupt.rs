@@ -0,0 +1,116 @@
+//! Ctrl-C while building or fetching.
+//!
+//! The first one asks Lair to stop: no new tasks are started (see [`crate::scheduler`]), running
+//! builds have idris2 killed, and git downloads are abandoned. Whatever finished is written down
+//! as usual, build logs, fingerprints, `Egg.lock` and timings included, so that the next run picks
+//! up where this one stopped. The second one exits right away, after saving what [`on_force_exit`]
+//! was asked to, and removing Lair's temporary directories.
+//!
+//! While a child owns the terminal, say the idris2 REPL or `lair run`, Ctrl-C is left to it, see
+//! [`hand_over`].
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once, OnceLock};
+
+use tokio::sync::Notify;
+
+use crate::error::exit_codes;
+
+static INSTALLED: Once = Once::new();
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static HANDED_OVER: AtomicUsize = AtomicUsize::new(0);
+static NOTIFY: OnceLock<Notify> = OnceLock::new();
+static FORCE_EXIT_HOOKS: Mutex<Vec<Box<dyn Fn() + Send + Sync>>> = Mutex::new(Vec::new());
+
+/// What running tasks fail with once interrupted.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Interrupted.")]
+pub struct Interrupted;
+
+/// Start listening for Ctrl-C. Only the first call does anything. Needs a tokio runtime.
+pub fn install() {
+    INSTALLED.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if HANDED_OVER.load(Ordering::SeqCst) > 0 {
+                    continue;
+                }
+                if REQUESTED.swap(true, Ordering::SeqCst) {
+                    force_exit();
+                }
+                notify().notify_waiters();
+                eprintln!("{:>12} after what's running. Press Ctrl-C again to stop right away.", "Stopping");
+            }
+        });
+    });
+}
+
+fn notify() -> &'static Notify {
+    NOTIFY.get_or_init(Notify::new)
+}
+
+/// Whether Ctrl-C was pressed.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Wait until Ctrl-C is pressed, or return right away if it was already.
+pub async fn wait() {
+    let notified = notify().notified();
+    if requested() {
+        return;
+    }
+    notified.await;
+}
+
+/// Run `hook` before exiting on the second Ctrl-C, to save whatever would otherwise be lost.
+/// Hooks run on the signal handling task, so they shouldn't block for long.
+pub fn on_force_exit(hook: impl Fn() + Send + Sync + 'static) {
+    FORCE_EXIT_HOOKS.lock().unwrap().push(Box::new(hook));
+}
+
+/// Leave Ctrl-C to a child process owning the terminal until the returned guard is dropped.
+pub fn hand_over() -> HandedOver {
+    HANDED_OVER.fetch_add(1, Ordering::SeqCst);
+    HandedOver(())
+}
+
+/// See [`hand_over`].
+#[derive(Debug)]
+pub struct HandedOver(());
+
+impl Drop for HandedOver {
+    fn drop(&mut self) {
+        HANDED_OVER.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn force_exit() -> ! {
+    eprintln!("{:>12} right away.", "Stopping");
+    // A hook panicking, say on a poisoned lock, mustn't keep us from exiting.
+    if let Ok(hooks) = FORCE_EXIT_HOOKS.try_lock() {
+        for hook in hooks.iter() {
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(hook));
+        }
+    }
+    remove_temp_dirs();
+    std::process::exit(exit_codes::INTERRUPTED.into())
+}
+
+/// Those of this process: checkouts [`crate::fetch`] looks into, signatures being checked (see
+/// [`crate::signature`]) and jobs of [`crate::remote_exec`] workers.
+fn remove_temp_dirs() {
+    let pid = std::process::id();
+    let prefixes = [format!("lair-{}-", pid), format!("lair-job-{}-", pid)];
+    let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if prefixes.iter().any(|prefix| name.starts_with(prefix.as_str())) {
+            let path = entry.path();
+            let _ = match path.is_dir() {
+                true => std::fs::remove_dir_all(&path),
+                false => std::fs::remove_file(&path),
+            };
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Reading the bits of idris2's own `.ipkg` package files we understand, as a fallback for
+//! packages which don't have an `Egg.toml`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Packages shipped with idris2, which don't need to be fetched.
+pub const BUILTIN_PACKAGES: &[&str] = &["prelude", "base", "contrib", "linear", "network", "test", "papers"];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ipkg {
+    /// From the `package CoolCollections` line.
+    pub name: String,
+
+    /// For example `0.1.0`, if there is a `version` field.
+    pub version: Option<String>,
+
+    /// Package names from the `depends` field, without version constraints.
+    pub depends: Vec<String>,
+
+    /// The version constraints from the `depends` field, as written, by package. For example
+    /// `>= 0.5 && < 0.7` for `contrib >= 0.5 && < 0.7`.
+    pub constraints: BTreeMap<String, String>,
+
+    /// All other `key = value` fields, unparsed.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Ipkg {
+    /// Parse an ipkg file. Returns `None` if there is no `package` line.
+    ///
+    /// Values may span multiple lines, as long as the continuation lines are indented or start
+    /// with a comma, like
+    /// ```text
+    /// depends = base
+    ///         , contrib
+    /// ```
+    pub fn parse(s: &str) -> Option<Ipkg> {
+        let mut name = None;
+        let mut fields: Vec<(String, String)> = Vec::new();
+
+        for line in strip_comments(s).lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let continuation = line.starts_with(char::is_whitespace) || line.starts_with(',');
+            match fields.last_mut() {
+                Some((_, value)) if continuation => {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                },
+                _ => {
+                    if let Some(rest) = line.strip_prefix("package ") {
+                        name = Some(rest.trim().to_owned());
+                    } else if let Some((key, value)) = line.split_once('=') {
+                        fields.push((key.trim().to_owned(), value.trim().to_owned()));
+                    }
+                },
+            }
+        }
+
+        let mut fields: BTreeMap<String, String> = fields.into_iter().collect();
+        let version = fields.remove("version");
+        let mut depends = Vec::new();
+        let mut constraints = BTreeMap::new();
+        for dep in fields.remove("depends").iter().flat_map(|depends| depends.split(',')) {
+            // `contrib >= 0.5` --> `contrib`
+            let Some(name) = dep.split_whitespace().next() else { continue };
+            let constraint = dep.trim()[name.len()..].trim();
+            if !constraint.is_empty() {
+                constraints.insert(name.to_owned(), constraint.to_owned());
+            }
+            depends.push(name.to_owned());
+        }
+
+        Some(Ipkg {
+            name: name?,
+            version,
+            depends,
+            constraints,
+            fields,
+        })
+    }
+
+    /// Dependencies which don't come with idris2 itself.
+    pub fn non_builtin_depends(&self) -> Vec<&str> {
+        self.depends.iter()
+            .map(String::as_str)
+            .filter(|dep| !BUILTIN_PACKAGES.contains(dep))
+            .collect()
+    }
+}
+
+/// Find an `.ipkg` file directly inside `dir`. If there are several, the alphabetically first wins.
+pub fn find(dir: &Path) -> std::io::Result<Option<PathBuf>> {
+    let mut ipkgs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "ipkg") && path.is_file() {
+            ipkgs.push(path);
+        }
+    }
+    ipkgs.sort();
+    Ok(ipkgs.into_iter().next())
+}
+
+/// Remove `-- line comments` and `{- block comments -}`, but not inside `"strings"` (think
+/// `opts = "--total"`).
+fn strip_comments(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('"') {
+            let end = after.find('"').map_or(after.len(), |i| i + 1);
+            out.push('"');
+            out.push_str(&after[..end]);
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix("{-") {
+            rest = after.split_once("-}").map_or("", |(_, after)| after);
+        } else if rest.starts_with("--") {
+            rest = rest.find('\n').map_or("", |i| &rest[i..]);
+        } else {
+            let c = rest.chars().next().unwrap();
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fields_and_depends() {
+        let ipkg = Ipkg::parse("\
+-- A comment
+package CoolCollections
+version = 0.1.0
+authors = \"Someone\"
+opts = \"--total\" -- not part of opts
+{- a block
+   comment = here -}
+depends = base
+        , contrib >= 0.5 && < 0.7
+        , NotJson
+main = Main
+").unwrap();
+        assert_eq!(ipkg.name, "CoolCollections");
+        assert_eq!(ipkg.version.as_deref(), Some("0.1.0"));
+        assert_eq!(ipkg.depends, ["base", "contrib", "NotJson"]);
+        assert_eq!(ipkg.constraints.len(), 1);
+        assert_eq!(ipkg.constraints["contrib"], ">= 0.5 && < 0.7");
+        assert_eq!(ipkg.fields["opts"], "\"--total\"");
+        assert_eq!(ipkg.fields["authors"], "\"Someone\"");
+        assert_eq!(ipkg.fields["main"], "Main");
+        assert!(!ipkg.fields.contains_key("comment"));
+        assert_eq!(ipkg.non_builtin_depends(), ["NotJson"]);
+    }
+
+    #[test]
+    fn needs_a_package_line() {
+        assert_eq!(Ipkg::parse("version = 0.1.0\n"), None);
+        assert_eq!(Ipkg::parse("-- package Commented\n"), None);
+        assert_eq!(Ipkg::parse("package Bare\n").unwrap(), Ipkg { name: "Bare".to_owned(), ..Ipkg::default() });
+    }
+}
@@ -0,0 +1,186 @@
+//! Where things live on disk, relative to the root package.
+//!
+//! ```text
+//! build/
+//!     .lair/                  -- Lair's own bookkeeping, like the graph snapshot.
+//!     deps/
+//!         NotJson-3f2a9c01/   -- Source of a dependency, keyed by name and descriptor hash.
+//!             build/idris2-0.6.0/ttc/
+//!                             -- Its TTC files, as built by idris2 0.6.0.
+//!         Monorepo-8b1d2e4c/  -- Checkout shared by all packages in subdirectories of a repository.
+//!             Lib/build/idris2-0.6.0/ttc/
+//!                             -- TTC files of the package in `Lib`.
+//!     exec/                   -- Programs compiled from the root package.
+//!     idris2-0.6.0/
+//!         idris2-packages/
+//!             NotJson-0.2.0/  -- TTC files of a dependency, installed the way idris2 >= 0.5 wants.
+//!         ttc/                -- TTC files of the root package.
+//!     logs/
+//!         NotJson.log         -- Full idris2 output of the last build of a package.
+//! ```
+//!
+//! Build artifacts are kept apart by [`Toolchain`](crate::compiler::Toolchain), since TTC files written by one build of
+//! idris2 can't be loaded by another. Switching between idris2 versions then doesn't need to
+//! rebuild what was already built with either.
+//!
+//! Things shared between projects, like registry indexes, live in the user's cache directory
+//! instead, see [`user_cache_dir`].
+//!
+//! Dependency directories contain a hash of the whole descriptor (for git dependencies, of the url
+//! and revision), so that two descriptors with the same package name (say, a diamond dependency
+//! pinned to two different revisions) don't overwrite each other.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::descriptor::Descriptor;
+use crate::fetch;
+
+/// `build`, the root package's build directory.
+pub fn build_dir() -> PathBuf {
+    PathBuf::from("build")
+}
+
+/// `build/deps`, where dependencies are downloaded to.
+pub fn deps_dir() -> PathBuf {
+    build_dir().join("deps")
+}
+
+/// `build/.lair`, for Lair's own bookkeeping files.
+pub fn lair_dir() -> PathBuf {
+    build_dir().join(".lair")
+}
+
+/// For example `{base_path}/build/idris2-0.6.0`, where idris2 builds the package in `base_path`
+/// with `toolchain`. See [`Toolchain::dir_name`](crate::compiler::Toolchain::dir_name).
+pub fn toolchain_build_dir(base_path: &Path, toolchain: &str) -> PathBuf {
+    base_path.join("build").join(toolchain)
+}
+
+/// For example `{base_path}/build/idris2-0.6.0/ttc`, the package's TTC files.
+pub fn ttc_dir(base_path: &Path, toolchain: &str) -> PathBuf {
+    toolchain_build_dir(base_path, toolchain).join("ttc")
+}
+
+/// `build/exec`, where programs compiled from the root package go.
+pub fn exec_dir() -> PathBuf {
+    build_dir().join("exec")
+}
+
+/// For example `build/idris2-0.6.0/idris2-packages`, where dependencies are installed for
+/// [`DepsMode::PackagePath`](crate::compiler::DepsMode::PackagePath).
+pub fn packages_dir(toolchain: &str) -> PathBuf {
+    build_dir().join(toolchain).join("idris2-packages")
+}
+
+/// For example `build/idris2-0.6.0/idris2-packages/NotJson-0.2.0`. Uses the actual package name,
+/// not the alias, since that's what `-p` refers to.
+pub fn installed_package_dir(desc: &Descriptor, version: &str, toolchain: &str) -> PathBuf {
+    packages_dir(toolchain).join(format!("{}-{}", desc.package_name(), version))
+}
+
+/// `build/logs`, where the output of each package's build goes.
+pub fn logs_dir() -> PathBuf {
+    build_dir().join("logs")
+}
+
+/// For example `build/logs/NotJson.log`.
+pub fn log_path(desc: &Descriptor) -> PathBuf {
+    logs_dir().join(format!("{}.log", desc.name()))
+}
+
+/// Where the source of a dependency goes, for example `build/deps/NotJson-3f2a9c01`.
+///
+/// Git dependencies on the same repository and revision share one checkout, even if they are
+/// different packages in different subdirectories of it. So their directory is named after the
+/// repository, and keyed by its canonical url and the revision only.
+pub fn source_dir(desc: &Descriptor) -> PathBuf {
+    match desc {
+        Descriptor::Git { url, version, .. } => {
+            let url = fetch::canonical_url(url);
+            let hash = short_hash_of(&(&url, version));
+            deps_dir().join(format!("{}-{}", fetch::repo_name(&url), hash))
+        },
+        _ => deps_dir().join(format!("{}-{}", desc.name(), short_hash(desc))),
+    }
+}
+
+/// Lair's cache shared by all projects of this user: `$LAIR_HOME` if set, otherwise
+/// `$XDG_CACHE_HOME/lair` or `~/.cache/lair`, or on Windows `%LOCALAPPDATA%\lair`.
+pub fn user_cache_dir() -> PathBuf {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if let Some(home) = var("LAIR_HOME") {
+        home
+    } else if let Some(local) = var("LOCALAPPDATA").filter(|_| cfg!(target_os = "windows")) {
+        local.join("lair")
+    } else if let Some(cache) = var("XDG_CACHE_HOME") {
+        cache.join("lair")
+    } else if let Some(home) = var("HOME") {
+        home.join(".cache").join("lair")
+    } else {
+        std::env::temp_dir().join("lair")
+    }
+}
+
+/// Where the user's own settings live, see [`crate::config`]: `$XDG_CONFIG_HOME/lair` or
+/// `~/.config/lair`, or on Windows `%APPDATA%\lair`. `None` if we can't tell where home is.
+pub fn user_config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if let Some(app_data) = var("APPDATA").filter(|_| cfg!(target_os = "windows")) {
+        Some(app_data.join("lair"))
+    } else if let Some(config) = var("XDG_CONFIG_HOME") {
+        Some(config.join("lair"))
+    } else {
+        var("HOME").map(|home| home.join(".config").join("lair"))
+    }
+}
+
+/// Lair's own files for this user which aren't a cache, so `lair clean --cache` leaves them alone:
+/// `~/.lair`, or on Windows `%USERPROFILE%\.lair`. In the cache directory if we can't tell where
+/// home is.
+pub fn user_data_dir() -> PathBuf {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let home = if cfg!(target_os = "windows") { var("USERPROFILE") } else { var("HOME") };
+    home.map_or_else(user_cache_dir, |home| home.join(".lair"))
+}
+
+/// Our clone of a registry index, for example `~/.cache/lair/registry/lair-index-3f2a9c01`.
+pub fn registry_dir(url: &str) -> PathBuf {
+    let url = fetch::canonical_url(url);
+    user_cache_dir().join("registry").join(format!("{}-{}", fetch::repo_name(&url), short_hash_of(&url)))
+}
+
+/// Where the package itself is once fetched: the checkout or unpacked archive (or their `subdir`),
+/// or the local path. `None` for the root package.
+pub fn package_dir(desc: &Descriptor) -> Option<PathBuf> {
+    match desc {
+        Descriptor::Root { .. } => None,
+        Descriptor::Git { subdir: Some(subdir), .. } | Descriptor::Archive { subdir: Some(subdir), .. } =>
+            Some(source_dir(desc).join(subdir)),
+        Descriptor::Git { subdir: None, .. } | Descriptor::Archive { subdir: None, .. } | Descriptor::Registry { .. } =>
+            Some(source_dir(desc)),
+        Descriptor::Local { path, .. } => Some(path.clone()),
+    }
+}
+
+/// How far up a package's workspace is looked for: no further than what was downloaded. `None`
+/// for the root and local packages, which are looked for up to the root of their repository.
+pub fn workspace_boundary(desc: &Descriptor) -> Option<PathBuf> {
+    match desc {
+        Descriptor::Git { .. } | Descriptor::Archive { .. } | Descriptor::Registry { .. } => Some(source_dir(desc)),
+        Descriptor::Root { .. } | Descriptor::Local { .. } => None,
+    }
+}
+
+/// First 8 hex digits of the sha256 of the descriptor. Stable across Lair versions, as long as
+/// the descriptor's serialized form doesn't change.
+pub fn short_hash(desc: &Descriptor) -> String {
+    short_hash_of(desc)
+}
+
+fn short_hash_of(value: &impl Serialize) -> String {
+    let serialized = serde_json::to_vec(value).expect("Descriptors are always serializable.");
+    Sha256::digest(&serialized).iter().take(4).map(|b| format!("{:02x}", b)).collect()
+}
@@ -14,26 +14,44 @@
 //! So we will maintain a "Descriptor --> Lazy<Source path>" mapping instead, and insert the lazy
 //! object immediately, but when users `get()` it, it will block (well, asynchronously block, but
 //! whateevr) until it is done downloading.
+//!
+//! A result is computed once, and then kept, errors included, so that everybody waiting for a
+//! failed download gets the same error without downloading again. Except that some errors, like a
+//! network blip, are worth another try, see [`Lazy::new_weak_retrying`].
+//!
+//! The result is handed out by reference, so that it doesn't need to be `Clone`, and isn't copied
+//! for every caller. Share owned values through an `Arc` inside.
 
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use either::Either;
 use futures::Future;
 use futures::future::BoxFuture;
 use tokio::sync::Mutex;
 
+/// How long to wait before the first retry, doubled for each one after that.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
 /// See the module-level docs.
 pub struct Lazy<T> {
-    inner: Mutex<Either<
-        T,
-        BoxFuture<'static, T>
-    >>,
+    value: OnceLock<T>,
+    /// How to get the value, until we have it. Whoever holds the lock is computing it.
+    recipe: Mutex<Option<BoxFuture<'static, T>>>,
+    retry: Option<Retry<T>>,
+}
+
+/// When to run a [`Lazy`]'s recipe again, see [`Lazy::new_weak_retrying`].
+struct Retry<T> {
+    attempts: u32,
+    retryable: fn(&T) -> bool,
+    /// Gets the result of the try before.
+    recipe: Box<dyn Fn(T) -> BoxFuture<'static, T> + Send + Sync>,
 }
 
 impl<T: Debug> Debug for Lazy<T> {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lazy").field("value", &self.value.get()).finish_non_exhaustive()
     }
 }
 
@@ -43,7 +61,9 @@ impl<T> Lazy<T> {
         F: Future<Output = T> + Send + 'static,
     {
         Self {
-            inner: Mutex::new(Either::Right(Box::pin(recipe))),
+            value: OnceLock::new(),
+            recipe: Mutex::new(Some(Box::pin(recipe))),
+            retry: None,
         }
     }
 
@@ -66,44 +86,99 @@ impl<T> Lazy<T> {
         };
 
         Self {
-            inner: Mutex::new(Either::Right(Box::pin(fut))),
+            value: OnceLock::new(),
+            recipe: Mutex::new(Some(Box::pin(fut))),
+            retry: None,
+        }
+    }
+
+    /// Like [`Lazy::new_weak`], but when the result is `retryable`, runs `recipe` again, up to
+    /// `attempts` more times, waiting a little longer before each. Only the last result is kept.
+    /// `recipe` gets the result of the try before it, or `None` on the first try.
+    pub fn new_weak_retrying<S, Fut, F>(arc: &Arc<S>, attempts: u32, retryable: fn(&T) -> bool, recipe: F) -> Self
+    where
+        T: Send + 'static,
+        S: Send + Sync + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+        F: (Fn(Arc<S>, Option<T>) -> Fut) + Send + Sync + 'static,
+    {
+        let weak = Arc::downgrade(arc);
+        let recipe = Arc::new(recipe);
+        let run = move |previous: Option<T>| -> BoxFuture<'static, T> {
+            let (weak, recipe) = (weak.clone(), recipe.clone());
+            Box::pin(async move {
+                let arc = weak.upgrade().expect("Failed to upgrade weak Arc.");
+                recipe(arc, previous).await
+            })
+        };
+
+        Self {
+            value: OnceLock::new(),
+            recipe: Mutex::new(Some(run(None))),
+            retry: Some(Retry { attempts, retryable, recipe: Box::new(move |previous| run(Some(previous))) }),
         }
     }
 
     pub fn new_immediate(val: T) -> Self {
         Self {
-            inner: Mutex::new(Either::Left(val))
+            value: OnceLock::from(val),
+            recipe: Mutex::new(None),
+            retry: None,
         }
     }
 
-    pub async fn get(&self) -> T
-        where T: Clone
-    {
-        let mut guard = self.inner.lock().await;
-        match &mut *guard {
-            Either::Left(result) => {
-                // result is already there, nice!
-                result.clone()
-            },
-            Either::Right(future) => {
-                // result is not yet there, but also since we got the lock, it means we're the first.
-                // so let's get it!
-                let result = future.await;
-                *guard = Either::Left(result.clone());
-                result
-            },
+    pub async fn get(&self) -> &T {
+        if let Some(result) = self.value.get() {
+            // result is already there, nice!
+            return result;
+        }
+        let mut guard = self.recipe.lock().await;
+        // Whoever had the lock before us may have finished it.
+        if let Some(result) = self.value.get() {
+            return result;
+        }
+        // result is not yet there, but also since we got the lock, it means we're the first.
+        // so let's get it!
+        let future = guard.as_mut().expect("A Lazy without value always has its recipe.");
+        let mut result = future.await;
+        if let Some(retry) = &self.retry {
+            let mut attempt = 0;
+            while attempt < retry.attempts && (retry.retryable)(&result) {
+                tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt)).await;
+                attempt += 1;
+                result = (retry.recipe)(result).await;
+            }
         }
+        *guard = None;
+        let _ = self.value.set(result);
+        self.value.get().expect("Just set it.")
     }
 
-    // pub async fn probe_progress(&self) -> Progress {
-    // 	if let Some(x) = self.inner.try
-    // }
+    /// The value, if it is already there. `None` if nobody asked for it yet, or it is still
+    /// being computed.
+    pub fn peek(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// How far along the value is, without waiting for it or starting it.
+    pub fn probe_progress(&self) -> Progress {
+        if self.value.get().is_some() {
+            return Progress::Done;
+        }
+        match self.recipe.try_lock() {
+            Ok(_) => Progress::NotStarted,
+            // Whoever holds the lock is computing it.
+            Err(_) => Progress::Working,
+        }
+    }
 }
 
-// pub enum Progress {
-// 	NotStarted,
-// 	Working,
-// 	Done,
-// }
+/// See [`Lazy::probe_progress`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    NotStarted,
+    Working,
+    Done,
+}
 
 
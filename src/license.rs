@@ -0,0 +1,96 @@
+//! Figuring out the licenses of packages, for `lair license`.
+//!
+//! The `license` field of the manifest is authoritative. Packages which don't have one (for
+//! example ipkg-only packages) may still ship a `LICENSE` file, which we try to recognize by a
+//! few telltale phrases. That's a best guess, not legal advice.
+
+use std::path::{Path, PathBuf};
+
+use crate::descriptor::Descriptor;
+
+/// File names we look at, in this order.
+const LICENSE_FILES: &[&str] = &[
+    "LICENSE", "LICENSE.md", "LICENSE.txt", "LICENCE", "LICENCE.md", "LICENCE.txt", "COPYING", "COPYING.md", "COPYING.txt",
+];
+
+/// Where a license came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LicenseOrigin {
+    /// The `license` field of the manifest.
+    Manifest,
+    /// Recognized from the contents of this file.
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct PackageLicense {
+    pub descriptor: Descriptor,
+    /// SPDX license expression, `None` if we couldn't find out.
+    pub license: Option<String>,
+    pub origin: Option<LicenseOrigin>,
+}
+
+/// All license files in `dir`, for shipping them along with a bundle.
+pub fn files(dir: &Path) -> Vec<PathBuf> {
+    LICENSE_FILES.iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Look for a license file in `dir`, and guess its SPDX identifier.
+pub fn detect(dir: &Path) -> Option<(String, PathBuf)> {
+    LICENSE_FILES.iter()
+        .map(|name| dir.join(name))
+        .filter_map(|path| Some((std::fs::read_to_string(&path).ok()?, path)))
+        .find_map(|(text, path)| Some((recognize(&text)?.to_owned(), path)))
+}
+
+/// Guess the SPDX identifier of a license text.
+fn recognize(text: &str) -> Option<&'static str> {
+    // Line breaks and indentation differ wildly between copies of the same license.
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let has = |phrase: &str| text.contains(phrase);
+
+    let id = if has("gnu affero general public license") {
+        "AGPL-3.0"
+    } else if has("gnu lesser general public license") {
+        if has("version 3") { "LGPL-3.0" } else { "LGPL-2.1" }
+    } else if has("gnu general public license") {
+        if has("version 3") { "GPL-3.0" } else { "GPL-2.0" }
+    } else if has("mozilla public license version 2.0") {
+        "MPL-2.0"
+    } else if has("apache license") && has("version 2.0") {
+        "Apache-2.0"
+    } else if has("permission is hereby granted, free of charge") {
+        "MIT"
+    } else if has("permission to use, copy, modify, and/or distribute this software for any purpose") {
+        "ISC"
+    } else if has("redistribution and use in source and binary forms") {
+        if has("neither the name") { "BSD-3-Clause" } else { "BSD-2-Clause" }
+    } else if has("this is free and unencumbered software released into the public domain") {
+        "Unlicense"
+    } else {
+        return None;
+    };
+    Some(id)
+}
+
+/// Does `expression` force us to accept one of the `denied` licenses? `MIT OR GPL-3.0` doesn't,
+/// since we can pick MIT, but `MIT AND GPL-3.0` does.
+///
+/// Denying `GPL-3.0` also denies `GPL-3.0-only` and `GPL-3.0-or-later`.
+pub fn is_denied(expression: &str, denied: &[String]) -> bool {
+    let matches = |id: &str| denied.iter().any(|deny| {
+        id.eq_ignore_ascii_case(deny) || id.to_lowercase().starts_with(&format!("{}-", deny.to_lowercase()))
+    });
+
+    match spdx::Expression::parse(expression) {
+        Ok(expression) => !expression.evaluate(|req| match req.license.id() {
+            Some(id) => !matches(id.name),
+            None => true,
+        }),
+        // Not SPDX (ipkgs can say anything), so the best we can do is compare it as a whole.
+        Err(_) => matches(expression.trim()),
+    }
+}
@@ -0,0 +1,478 @@
+//! `Egg.lock`: the exact commit every git dependency resolved to, so that builds are
+//! reproducible, and branches only move when asked to (`lair lock --update <package>`).
+//!
+//! ```toml
+//! [[package]]
+//! name = "CoolCollections"
+//! git = "https://github.com/Kiiyya/CoolCollections"
+//! branch = "main"
+//! commit = "3f2a9c01d5e8..."
+//! ```
+//!
+//! Dependencies without `branch`, `tag` or `rev` follow the repository's default branch, and record
+//! which one that turned out to be as `default-branch = "master"` instead of `branch`.
+//!
+//! Registry dependencies also record which version they resolved to, and where that came from:
+//!
+//! ```toml
+//! [[package]]
+//! name = "NotJson"
+//! registry = "https://github.com/Kiiyya/lair-index"
+//! requirement = "0.2"
+//! version = "0.2.1"
+//! git = "https://github.com/Kiiyya/NotJson"
+//! commit = "8b1d2e4c90aa..."
+//! ```
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::descriptor::{Descriptor, GitVersion};
+use crate::fetch;
+use crate::graph::Graph;
+use crate::layout;
+use crate::manifest::Manifest;
+use crate::modules::{self, Change};
+use crate::package_name::PackageName;
+use crate::prompt::{self, Prompt};
+use crate::registry::{Index, RegistryVersion};
+
+pub const LOCKFILE_NAME: &str = "Egg.lock";
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub name: PackageName,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<PackageName>,
+    /// Index url, for registry dependencies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// Which versions the manifest allows, for registry and `tag-prefix` dependencies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirement: Option<String>,
+    /// The version picked from the registry, or from the tags with the `tag-prefix`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub git: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    #[serde(default, rename = "tag-prefix", skip_serializing_if = "Option::is_none")]
+    pub tag_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<PathBuf>,
+    /// Which branch the default branch was, for git dependencies with neither `branch`, `tag` nor
+    /// `rev`. Only informative, the commit is what counts.
+    #[serde(default, rename = "default-branch", skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+
+    /// What the descriptor resolved to, full hex hash.
+    pub commit: String,
+}
+
+impl LockedPackage {
+    /// `None` for anything but git dependencies, which don't need locking.
+    pub fn new(desc: &Descriptor, commit: String) -> Option<Self> {
+        match desc {
+            Descriptor::Git { name, package, url, version, subdir } => {
+                let (branch, tag, rev) = match version {
+                    GitVersion::Branch(branch) => (Some(branch.clone()), None, None),
+                    GitVersion::Tag(tag) => (None, Some(tag.clone()), None),
+                    GitVersion::Rev(rev) => (None, None, Some(rev.clone())),
+                    GitVersion::DefaultBranch | GitVersion::TagPrefix { .. } => (None, None, None),
+                };
+                let (tag_prefix, requirement) = match version {
+                    GitVersion::TagPrefix { prefix, requirement } => (Some(prefix.clone()), Some(requirement.clone())),
+                    _ => (None, None),
+                };
+                Some(Self {
+                    name: name.clone(),
+                    package: package.clone(),
+                    registry: None,
+                    requirement,
+                    version: None,
+                    git: url.clone(),
+                    branch,
+                    tag,
+                    rev,
+                    tag_prefix,
+                    subdir: subdir.clone(),
+                    default_branch: None,
+                    commit,
+                })
+            },
+            _ => None,
+        }
+    }
+
+    /// Lock a registry dependency to one of its versions. `None` for anything but registry
+    /// dependencies.
+    pub fn from_registry(desc: &Descriptor, version: &RegistryVersion) -> Option<Self> {
+        match desc {
+            Descriptor::Registry { name, package, registry, version: requirement } => Some(Self {
+                name: name.clone(),
+                package: package.clone(),
+                registry: Some(registry.clone()),
+                requirement: Some(requirement.clone()),
+                version: Some(version.version.clone()),
+                git: version.git.clone(),
+                branch: None,
+                tag: None,
+                rev: None,
+                tag_prefix: None,
+                subdir: None,
+                default_branch: None,
+                commit: version.rev.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The registry version this entry locks, for registry dependencies. `yanked` is unknown, so
+    /// always `false`.
+    pub fn registry_version(&self) -> Option<RegistryVersion> {
+        self.registry.as_ref()?;
+        Some(RegistryVersion {
+            version: self.version.clone()?,
+            git: self.git.clone(),
+            rev: self.commit.clone(),
+            yanked: false,
+        })
+    }
+
+    /// The descriptor this entry locks.
+    pub fn descriptor(&self) -> Option<Descriptor> {
+        if let (Some(registry), Some(requirement)) = (&self.registry, &self.requirement) {
+            return Some(Descriptor::Registry {
+                name: self.name.clone(),
+                package: self.package.clone(),
+                registry: registry.clone(),
+                version: requirement.clone(),
+            });
+        }
+        let version = match (&self.branch, &self.tag, &self.rev, &self.tag_prefix) {
+            (Some(branch), None, None, None) => GitVersion::Branch(branch.clone()),
+            (None, Some(tag), None, None) => GitVersion::Tag(tag.clone()),
+            (None, None, Some(rev), None) => GitVersion::Rev(rev.clone()),
+            (None, None, None, Some(prefix)) => GitVersion::TagPrefix {
+                prefix: prefix.clone(),
+                requirement: self.requirement.clone().unwrap_or_else(|| "*".to_owned()),
+            },
+            (None, None, None, None) => GitVersion::DefaultBranch,
+            _ => return None,
+        };
+        Some(Descriptor::Git {
+            name: self.name.clone(),
+            package: self.package.clone(),
+            url: self.git.clone(),
+            version,
+            subdir: self.subdir.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "package")]
+    packages: Vec<LockedPackage>,
+
+    /// Changed since loading?
+    #[serde(skip)]
+    dirty: bool,
+
+    /// Where we loaded from, and will save to. `None` for lockfiles which only live in memory.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Lockfile {
+    /// Load the lockfile, or start with an empty one if there is none yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let mut lockfile: Self = match std::fs::read_to_string(path) {
+            Ok(s) => toml::from_str(&s)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        lockfile.path = Some(path.to_owned());
+        Ok(lockfile)
+    }
+
+    /// Where this lockfile is saved to, if anywhere.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Write the lockfile back to where it was loaded from, but only if anything changed.
+    pub fn save(&mut self) -> Result<(), anyhow::Error> {
+        if let (true, Some(path)) = (self.dirty, &self.path) {
+            self.packages.sort();
+            std::fs::write(path, toml::to_string(self)?)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn packages(&self) -> &[LockedPackage] {
+        &self.packages
+    }
+
+    /// The locked commit of a descriptor, if any.
+    pub fn get(&self, desc: &Descriptor) -> Option<&str> {
+        self.locked(desc).map(|locked| locked.commit.as_str())
+    }
+
+    /// The whole entry locking a descriptor, if any.
+    pub fn locked(&self, desc: &Descriptor) -> Option<&LockedPackage> {
+        self.position(desc).map(|index| &self.packages[index])
+    }
+
+    /// Index of the entry locking `desc`. Lair used to follow `main` rather than the default
+    /// branch, so for the default branch, an entry for `main` will do until it's replaced.
+    fn position(&self, desc: &Descriptor) -> Option<usize> {
+        match desc {
+            Descriptor::Git { version: GitVersion::DefaultBranch, .. } => self.exact_position(desc).or_else(|| {
+                let mut legacy = desc.clone();
+                if let Descriptor::Git { version, .. } = &mut legacy {
+                    *version = GitVersion::Branch("main".to_owned());
+                }
+                self.exact_position(&legacy)
+            }),
+            _ => self.exact_position(desc),
+        }
+    }
+
+    /// Index of the entry for exactly `desc`, without falling back to `main`.
+    fn exact_position(&self, desc: &Descriptor) -> Option<usize> {
+        self.packages.iter().position(|locked| locked.descriptor().as_ref() == Some(desc))
+    }
+
+    /// Lock `desc` to `commit`. Does nothing for non-git descriptors. A `main` entry standing in
+    /// for the default branch stays, since a dependency might still name `main`, until
+    /// [`Self::retain_graph`] drops it.
+    pub fn set(&mut self, desc: &Descriptor, commit: String) {
+        if let Some(index) = self.exact_position(desc) {
+            if self.packages[index].commit == commit {
+                return;
+            }
+            self.packages.remove(index);
+            self.dirty = true;
+        }
+        if let Some(locked) = LockedPackage::new(desc, commit) {
+            self.packages.push(locked);
+            self.dirty = true;
+        }
+    }
+
+    /// Record which version the tag `desc`, already locked to its newest tag with a `tag-prefix`,
+    /// is at.
+    pub fn set_tag_version(&mut self, desc: &Descriptor, version: String) {
+        let Some(index) = self.position(desc) else {
+            return;
+        };
+        let locked = &mut self.packages[index];
+        if locked.version.as_ref() != Some(&version) {
+            locked.version = Some(version);
+            self.dirty = true;
+        }
+    }
+
+    /// Record which branch the default branch of `desc`, already locked, turned out to be.
+    pub fn set_default_branch(&mut self, desc: &Descriptor, branch: String) {
+        let Some(index) = self.position(desc) else {
+            return;
+        };
+        let locked = &mut self.packages[index];
+        if locked.default_branch.as_ref() != Some(&branch) {
+            locked.default_branch = Some(branch);
+            self.dirty = true;
+        }
+    }
+
+    /// Lock registry dependency `desc` to `version`. Does nothing for other descriptors.
+    pub fn set_registry(&mut self, desc: &Descriptor, version: &RegistryVersion) {
+        let Some(new) = LockedPackage::from_registry(desc, version) else {
+            return;
+        };
+        if self.locked(desc) == Some(&new) {
+            return;
+        }
+        self.remove(desc);
+        self.packages.push(new);
+        self.dirty = true;
+    }
+
+    /// Returns the commit `desc` was locked to.
+    pub fn remove(&mut self, desc: &Descriptor) -> Option<String> {
+        let index = self.position(desc)?;
+        self.dirty = true;
+        Some(self.packages.remove(index).commit)
+    }
+
+    /// Drop entries for anything not in `graph` anymore, for example removed dependencies. Entries
+    /// for dependencies the graph left out for other targets stay.
+    pub fn retain_graph(&mut self, graph: &Graph) {
+        let before = self.packages.len();
+        self.packages.retain(|locked| locked.descriptor().is_some_and(|desc| graph.package(&desc).is_some() || graph.inactive.contains(&desc)));
+        self.dirty |= self.packages.len() != before;
+    }
+}
+
+/// One dependency moved to a new commit by [`update`].
+#[derive(Clone, Debug)]
+pub struct LockChange {
+    pub descriptor: Descriptor,
+    pub old: Option<String>,
+    pub new: String,
+    /// One-line summaries of the commits in between, newest first, if we could find out.
+    pub commits: Vec<String>,
+    /// Modules whose source files were added, edited or removed in between, sorted by name.
+    /// Empty if we couldn't find out.
+    pub modules: Vec<(String, Change)>,
+}
+
+/// Move the named packages and everything they (transitively) depend on to the newest commits of
+/// their branches (or tags), or for registry dependencies to the newest matching version which
+/// isn't yanked, checking them out and updating `lockfile`. Everything else stays pinned.
+/// Blocking.
+///
+/// The checkouts must already exist, so resolve `graph` first. `prompt` decides whether local
+/// changes in them may be overwritten.
+pub fn update(lockfile: &mut Lockfile, graph: &Graph, names: &[String], prompt: Prompt) -> Result<Vec<LockChange>, anyhow::Error> {
+    let mut targets = BTreeSet::new();
+    for name in names {
+        let mut found = false;
+        for package in graph.packages_named(name) {
+            found = true;
+            targets.extend(graph.closure(&package.descriptor));
+        }
+        if !found {
+            anyhow::bail!("`{}` is not part of the dependency graph.", name);
+        }
+    }
+
+    // Packages sharing a checkout (see `layout::source_dir`) can only move together.
+    let checkouts: BTreeSet<PathBuf> = targets.iter()
+        .filter(|desc| matches!(desc, Descriptor::Git { .. }))
+        .map(layout::source_dir)
+        .collect();
+    targets.extend(graph.packages.iter()
+        .filter(|package| matches!(package.descriptor, Descriptor::Git { .. }))
+        .filter(|package| checkouts.contains(&layout::source_dir(&package.descriptor)))
+        .map(|package| package.descriptor.clone()));
+
+    let mut indexes = BTreeMap::new();
+    let mut changes = Vec::new();
+    for desc in targets {
+        let path = layout::source_dir(&desc);
+        let (new, picked) = match &desc {
+            Descriptor::Git { version, .. } => {
+                fetch::fetch_origin(&path)?;
+                if *version == GitVersion::DefaultBranch {
+                    fetch::update_default_branch(&path)?;
+                }
+                (fetch::resolve_version(&path, version)?, None)
+            },
+            Descriptor::Registry { version: requirement, .. } => {
+                let Descriptor::Registry { registry, .. } = desc.expanded()? else { unreachable!() };
+                // Only fetch each index once.
+                let index = match indexes.entry(registry) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let index = Index::update(entry.key())?;
+                        entry.insert(index)
+                    },
+                };
+                let picked = index.pick(desc.package_name(), requirement)?;
+                (picked.rev.clone(), Some(picked))
+            },
+            _ => continue,
+        };
+        let old = lockfile.get(&desc).map(str::to_owned);
+        if old.as_deref() == Some(new.as_str()) {
+            continue;
+        }
+
+        prompt::allow_overwrite(prompt, desc.name(), &path, Some(&new))?;
+        fetch::checkout_fetching(&path, &new)?;
+        let (commits, modules) = match &old {
+            Some(old) => (fetch::log_between(&path, old, &new).unwrap_or_default(), changed_modules(&desc, &path, old, &new)),
+            None => (Vec::new(), Vec::new()),
+        };
+        match &picked {
+            Some(picked) => lockfile.set_registry(&desc, picked),
+            None => lockfile.set(&desc, new.clone()),
+        }
+        if matches!(&desc, Descriptor::Git { version: GitVersion::DefaultBranch, .. }) {
+            if let Ok(branch) = fetch::default_branch(&path) {
+                lockfile.set_default_branch(&desc, branch);
+            }
+        }
+        if let Descriptor::Git { version: version @ GitVersion::TagPrefix { prefix, .. }, .. } = &desc {
+            if let Some(tag) = fetch::tag_at(&path, version, &new) {
+                lockfile.set_tag_version(&desc, tag[prefix.len()..].to_owned());
+            }
+        }
+        changes.push(LockChange { descriptor: desc, old, new, commits, modules });
+    }
+    Ok(changes)
+}
+
+/// Which modules in the source directory of `desc` changed between the commits `old` and `new` of
+/// its checkout `path`, which is at `new`.
+fn changed_modules(desc: &Descriptor, path: &Path, old: &str, new: &str) -> Vec<(String, Change)> {
+    let package = match desc {
+        Descriptor::Git { subdir: Some(subdir), .. } => subdir.clone(),
+        _ => PathBuf::new(),
+    };
+    let source_dir = Manifest::from_path_within(path.join(&package).join("Egg.toml"), Some(path)).ok()
+        .and_then(|manifest| manifest.source_dir)
+        .or_else(|| modules::guess_source_dir(&path.join(&package), desc.package_name()))
+        .unwrap_or_else(|| PathBuf::from(modules::DEFAULT_SOURCE_DIR));
+    // Without any `.`, for `strip_prefix`.
+    let source_dir: PathBuf = package.join(source_dir).components().filter(|component| component != &Component::CurDir).collect();
+    let mut changes: Vec<_> = fetch::files_between(path, old, new, &source_dir).unwrap_or_default().into_iter()
+        .filter_map(|(status, file)| {
+            let module = modules::module_of(file.strip_prefix(&source_dir).ok()?)?;
+            let change = match status {
+                'A' => Change::Added,
+                'D' => Change::Removed,
+                _ => Change::Modified,
+            };
+            Some((module, change))
+        })
+        .collect();
+    changes.sort();
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::fixtures::{graph, root};
+
+    fn git(name: &str) -> Descriptor {
+        Descriptor::Git { name: name.parse().unwrap(), package: None, url: format!("https://example.com/{}", name), version: GitVersion::DefaultBranch, subdir: None }
+    }
+
+    #[test]
+    fn retains_active_and_inactive() {
+        let (a, b, removed) = (git("A"), git("B"), git("Removed"));
+        let mut lockfile = Lockfile::default();
+        for desc in [&a, &b, &removed] {
+            lockfile.set(desc, "0".repeat(40));
+        }
+        let mut graph = graph(vec![(root(), vec![a.clone()]), (a.clone(), Vec::new())]);
+        graph.inactive.insert(b.clone());
+        lockfile.retain_graph(&graph);
+        assert!(lockfile.get(&a).is_some());
+        assert!(lockfile.get(&b).is_some());
+        assert!(lockfile.get(&removed).is_none());
+    }
+}
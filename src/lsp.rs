@@ -0,0 +1,111 @@
+//! `lair lsp-config`: what an Idris2 LSP server started by an editor (rather than through
+//! `lair exec`) needs in order to see the project's dependencies.
+//!
+//! With `--write` this goes to well-known files next to the rest of the build output:
+//! - `build/lsp.json`: the [`LspConfig`] itself, for editor plugins.
+//! - `build/lsp.env`: just the environment variables, one `KEY=value` per line, for editors which
+//!   can load a dotenv file when starting a language server.
+//!
+//! and, unless the package already has one, a `{name}.ipkg` next to `Egg.toml`, since that is
+//! where idris2-lsp looks for the source directory and the packages to load. The generated ipkg
+//! starts with [`GENERATED`], and is only ever overwritten if that line is still there.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::environment::Idris2Env;
+use crate::error::BuildTtcError;
+use crate::ipkg;
+use crate::layout;
+
+/// First line of the `.ipkg` files we generate.
+pub const GENERATED: &str = "-- Generated by `lair lsp-config`. Delete this line to keep your own changes.";
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LspConfig {
+    /// Environment variables to start the server with, for example `IDRIS2_PATH`.
+    pub env: BTreeMap<String, String>,
+
+    /// Where the root package's modules are.
+    pub source_dir: PathBuf,
+
+    /// The root package's main module.
+    pub main: PathBuf,
+
+    /// Installed packages the server has to load, as with idris2's `-p`.
+    pub packages: Vec<String>,
+}
+
+impl LspConfig {
+    /// Paths are made absolute, since editors start servers from wherever they like. `root` is
+    /// the root package's directory.
+    pub fn new(env: &Idris2Env, root: &Path, source_dir: &Path, main: &Path) -> Result<Self, BuildTtcError> {
+        let absolute = |path: &Path| if path.is_absolute() { path.to_owned() } else { root.join(path) };
+        let env = Idris2Env {
+            idris2_path: env.idris2_path.iter().map(|path| absolute(path)).collect(),
+            package_path: env.package_path.iter().map(|path| absolute(path)).collect(),
+            packages: env.packages.clone(),
+        };
+        Ok(Self {
+            env: env.vars()?.into_iter()
+                .map(|(key, value)| (key.to_owned(), value.to_string_lossy().into_owned()))
+                .collect(),
+            source_dir: absolute(source_dir),
+            main: absolute(main),
+            packages: env.packages,
+        })
+    }
+
+    /// `build/lsp.env`'s contents.
+    pub fn env_file(&self) -> String {
+        self.env.iter().map(|(key, value)| format!("{}={}\n", key, value)).collect()
+    }
+
+    /// An ipkg in `root` telling idris2-lsp where package `name`'s modules are and what to load.
+    pub fn ipkg(&self, root: &Path, name: &str, version: &str) -> String {
+        let sourcedir = self.source_dir.strip_prefix(root).unwrap_or(&self.source_dir);
+        let mut ipkg = format!("{}\npackage {}\nversion = {}\nsourcedir = \"{}\"\n", GENERATED, name, version, sourcedir.display());
+        // `src/Foo/Bar.idr` --> `Foo.Bar`
+        let module = self.main.strip_prefix(&self.source_dir).ok()
+            .map(|path| path.with_extension("").components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("."));
+        if let Some(module) = module {
+            ipkg.push_str(&format!("modules = {}\n", module));
+        }
+        if !self.packages.is_empty() {
+            ipkg.push_str(&format!("depends = {}\n", self.packages.join(", ")));
+        }
+        ipkg
+    }
+
+    /// Write `build/lsp.json`, `build/lsp.env`, and unless the package has its own, `{root}/{name}.ipkg`.
+    /// Returns the paths written to.
+    pub fn write(&self, root: &Path, name: &str, version: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let build = root.join(layout::build_dir());
+        std::fs::create_dir_all(&build)?;
+        let json = build.join("lsp.json");
+        std::fs::write(&json, serde_json::to_string_pretty(self)? + "\n")?;
+        let env = build.join("lsp.env");
+        std::fs::write(&env, self.env_file())?;
+        let mut written = vec![json, env];
+
+        let ipkg_path = match ipkg::find(root)? {
+            Some(existing) if !is_generated(&existing) => None,
+            Some(existing) => Some(existing),
+            None => Some(root.join(format!("{}.ipkg", name))),
+        };
+        if let Some(path) = ipkg_path {
+            std::fs::write(&path, self.ipkg(root, name, version))?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+fn is_generated(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|s| s.lines().next() == Some(GENERATED))
+}
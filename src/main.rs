@@ -1,46 +1,250 @@
 #![feature(exit_status_error)]
 #![feature(map_try_insert)]
 #![feature(arc_new_cyclic)]
+// Kept for now, though `arc_new_cyclic` is stable, and `map_try_insert` and `exit_status_error` currently unused.
+#![allow(stable_features, unused_features)]
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::io::{IsTerminal, Read, Write};
 use std::path::PathBuf;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::process::{Command, ExitCode, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
 use std::{fs::create_dir_all, io::ErrorKind, path::Path};
 
 use anyhow::Context;
-use descriptor::Descriptor;
-use error::{ManifestFetchError, SourceFetchError, BuildTtcError};
-use futures::future::join;
+use archive::ArchiveKind;
+use cache::{Area, CacheIndex, GcPolicy};
+use compiler::{Backend, DepsMode, Idris2Version, Toolchain};
+use db::Db;
+use descriptor::{Descriptor, GitVersion, ResolvedDescriptor};
+use environment::Idris2Env;
+use graph::{Graph, GraphPackage, Snapshot};
+use fingerprint::Fingerprint;
+use fetch::DirtyDeps;
+use intern::Interner;
+use error::{ManifestFetchError, SourceFetchError, BuildTtcError, Phase, Provenance};
+use futures::future::{join, try_join_all};
 use lazy::Lazy;
+use license::{LicenseOrigin, PackageLicense};
+use lockfile::{LockChange, Lockfile, LOCKFILE_NAME};
 use maplit::btreemap;
+use modules::Entrypoint;
 use node::Node;
+use package_name::PackageName;
+use profile::Profiler;
+use prompt::Prompt;
+use remote_cache::RemoteCache;
+use remote_exec::Worker;
+use sandbox::Sandbox;
+use scheduler::{Dag, Limits, Task, TaskKind};
+use signature::{Signed, Trust, Verified};
+use target::Target;
+use timings::Timings;
 use structopt::StructOpt;
-use tracing::simple::SimpleTracer;
-use tracing::{Tracer, SourceProgress, BuildProgress, ManifestProgress, SourceProgressMethod};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::scope::Stream;
+use tracing::simple::{OutputMode, SimpleTracer};
+use itertools::Itertools;
+use verify::Drift;
+use tracing::{Tracer, SourceProgress, BuildProgress, ManifestProgress, RunOutcome, SourceProgressMethod, Verbosity};
 
+use crate::ipkg::Ipkg;
 use crate::manifest::Manifest;
 use crate::paths::Idris2Paths;
 
+pub mod archive;
+pub mod bench;
+pub mod bundle;
+pub mod cache;
+pub mod compiler;
+pub mod config;
+pub mod credentials;
+pub mod db;
 pub mod manifest;
 pub mod lazy;
 pub mod descriptor;
+pub mod doc;
+pub mod environment;
 pub mod error;
+pub mod explain;
+pub mod fetch;
+pub mod fetch_failures;
+pub mod files;
+pub mod fingerprint;
+pub mod graph;
+pub mod intern;
+pub mod interpolate;
+pub mod interrupt;
+pub mod ipkg;
+pub mod layout;
+pub mod license;
+pub mod lockfile;
+pub mod lsp;
+pub mod metadata;
+pub mod migrate;
+pub mod modules;
 pub mod node;
+pub mod outdated;
+pub mod package;
+pub mod package_name;
 pub mod paths;
+pub mod plan;
+pub mod profile;
+pub mod prompt;
+pub mod proxy;
+pub mod registry;
+pub mod registry_api;
+pub mod sandbox;
+pub mod remote_cache;
+pub mod remote_exec;
+pub mod reproducible;
+pub mod sbom;
+pub mod scaffold;
+pub mod scheduler;
+pub mod scripts;
+pub mod semver;
+pub mod signature;
+pub mod stats;
+pub mod status;
+pub mod target;
+pub mod timestamp;
+pub mod timings;
 pub mod tracing;
+pub mod udeps;
+pub mod updates;
+pub mod verify;
+pub mod watch;
+
+/// How many more times a download which failed because of the network is tried again, see
+/// [`SourceFetchError::is_transient`].
+const FETCH_RETRIES: u32 = 2;
 
 #[derive(Debug)]
 struct LairInner<Tr: Tracer = ()> {
-    /// Flat collection of package descriptors associated with their data.
-    db: std::sync::Mutex<BTreeMap<Descriptor, Arc<Node<Tr>>>>,
+    /// Flat collection of package descriptors associated with their data, see [`db`].
+    db: Db<Arc<Node<Tr>>>,
+    /// How each descriptor we've been asked for a node for resolved, see [`ResolvedDescriptor`].
+    /// Read far more often than written, like `db`.
+    resolved: RwLock<BTreeMap<Descriptor, ResolvedDescriptor>>,
 
     /// The root node, i.e. our root package.
     root: Arc<Node<Tr>>,
 
+    /// Which commits git dependencies are pinned to. Filled in as we fetch.
+    lockfile: std::sync::Mutex<Lockfile>,
+
+    /// From the root manifest's `[patch]` section: package name --> what to use instead.
+    patches: BTreeMap<String, Descriptor>,
+
+    /// From the root manifest's `idris-flags`: package name --> extra flags to build it with.
+    idris_flags: BTreeMap<String, Vec<String>>,
+
+    /// Git checkouts, each shared by all packages from the same repository and revision (see
+    /// [`layout::source_dir`]) --> the commit it was settled on during this run, if any. Locked
+    /// while one of these packages is fetched, so that they don't clone over each other.
+    checkouts: Mutex<BTreeMap<PathBuf, Arc<tokio::sync::Mutex<Option<String>>>>>,
+
+    /// Registry index urls --> whether we already tried to bring our clone of the index up to
+    /// date during this run. Locked while reading the index.
+    registries: Mutex<BTreeMap<String, Arc<tokio::sync::Mutex<bool>>>>,
+
+    /// Which `idris2` is on PATH, asked for the first time we need to know.
+    toolchain: Lazy<Option<Toolchain>>,
+
+    /// Give up on builds taking longer than this.
+    build_timeout: Mutex<Option<Duration>>,
+
+    /// How to answer questions, like whether to overwrite local changes in `build/deps`.
+    prompt: Mutex<Prompt>,
+
+    /// What `[target.'cfg(...)']` dependencies are chosen for.
+    target: Mutex<Target>,
+
+    /// Where to download TTC files from instead of building, and maybe upload them to.
+    remote_cache: Mutex<Option<RemoteCache>>,
+
+    /// Where to build dependencies instead of here, see [`remote_exec`].
+    remote_exec: Mutex<Option<Worker>>,
+
+    /// Never touch the network, and fail if something isn't downloaded yet.
+    offline: Mutex<bool>,
+
+    /// After a failure, still build everything which doesn't depend on what failed.
+    keep_going: Mutex<bool>,
+
+    /// Try downloads again even if they failed a moment ago, see [`fetch_failures`].
+    refresh: Mutex<bool>,
+    dirty_deps: Mutex<DirtyDeps>,
+
+    /// Whose signatures dependencies need, if any.
+    trust: Mutex<Trust>,
+
+    /// How third-party packages are built, see [`sandbox`].
+    sandbox: Mutex<Sandbox>,
+
+    /// Symlink `path` dependencies into `idris2-packages` instead of copying them.
+    link_local: Mutex<bool>,
+
+    /// How many builds and downloads the scheduler runs at once.
+    limits: Mutex<Limits>,
+
+    /// How long packages took to build, to schedule the slowest chains first.
+    timings: Mutex<Timings>,
+
+    /// What happened so far, for [`Tracer::run_finished`] and [`stats`].
+    stats: Arc<RunStats>,
+
+    /// Shared copies of names, paths and manifests, see [`intern`].
+    interner: Interner,
+
+    /// Where to record what takes Lair itself so long, if anywhere, see [`profile`].
+    profiler: OnceLock<Arc<Profiler>>,
+
     tracer: Tr,
 }
 
+/// Counters behind [`RunOutcome`].
+#[derive(Debug, Default)]
+struct RunStats {
+    fetched: AtomicUsize,
+    built: AtomicUsize,
+    cached: AtomicUsize,
+    remote: AtomicUsize,
+    failed: AtomicUsize,
+    /// Of the `built` packages.
+    build_millis: AtomicU64,
+}
+
+impl RunStats {
+    fn outcome(&self, duration: Duration, success: bool) -> RunOutcome {
+        RunOutcome {
+            fetched: self.fetched.load(Ordering::Relaxed),
+            built: self.built.load(Ordering::Relaxed),
+            cached: self.cached.load(Ordering::Relaxed),
+            remote: self.remote.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            duration,
+            success,
+            logs_dir: layout::logs_dir(),
+        }
+    }
+
+    fn counts(&self) -> stats::Counts {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u64;
+        stats::Counts {
+            fetched: load(&self.fetched),
+            built: load(&self.built),
+            cached: load(&self.cached),
+            remote: load(&self.remote),
+            failed: load(&self.failed),
+            build_millis: self.build_millis.load(Ordering::Relaxed),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Lair<Tr: Tracer = ()> {
     inner: Arc<LairInner<Tr>>,
@@ -52,10 +256,22 @@ impl<Tr: Tracer> Lair<Tr> {
     /// You can then try getting the TTC files for the root node, which will trigger the
     /// recipes stored inside the root node, which in turn will trigger fetching its dependencies'
     /// manifests, sources, TTCs, and so forth recursively.
-    pub fn new(root_manifest: Manifest, root_path: impl AsRef<Path>) -> Self
+    pub fn new(root_manifest: impl Into<Arc<Manifest>>, root_path: impl AsRef<Path>) -> Self
         where Tr: Default
     {
-        let root_descriptor = Descriptor::Root { name: root_manifest.name.clone() };
+        Self::with_tracer(root_manifest, root_path, Tr::default())
+    }
+
+    /// Same as [`Lair::new`], but with an already configured tracer.
+    pub fn with_tracer(root_manifest: impl Into<Arc<Manifest>>, root_path: impl AsRef<Path>, tracer: Tr) -> Self {
+        let mut root_manifest: Arc<Manifest> = root_manifest.into();
+        // Even for the current directory, so that `./packages/Core` and a workspace member
+        // `packages/Core` are the same package.
+        Arc::make_mut(&mut root_manifest).rebase_local_paths(root_path.as_ref());
+        let patches = root_manifest.patches.clone();
+        let idris_flags = root_manifest.idris_flags.clone();
+        let interner = Interner::default();
+        let root_descriptor = Descriptor::Root { name: interner.name(&root_manifest.name) };
         let root_descriptor_clone = root_descriptor.clone();
         let root_descriptor_clone2 = root_descriptor.clone();
 
@@ -68,23 +284,209 @@ impl<Tr: Tracer> Lair<Tr> {
                 root_path.as_ref(),
                 Lazy::new(async move {
                     let inner: Arc<LairInner<Tr>> = weak.upgrade().context("Failed to upgrade weak Arc.")?;
-                    inner.build_ttc(root_descriptor_clone).await
+                    inner.build_ttc(root_descriptor_clone.clone()).await
+                        .map(|ttc| inner.interner.path(ttc))
+                        .map_err(|e| e.in_package(inner.provenance(&root_descriptor_clone, Phase::Building)))
                 }),
             ));
 
             LairInner {
-                db: Mutex::new(btreemap! {
-                    root_descriptor => root_node.clone(),
+                db: {
+                    let db = Db::new();
+                    db.insert(ResolvedDescriptor::Unresolved(root_descriptor.clone()), root_node.clone());
+                    db
+                },
+                resolved: RwLock::new(btreemap! {
+                    root_descriptor.clone() => ResolvedDescriptor::Unresolved(root_descriptor),
                 }),
                 root: root_node,
-                tracer: Tr::default(),
+                lockfile: Mutex::new(Lockfile::default()),
+                patches,
+                idris_flags,
+                checkouts: Mutex::new(BTreeMap::new()),
+                registries: Mutex::new(BTreeMap::new()),
+                toolchain: Lazy::new(async {
+                    tokio::task::spawn_blocking(Toolchain::detect).await.unwrap()
+                }),
+                build_timeout: Mutex::new(None),
+                prompt: Mutex::new(Prompt::default()),
+                target: Mutex::new(Target::default()),
+                remote_cache: Mutex::new(None),
+                remote_exec: Mutex::new(None),
+                offline: Mutex::new(false),
+                keep_going: Mutex::new(false),
+                refresh: Mutex::new(false),
+                dirty_deps: Mutex::new(DirtyDeps::default()),
+                trust: Mutex::new(Trust::default()),
+                sandbox: Mutex::new(Sandbox::default()),
+                link_local: Mutex::new(false),
+                limits: Mutex::new(Limits::default()),
+                timings: Mutex::new(Timings::load()),
+                stats: Arc::default(),
+                interner,
+                profiler: OnceLock::new(),
+                tracer,
             }
         });
 
         inner.tracer.new_descriptor(&root_descriptor_clone2);
+        // How long builds took is only saved once they all finished, so it may not get there.
+        let weak = Arc::downgrade(&inner);
+        interrupt::on_force_exit(move || if let Some(inner) = weak.upgrade() {
+            if let Ok(mut timings) = inner.timings.try_lock() {
+                let _ = timings.save();
+            }
+        });
         Self { inner }
     }
 
+    /// Use (and keep up to date) the lockfile at `path`, usually `Egg.lock`. Without this, git
+    /// dependencies are not pinned, and nothing is written to disk.
+    ///
+    /// Call this before fetching anything.
+    pub fn with_lockfile(self, path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        *self.inner.lockfile.lock().unwrap() = Lockfile::load(path)?;
+        Ok(self)
+    }
+
+    /// Kill idris2 and fail if building a single package takes longer than `timeout`.
+    pub fn with_build_timeout(self, timeout: Option<Duration>) -> Self {
+        *self.inner.build_timeout.lock().unwrap() = timeout;
+        self
+    }
+
+    /// How to answer questions Lair has while fetching, see [`prompt`]. By default, everything is
+    /// answered with no.
+    pub fn with_prompt(self, prompt: Prompt) -> Self {
+        *self.inner.prompt.lock().unwrap() = prompt;
+        self
+    }
+
+    /// Pick conditional dependencies for `target`, see [`target`]. By default, the chez backend on
+    /// this computer.
+    ///
+    /// Call this before fetching anything.
+    pub fn with_target(self, target: Target) -> Self {
+        *self.inner.target.lock().unwrap() = target;
+        self
+    }
+
+    /// Try downloading dependencies' TTC files from `cache` before building them, see
+    /// [`remote_cache`].
+    pub fn with_remote_cache(self, cache: Option<RemoteCache>) -> Self {
+        *self.inner.remote_cache.lock().unwrap() = cache;
+        self
+    }
+
+    /// Build dependencies on `worker` rather than here, falling back to building them here if it
+    /// can't, see [`remote_exec`]. Experimental.
+    pub fn with_remote_exec(self, worker: Option<Worker>) -> Self {
+        *self.inner.remote_exec.lock().unwrap() = worker;
+        self
+    }
+
+    /// Don't download anything, and fail if something the build needs (a checkout, a locked
+    /// commit, a registry index) isn't there yet, see [`Lair::fetch`]. Also skips the remote cache.
+    pub fn with_offline(self, offline: bool) -> Self {
+        *self.inner.offline.lock().unwrap() = offline;
+        self
+    }
+
+    /// Instead of stopping at the first failure, build everything which doesn't depend on a
+    /// package which failed, then fail with all errors together, see [`error::Failures`].
+    pub fn with_keep_going(self, keep_going: bool) -> Self {
+        *self.inner.keep_going.lock().unwrap() = keep_going;
+        self
+    }
+
+    /// Download again what failed to download in a recent run, instead of failing with the same
+    /// error right away, see [`fetch_failures`].
+    pub fn with_refresh(self, refresh: bool) -> Self {
+        *self.inner.refresh.lock().unwrap() = refresh;
+        self
+    }
+
+    /// Record phases, lock waits and the scheduler's queue in `profiler`, see [`profile`]. It can
+    /// be shared with other `Lair`s.
+    pub fn with_profiler(self, profiler: Option<Arc<Profiler>>) -> Self {
+        if let Some(profiler) = profiler {
+            let _ = self.inner.profiler.set(profiler);
+        }
+        self
+    }
+
+    /// Count what gets built and reused in `usage`, see [`stats`]. It can be shared with other
+    /// `Lair`s.
+    pub fn with_usage(self, usage: &stats::Usage) -> Self {
+        let stats = self.inner.stats.clone();
+        usage.watch(move || stats.counts());
+        self
+    }
+
+    /// What to do when the checkout of a git or registry dependency has local changes. Warns by
+    /// default.
+    pub fn with_dirty_deps(self, dirty_deps: DirtyDeps) -> Self {
+        *self.inner.dirty_deps.lock().unwrap() = dirty_deps;
+        self
+    }
+
+    /// Check that git and archive dependencies are signed by one of `trust.keys`, see
+    /// [`signature`]. By default, nothing is checked.
+    pub fn with_trust(self, trust: Trust) -> Self {
+        *self.inner.trust.lock().unwrap() = trust;
+        self
+    }
+
+    /// Build git, archive and registry dependencies in `sandbox`, see [`sandbox`]. By default,
+    /// they're built like any other package.
+    pub fn with_sandbox(self, sandbox: Sandbox) -> Self {
+        *self.inner.sandbox.lock().unwrap() = sandbox;
+        self
+    }
+
+    /// Install `path` dependencies as symlinks (junctions on Windows, if need be) to their own
+    /// `build/{toolchain}/ttc`, rather than copies of it, so building one in its own directory
+    /// shows up right away. Where the file system can't, they're copied anyway. Only matters
+    /// with idris2's `IDRIS2_PACKAGE_PATH`, without it nothing is installed. By default, copies.
+    pub fn with_link_local(self, link_local: bool) -> Self {
+        *self.inner.link_local.lock().unwrap() = link_local;
+        self
+    }
+
+    /// Build at most `jobs` packages at the same time, instead of one per CPU.
+    pub fn with_jobs(self, jobs: Option<usize>) -> Self {
+        if let Some(jobs) = jobs {
+            self.inner.limits.lock().unwrap().builds = jobs;
+        }
+        self
+    }
+
+    /// A copy of the current lockfile.
+    pub fn lockfile(&self) -> Lockfile {
+        self.inner.lockfile.lock().unwrap().clone()
+    }
+
+    /// Move the named packages (and their dependencies) to the newest commits of their branches,
+    /// leaving everything else pinned, and save the lockfile.
+    ///
+    /// The nodes of this `Lair` keep seeing the old manifests, so to build the updated packages
+    /// (or lock newly added dependencies), create a new [`Lair`].
+    pub async fn update_lockfile(&self, names: &[String]) -> Result<Vec<LockChange>, anyhow::Error> {
+        let graph = self.resolve().await?;
+        let mut lockfile = self.lockfile();
+        let names = names.to_vec();
+        let prompt = *self.inner.prompt.lock().unwrap();
+
+        let (mut lockfile, changes) = tokio::task::spawn_blocking(move || {
+            let changes = lockfile::update(&mut lockfile, &graph, &names, prompt);
+            (lockfile, changes)
+        }).await.unwrap();
+
+        lockfile.save()?;
+        *self.inner.lockfile.lock().unwrap() = lockfile;
+        changes
+    }
+
     /// Get the root node.
     pub fn root(&self) -> &Node<Tr> {
         &self.inner.root
@@ -99,184 +501,3503 @@ impl<Tr: Tracer> Lair<Tr> {
         self.inner.node(desc)
     }
 
+    /// Every descriptor we know a node for so far, in order. After [`Lair::resolve`] (or
+    /// [`Lair::resolve_metadata_only`]), that's the whole dependency graph.
+    pub fn descriptors(&self) -> impl Iterator<Item = Descriptor> {
+        let mut descriptors: Vec<_> = self.inner.db.entries().into_iter().map(|(_, node)| node.descriptor.clone()).collect();
+        descriptors.sort();
+        descriptors.into_iter()
+    }
+
+    /// Every node so far, see [`Lair::descriptors`].
+    pub fn nodes(&self) -> impl Iterator<Item = Arc<Node<Tr>>> {
+        let nodes: Vec<_> = self.inner.db.entries().into_iter().map(|(_, node)| node).collect();
+        nodes.into_iter()
+    }
+
+    /// (dependent, dependency) for every dependency (after `[patch]`) of every node whose
+    /// manifest was read so far.
+    pub fn edges(&self) -> impl Iterator<Item = (Descriptor, Descriptor)> {
+        let edges: Vec<_> = self.nodes()
+            .flat_map(|node| {
+                let deps = node.peek_dependencies().unwrap_or_default();
+                deps.into_iter().map(move |dep| (node.descriptor.clone(), dep))
+            })
+            .collect();
+        edges.into_iter()
+    }
+
     pub async fn build(&self) -> Result<(), anyhow::Error> {
-        let build_deps_dir = PathBuf::from("build").join("deps");
-        create_dir_all(build_deps_dir)?; // ./build/deps
+        self.build_packages(std::slice::from_ref(&self.inner.root.descriptor)).await
+    }
 
-        self.root().ttc().await?;
+    /// Build `packages`, usually the root package and some of its workspace's
+    /// [members](Manifest::members), and everything they depend on, but nothing else.
+    pub async fn build_packages(&self, packages: &[Descriptor]) -> Result<(), anyhow::Error> {
+        self.inner.tracer.run_started(&self.inner.root.descriptor);
+        let started = Instant::now();
+        let result = async {
+            let graph = self.check_graph().await?;
+            if self.inner.keep_going() {
+                return self.build_packages_keep_going(&graph, packages).await;
+            }
+            let wanted: BTreeSet<Descriptor> = packages.iter().flat_map(|desc| graph.closure(desc)).collect();
+            // Download everything first, so that module collisions are found before building.
+            self.schedule(&graph, |task| wanted.contains(&task.descriptor) && task.kind != TaskKind::Build).await?;
+            self.check_modules(&graph, packages).await?;
+            self.schedule(&graph, |task| wanted.contains(&task.descriptor) && task.kind == TaskKind::Build).await?;
+            try_join_all(packages.iter().map(|desc| async move {
+                self.node(desc).ttc().await
+            })).await?;
+            Ok(())
+        }.await;
+        self.inner.tracer.run_finished(&self.inner.stats.outcome(started.elapsed(), result.is_ok()));
 
-        Ok(())
+        result
     }
 
-    pub async fn run(&self) -> Result<(), anyhow::Error> {
-        let deps_ttc_paths = self.root().dependencies_ttc_paths().await?; // will complete instantly, because we've already built everything.
+    /// [`Lair::build_packages`], going on after failures: whatever could be downloaded is, and
+    /// every package whose dependencies all could be is built.
+    async fn build_packages_keep_going(&self, graph: &Graph, packages: &[Descriptor]) -> Result<(), anyhow::Error> {
+        let wanted: BTreeSet<Descriptor> = packages.iter().flat_map(|desc| graph.closure(desc)).collect();
+        let mut errors = Vec::new();
+        let mut broken = BTreeSet::new();
+        if let Err(failed) = self.schedule_tasks(graph, |task| wanted.contains(&task.descriptor) && task.kind != TaskKind::Build).await {
+            for error in &failed.errors {
+                match error::provenance_of(error) {
+                    Some(provenance) => broken.insert(provenance.desc.clone()),
+                    // No telling what's missing.
+                    None => return Err(error::Failures { errors: failed.errors, skipped: Vec::new() }.into()),
+                };
+            }
+            broken.extend(failed.skipped.into_iter().map(|task| task.descriptor));
+            errors.extend(failed.errors);
+        }
 
-        Command::new("idris2")
-            .env("IDRIS2_PATH", deps_ttc_paths.join_idris2())
-            .arg("--source-dir").arg("src")
-            .arg(self.root().main().await?)
-            .arg("--exec").arg("main")
-            .status().unwrap().exit_ok().unwrap(); // TODO: fix both unwraps here, check for errors idris returned.
+        // What can be built: everything wanted which has all its dependencies.
+        let buildable: Vec<Descriptor> = wanted.iter()
+            .filter(|desc| graph.closure(desc).iter().all(|dep| !broken.contains(dep)))
+            .cloned()
+            .collect();
+        self.check_modules(graph, &buildable).await?;
+        let mut skipped: BTreeSet<Descriptor> = wanted.iter().filter(|desc| !buildable.contains(desc) && !broken.contains(desc)).cloned().collect();
+        if let Err(failed) = self.schedule_tasks(graph, |task| buildable.contains(&task.descriptor) && task.kind == TaskKind::Build).await {
+            errors.extend(failed.errors);
+            skipped.extend(failed.skipped.into_iter().map(|task| task.descriptor));
+        }
 
-        Ok(())
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(error::Failures { errors, skipped: skipped.into_iter().collect() }.into())
     }
-}
 
-impl<Tr: Tracer> LairInner<Tr> {
-    pub fn node(self: &Arc<Self>, desc: &Descriptor) -> Arc<Node<Tr>> {
-        let mut db = self.db.lock().unwrap();
+    /// The root package and the [members](Manifest::members) of its workspace called `names`, or
+    /// all of them.
+    pub async fn workspace_packages(&self, names: &[String], all: bool) -> Result<Vec<Descriptor>, anyhow::Error> {
+        let graph = self.graph().await?;
+        let packages = std::iter::once(&graph.root).chain(&graph.members);
+        if all {
+            return Ok(packages.cloned().collect());
+        }
+        names.iter()
+            .map(|name| match packages.clone().find(|desc| desc.name() == name) {
+                Some(desc) => Ok(desc.clone()),
+                None => anyhow::bail!(
+                    "`{}` isn't part of this workspace, which has {}.",
+                    name, packages.clone().map(|desc| format!("`{}`", desc.name())).join(", "),
+                ),
+            })
+            .collect()
+    }
 
-        if let Some(node) = db.get(desc) {
-            node.clone()
-        } else {
-            let desc_clone1: Descriptor = desc.clone();
-            let desc_clone2: Descriptor = desc.clone();
-            let desc_clone3: Descriptor = desc.clone();
+    /// Download everything building the root package needs: sources of all dependencies, and the
+    /// registry indexes they come from. Doesn't build anything, so that Docker layers and CI
+    /// caches can be warmed before building [offline](Lair::with_offline). Also saves the lockfile.
+    pub async fn fetch(&self) -> Result<Graph, anyhow::Error> {
+        self.inner.tracer.run_started(&self.inner.root.descriptor);
+        let started = Instant::now();
+        let result = async {
+            let graph = self.resolve().await?;
+            self.schedule(&graph, |task| task.kind != TaskKind::Build).await?;
+            Ok(graph)
+        }.await;
+        self.inner.tracer.run_finished(&self.inner.stats.outcome(started.elapsed(), result.is_ok()));
 
-            // Create a new node, with recipes on how to obtain its source/ttcs, which will be
-            // invoked when necessary.
-            // This only creates futures, which may or may not be invoked in the future (harr harr).
-            let node = Arc::new(Node::new(
-                Arc::downgrade(self),
-                desc.clone(),
-                Lazy::new_weak(self, move |lair| async move { lair.fetch_manifest(desc_clone1).await }),
-                Lazy::new_weak(self, move |lair| async move { lair.fetch_source(desc_clone2).await }),
-                Lazy::new_weak(self, move |lair| async move { lair.build_ttc(desc_clone3).await }),
-            ));
+        result
+    }
 
-            self.tracer.new_descriptor(desc);
+    /// Run the tasks of `graph` which `include` accepts, see [`scheduler`].
+    async fn schedule(&self, graph: &Graph, include: impl Fn(&Task) -> bool) -> Result<(), anyhow::Error> {
+        self.schedule_tasks(graph, include).await.map_err(|mut failed| match self.inner.keep_going() {
+            true => error::Failures {
+                errors: failed.errors,
+                skipped: failed.skipped.into_iter().filter(|task| task.kind == TaskKind::Build).map(|task| task.descriptor).collect(),
+            }.into(),
+            false => failed.errors.remove(0),
+        })
+    }
 
-            db.insert(desc.clone(), node.clone());
-            node
+    /// [`Lair::schedule`], with every error when keeping going.
+    async fn schedule_tasks(&self, graph: &Graph, include: impl Fn(&Task) -> bool) -> Result<(), scheduler::Failed<anyhow::Error>> {
+        let _phase = self.inner.phase("run tasks");
+        let dag = self.dag(graph, include);
+        let limits = *self.inner.limits.lock().unwrap();
+        let result = scheduler::run(&dag, limits, self.inner.keep_going(), &self.inner.tracer, self.inner.profiler(), |task| {
+            let (node, kind) = (self.node(&task.descriptor), task.kind);
+            async move {
+                match kind {
+                    TaskKind::Fetch => drop(node.base_path().await?),
+                    TaskKind::Manifest => drop(node.manifest().await?),
+                    TaskKind::Build => drop(node.ttc().await?),
+                }
+                Ok(())
+            }
+        }).await;
+        // Running tasks may have finished fine, but others weren't started.
+        let result = match result {
+            Ok(()) if interrupt::requested() => Err(scheduler::Failed { errors: vec![interrupt::Interrupted.into()], skipped: Vec::new() }),
+            result => result,
+        };
+        // Also what was built before a failure.
+        let saved = self.inner.timings.lock().unwrap().save();
+        match (result, saved) {
+            (Err(failed), _) => Err(failed),
+            (Ok(()), Err(e)) => Err(scheduler::Failed { errors: vec![e.into()], skipped: Vec::new() }),
+            (Ok(()), Ok(())) => Ok(()),
         }
     }
 
-    /// Recipe for building TTC files.
-    async fn build_ttc(self: &Arc<Self>, desc: Descriptor) -> Result<PathBuf, BuildTtcError> {
-        let node = self.node(&desc);
+    /// The tasks of building `graph`, or those of them `include` accepts, with how long each
+    /// will probably take.
+    fn dag(&self, graph: &Graph, include: impl Fn(&Task) -> bool) -> Dag {
+        let timings = self.inner.timings.lock().unwrap().clone();
+        Dag::new(graph, include, |task| Self::estimate(&timings, task))
+    }
 
-        // Build dependencies in parallel (and recurse, kind of). Then unpack results, making sure
-        // they all built correctly, and collect into an IDRIS2_PATH.
-        let (base_path, deps_paths) = join(node.base_path(), node.dependencies_ttc_paths()).await;
-        let deps_paths = deps_paths?;
-        let base_path = base_path?;
+    /// How long `task` will probably take. Fetching and reading manifests is quick compared to
+    /// building, and unknown anyway.
+    fn estimate(timings: &Timings, task: &Task) -> Duration {
+        match task.kind {
+            TaskKind::Build => timings.estimate(&task.descriptor),
+            TaskKind::Fetch | TaskKind::Manifest => Duration::from_millis(1),
+        }
+    }
 
-        let guard = self.tracer.building(&desc);
-        let build_dir = base_path.join("build"); // `{base_path}/build`
-        let source_dir = base_path.join("src"); // `{base_path}/src`
-        let main_idr = node.main().await?; // `{base_path}/src/AmazingTool.idr`
-        let idris2_path = deps_paths.join_idris2();
+    /// Refuse to build if it can't work out anyway:
+    /// - Several versions of a package can be downloaded side by side, but idris2 can't load more
+    ///   than one of them.
+    /// - Packages may say which idris2 versions they work with, and the one on PATH has to be
+    ///   one of them.
+    ///
+    /// Returns the graph it checked.
+    async fn check_graph(&self) -> Result<Graph, anyhow::Error> {
+        let graph = self.graph().await?;
+        let _phase = self.inner.phase("check graph");
+        if let Some((name, descriptors)) = graph.conflicts().into_iter().next() {
+            let requirements = graph.explain_conflict(&descriptors);
+            let descriptors: Vec<Descriptor> = descriptors.into_iter().cloned().collect();
+            let satisfying = tokio::task::spawn_blocking(move || registry::satisfying_all(&descriptors)).await.unwrap();
+            return Err(BuildTtcError::Conflict { name: name.to_owned(), requirements, satisfying }.into());
+        }
+        let collisions = graph.case_collisions();
+        if !collisions.is_empty() {
+            let names = collisions.into_iter().map(|names| names.into_iter().map(str::to_owned).collect()).collect();
+            return Err(BuildTtcError::CaseCollision { names }.into());
+        }
 
-        // println!("{} [TTC] Running command: `idris2 --build-dir {} --source-dir {} --check {}` with IDRIS2_PATH=\"{}\"",
-        //     desc.name(), build_dir.to_string_lossy(), source_dir.to_string_lossy(), main_idr.to_string_lossy(), idris2_path);
+        let requirements = |packages: Vec<(&Descriptor, &compiler::Idris2Requirement)>| packages.into_iter()
+            .map(|(desc, requirement)| (desc.name().to_owned(), requirement.clone()))
+            .collect();
+        let all = graph.idris2_requirements();
+        if !compiler::Idris2Requirement::satisfiable(all.iter().map(|(_, requirement)| *requirement)) {
+            return Err(BuildTtcError::Idris2Unsatisfiable { packages: requirements(all) }.into());
+        }
+        if let Some(version) = self.inner.idris2_version().await {
+            let unsupported = graph.unsupported_by(version);
+            if !unsupported.is_empty() {
+                return Err(BuildTtcError::Idris2Unsupported { version, packages: requirements(unsupported) }.into());
+            }
+        }
+        Ok(graph)
+    }
 
-        Command::new("idris2")
-            .arg("--build-dir").arg(build_dir)
-            .arg("--source-dir").arg(source_dir)
-            .arg("--check")
-            .env("IDRIS2_PATH", &idris2_path)
-            .arg(main_idr)
-            .status().unwrap().exit_ok().unwrap(); // TODO: fix both unwraps here, check for errors idris returned.
+    /// Refuse to build `packages` if anything one of them loads, itself included, provides the
+    /// same module as something else it loads, see [`modules`]. Only modules which are built
+    /// count: a package's main module and what it imports from the package, see
+    /// [`modules::closure`], or all of them if there is no main module. Expects the packages to be
+    /// downloaded.
+    async fn check_modules(&self, graph: &Graph, packages: &[Descriptor]) -> Result<(), anyhow::Error> {
+        let _phase = self.inner.phase("check modules");
+        let wanted: BTreeSet<Descriptor> = packages.iter().flat_map(|desc| graph.closure(desc)).collect();
+        let provided: BTreeMap<Descriptor, Vec<String>> = try_join_all(wanted.into_iter().map(|desc| async move {
+            let node = self.node(&desc);
+            let (source_dir, main) = (node.source_dir().await?, node.main().await?);
+            let modules = tokio::task::spawn_blocking(move || match main.strip_prefix(&source_dir).ok().and_then(modules::module_of) {
+                Some(module) if main.is_file() => modules::closure(&source_dir, &module).map(|closure| closure.into_keys().collect()),
+                _ => modules::scan(&source_dir),
+            }).await.unwrap()?;
+            Ok::<_, anyhow::Error>((desc, modules))
+        })).await?.into_iter().collect();
 
-        let ttc = base_path.join("build").join("ttc"); // `{base_path}/build/ttc`
-        guard.success(&ttc);
-        Ok(ttc)
+        let collisions: BTreeSet<modules::Collision> = packages.iter()
+            .flat_map(|desc| modules::collisions(graph.closure(desc).into_iter()
+                .filter_map(|dep| provided.get_key_value(&dep))
+                .map(|(dep, modules)| (dep, modules.as_slice()))))
+            .collect();
+        if !collisions.is_empty() {
+            return Err(BuildTtcError::ModuleCollision { collisions: collisions.into_iter().collect() }.into());
+        }
+        Ok(())
     }
 
-    /// Recipe for fetching source.
-    ///
-    /// Returns path to source code, so that `{return value}/Egg.toml` exists.
-    async fn fetch_source(self: &Arc<Self>, desc: Descriptor) -> Result<PathBuf, SourceFetchError> {
+    /// Read the manifests of all packages reachable from the root (downloading them if necessary),
+    /// but don't build anything. Also writes the snapshot for [`Lair::graph`], and saves the
+    /// lockfile.
+    pub async fn resolve(&self) -> Result<Graph, anyhow::Error> {
+        let (graph, mut inputs) = self.resolve_inner().await?;
 
-        match desc.clone() {
-            Descriptor::Root { .. } => {
-                unreachable!("There must only be one root node, and it must be initialized with a path (usually `./`) at startup.")
-            },
-            Descriptor::Git { name, url, .. } => {
-                let path = PathBuf::from(format!("build/deps/{}", name)); // TODO: make sure directory doesn't exist yet.
+        // Save the lockfile before looking at its mtime for the snapshot.
+        {
+            let mut lockfile = self.inner.lockfile.lock().unwrap();
+            // Descriptors sharing a node are at its commit, and should share it next time, too.
+            for alias in &graph.aliases {
+                if let Some(commit) = lockfile.get(&alias.same_as).map(str::to_owned) {
+                    lockfile.set(&alias.descriptor, commit);
+                    if let Descriptor::Git { version: GitVersion::Branch(branch), .. } = &alias.same_as {
+                        lockfile.set_default_branch(&alias.descriptor, branch.clone());
+                    }
+                }
+            }
+            lockfile.retain_graph(&graph);
+            lockfile.save()?;
+            if let Some(path) = lockfile.path() {
+                inputs.push(graph::Input::of(path));
+            }
+        }
+        Snapshot { inputs, target: self.inner.target(), graph: graph.clone() }.save()?;
+        Self::record_usage(&graph)?;
+        Ok(graph)
+    }
 
-                if path.exists() {
-                    let guard =self.tracer
-                        .fetching_repo(&desc, SourceProgressMethod::AlreadyDownloaded);
-                    guard.success(&path);
-                    Ok(path)
-                } else {
-                    let guard = self.tracer.fetching_repo(&desc,
-                        SourceProgressMethod::Git { url: &url} );
-                    let path_clone = path.clone();
-                    let _repo = tokio::task::spawn_blocking(move || {
-                        // TODO: proper error handling.
-                        git2::Repository::clone(&url, &path_clone)
-                    }).await.unwrap()?;
+    /// Read the manifests of all packages reachable from the root, downloading them if necessary,
+    /// but never build anything, nor write anything besides the downloads. For tools which only
+    /// care about the dependency graph. Afterwards, [`Lair::descriptors`], [`Lair::nodes`] and
+    /// [`Lair::edges`] cover all of it.
+    pub async fn resolve_metadata_only(&self) -> Result<Graph, anyhow::Error> {
+        Ok(self.resolve_inner().await?.0)
+    }
+
+    /// The graph, and the files it was computed from.
+    async fn resolve_inner(&self) -> Result<(Graph, Vec<graph::Input>), anyhow::Error> {
+        let _phase = self.inner.phase("resolve");
+        create_dir_all(layout::deps_dir())?; // ./build/deps
+
+        let target = self.inner.target();
+        let mut packages = Vec::new();
+        let mut inputs = Vec::new();
+        let mut inactive = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut frontier = vec![self.inner.root.clone()];
+        visited.insert(self.inner.root.descriptor.clone());
+        let mut members = Vec::new();
+        for member in self.inner.members()? {
+            let node = self.node(&member);
+            members.push(node.descriptor.clone());
+            if visited.insert(node.descriptor.clone()) {
+                frontier.push(node);
+            }
+        }
+
+        // Breadth-first, one layer of the graph at a time, in parallel.
+        let mut depth = 0;
+        while !frontier.is_empty() {
+            if let Some(profiler) = self.inner.profiler() {
+                profiler.resolved_layer(depth, frontier.len(), visited.len());
+            }
+            depth += 1;
+            let layer = try_join_all(frontier.drain(..).map(|node| async move {
+                let manifest = node.manifest().await?;
+                let base_path = node.base_path().await?;
+                let deps = node.dependencies().await?;
+                Ok::<_, anyhow::Error>((node, manifest, base_path, deps))
+            })).await?;
 
-                    guard.success(&path);
-                    Ok(path)
+            for (node, manifest, base_path, deps) in layer {
+                inputs.push(graph::Input::of(base_path.join("Egg.toml")));
+                if let Some(workspace) = &manifest.workspace {
+                    inputs.push(graph::Input::of(base_path.join(workspace)));
+                }
+                inactive.extend(manifest.inactive_dependencies(&target).iter().map(|dep| self.inner.patched(dep)));
+                packages.push(GraphPackage {
+                    descriptor: node.descriptor.clone(),
+                    version: manifest.version.clone(),
+                    idris2: manifest.idris2.clone(),
+                    // Not `manifest.dependencies`, since some of them may have been patched.
+                    dependencies: deps.iter().map(|dep| dep.descriptor.clone()).collect(),
+                    origins: node.origins().await?,
+                });
+                for dep in deps {
+                    if visited.insert(dep.descriptor.clone()) {
+                        frontier.push(dep);
+                    }
                 }
+            }
+        }
+
+        inactive.retain(|dep| !visited.contains(dep));
+        let graph = Graph {
+            root: self.inner.root.descriptor.clone(),
+            members,
+            packages,
+            inactive,
+            aliases: self.inner.aliases(),
+        };
+        self.inner.tracer.graph_resolved(&graph);
+        Ok((graph, inputs))
+    }
+
+    /// The dependency graph, from the snapshot in `build/.lair/graph.json` if none of the
+    /// manifests changed since it was written, otherwise freshly resolved.
+    pub async fn graph(&self) -> Result<Graph, anyhow::Error> {
+        let snapshot = {
+            let _phase = self.inner.phase("load snapshot");
+            Snapshot::load_fresh(&self.inner.target())
+        };
+        match snapshot {
+            Some(mut graph) => {
+                self.inner.interner.graph(&mut graph);
+                self.inner.tracer.graph_resolved(&graph);
+                Self::record_usage(&graph)?;
+                Ok(graph)
             },
-            Descriptor::Local { .. } => todo!(),
+            None => self.resolve().await,
         }
     }
 
-    /// Recipe for fetching manifest.
-    async fn fetch_manifest(self: &Arc<Self>, desc: Descriptor) -> Result<Manifest, ManifestFetchError> {
-        let guard = self.tracer.fetching_manifest(&desc);
+    /// Remember that the checkouts of `graph` are still in use, for [`Lair::gc`].
+    fn record_usage(graph: &Graph) -> Result<(), anyhow::Error> {
+        let mut index = CacheIndex::load();
+        index.touch(graph);
+        index.save()
+    }
 
-        let node = self.node(&desc);
-        let path = node.base_path().await?.join("Egg.toml");
+    /// Delete checkouts in `build/deps` which the dependency graph doesn't use anymore, according
+    /// to `policy`.
+    pub async fn gc(&self, policy: GcPolicy) -> Result<Vec<cache::Removed>, anyhow::Error> {
+        let graph = self.graph().await?;
+        tokio::task::spawn_blocking(move || {
+            let mut index = CacheIndex::load();
+            let removed = cache::gc(&mut index, &graph, policy)?;
+            index.save()?;
+            Ok(removed)
+        }).await.unwrap()
+    }
 
-        let ret = manifest::Manifest::from_string(std::fs::read_to_string(path)?)?;
-        guard.success(&ret);
-        Ok(ret)
+    /// Check that the lockfile, the downloaded dependencies and the build outputs still match the
+    /// manifests, without downloading, building or writing anything. See [`verify`].
+    pub async fn verify(&self) -> Result<Vec<Drift>, anyhow::Error> {
+        let packages = self.packages_on_disk().await?;
+        let lockfile = self.lockfile();
+        let extra = self.inner.fingerprint_extra().await;
+        let toolchain = self.inner.toolchain_dir().await;
+        Ok(tokio::task::spawn_blocking(move || verify::check(&packages, &lockfile, &extra, &toolchain)).await.unwrap())
     }
 
-}
+    /// Dependencies of the root package and its workspace members which none of their modules
+    /// import, and imports from packages they don't depend on. Downloads whatever isn't yet, but
+    /// builds nothing. See [`udeps`].
+    pub async fn udeps(&self) -> Result<Vec<udeps::Finding>, anyhow::Error> {
+        let graph = self.graph().await?;
+        self.schedule(&graph, |task| task.kind != TaskKind::Build).await?;
+        let source_dirs: BTreeMap<Descriptor, PathBuf> = try_join_all(graph.packages.iter().map(|package| async move {
+            let source_dir = self.node(&package.descriptor).source_dir().await?;
+            Ok::<_, anyhow::Error>((package.descriptor.clone(), source_dir))
+        })).await?.into_iter().collect();
+        let packages: Vec<Descriptor> = std::iter::once(graph.root.clone()).chain(graph.members.iter().cloned()).collect();
+        Ok(tokio::task::spawn_blocking(move || udeps::check(&graph, &source_dirs, &packages)).await.unwrap()?)
+    }
 
-/// Ensure a directory and sub-dirs are gone.
-/// Do not fail when it's not there in the first place.
-fn clean(path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
-    match std::fs::remove_dir_all(path) {
-        Ok(()) => Ok(()),
-        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
-        Err(e) => Err(e.into()),
+    /// What `lair build` would do with each package, in build order, without downloading,
+    /// building or writing anything. See [`plan`].
+    pub async fn plan(&self) -> Result<Vec<plan::Step>, anyhow::Error> {
+        let packages = self.packages_on_disk().await?;
+        let root = self.root().descriptor.clone();
+        let lockfile = self.lockfile();
+        let extra = self.inner.fingerprint_extra().await;
+        let toolchain = self.inner.toolchain_dir().await;
+        Ok(tokio::task::spawn_blocking(move || plan::plan(&packages, &root, &lockfile, &extra, &toolchain)).await.unwrap())
     }
-}
 
-/// Command-line thingie.
-#[derive(Debug, StructOpt)]
-#[structopt(about = "Package manager for Idris2.")]
-enum Opt {
-    Build,
-    Clean,
-    Run,
-}
+    /// The plan of building the root package as JSON, for `lair build --emit`: every task with
+    /// what it waits for, how long it will probably take, and whether there's anything to do for
+    /// it. Resolves the dependency graph, downloading what that needs, but builds nothing. See
+    /// [`plan::export`].
+    pub async fn plan_document(&self) -> Result<serde_json::Value, anyhow::Error> {
+        let graph = self.check_graph().await?;
+        let dag = self.dag(&graph, |_| true);
+        let steps = self.plan().await?;
+        let timings = self.inner.timings.lock().unwrap().clone();
+        Ok(plan::export(&graph, &dag, &steps, |task| Self::estimate(&timings, task)))
+    }
 
-async fn real_main() -> anyhow::Result<()> {
-    // Read in command line options
-    let opt: Opt = Opt::from_args();
+    /// Where each package reachable from the root stands, without downloading, building or
+    /// writing anything. See [`status`].
+    pub async fn status(&self) -> Result<Vec<status::Status>, anyhow::Error> {
+        let packages = self.packages_on_disk().await?;
+        let progress = self.nodes()
+            .map(|node| (node.descriptor.clone(), (node.source_progress(), node.ttc_progress())))
+            .collect();
+        let lockfile = self.lockfile();
+        let extra = self.inner.fingerprint_extra().await;
+        let toolchain = self.inner.toolchain_dir().await;
+        Ok(tokio::task::spawn_blocking(move || status::status(&packages, &progress, &lockfile, &extra, &toolchain)).await.unwrap())
+    }
 
-    let manifest: Manifest = manifest::Manifest::from_string(std::fs::read_to_string("Egg.toml")?)?;
+    /// Every package reachable from the root, as far as it can be found on disk: packages which
+    /// aren't downloaded yet are included, but not their dependencies. Reads manifests directly
+    /// rather than through the nodes, so that nothing is fetched.
+    async fn packages_on_disk(&self) -> Result<Vec<verify::Package>, anyhow::Error> {
+        let root = self.root();
+        let lockfile = self.lockfile();
+        let mut packages = Vec::new();
+        // Descriptor --> those sharing its checkout.
+        let mut aliases: BTreeMap<Descriptor, BTreeSet<Descriptor>> = BTreeMap::new();
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![root.descriptor.clone()];
+        visited.insert(root.descriptor.clone());
 
-    match opt {
-        Opt::Build => {
-            let lair = Lair::<SimpleTracer>::new(manifest, "");
-            lair.build().await?;
+        while let Some(desc) = stack.pop() {
+            let (base_path, manifest) = match layout::package_dir(&desc) {
+                None => (root.base_path().await?.to_path_buf(), Some(root.manifest().await?)),
+                Some(base_path) if base_path.is_dir() => {
+                    let path = base_path.join("Egg.toml");
+                    let mut manifest = if path.exists() {
+                        Manifest::from_path_within(path, layout::workspace_boundary(&desc).as_deref())?
+                    } else {
+                        LairInner::<Tr>::manifest_from_ipkg(&desc, &base_path, path)?
+                    };
+                    manifest.rebase_local_paths(&base_path);
+                    (base_path, Some(Arc::new(manifest)))
+                },
+                Some(base_path) => (base_path, None),
+            };
 
-            Ok(())
-        },
-        Opt::Run => {
-            let lair = Lair::<SimpleTracer>::new(manifest, "");
-            lair.build().await?;
-            lair.run().await?;
+            let dependencies = manifest.map(|manifest| manifest.dependencies_for(&self.inner.target()).iter()
+                .map(|dep| {
+                    let dep = self.inner.patched(dep);
+                    match sharing_checkout(&dep, &lockfile) {
+                        Some(shared) => {
+                            aliases.entry(shared.clone()).or_default().insert(dep);
+                            shared
+                        },
+                        None => dep,
+                    }
+                })
+                .collect::<BTreeSet<_>>());
+            for dep in dependencies.iter().flatten() {
+                if visited.insert(dep.clone()) {
+                    stack.push(dep.clone());
+                }
+            }
+            let idris_flags = self.inner.idris_flags(&desc);
+            packages.push(verify::Package { descriptor: desc, base_path, dependencies, idris_flags, aliases: BTreeSet::new() });
+        }
+        for package in &mut packages {
+            package.aliases = aliases.remove(&package.descriptor).unwrap_or_default();
+        }
+        packages.sort_by(|a, b| a.descriptor.cmp(&b.descriptor));
+        Ok(packages)
+    }
 
-            Ok(())
-        },
-        Opt::Clean => {
-            clean("build")
-        },
+    /// How far every locked dependency is behind its remote or registry, without changing
+    /// anything. See [`outdated`].
+    pub async fn outdated(&self) -> Vec<outdated::Outdated> {
+        let lockfile = self.lockfile();
+        tokio::task::spawn_blocking(move || outdated::check(&lockfile)).await.unwrap()
     }
-}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    real_main().await
+    /// The license of every package in the graph, from its manifest, or else guessed from its
+    /// `LICENSE` file. Sorted by descriptor.
+    pub async fn licenses(&self) -> Result<Vec<PackageLicense>, anyhow::Error> {
+        let graph = self.graph().await?;
+        let licenses = try_join_all(graph.packages.iter().map(|package| async move {
+            let node = self.node(&package.descriptor);
+            let manifest = node.manifest().await?;
+            let base_path = node.base_path().await?;
+
+            let (license, origin) = match &manifest.metadata.license {
+                Some(license) => (Some(license.clone()), Some(LicenseOrigin::Manifest)),
+                None => match license::detect(&base_path) {
+                    Some((license, path)) => (Some(license), Some(LicenseOrigin::File(path))),
+                    None => (None, None),
+                },
+            };
+            Ok::<_, anyhow::Error>(PackageLicense { descriptor: package.descriptor.clone(), license, origin })
+        })).await?;
+
+        Ok(licenses.into_iter().sorted_by(|a, b| a.descriptor.cmp(&b.descriptor)).collect())
+    }
+
+    /// A software bill of materials of the dependency graph, see [`sbom`].
+    pub async fn sbom(&self, format: sbom::Format) -> Result<serde_json::Value, anyhow::Error> {
+        let graph = self.graph().await?;
+        let licenses = self.licenses().await?;
+        Ok(sbom::generate(format, &graph, &self.lockfile(), &licenses))
+    }
+
+    /// The whole project as one JSON document, for editor plugins and other tools, see
+    /// [`metadata`]. Downloads what is missing, but doesn't build anything.
+    pub async fn metadata(&self) -> Result<serde_json::Value, anyhow::Error> {
+        let graph = self.graph().await?;
+        let locations = try_join_all(graph.packages.iter().map(|package| async move {
+            let node = self.node(&package.descriptor);
+            let root = node.base_path().await?;
+            node.manifest().await?;
+            let egg = root.join("Egg.toml");
+            let manifest_path = if egg.exists() {
+                egg
+            } else {
+                ipkg::find(&root)?.unwrap_or(egg)
+            };
+            Ok::<_, anyhow::Error>(metadata::PackageLocation {
+                descriptor: package.descriptor.clone(),
+                manifest_path,
+                source_dir: node.source_dir().await?,
+                main: node.main().await?,
+                root: root.to_path_buf(),
+            })
+        })).await?;
+        let idris2_version = self.inner.idris2_version().await;
+        Ok(metadata::generate(&graph, &self.lockfile(), &locations, &self.inner.target(), idris2_version))
+    }
+
+    /// Build all dependencies (but not the root package itself), and assemble the environment
+    /// idris2 needs to find them.
+    pub async fn environment(&self) -> Result<Idris2Env, anyhow::Error> {
+        self.environment_of(&self.inner.root.descriptor).await
+    }
+
+    /// Like [`Lair::environment`], but for the dependencies of `desc`, for example a workspace
+    /// member, and nothing else.
+    pub async fn environment_of(&self, desc: &Descriptor) -> Result<Idris2Env, anyhow::Error> {
+        let graph = self.check_graph().await?;
+        let wanted = graph.closure(desc);
+        self.schedule(&graph, |task| wanted.contains(&task.descriptor) && task.kind != TaskKind::Build).await?;
+        self.check_modules(&graph, std::slice::from_ref(desc)).await?;
+        self.schedule(&graph, |task| wanted.contains(&task.descriptor) && task.kind == TaskKind::Build && &task.descriptor != desc).await?;
+
+        let node = self.node(desc);
+        let idris2_path = node.dependencies_ttc_paths().await?.iter().map(|path| path.to_path_buf()).collect();
+        match self.inner.deps_mode().await {
+            DepsMode::Idris2Path => Ok(Idris2Env {
+                idris2_path,
+                ..Default::default()
+            }),
+            DepsMode::PackagePath => Ok(Idris2Env {
+                package_path: vec![layout::packages_dir(&self.inner.toolchain_dir().await)],
+                packages: node.dependencies().await?.iter()
+                    .map(|dep| dep.descriptor.package_name().to_owned())
+                    .collect(),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Build all dependencies, and work out what an LSP server started by an editor needs to see
+    /// them, see [`lsp`].
+    pub async fn lsp_config(&self) -> Result<lsp::LspConfig, anyhow::Error> {
+        let env = self.environment().await?;
+        let root = std::env::current_dir()?.join(self.root().base_path().await?);
+        let source_dir = self.root().source_dir().await?;
+        let main = self.root().main().await?;
+        Ok(lsp::LspConfig::new(&env, &root, &source_dir, &main)?)
+    }
+
+    /// Run an arbitrary command, for example `idris2 --repl` or an LSP server, with the
+    /// dependencies built and visible to it.
+    pub async fn exec(&self, program: impl AsRef<OsStr>, args: &[OsString]) -> Result<ExitStatus, anyhow::Error> {
+        let env = self.environment().await?;
+        let mut command = Command::new(program.as_ref());
+        env.apply(&mut command)?.args(args);
+        let _terminal = interrupt::hand_over();
+        let status = tokio::process::Command::from(command).status().await
+            .with_context(|| format!("Failed to run `{}`", program.as_ref().to_string_lossy()))?;
+        Ok(status)
+    }
+
+    /// Start an interactive idris2 REPL with the root package's main module loaded, and the
+    /// dependencies built and visible to it. Stdin and stdout are forwarded to the REPL.
+    ///
+    /// The root package itself is not built beforehand, since the REPL will load (and report
+    /// errors in) it anyway.
+    pub async fn repl(&self) -> Result<ExitStatus, anyhow::Error> {
+        let env = self.environment().await?;
+        let mut command = Command::new(paths::idris2());
+        env.apply(&mut command)?
+            .args(env.idris2_args())
+            .arg("--build-dir").arg(layout::toolchain_build_dir(Path::new(""), &self.inner.toolchain_dir().await))
+            .arg("--source-dir").arg(self.root().source_dir().await?)
+            .arg(self.root().main().await?);
+        let _terminal = interrupt::hand_over();
+        let status = tokio::process::Command::from(command).status().await
+            .context("Failed to run `idris2`")?;
+        Ok(status)
+    }
+
+    /// Compile the root package into an executable program with `backend`. Returns the
+    /// directory idris2 wrote it to, usually `build/exec`.
+    pub async fn compile(&self, backend: Backend) -> Result<PathBuf, anyhow::Error> {
+        let main = self.root().main().await?;
+        let exec_dir = layout::exec_dir();
+        self.compile_program(&main, self.inner.root.descriptor.package_name(), &exec_dir, backend, &[]).await?;
+        Ok(exec_dir)
+    }
+
+    /// Compile the root package's benchmarks `names` (or all of them, if none are given) with
+    /// `backend`, then run each `runs` times, see [`bench`].
+    pub async fn bench(&self, names: &[String], backend: Backend, runs: usize) -> Result<Vec<bench::Measurement>, anyhow::Error> {
+        let manifest = self.root().manifest().await?;
+        let benches: Vec<(&String, &PathBuf)> = match names {
+            [] => manifest.benches.iter().collect(),
+            names => names.iter()
+                .map(|name| match manifest.benches.get_key_value(name) {
+                    Some(bench) => Ok(bench),
+                    None => match manifest.benches.keys().join(", ") {
+                        known if known.is_empty() => anyhow::bail!("`{}` has no `[bench]` in its Egg.toml.", manifest.name),
+                        known => anyhow::bail!("`{}` has no benchmark `{}`, only {}.", manifest.name, name, known),
+                    },
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        if benches.is_empty() {
+            anyhow::bail!("`{}` has no `[bench]` in its Egg.toml.", manifest.name);
+        }
+
+        let dir = bench::dir();
+        let env = bench::release_env(backend);
+        let mut measurements = Vec::new();
+        for (name, main) in benches {
+            self.compile_program(main, name, &dir, backend, &env).await?;
+            let (dir, name) = (dir.clone(), name.clone());
+            measurements.push(tokio::task::spawn_blocking(move || bench::run(&dir, &name, backend, runs)).await.unwrap()?);
+        }
+        Ok(measurements)
+    }
+
+    /// Generate the HTML documentation of the root package, and of all its dependencies if `deps`,
+    /// into [`doc::dir`]. Returns the index page, see [`doc`].
+    pub async fn doc(&self, deps: bool) -> Result<PathBuf, anyhow::Error> {
+        let graph = self.check_graph().await?;
+        let root = self.inner.root.descriptor.clone();
+        let mut packages: Vec<&GraphPackage> = graph.packages.iter()
+            .filter(|package| deps || package.descriptor == root)
+            .collect();
+        packages.sort_by_key(|package| (package.descriptor != root, package.descriptor.package_name().to_owned()));
+
+        let dest = doc::dir();
+        if dest.exists() {
+            std::fs::remove_dir_all(&dest)?;
+        }
+        let toolchain = self.inner.toolchain_dir().await;
+        let mut index = Vec::new();
+        for package in packages {
+            let desc = &package.descriptor;
+            let env = self.environment_of(desc).await?;
+            let node = self.node(desc);
+            let base_path = std::env::current_dir()?.join(node.base_path().await?);
+            let source_dir = std::env::current_dir()?.join(node.source_dir().await?);
+            let build_dir = layout::toolchain_build_dir(&base_path, &toolchain);
+            let name = desc.package_name().to_owned();
+
+            let modules = modules::scan(&source_dir)?;
+            let ipkg_path = doc::ipkg_path(&name);
+            std::fs::create_dir_all(ipkg_path.parent().unwrap())?;
+            std::fs::write(&ipkg_path, doc::ipkg(&name, &package.version, &source_dir, &build_dir, &modules, &env.packages))?;
+
+            let guard = self.inner.tracer.building(desc);
+            let mut command = Command::new(paths::idris2());
+            env.apply(&mut command)?
+                .args(env.idris2_args())
+                .arg("--mkdoc").arg(&ipkg_path);
+            self.inner.run_idris2(command, desc, &guard).await
+                .map_err(|e| e.in_package(self.inner.provenance(desc, Phase::Building)))?;
+
+            let (generated, dest_clone, name_clone) = (build_dir.join("docs"), dest.clone(), name.clone());
+            tokio::task::spawn_blocking(move || doc::collect(&generated, &dest_clone, &name_clone)).await.unwrap()?;
+            guard.success(&dest);
+            index.push((name, package.version.clone(), node.manifest().await?.metadata.description.clone()));
+        }
+        doc::write_index(&dest, &index)?;
+        Ok(dest.join("index.html"))
+    }
+
+    /// Compile the program `name` of the root package, whose main module is `main`, with
+    /// `backend` into `output_dir`, passing `env` to idris2.
+    async fn compile_program(&self, main: &Path, name: &str, output_dir: &Path, backend: Backend, env: &[(&str, String)]) -> Result<(), anyhow::Error> {
+        let build_dir = layout::toolchain_build_dir(Path::new(""), &self.inner.toolchain_dir().await);
+        self.compile_program_in(main, name, &build_dir, output_dir, backend, env).await
+    }
+
+    /// Like [`Lair::compile_program`], but with the root package's TTC files going into
+    /// `build_dir`.
+    async fn compile_program_in(&self, main: &Path, name: &str, build_dir: &Path, output_dir: &Path, backend: Backend, env: &[(&str, String)]) -> Result<(), anyhow::Error> {
+        let mut args: Vec<OsString> = vec!["--output-dir".into(), output_dir.into(), "--cg".into(), backend.cg().into()];
+        args.extend(["-o".into(), name.into()]);
+        self.run_on_root(main, build_dir, &args, env, output_dir).await
+    }
+
+    /// Run idris2 on the module `main` of the root package, with its dependencies, building into
+    /// `build_dir`, with `args` and the environment variables `env`. `output` is where the result
+    /// ends up, for the tracer.
+    async fn run_on_root(&self, main: &Path, build_dir: &Path, args: &[OsString], env: &[(&str, String)], output: &Path) -> Result<(), anyhow::Error> {
+        let idris2_env = self.environment().await?;
+        let root = &self.inner.root.descriptor;
+
+        let guard = self.inner.tracer.building(root);
+        let mut command = Command::new(paths::idris2());
+        idris2_env.apply(&mut command)?
+            .args(idris2_env.idris2_args())
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .arg("--build-dir").arg(build_dir)
+            .arg("--source-dir").arg(self.root().source_dir().await?)
+            .args(args)
+            .arg(main);
+        self.inner.run_idris2(command, root, &guard).await
+            .map_err(|e| e.in_package(self.inner.provenance(root, Phase::Building)))?;
+
+        guard.success(output);
+        Ok(())
+    }
+
+    /// Build the root package twice, each time into a directory of its own below
+    /// [`reproducible::dir`], and compare what came out. Returns the files which differ, and how
+    /// many files there were. See [`reproducible`].
+    pub async fn reproducible_check(&self) -> Result<(Vec<reproducible::Difference>, usize), anyhow::Error> {
+        let main = self.root().main().await?;
+        let name = self.inner.root.descriptor.package_name();
+        let program = reproducible::has_main(&main)?;
+        let dir = reproducible::dir();
+        if dir.exists() {
+            std::fs::remove_dir_all(paths::long(&dir))?;
+        }
+
+        let started = timestamp::now();
+        for run in reproducible::RUNS {
+            let build_dir = dir.join(run).join("build");
+            if program {
+                self.compile_program_in(&main, name, &build_dir, &dir.join(run).join("exec"), Backend::Chez, &[]).await?;
+            } else {
+                self.run_on_root(&main, &build_dir, &["--check".into()], &[], &build_dir).await?;
+            }
+        }
+        let built = (started, timestamp::now());
+
+        let [first, second] = reproducible::RUNS.map(|run| dir.join(run));
+        Ok(tokio::task::spawn_blocking(move || reproducible::compare(&first, &second, built)).await.unwrap()?)
+    }
+
+    /// Compile the root package with `backend`, and copy the program, along with everything it
+    /// needs at runtime and the license files of all packages, to `dest`. Returns the path of the
+    /// program to run. See [`bundle`].
+    pub async fn bundle(&self, backend: Backend, dest: &Path) -> Result<PathBuf, anyhow::Error> {
+        let exec_dir = self.compile(backend).await?;
+
+        let graph = self.graph().await?;
+        let licenses = try_join_all(graph.packages.iter().map(|package| async move {
+            let base_path = self.node(&package.descriptor).base_path().await?;
+            Ok::<_, anyhow::Error>((package.descriptor.name().to_owned(), license::files(&base_path)))
+        })).await?;
+
+        let name = self.inner.root.descriptor.package_name().to_owned();
+        let dest = dest.to_owned();
+        tokio::task::spawn_blocking(move || bundle::assemble(&exec_dir, &name, backend, &dest, &licenses)).await.unwrap()
+    }
+
+    /// Build the root package, then write its sources, a generated ipkg and metadata as an
+    /// archive idris2 can install without Lair, to `dest`, and its checksum next to it. Returns
+    /// the checksum. See [`package`].
+    pub async fn package(&self, dest: &Path) -> Result<String, anyhow::Error> {
+        self.build().await?;
+
+        let graph = self.graph().await?;
+        let root = self.root();
+        let manifest = root.manifest().await?;
+        let dependencies = graph.package(&root.descriptor).into_iter()
+            .flat_map(|package| &package.dependencies)
+            .map(|desc| package::Dependency {
+                name: desc.package_name().to_owned(),
+                version: graph.package(desc).map(|package| package.version.clone()).unwrap_or_default(),
+                source: desc.source(),
+            })
+            .collect();
+        let contents = package::Package {
+            name: manifest.name.to_string(),
+            version: manifest.version.clone(),
+            metadata: manifest.metadata.clone(),
+            idris2: manifest.idris2.clone(),
+            built_with: self.inner.idris2_version().await.map(|version| version.to_string()),
+            dependencies,
+            source_dir: root.source_dir().await?.to_path_buf(),
+            licenses: license::files(&root.base_path().await?),
+        };
+
+        let dest = dest.to_owned();
+        tokio::task::spawn_blocking(move || package::assemble(&contents, &dest)).await.unwrap()
+    }
+
+    /// The manifests and source directories of the root, the workspace members and `path`
+    /// dependencies, along with the lockfile, for [`watch`].
+    pub async fn watched(&self) -> Result<watch::Paths, anyhow::Error> {
+        let graph = self.graph().await?;
+        let mut paths = watch::Paths::root();
+        for package in &graph.packages {
+            if !matches!(package.descriptor, Descriptor::Root { .. } | Descriptor::Local { .. }) {
+                continue;
+            }
+            let node = self.node(&package.descriptor);
+            let base_path = node.base_path().await?;
+            let manifest = node.manifest().await?;
+            let egg = base_path.join("Egg.toml");
+            let manifest_path = if egg.exists() {
+                egg
+            } else {
+                ipkg::find(&base_path)?.unwrap_or(egg)
+            };
+            paths.manifests.push(manifest_path);
+            if let Some(workspace) = &manifest.workspace {
+                paths.manifests.push(base_path.join(workspace));
+            }
+            paths.source_dirs.push(node.source_dir().await?);
+        }
+        paths.manifests.sort();
+        paths.manifests.dedup();
+        Ok(paths)
+    }
+
+    /// Build the root package, then run its script `name` with `args`, see [`scripts`].
+    pub async fn script(&self, name: &str, args: &[OsString]) -> Result<ExitStatus, anyhow::Error> {
+        let manifest = self.root().manifest().await?;
+        let Some(script) = manifest.scripts.get(name) else {
+            match manifest.scripts.keys().join(", ") {
+                known if known.is_empty() => anyhow::bail!("`{}` has no `[scripts]` in its Egg.toml.", manifest.name),
+                known => anyhow::bail!("`{}` has no script `{}`, only {}.", manifest.name, name, known),
+            }
+        };
+        self.build().await?;
+        self.run_script(name, script, args).await
+    }
+
+    /// Run the root package's [`scripts::POST_BUILD`] script, if it has one. Expects everything to
+    /// be built already.
+    pub async fn post_build(&self) -> Result<(), anyhow::Error> {
+        let manifest = self.root().manifest().await?;
+        if let Some(script) = manifest.scripts.get(scripts::POST_BUILD) {
+            let status = self.run_script(scripts::POST_BUILD, script, &[]).await?;
+            if !status.success() {
+                anyhow::bail!("The `{}` script failed ({}).", scripts::POST_BUILD, status);
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_script(&self, name: &str, script: &str, args: &[OsString]) -> Result<ExitStatus, anyhow::Error> {
+        if scripts::needs_exe(script) {
+            self.compile(self.inner.target().backend).await?;
+        }
+        let env = self.environment().await?;
+        let root = std::env::current_dir()?.join(self.root().base_path().await?);
+        let package = self.root().descriptor.package_name().to_owned();
+        let mut command = scripts::command(script, args);
+        env.apply(&mut command)?
+            .current_dir(&root)
+            .env("LAIR_PACKAGE", &package)
+            .env("LAIR_BUILD_DIR", root.join(layout::build_dir()))
+            .env("LAIR_EXE", root.join(layout::exec_dir()).join(&package));
+        self.inner.tracer.script_started(name, script);
+        let _terminal = interrupt::hand_over();
+        let status = tokio::process::Command::from(command).status().await
+            .with_context(|| format!("Failed to run the `{}` script", name))?;
+        Ok(status)
+    }
+
+    /// Run the root package's `main`, or `entrypoint`, see [`Lair::run_package`].
+    pub async fn run(&self, entrypoint: Option<&Entrypoint>) -> Result<ExitStatus, anyhow::Error> {
+        self.run_package(&self.inner.root.descriptor, entrypoint).await
+    }
+
+    /// Run the `main` function of `desc`, for example a workspace member, or `entrypoint` from
+    /// any module in its `src` instead. idris2 checks that module first if nothing built it yet.
+    /// Like [`Lair::run`], expects `desc` to be built already.
+    ///
+    /// Returns the program's exit status, which is idris2's own if it refused to run `function`.
+    pub async fn run_package(&self, desc: &Descriptor, entrypoint: Option<&Entrypoint>) -> Result<ExitStatus, anyhow::Error> {
+        let env = self.environment_of(desc).await?; // will complete instantly, because we've already built everything.
+        let node = self.node(desc);
+        let base_path = node.base_path().await?;
+        let source_dir = node.source_dir().await?;
+        let (module, function) = match entrypoint {
+            None => (node.main().await?, "main".to_owned()),
+            Some(entrypoint) => match modules::file_of(&source_dir, &entrypoint.module) {
+                Some(module) => (module, entrypoint.to_string()),
+                None => anyhow::bail!("`{}` has no module `{}` in `{}`.", desc.name(), entrypoint.module, source_dir.display()),
+            },
+        };
+
+        let mut command = Command::new(paths::idris2());
+        env.apply(&mut command)?
+            .args(env.idris2_args())
+            .arg("--build-dir").arg(layout::toolchain_build_dir(&base_path, &self.inner.toolchain_dir().await))
+            .arg("--source-dir").arg(source_dir)
+            .arg(module)
+            .arg("--exec").arg(function);
+        let _terminal = interrupt::hand_over();
+        let status = tokio::process::Command::from(command).status().await
+            .context("Failed to run `idris2`")?;
+        Ok(status)
+    }
+}
+
+impl<Tr: Tracer> LairInner<Tr> {
+    /// The node for `desc`, or rather for its replacement if `[patch]` says so.
+    pub fn node(self: &Arc<Self>, desc: &Descriptor) -> Arc<Node<Tr>> {
+        let desc = self.patched(desc);
+        self.node_in(&desc)
+    }
+
+    /// Where `manifest` declares its dependency `dep`, and the root's `[patch]` replaces it. `None`
+    /// for manifests not read from an `Egg.toml`.
+    pub fn origin(self: &Arc<Self>, manifest: &Manifest, dep: &Descriptor) -> Option<graph::Origin> {
+        let declared = manifest.declarations.get(dep)?.clone();
+        let patched = self.patched(dep);
+        Some(graph::Origin {
+            patched: (&patched != dep)
+                .then(|| self.root.peek_manifest()?.patch_declarations.get(dep.name()).cloned())
+                .flatten(),
+            dependency: self.node(&patched).descriptor.clone(),
+            declared,
+        })
+    }
+
+    /// The other packages of the root's workspace, see [`Manifest::members`]. Their names are
+    /// taken from their manifests.
+    fn members(&self) -> Result<Vec<Descriptor>, anyhow::Error> {
+        self.root.peek_manifest().into_iter().flat_map(|manifest| manifest.members.clone())
+            .map(|path| {
+                let manifest = Manifest::from_path(path.join("Egg.toml"))
+                    .with_context(|| format!("Failed to read the workspace member `{}`", path.display()))?;
+                Ok(Descriptor::Local { name: manifest.name, package: None, path })
+            })
+            .collect()
+    }
+
+    /// What conditional dependencies are chosen for, see [`Lair::with_target`].
+    pub(crate) fn target(&self) -> Target {
+        self.target.lock().unwrap().clone()
+    }
+
+    /// For errors happening to `desc` while Lair is at `phase`.
+    fn provenance(&self, desc: &Descriptor, phase: Phase) -> Provenance {
+        let resolution = self.resolved.read().unwrap().get(desc).cloned();
+        let introduced_by = resolution.and_then(|resolution| self.db.get(&resolution, self.profiler())?.introduced_by().cloned());
+        Provenance {
+            desc: desc.clone(),
+            phase,
+            introduced_by,
+            commit: self.lockfile.lock().unwrap().locked(desc).map(|locked| locked.commit.clone()),
+        }
+    }
+
+    /// See [`Lair::with_offline`].
+    fn offline(&self) -> bool {
+        *self.offline.lock().unwrap()
+    }
+
+    fn keep_going(&self) -> bool {
+        *self.keep_going.lock().unwrap()
+    }
+
+    fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.get().map(Arc::as_ref)
+    }
+
+    /// Record the phase `name` until the returned span is dropped, if profiling.
+    fn phase(&self, name: &str) -> Option<profile::Span<'_>> {
+        self.profiler().map(|profiler| profiler.phase(name))
+    }
+
+    /// Fail with the error downloading `url` failed with in an earlier run, unless that was a
+    /// while ago, or we're told to try again. Blocking.
+    fn check_failed_before(refresh: bool, url: &str) -> Result<(), SourceFetchError> {
+        match refresh {
+            true => Ok(()),
+            false => fetch_failures::check(url),
+        }
+    }
+
+    /// What to use instead of `desc`, according to the root manifest's `[patch]` section.
+    fn patched(&self, desc: &Descriptor) -> Descriptor {
+        match (desc, self.patches.get(desc.name())) {
+            (Descriptor::Root { .. }, _) | (_, None) => desc.clone(),
+            (_, Some(patch)) => {
+                let mut patch = patch.clone();
+                // Patching an aliased dependency shouldn't need to repeat its actual name.
+                if patch.package_name() == patch.name() && desc.package_name() != desc.name() {
+                    patch.set_package(desc.package().cloned());
+                }
+                patch
+            },
+        }
+    }
+
+    /// How `desc` resolves, see [`ResolvedDescriptor`]. The same for the whole run.
+    fn resolution(&self, desc: &Descriptor) -> ResolvedDescriptor {
+        if let Some(resolution) = self.resolved.read().unwrap().get(desc) {
+            return resolution.clone();
+        }
+        let mut resolved = self.resolved.write().unwrap();
+        resolved.entry(desc.clone())
+            .or_insert_with(|| ResolvedDescriptor::new(desc, self.lockfile.lock().unwrap().get(desc)))
+            .clone()
+    }
+
+    /// If `desc` is a registry package its registry doesn't have, but has some with similar
+    /// names, ask which one was meant, and have `desc` resolve to that one from now on, so that
+    /// its node is the chosen package's. Asks at most once per descriptor, and not at all once
+    /// `desc` was resolved or locked. Nothing to do for other descriptors.
+    async fn correct_registry_name(self: &Arc<Self>, desc: &Descriptor) -> Result<(), SourceFetchError> {
+        let Descriptor::Registry { name, package, registry, version } = desc else { return Ok(()) };
+        let prompt = *self.prompt.lock().unwrap();
+        let settled = || self.resolved.read().unwrap().contains_key(desc) || self.lockfile.lock().unwrap().locked(desc).is_some();
+        if prompt == Prompt::No || settled() {
+            return Ok(());
+        }
+
+        // The index is the one of the url, like for fetching.
+        let Descriptor::Registry { registry: registry_clone, .. } = desc.expanded()? else { unreachable!() };
+        let updated = self.registries.lock().unwrap().entry(registry_clone.clone()).or_default().clone();
+        let mut updated = updated.lock_owned().await;
+        // Someone else asked while we waited.
+        if settled() {
+            return Ok(());
+        }
+        let offline = self.offline();
+        let (requirement, wanted) = (version.clone(), desc.package_name().to_owned());
+        let chosen = tokio::task::spawn_blocking(move || {
+            let index = match *updated || offline {
+                true => registry::Index::open(&registry_clone),
+                // Fetching fails properly if the index can't be had at all.
+                false => registry::Index::update(&registry_clone).ok().or_else(|| registry::Index::open(&registry_clone)),
+            };
+            *updated = true;
+            let Some(index) = index else { return Ok(None) };
+            match index.pick(&wanted, &requirement) {
+                Err(SourceFetchError::NotInRegistry { name, registry, similar }) if !similar.is_empty() => {
+                    let question = format!("Registry `{}` has no package `{}`. Did you mean one of these?", registry, name);
+                    Ok(prompt.choose(&question, &similar).map(|i| similar[i].clone()))
+                },
+                _ => Ok::<_, SourceFetchError>(None),
+            }
+        }).await.unwrap()?;
+
+        let Some(chosen) = chosen else { return Ok(()) };
+        let chosen: PackageName = chosen.parse().map_err(|e: String| SourceFetchError::Other(Arc::new(anyhow::anyhow!(e))))?;
+        let (name, package) = match package {
+            // Keep the alias, only the actual package was misspelled.
+            Some(_) => (name.clone(), Some(chosen)),
+            None => (chosen, None),
+        };
+        let chosen = Descriptor::Registry { name, package, registry: registry.clone(), version: version.clone() };
+        let resolution = self.resolution(&chosen);
+        self.resolved.write().unwrap().insert(desc.clone(), resolution);
+        Ok(())
+    }
+
+    /// If `desc` is a git dependency following the default branch, and isn't locked, ask the
+    /// remote which branch that is, so that it shares its node with dependencies naming that
+    /// branch, see [`ResolvedDescriptor`]. Nothing to do otherwise, or if the remote doesn't say.
+    async fn resolve_default_branch(self: &Arc<Self>, desc: &Descriptor) {
+        if !matches!(desc, Descriptor::Git { version: GitVersion::DefaultBranch, .. }) || self.offline() {
+            return;
+        }
+        if self.resolved.read().unwrap().contains_key(desc) || self.lockfile.lock().unwrap().get(desc).is_some() {
+            return;
+        }
+        let Ok(Descriptor::Git { url, .. }) = desc.expanded() else { return };
+        let Ok(branch) = tokio::task::spawn_blocking(move || fetch::remote_default_branch(&url)).await.unwrap() else { return };
+        let Some(named) = ResolvedDescriptor::following(desc, &branch) else { return };
+        let resolution = self.resolution(&named);
+        self.resolved.write().unwrap().entry(desc.clone()).or_insert(resolution);
+    }
+
+    /// The descriptor of the node `desc` shares, if another descriptor resolving to the same
+    /// commit got there first. Otherwise `desc` itself. Doesn't create any nodes.
+    pub fn known_as(&self, desc: &Descriptor) -> Descriptor {
+        let Some(resolution) = self.resolved.read().unwrap().get(desc).cloned() else { return desc.clone() };
+        match self.db.get(&resolution, self.profiler()) {
+            Some(node) => node.descriptor.clone(),
+            None => desc.clone(),
+        }
+    }
+
+    /// Descriptors which share the node of another one, with that one's descriptor.
+    pub fn aliases(&self) -> Vec<graph::Alias> {
+        let resolved = self.resolved.read().unwrap().clone();
+        resolved.into_iter()
+            .filter_map(|(desc, resolution)| {
+                let node = self.db.get(&resolution, self.profiler())?;
+                (node.descriptor != desc).then(|| graph::Alias { descriptor: desc, same_as: node.descriptor.clone() })
+            })
+            .collect()
+    }
+
+    fn node_in(self: &Arc<Self>, desc: &Descriptor) -> Arc<Node<Tr>> {
+        let resolution = self.resolution(desc);
+        // Usually there already is one, and that only takes a read lock.
+        // A misspelled registry package is the one picked in its place, see
+        // `Self::correct_registry_name`, otherwise that's `desc` itself.
+        let desc = match &resolution {
+            ResolvedDescriptor::Unresolved(resolved) => resolved,
+            ResolvedDescriptor::Git { .. } | ResolvedDescriptor::Branch { .. } => desc,
+        };
+        // If another task made one meanwhile, ours is dropped, and only theirs was new.
+        let mut made = None;
+        let node = self.db.get_or_insert_with(resolution.clone(), self.profiler(), || {
+            let mut desc = desc.clone();
+            self.interner.descriptor(&mut desc);
+            let desc = &desc;
+            let desc_clone1: Descriptor = desc.clone();
+            let desc_clone2: Descriptor = desc.clone();
+            let desc_clone3: Descriptor = desc.clone();
+
+            // Create a new node, with recipes on how to obtain its source/ttcs, which will be
+            // invoked when necessary.
+            // This only creates futures, which may or may not be invoked in the future (harr harr).
+            let node = Arc::new(Node::new(
+                Arc::downgrade(self),
+                desc.clone(),
+                Lazy::new_weak(self, move |lair| async move {
+                    lair.fetch_manifest(desc_clone1.clone()).await
+                        .map_err(|e| e.in_package(lair.provenance(&desc_clone1, Phase::ReadingManifest)))
+                }),
+                Lazy::new_weak_retrying(self, FETCH_RETRIES, |source| source.as_ref().is_err_and(SourceFetchError::is_transient), move |lair, previous| {
+                    let desc = desc_clone2.clone();
+                    async move {
+                        let retrying = match &previous {
+                            Some(Err(e)) => {
+                                // It didn't fail after all, not yet anyway, and is only counted as
+                                // one download.
+                                lair.stats.failed.fetch_sub(1, Ordering::Relaxed);
+                                lair.stats.fetched.fetch_sub(1, Ordering::Relaxed);
+                                let e = match e {
+                                    SourceFetchError::Package { error, .. } => error,
+                                    e => e,
+                                };
+                                lair.tracer.retrying_fetch(&desc, &e.to_string());
+                                true
+                            },
+                            _ => false,
+                        };
+                        let source = lair.fetch_source(desc.clone(), retrying).await;
+                        if source.is_err() {
+                            lair.stats.failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                        source.map(|path| lair.interner.path(path)).map_err(|e| e.in_package(lair.provenance(&desc, Phase::Fetching)))
+                    }
+                }),
+                Lazy::new_weak(self, move |lair| async move {
+                    lair.build_ttc(desc_clone3.clone()).await
+                        .map(|ttc| lair.interner.path(ttc))
+                        .map_err(|e| e.in_package(lair.provenance(&desc_clone3, Phase::Building)))
+                }),
+            ));
+            made = Some(node.clone());
+            node
+        });
+        if made.is_some_and(|made| Arc::ptr_eq(&made, &node)) {
+            self.tracer.new_descriptor(&node.descriptor);
+        }
+        node
+    }
+
+    /// Recipe for building TTC files.
+    async fn build_ttc(self: &Arc<Self>, desc: Descriptor) -> Result<PathBuf, BuildTtcError> {
+        let node = self.node(&desc);
+
+        // Build dependencies in parallel (and recurse, kind of). Then unpack results, making sure
+        // they all built correctly, and collect into an IDRIS2_PATH.
+        let (base_path, deps_paths) = join(node.base_path(), node.dependencies_ttc_paths()).await;
+        let deps_paths = deps_paths?;
+        let base_path = base_path?;
+
+        let mode = self.deps_mode().await;
+        let toolchain = self.toolchain_dir().await;
+        let ttc = layout::ttc_dir(&base_path, &toolchain); // `{base_path}/build/idris2-0.6.0/ttc`
+        let installed = match (mode, &desc) {
+            (DepsMode::PackagePath, Descriptor::Root { .. }) | (DepsMode::Idris2Path, _) => None,
+            (DepsMode::PackagePath, _) => Some(layout::installed_package_dir(&desc, &node.manifest().await?.version, &toolchain)),
+        };
+        let link = *self.link_local.lock().unwrap() && matches!(desc, Descriptor::Local { .. });
+
+        // Skip the build if nothing changed since the last one.
+        let dep_fingerprints: Vec<String> = node.dependencies().await?.iter()
+            .filter_map(|dep| dep.fingerprint().map(str::to_owned))
+            .collect();
+        let flags = self.idris_flags(&desc);
+        let extra = Fingerprint::extra_with_flags(&self.fingerprint_extra().await, &flags);
+        let remote_cache = self.remote_cache.lock().unwrap().clone().filter(|_| !self.offline());
+        let remote_key = match remote_cache {
+            Some(_) => self.remote_key(&node, &extra).await?,
+            None => None,
+        };
+        if let Some(key) = &remote_key {
+            node.set_remote_key(key.clone());
+        }
+        // Only worth it for the package being edited, see the docs of `fingerprint`.
+        let checked = match &desc {
+            Descriptor::Root { .. } => Some((node.source_dir().await?, node.main().await?)),
+            _ => None,
+        };
+        let fingerprint = {
+            let (desc, base_path) = (desc.clone(), base_path.clone());
+            tokio::task::spawn_blocking(move || {
+                let fingerprint = Fingerprint::compute(&desc, &base_path, &extra, &dep_fingerprints)?;
+                match checked {
+                    Some((source_dir, main)) => fingerprint.with_checked(&source_dir, &main),
+                    None => Ok(fingerprint),
+                }
+            }).await.unwrap()?
+        };
+        let previous = Fingerprint::load(&desc, &toolchain);
+        if previous.as_ref().is_some_and(|previous| fingerprint.builds_like(previous)) && ttc.is_dir() && installed.as_ref().is_none_or(|dir| dir.is_dir()) {
+            self.stats.cached.fetch_add(1, Ordering::Relaxed);
+            self.tracer.build_fresh(&desc, &ttc);
+            if previous.as_ref() != Some(&fingerprint) {
+                fingerprint.save(&desc, &toolchain)?;
+            }
+            // Installed before `link_local` was switched, or copied since linking failed, in which
+            // case we try again.
+            if let Some(installed) = installed.as_ref().filter(|dir| files::is_link(dir) != link) {
+                install_package(&ttc, installed, link)?;
+            }
+            node.set_fingerprint(fingerprint.combined());
+            return Ok(ttc);
+        }
+        if previous.is_some_and(|previous| previous.deps != fingerprint.deps) {
+            // Built against different dependencies, don't let idris2 trust those TTC files.
+            match std::fs::remove_dir_all(paths::long(&ttc)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {},
+            }
+        }
+
+        if let (Some(cache), Some(key)) = (&remote_cache, &remote_key) {
+            let (cache, key, dest) = (cache.clone(), key.clone(), ttc.clone());
+            match tokio::task::spawn_blocking(move || cache.fetch(&key, &dest)).await.unwrap() {
+                Ok(true) => {
+                    if let Some(installed) = &installed {
+                        install_package(&ttc, installed, link)?;
+                    }
+                    fingerprint.save(&desc, &toolchain)?;
+                    node.set_fingerprint(fingerprint.combined());
+                    self.stats.remote.fetch_add(1, Ordering::Relaxed);
+                    self.tracer.remote_cache_hit(&desc, &ttc);
+                    return Ok(ttc);
+                },
+                Ok(false) => {},
+                Err(e) => self.tracer.remote_cache_failed(&desc, &e.to_string()),
+            }
+        }
+
+        let guard = self.tracer.building(&desc);
+        let sandbox = *self.sandbox.lock().unwrap();
+        // In the sandbox, idris2 runs in the package's directory, so it needs absolute paths.
+        let cwd = std::env::current_dir()?;
+        let sandboxed = |path: PathBuf| match sandbox.applies_to(&desc) {
+            true => cwd.join(path),
+            false => path,
+        };
+        let build_dir = layout::toolchain_build_dir(&base_path, &toolchain); // `{base_path}/build/idris2-0.6.0`
+        let source_dir = node.source_dir().await?; // `{base_path}/src`
+        let main_idr = node.main().await?; // `{base_path}/src/AmazingTool.idr`
+
+        let mut command = Command::new(paths::idris2());
+        command
+            .arg("--build-dir").arg(sandboxed(build_dir))
+            .arg("--source-dir").arg(sandboxed(source_dir))
+            .args(&flags)
+            .arg("--check");
+        match mode {
+            DepsMode::Idris2Path => {
+                let deps_paths: Vec<PathBuf> = deps_paths.iter().map(|path| sandboxed(path.to_path_buf())).collect();
+                command.env("IDRIS2_PATH", deps_paths.join_idris2()?);
+            },
+            DepsMode::PackagePath => {
+                command.env("IDRIS2_PACKAGE_PATH", [sandboxed(layout::packages_dir(&toolchain))].join_idris2()?);
+                for dep in node.dependencies().await? {
+                    command.arg("-p").arg(dep.descriptor.package_name());
+                }
+            },
+        }
+        command.arg(sandboxed(main_idr));
+        if sandbox.applies_to(&desc) {
+            command = sandbox.confine(command, &base_path).map_err(|e| BuildTtcError::Other(Arc::new(e)))?;
+        }
+        let worker = self.remote_exec.lock().unwrap().clone()
+            .filter(|_| !self.offline() && !matches!(desc, Descriptor::Root { .. }));
+        let started = Instant::now();
+        let remote = match worker {
+            Some(worker) => match self.build_remotely(&worker, &node, &ttc, &guard).await {
+                Ok(result) => Some(result),
+                Err(e) => {
+                    self.tracer.remote_exec_failed(&desc, &worker.to_string(), &format!("{:#}", e));
+                    None
+                },
+            },
+            None => None,
+        };
+        match remote {
+            Some(result) => result,
+            None => self.run_idris2(command, &desc, &guard).await,
+        }.inspect_err(|_| {
+            self.stats.failed.fetch_add(1, Ordering::Relaxed);
+        })?;
+        let took = started.elapsed();
+        self.timings.lock().unwrap().record(&desc, took);
+        self.stats.build_millis.fetch_add(took.as_millis().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+
+        if let Some(installed) = &installed {
+            install_package(&ttc, installed, link)?;
+        }
+        fingerprint.save(&desc, &toolchain)?;
+        node.set_fingerprint(fingerprint.combined());
+        self.stats.built.fetch_add(1, Ordering::Relaxed);
+        if let (Some(cache), Some(key)) = (remote_cache.filter(RemoteCache::uploads), remote_key) {
+            let source = ttc.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || cache.store(&key, &source)).await.unwrap() {
+                self.tracer.remote_cache_failed(&desc, &e.to_string());
+            }
+        }
+        guard.success(&ttc);
+        Ok(ttc)
+    }
+
+    /// Run `command`, an idris2 invocation building `desc`, passing its output to `guard` as it
+    /// comes, and write all of it to `desc`'s build log. Fails if idris2 does, or if it takes
+    /// longer than the build timeout.
+    async fn run_idris2(&self, command: Command, desc: &Descriptor, guard: &Tr::Build) -> Result<(), BuildTtcError> {
+        let command_line = display_command(&command);
+        guard.command(&command_line);
+        let timeout = *self.build_timeout.lock().unwrap();
+        let (status, lines) = run_streaming(command, guard, timeout).await?;
+
+        let log = layout::log_path(desc);
+        write_build_log(&log, &command_line, &lines, status)?;
+        match (status, timeout) {
+            (Some(status), _) if status.success() => Ok(()),
+            // Also when Ctrl-C got to idris2 before us.
+            _ if interrupt::requested() => Err(BuildTtcError::Interrupted { name: desc.name().to_owned(), log }),
+            (Some(status), _) => Err(BuildTtcError::Idris2 { name: desc.name().to_owned(), status, log }),
+            (None, timeout) => Err(BuildTtcError::Timeout {
+                name: desc.name().to_owned(),
+                timeout: timeout.unwrap_or_default(),
+                log,
+            }),
+        }
+    }
+
+    /// Build `node` on `worker` into `ttc`, passing its output to `guard` once it's done, see
+    /// [`remote_exec`]. Fails if the worker can't build it at all, the inner error is idris2
+    /// failing there.
+    async fn build_remotely(&self, worker: &Worker, node: &Node<Tr>, ttc: &Path, guard: &Tr::Build) -> Result<Result<(), BuildTtcError>, anyhow::Error> {
+        let desc = &node.descriptor;
+        let toolchain = self.toolchain.get().await.as_ref().context("Can't tell which idris2 this is, so no worker can match it")?;
+        let toolchain_dir = self.toolchain_dir().await;
+        let base_path = node.base_path().await?;
+        let in_job = |path: &Path| Path::new(remote_exec::PACKAGE).join(path.strip_prefix(&base_path).unwrap_or(path)).to_string_lossy().into_owned();
+
+        let (mut idris2_path, mut package_path, mut packages, mut inputs) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        match self.deps_mode().await {
+            DepsMode::Idris2Path => for (i, path) in node.dependencies_ttc_paths().await?.into_iter().enumerate() {
+                let there = Path::new("deps").join(i.to_string());
+                idris2_path.push(there.clone());
+                inputs.push((there, path.to_path_buf()));
+            },
+            DepsMode::PackagePath => {
+                package_path.push(PathBuf::from("packages"));
+                // Everything it loads, not just its direct dependencies.
+                let mut installed = BTreeMap::new();
+                let mut stack = node.dependencies().await?;
+                while let Some(dep) = stack.pop() {
+                    let dir = layout::installed_package_dir(&dep.descriptor, &dep.manifest().await?.version, &toolchain_dir);
+                    if installed.insert(dir.file_name().unwrap_or_default().to_owned(), dir).is_none() {
+                        stack.extend(dep.dependencies().await?);
+                    }
+                }
+                inputs.extend(installed.into_iter().map(|(name, dir)| (Path::new("packages").join(name), dir)));
+                for dep in node.dependencies().await? {
+                    packages.push(dep.descriptor.package_name().to_owned());
+                }
+            },
+        }
+        let job = remote_exec::Job {
+            version: remote_exec::JOB_VERSION,
+            toolchain: toolchain.full.clone(),
+            build_dir: PathBuf::from(in_job(&layout::toolchain_build_dir(&base_path, &toolchain_dir))),
+            source_dir: PathBuf::from(in_job(&node.source_dir().await?)),
+            flags: self.idris_flags(desc),
+            packages,
+            main: PathBuf::from(in_job(&node.main().await?)),
+            idris2_path,
+            package_path,
+            ttc: PathBuf::from(in_job(ttc)),
+            timeout_secs: self.build_timeout.lock().unwrap().map(|timeout| timeout.as_secs().max(1)),
+            sandbox: Some(*self.sandbox.lock().unwrap()).filter(|sandbox| sandbox.applies_to(desc)).unwrap_or_default(),
+        };
+        // The worker would refuse it anyway.
+        job.check()?;
+        let command_line = format!("{} (on {})", std::iter::once("idris2".to_owned()).chain(job.args()).join(" "), worker);
+        guard.command(&command_line);
+
+        let (worker_clone, ttc_clone) = (worker.clone(), ttc.to_owned());
+        let outcome = tokio::task::spawn_blocking(move || {
+            let result = worker_clone.run(remote_exec::pack(&job, &base_path, &inputs)?)?;
+            remote_exec::unpack(&result, &ttc_clone)
+        }).await.unwrap()?;
+
+        for line in &outcome.output {
+            guard.output_line(line);
+        }
+        let log = layout::log_path(desc);
+        let name = desc.name().to_owned();
+        write_build_log(&log, &command_line, &outcome.output, outcome.code.map(|code| format!("exit status: {}", code)))?;
+        Ok(match outcome.code {
+            Some(0) => Ok(()),
+            Some(code) => Err(BuildTtcError::RemoteIdris2 { name, worker: worker.to_string(), code, log }),
+            None => Err(BuildTtcError::Timeout { name, timeout: self.build_timeout.lock().unwrap().unwrap_or_default(), log }),
+        })
+    }
+
+    /// Key of `node`'s TTC files in the remote cache. `None` if its sources aren't pinned to
+    /// something every machine agrees on, or one of its dependencies' aren't. See
+    /// [`remote_cache::key`].
+    async fn remote_key(&self, node: &Node<Tr>, extra: &str) -> Result<Option<String>, BuildTtcError> {
+        let desc = &node.descriptor;
+        let source = match desc {
+            Descriptor::Git { .. } | Descriptor::Registry { .. } => {
+                let Some(commit) = self.lockfile.lock().unwrap().locked(desc).map(|locked| locked.commit.clone()) else {
+                    return Ok(None);
+                };
+                let checkout = layout::source_dir(desc);
+                if tokio::task::spawn_blocking(move || fetch::is_dirty(&checkout)).await.unwrap().unwrap_or(true) {
+                    return Ok(None);
+                }
+                commit
+            },
+            Descriptor::Archive { checksum: Some(checksum), .. } => checksum.trim().to_lowercase(),
+            Descriptor::Archive { checksum: None, .. } | Descriptor::Local { .. } | Descriptor::Root { .. } => return Ok(None),
+        };
+        let mut dependencies = Vec::new();
+        for dep in node.dependencies().await? {
+            match dep.remote_key() {
+                Some(key) => dependencies.push(key.to_owned()),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(remote_cache::key(desc, &source, extra, &dependencies)))
+    }
+
+    /// What, besides the sources, should trigger a rebuild when it changes. See
+    /// [`Fingerprint::compute`].
+    async fn fingerprint_extra(&self) -> String {
+        format!("{:?} {:?}", self.toolchain.get().await, self.deps_mode().await)
+    }
+
+    /// Extra flags to build `desc` with, from the root manifest's `idris-flags`.
+    fn idris_flags(&self, desc: &Descriptor) -> Vec<String> {
+        self.idris_flags.get(desc.name()).cloned().unwrap_or_default()
+    }
+
+    /// The version of the idris2 on PATH, `None` if we can't tell.
+    async fn idris2_version(&self) -> Option<Idris2Version> {
+        self.toolchain.get().await.as_ref().map(|toolchain| toolchain.version)
+    }
+
+    /// Where build artifacts of the idris2 on PATH go, see [`Toolchain::dir_name`].
+    async fn toolchain_dir(&self) -> String {
+        Toolchain::dir_name(self.toolchain.get().await.as_ref())
+    }
+
+    /// How to pass dependencies to the idris2 on PATH.
+    async fn deps_mode(&self) -> DepsMode {
+        DepsMode::for_version(self.idris2_version().await)
+    }
+
+    /// Recipe for fetching source.
+    ///
+    /// Returns path to source code, so that `{return value}/Egg.toml` exists. When `retrying`,
+    /// an earlier try just failed, which is no reason to give up, unlike failures of earlier runs.
+    async fn fetch_source(self: &Arc<Self>, desc: Descriptor, retrying: bool) -> Result<PathBuf, SourceFetchError> {
+        // Downloads go by the urls the `${env:VAR}` expand to, paths and the lockfile by `desc`.
+        match desc.expanded()? {
+            Descriptor::Root { .. } => {
+                unreachable!("There must only be one root node, and it must be initialized with a path (usually `./`) at startup.")
+            },
+            Descriptor::Git { url, version, subdir, .. } => {
+                let path = layout::source_dir(&desc);
+                let base_path = match &subdir {
+                    Some(subdir) => path.join(subdir),
+                    None => path.clone(),
+                };
+                let locked = self.lockfile.lock().unwrap().get(&desc).map(str::to_owned);
+                let checkout = self.checkouts.lock().unwrap().entry(path.clone()).or_default().clone();
+                let mut settled = checkout.lock_owned().await;
+
+                if self.offline() && !path.exists() {
+                    return Err(SourceFetchError::Offline { name: desc.name().to_owned() });
+                }
+                let guard = if path.exists() {
+                    self.tracer.fetching_repo(&desc, SourceProgressMethod::AlreadyDownloaded)
+                } else {
+                    self.stats.fetched.fetch_add(1, Ordering::Relaxed);
+                    self.tracer.fetching_repo(&desc, SourceProgressMethod::Git { url: &url })
+                };
+
+                let (path_clone, name, prompt) = (path.clone(), desc.name().to_owned(), *self.prompt.lock().unwrap());
+                let (offline, existed, subdir_clone) = (self.offline(), path.exists(), subdir.clone());
+                let refresh = *self.refresh.lock().unwrap() || retrying;
+                let default_branch = version == GitVersion::DefaultBranch;
+                let version_clone = version.clone();
+                let (guard, commit) = tokio::task::spawn_blocking(move || {
+                    let commit = (|| -> Result<String, SourceFetchError> {
+                        if !path_clone.exists() {
+                            Self::check_failed_before(refresh, &url)?;
+                            prompt::trust_ssh_host(prompt, &url)?;
+                            let progress = |received, total, bytes| guard.progress(received, total, bytes);
+                            fetch_failures::recording(&url, || fetch::clone_git(&url, &path_clone, subdir.as_deref(), &progress))?;
+                        } else if let Some(subdir) = &subdir {
+                            fetch::add_subdir(&path_clone, subdir)?;
+                        }
+                        // Another package from the same checkout already got it to its commit.
+                        if let Some(commit) = &*settled {
+                            return Ok(commit.clone());
+                        }
+                        let commit = match locked {
+                            Some(commit) => {
+                                if offline && !fetch::has_commit(&path_clone, &commit)? {
+                                    return Err(SourceFetchError::Offline { name });
+                                }
+                                prompt::allow_overwrite(prompt, &name, &path_clone, Some(&commit))?;
+                                fetch::checkout_fetching(&path_clone, &commit)?;
+                                commit
+                            },
+                            None => {
+                                // A fresh clone is as new as it gets, an older checkout's branches
+                                // may have moved on since.
+                                if existed && !offline {
+                                    fetch::refresh_version(&path_clone, &version)?;
+                                }
+                                let commit = fetch::resolve_version(&path_clone, &version).ok();
+                                if offline && commit.is_none() {
+                                    return Err(SourceFetchError::Offline { name });
+                                }
+                                prompt::allow_overwrite(prompt, &name, &path_clone, commit.as_deref())?;
+                                fetch::checkout_version(&path_clone, &url, &version)?
+                            },
+                        };
+                        *settled = Some(commit.clone());
+                        Ok(commit)
+                    })();
+                    (guard, commit)
+                }).await.unwrap();
+                let commit = commit?;
+                let tag = {
+                    let (path, commit) = (path.clone(), commit.clone());
+                    tokio::task::spawn_blocking(move || fetch::tag_at(&path, &version_clone, &commit)).await.unwrap()
+                };
+                let signed = tag.clone().map_or_else(|| Signed::Commit(commit.clone()), Signed::Tag);
+                self.check_signature(&desc, &path, signed).await?;
+                self.lockfile.lock().unwrap().set(&desc, commit);
+                if let (Descriptor::Git { version: GitVersion::TagPrefix { prefix, .. }, .. }, Some(tag)) = (&desc, &tag) {
+                    self.lockfile.lock().unwrap().set_tag_version(&desc, tag[prefix.len()..].to_owned());
+                }
+                if default_branch {
+                    let path = path.clone();
+                    if let Ok(branch) = tokio::task::spawn_blocking(move || fetch::default_branch(&path)).await.unwrap() {
+                        self.lockfile.lock().unwrap().set_default_branch(&desc, branch);
+                    }
+                }
+                if existed {
+                    self.check_dirty(&desc, &path, subdir_clone.as_deref()).await?;
+                }
+
+                guard.success(&base_path);
+                Ok(base_path)
+            },
+            Descriptor::Archive { url, checksum, signature, subdir, .. } => {
+                let path = layout::source_dir(&desc);
+                let base_path = match &subdir {
+                    Some(subdir) => path.join(subdir),
+                    None => path.clone(),
+                };
+                let trust = self.trust.lock().unwrap().clone();
+                if trust.require && signature.is_none() {
+                    return Err(SourceFetchError::Untrusted {
+                        name: desc.name().to_owned(),
+                        reason: "it has no `signature`, and `--require-signatures` only accepts signed dependencies".to_owned(),
+                    });
+                }
+
+                // Checked before unpacking, and what it unpacked to on later runs.
+                let check = trust.checks() && signature.is_some();
+                let trusted = match (path.exists(), check) {
+                    (false, _) => false,
+                    (true, false) => true,
+                    (true, true) => {
+                        let (desc, base_path, keys) = (desc.clone(), base_path.clone(), trust.keys.clone());
+                        tokio::task::spawn_blocking(move || Verified::load(&desc).is_some_and(|verified| verified.keys == keys
+                            && signature::tree_digest(&base_path).is_ok_and(|digest| digest == verified.digest))).await.unwrap()
+                    },
+                };
+                if trusted {
+                    let guard = self.tracer
+                        .fetching_repo(&desc, SourceProgressMethod::AlreadyDownloaded);
+                    guard.success(&base_path);
+                    Ok(base_path)
+                } else if self.offline() && path.exists() {
+                    Err(SourceFetchError::Untrusted {
+                        name: desc.name().to_owned(),
+                        reason: "it changed since its signature was checked, or was downloaded before there were keys to check it with, and can't be downloaded again offline".to_owned(),
+                    })
+                } else if self.offline() {
+                    Err(SourceFetchError::Offline { name: desc.name().to_owned() })
+                } else {
+                    let kind = ArchiveKind::from_url(&url)
+                        .ok_or_else(|| SourceFetchError::UnknownArchiveKind { url: url.clone() })?;
+                    self.stats.fetched.fetch_add(1, Ordering::Relaxed);
+                    let guard = self.tracer.fetching_repo(&desc,
+                        SourceProgressMethod::Archive { url: &url });
+                    let (path_clone, name) = (path.clone(), desc.name().to_owned());
+                    let (desc_clone, base_path_clone) = (desc.clone(), base_path.clone());
+                    let refresh = *self.refresh.lock().unwrap() || retrying;
+                    tokio::task::spawn_blocking(move || {
+                        // Whatever is there failed the check.
+                        if path_clone.exists() {
+                            std::fs::remove_dir_all(paths::long(&path_clone))?;
+                        }
+                        Self::check_failed_before(refresh, &url)?;
+                        let bytes = fetch_failures::recording(&url, || archive::download(&url))?;
+                        if let Some(expected) = &checksum {
+                            archive::verify(&url, &bytes, expected)?;
+                        }
+                        if let (Some(signature), true) = (&signature, trust.checks()) {
+                            let signature = archive::download(signature)?;
+                            signature::verify_detached(&bytes, &signature, &trust.keys)
+                                .map_err(|reason| SourceFetchError::Untrusted { name, reason })?;
+                        }
+                        archive::extract(&bytes, kind, &path_clone)?;
+                        if check {
+                            Verified { digest: signature::tree_digest(&base_path_clone)?, keys: trust.keys }.save(&desc_clone)?;
+                        }
+                        Ok::<_, SourceFetchError>(())
+                    }).await.unwrap()?;
+
+                    guard.success(&base_path);
+                    Ok(base_path)
+                }
+            },
+            Descriptor::Registry { registry, version: requirement, .. } => {
+                let path = layout::source_dir(&desc);
+                let locked = self.lockfile.lock().unwrap().locked(&desc).and_then(|locked| locked.registry_version());
+
+                let updated = self.registries.lock().unwrap().entry(registry.clone()).or_default().clone();
+                let mut updated = updated.lock_owned().await;
+
+                // Pinned versions are used even if yanked, everything else is looked up afresh.
+                let prompt = *self.prompt.lock().unwrap();
+                let offline = self.offline();
+                let (picked, yanked) = {
+                    let (desc_name, name) = (desc.name().to_owned(), desc.package_name().to_owned());
+                    tokio::task::spawn_blocking(move || {
+                        let index = match (*updated, &locked) {
+                            (true, _) => registry::Index::open(&registry),
+                            _ if offline => registry::Index::open(&registry),
+                            // Only needed for the warning, so being offline is fine.
+                            (false, Some(_)) => registry::Index::update(&registry).ok().or_else(|| registry::Index::open(&registry)),
+                            (false, None) => Some(registry::Index::update(&registry)?),
+                        };
+                        *updated = true;
+                        match (locked, index) {
+                            (Some(locked), index) => {
+                                let yanked = index.is_some_and(|index| index.is_yanked(&name, &locked.version));
+                                Ok::<_, SourceFetchError>((locked, yanked))
+                            },
+                            (None, index) => {
+                                let index = match index {
+                                    Some(index) => index,
+                                    None if offline => return Err(SourceFetchError::Offline { name: desc_name }),
+                                    None => registry::Index::update(&registry)?,
+                                };
+                                // Misspellings were asked about before the node was created,
+                                // see `Self::correct_registry_name`.
+                                Ok((index.pick(&name, &requirement)?, false))
+                            },
+                        }
+                    }).await.unwrap()?
+                };
+                if yanked {
+                    self.tracer.yanked(&desc, &picked.version);
+                }
+                if offline && !path.exists() {
+                    return Err(SourceFetchError::Offline { name: desc.name().to_owned() });
+                }
+
+                let guard = if path.exists() {
+                    self.tracer.fetching_repo(&desc, SourceProgressMethod::AlreadyDownloaded)
+                } else {
+                    self.stats.fetched.fetch_add(1, Ordering::Relaxed);
+                    self.tracer.fetching_repo(&desc, SourceProgressMethod::Git { url: &picked.git })
+                };
+                let (path_clone, git, rev) = (path.clone(), picked.git.clone(), picked.rev.clone());
+                let (name, existed) = (desc.name().to_owned(), path.exists());
+                let refresh = *self.refresh.lock().unwrap() || retrying;
+                let guard = tokio::task::spawn_blocking(move || {
+                    let result = (|| {
+                        if offline && !fetch::has_commit(&path_clone, &rev)? {
+                            return Err(SourceFetchError::Offline { name });
+                        }
+                        if path_clone.exists() {
+                            prompt::allow_overwrite(prompt, &name, &path_clone, Some(&rev))?;
+                        } else {
+                            Self::check_failed_before(refresh, &git)?;
+                            prompt::trust_ssh_host(prompt, &git)?;
+                            let progress = |received, total, bytes| guard.progress(received, total, bytes);
+                            fetch_failures::recording(&git, || fetch::clone_git(&git, &path_clone, None, &progress))?;
+                        }
+                        fetch::checkout_fetching(&path_clone, &rev)
+                    })();
+                    result.map(|()| guard)
+                }).await.unwrap()?;
+                self.check_signature(&desc, &path, Signed::Commit(picked.rev.clone())).await?;
+                self.lockfile.lock().unwrap().set_registry(&desc, &picked);
+                if existed {
+                    self.check_dirty(&desc, &path, None).await?;
+                }
+
+                guard.success(&path);
+                Ok(path)
+            },
+            Descriptor::Local { path, .. } => {
+                let guard = self.tracer.fetching_repo(&desc, SourceProgressMethod::Local { path: &path });
+                if !path.is_dir() {
+                    return Err(SourceFetchError::LocalPathMissing { path });
+                }
+                guard.success(&path);
+                Ok(path)
+            },
+        }
+    }
+
+    /// Fail unless `object` in the checkout `path` is signed by a trusted key, see
+    /// [`Lair::with_trust`]. Unsigned is fine unless signatures are required.
+    async fn check_signature(&self, desc: &Descriptor, path: &Path, object: Signed) -> Result<(), SourceFetchError> {
+        let trust = self.trust.lock().unwrap().clone();
+        if !trust.checks() {
+            return Ok(());
+        }
+        let (path, object_clone, require) = (path.to_owned(), object.clone(), trust.require);
+        let signed = tokio::task::spawn_blocking(move || signature::verify_git(&path, &object_clone, &trust.keys)).await.unwrap();
+        let reason = match (signed, object) {
+            (Ok(true), _) => return Ok(()),
+            (Ok(false), _) if !require => return Ok(()),
+            (Ok(false), Signed::Tag(tag)) => format!("its tag `{}` isn't signed, and `--require-signatures` only accepts signed dependencies", tag),
+            (Ok(false), Signed::Commit(commit)) => format!("its commit {} isn't signed, and `--require-signatures` only accepts signed dependencies", commit),
+            (Err(reason), _) => reason,
+        };
+        Err(SourceFetchError::Untrusted { name: desc.name().to_owned(), reason })
+    }
+
+    /// Warn about, or fail on, local changes to the files of `desc` in its checkout `path`, see
+    /// [`Lair::with_dirty_deps`]. Changes elsewhere in the checkout belong to other packages.
+    async fn check_dirty(&self, desc: &Descriptor, path: &Path, subdir: Option<&Path>) -> Result<(), SourceFetchError> {
+        let mode = *self.dirty_deps.lock().unwrap();
+        if mode == DirtyDeps::Allow {
+            return Ok(());
+        }
+        let path_clone = path.to_owned();
+        let mut files = tokio::task::spawn_blocking(move || fetch::changed_files(&path_clone)).await.unwrap()?;
+        if let Some(subdir) = subdir {
+            files.retain(|file| file.starts_with(subdir));
+        }
+        if files.is_empty() {
+            Ok(())
+        } else if mode == DirtyDeps::Deny {
+            Err(SourceFetchError::Dirty { name: desc.name().to_owned(), path: path.to_owned(), files })
+        } else {
+            self.tracer.dirty_dependency(desc, path, &files);
+            Ok(())
+        }
+    }
+
+    /// Recipe for fetching manifest.
+    async fn fetch_manifest(self: &Arc<Self>, desc: Descriptor) -> Result<Arc<Manifest>, ManifestFetchError> {
+        let guard = self.tracer.fetching_manifest(&desc);
+
+        let node = self.node(&desc);
+        let base_path = node.base_path().await?;
+        let boundary = layout::workspace_boundary(&desc);
+        // Another descriptor led to the same checkout, and sees the same workspace.
+        if let Some(manifest) = self.interner.manifest(&base_path, boundary.as_deref()) {
+            guard.success(&manifest);
+            return Ok(manifest);
+        }
+        let path = base_path.join("Egg.toml");
+
+        let mut ret = if path.exists() {
+            manifest::Manifest::from_path_within(path, boundary.as_deref())?
+        } else {
+            Self::manifest_from_ipkg(&desc, &base_path, path)?
+        };
+        ret.rebase_local_paths(&base_path);
+        guard.success(&ret);
+        Ok(self.interner.insert_manifest(&base_path, boundary.as_deref(), ret))
+    }
+
+    /// Fallback for packages without an `Egg.toml`, but with an `.ipkg` file which doesn't depend
+    /// on anything but idris2's builtin packages.
+    fn manifest_from_ipkg(desc: &Descriptor, base_path: &Path, searched_path: PathBuf) -> Result<Manifest, ManifestFetchError> {
+        let missing = || ManifestFetchError::MissingManifest { desc: Box::new(desc.clone()), searched_path: searched_path.clone() };
+
+        if !base_path.is_dir() {
+            // Most likely a wrong `subdir`.
+            return Err(missing());
+        }
+        let ipkg_path = ipkg::find(base_path)?.ok_or_else(missing)?;
+        let ipkg = Ipkg::parse(&std::fs::read_to_string(&ipkg_path)?).ok_or_else(missing)?;
+
+        let depends = ipkg.non_builtin_depends();
+        if depends.is_empty() {
+            Manifest::from_ipkg(&ipkg).map_err(|e| ManifestFetchError::Invalid { path: ipkg_path, error: Arc::new(e) })
+        } else {
+            Err(ManifestFetchError::IpkgDependencies {
+                desc: Box::new(desc.clone()),
+                ipkg_path,
+                depends: depends.into_iter().map(str::to_owned).collect(),
+            })
+        }
+    }
+
+}
+
+/// Copy a package's TTC files to where idris2 expects installed packages, replacing whatever was
+/// installed there before. With `link`, symlink them instead if the file system lets us.
+fn install_package(ttc: &Path, dest: &Path, link: bool) -> std::io::Result<()> {
+    let long_dest = paths::long(dest);
+    // Only removes the link if it is one, not what it points to.
+    match std::fs::remove_dir_all(&long_dest) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {},
+    }
+    if link {
+        if let Some(parent) = long_dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if files::link_dir(&std::path::absolute(ttc)?, dest).is_ok() {
+            return Ok(());
+        }
+    }
+    files::copy_dir(&paths::long(ttc), &long_dest)
+}
+
+/// Ensure a directory and sub-dirs are gone.
+/// Do not fail when it's not there in the first place.
+/// Delete `areas` for `lair clean`, saying how much space each freed, or with `dry_run` only how
+/// much each takes.
+fn clean_areas(areas: &[Area], dry_run: bool) -> Result<(), anyhow::Error> {
+    for area in areas {
+        let size = area.size()?;
+        if dry_run {
+            println!("{:>10}  {}", cache::format_size(size), area.describe());
+            continue;
+        }
+        for dir in area.dirs()? {
+            clean(dir)?;
+        }
+        println!("{:>12} {} ({})", "Removed", area.describe(), cache::format_size(size));
+    }
+    Ok(())
+}
+
+fn clean(path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+    match std::fs::remove_dir_all(paths::long(path.as_ref())) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The locked descriptor whose checkout `desc` shares, since they're at the same commit of the
+/// same repository (see [`ResolvedDescriptor`]), if `desc` has no checkout of its own.
+fn sharing_checkout(desc: &Descriptor, lockfile: &Lockfile) -> Option<Descriptor> {
+    let has_checkout = |desc: &Descriptor| layout::package_dir(desc).is_some_and(|dir| dir.is_dir());
+    if !matches!(desc, Descriptor::Git { .. }) || has_checkout(desc) {
+        return None;
+    }
+    let resolved = ResolvedDescriptor::new(desc, Some(lockfile.get(desc)?));
+    lockfile.packages().iter()
+        .filter_map(|locked| locked.descriptor())
+        .find(|other| other != desc && has_checkout(other) && ResolvedDescriptor::new(other, lockfile.get(other)) == resolved)
+}
+
+/// Everything idris2 printed while building a package, so that it can be looked at later without
+/// rebuilding.
+fn write_build_log(path: &Path, command_line: &str, lines: &[String], status: Option<impl std::fmt::Display>) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut log = format!("$ {}\n", command_line);
+    for line in lines {
+        log.push_str(line);
+        log.push('\n');
+    }
+    match status {
+        Some(status) => log.push_str(&format!("\n[{}]\n", status)),
+        None if interrupt::requested() => log.push_str("\n[killed, interrupted]\n"),
+        None => log.push_str("\n[killed, took too long]\n"),
+    }
+    std::fs::write(path, log)
+}
+
+/// Run `command`, passing its output (stdout and stderr, as they come) to `guard` line by line.
+/// Kills it if it runs for longer than `timeout`, or on Ctrl-C. Returns its exit status, `None` if
+/// it was killed, and everything it printed.
+async fn run_streaming(command: Command, guard: &impl BuildProgress, timeout: Option<Duration>) -> Result<(Option<ExitStatus>, Vec<String>), BuildTtcError> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = tokio::process::Command::from(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| BuildTtcError::Spawn { program, error: Arc::new(error) })?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).split(b'\n');
+    let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped")).split(b'\n');
+
+    let mut lines = Vec::new();
+    let mut on_line = |line: Vec<u8>| {
+        let line = String::from_utf8_lossy(&line).trim_end_matches('\r').to_owned();
+        guard.output_line(&line);
+        lines.push(line);
+    };
+    let run = async {
+        let (mut stdout_done, mut stderr_done) = (false, false);
+        while !(stdout_done && stderr_done) {
+            tokio::select! {
+                line = stdout.next_segment(), if !stdout_done => match line? {
+                    Some(line) => on_line(line),
+                    None => stdout_done = true,
+                },
+                line = stderr.next_segment(), if !stderr_done => match line? {
+                    Some(line) => on_line(line),
+                    None => stderr_done = true,
+                },
+            }
+        }
+        child.wait().await
+    };
+
+    let run = async {
+        tokio::select! {
+            status = run => Some(status),
+            () = interrupt::wait() => None,
+        }
+    };
+    let status = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, run).await.ok().flatten(),
+        None => run.await,
+    };
+    match status {
+        Some(status) => Ok((Some(status?), lines)),
+        None => {
+            child.kill().await?;
+            Ok((None, lines))
+        },
+    }
+}
+
+/// Render a command the way you'd type it into a shell, for example
+/// `IDRIS2_PATH="a:b" idris2 --check src/Main.idr`.
+fn display_command(command: &Command) -> String {
+    let envs = command.get_envs()
+        .filter_map(|(key, value)| Some(format!("{}=\"{}\"", key.to_string_lossy(), value?.to_string_lossy())));
+    let program = std::iter::once(command.get_program().to_string_lossy().into_owned());
+    let args = command.get_args().map(|arg| arg.to_string_lossy().into_owned());
+
+    envs.chain(program).chain(args).join(" ")
+}
+
+fn warn_conflicts(graph: &Graph) {
+    for (name, descriptors) in graph.conflicts() {
+        eprintln!("warning: `{}` is required in {} different versions, see `lair why {}`", name, descriptors.len(), name);
+    }
+}
+
+/// For example
+/// ```text
+///     Updating CoolCollections 3f2a9c0 -> 8b1d2e4 (2 new commits)
+///              8b1d2e4 Add SimpleSet
+///              77c0a1f Fix SimpleMap.insert
+///              modules: ~CoolCollections.SimpleMap +CoolCollections.SimpleSet
+/// ```
+/// with `+` for added, `~` for edited and `-` for removed modules.
+fn print_lock_changes(changes: &[LockChange]) {
+    let short = |commit: &str| commit.chars().take(7).collect::<String>();
+
+    if changes.is_empty() {
+        println!("Nothing to update, already at the newest commits.");
+    }
+    for change in changes {
+        match &change.old {
+            Some(old) => println!("{:>12} {} {} -> {} ({} new commit{})",
+                "Updating", change.descriptor.name(), short(old), short(&change.new), change.commits.len(),
+                if change.commits.len() == 1 { "" } else { "s" }),
+            None => println!("{:>12} {} {}", "Locking", change.descriptor.name(), short(&change.new)),
+        }
+        for commit in &change.commits {
+            println!("{:>12} {}", "", commit);
+        }
+        if !change.modules.is_empty() {
+            let modules = change.modules.iter().map(|(module, change)| match change {
+                modules::Change::Added => format!("+{}", module),
+                modules::Change::Modified => format!("~{}", module),
+                modules::Change::Removed => format!("-{}", module),
+            });
+            println!("{:>12} modules: {}", "", modules.format(" "));
+        }
+    }
+}
+
+/// For example
+/// ```text
+///        name: NotJson
+///     version: 0.2.0
+///      source: https://github.com/Kiiyya/NotJson, branch main
+///     license: MIT
+/// ```
+/// Fields which aren't set are left out.
+fn print_info(desc: &Descriptor, manifest: &Manifest) {
+    let metadata = &manifest.metadata;
+    let fields = [
+        ("name", Some(desc.name().to_owned())),
+        ("package", (desc.package_name() != desc.name()).then(|| desc.package_name().to_owned())),
+        ("version", Some(manifest.version.clone())),
+        ("idris2", manifest.idris2.as_ref().map(ToString::to_string)),
+        ("source", desc.source()),
+        ("description", metadata.description.clone()),
+        ("license", metadata.license.clone()),
+        ("authors", (!metadata.authors.is_empty()).then(|| metadata.authors.join(", "))),
+        ("repository", metadata.repository.clone()),
+        ("keywords", (!metadata.keywords.is_empty()).then(|| metadata.keywords.join(", "))),
+    ];
+    for (key, value) in fields {
+        if let Some(value) = value {
+            println!("{:>12} {}", format!("{}:", key), value);
+        }
+    }
+}
+
+/// Command-line thingie.
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Package manager for Idris2.")]
+struct Opt {
+    /// Only print errors.
+    #[structopt(short, long, global = true)]
+    quiet: bool,
+
+    /// Print more about what's going on, `-vv` for even more.
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+
+    /// Plain output for CI logs: no colors or progress, and the output of each package's build
+    /// grouped together. Plain output is also used whenever stdout isn't a terminal.
+    #[structopt(long, global = true)]
+    ci: bool,
+
+    /// The root package's Egg.toml, if it isn't in the current directory. Lair then works in that
+    /// package's directory, as if started there.
+    #[structopt(long, global = true, value_name = "path", parse(from_os_str))]
+    manifest_path: Option<PathBuf>,
+
+    /// Kill idris2 and fail if building a single package takes longer than this, for example
+    /// `10m`.
+    #[structopt(long, global = true, value_name = "duration", parse(try_from_str = cache::parse_duration))]
+    build_timeout: Option<Duration>,
+
+    /// Answer yes to every question, for example whether to overwrite local changes in
+    /// build/deps, or to trust an SSH host Lair never connected to before. Without this, Lair asks
+    /// when run in a terminal, and otherwise assumes no.
+    #[structopt(short, long, global = true)]
+    yes: bool,
+
+    /// Download dependencies' TTC files from this cache instead of building them, if it has
+    /// them: an https:// url, or s3://bucket/prefix.
+    #[structopt(long, global = true, env = "LAIR_REMOTE_CACHE", value_name = "url")]
+    remote_cache: Option<String>,
+
+    /// Also upload the TTC files of dependencies built here to the remote cache, for example
+    /// from CI.
+    #[structopt(long, global = true)]
+    remote_cache_upload: bool,
+
+    /// Experimental: build dependencies on this machine instead, which needs the same idris2: an
+    /// ssh://[user@]host url, to run `lair worker` there, or an https:// url of a worker.
+    #[structopt(long, global = true, env = "LAIR_REMOTE_EXEC", value_name = "url")]
+    remote_exec: Option<String>,
+
+    /// Don't download anything, and fail if something isn't downloaded yet (see `lair fetch`).
+    #[structopt(long, global = true)]
+    offline: bool,
+
+    /// Fail if a dependency's checkout in build/deps has local changes, instead of warning and
+    /// building them, and don't expand `${env:VAR}` in manifests.
+    #[structopt(long, global = true, conflicts_with = "allow-dirty-deps")]
+    frozen: bool,
+
+    /// Build dependencies with local changes in build/deps without warning.
+    #[structopt(long, global = true)]
+    allow_dirty_deps: bool,
+
+    /// Refuse git and archive dependencies which aren't signed by a key from `[trust]` in the
+    /// config files.
+    #[structopt(long, global = true)]
+    require_signatures: bool,
+
+    /// Download dependencies again even if that failed in a run a few minutes ago, instead of
+    /// failing right away with the same error.
+    #[structopt(long, global = true)]
+    refresh: bool,
+
+    /// When a package fails, build everything which doesn't depend on it anyway, then list all
+    /// failures together.
+    #[structopt(long, global = true)]
+    keep_going: bool,
+
+    /// Build git, archive and registry dependencies with less privileges: `env` runs idris2 in
+    /// the package's directory with only the environment it needs, `bwrap` also inside
+    /// bubblewrap (Linux only), with a read-only file system and no network. Default: `off`, or
+    /// `sandbox` in `[build]` of the config files.
+    #[structopt(long, global = true, env = "LAIR_SANDBOX", value_name = "off|env|bwrap")]
+    sandbox: Option<Sandbox>,
+
+    /// Build at most this many packages at the same time. Default: one per CPU.
+    #[structopt(short, long, global = true, value_name = "n")]
+    jobs: Option<usize>,
+
+    /// Write where Lair itself spent its time, not counting idris2, to this file: its phases,
+    /// waiting for locks, and the scheduler's queue. In the chrome tracing format, for
+    /// https://ui.perfetto.dev or chrome://tracing.
+    #[structopt(long, global = true, value_name = "path", parse(from_os_str))]
+    profile_lair: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Debug, StructOpt)]
+enum Cmd {
+    Build {
+        /// Only print the order packages would be built in, and which would be downloaded,
+        /// rebuilt, or are up to date. Downloads, builds and writes nothing.
+        #[structopt(long, conflicts_with_all = &["package", "all"])]
+        dry_run: bool,
+
+        /// Build only this package of the workspace and what it depends on, instead of the root
+        /// package. Can be given several times.
+        #[structopt(short, long = "package", value_name = "name")]
+        package: Vec<String>,
+
+        /// Build the root package and all members of its workspace.
+        #[structopt(long, conflicts_with = "package")]
+        all: bool,
+
+        /// Build the root package twice, into build/.lair/reproducible/a and .../b, and report
+        /// which TTC files and program files differ, and what seems to make them differ. Fails if
+        /// any do.
+        #[structopt(long, conflicts_with_all = &["dry-run", "package", "all"])]
+        reproducible_check: bool,
+
+        /// Instead of building, write every task the build would run to this file (`-` for
+        /// stdout) as JSON, with what each waits for, how long it will probably take, and whether
+        /// it's up to date. For running the build with other tools.
+        #[structopt(long, value_name = "file", conflicts_with_all = &["dry-run", "package", "all", "reproducible-check"])]
+        emit: Option<PathBuf>,
+    },
+    /// Download all dependencies (and the registry indexes they come from) without building
+    /// anything, so that later builds work with `--offline`.
+    Fetch {
+        /// Pick conditional dependencies for this code generator: chez, racket, node or refc.
+        #[structopt(long, default_value = "chez")]
+        backend: Backend,
+    },
+    /// Delete `./build`, or only some parts of it, and say how much space that freed.
+    Clean {
+        /// Only delete the downloaded dependencies, `build/deps`.
+        #[structopt(long)]
+        deps: bool,
+
+        /// Only delete the TTC files, of the root package and of the dependencies.
+        #[structopt(long)]
+        ttc: bool,
+
+        /// Only delete the build output, `build/logs`.
+        #[structopt(long)]
+        logs: bool,
+
+        /// Delete the cache shared by all projects, `~/.cache/lair`. Not part of `./build`, and
+        /// on its own works outside of packages, too.
+        #[structopt(long)]
+        cache: bool,
+
+        /// Only print how much space each part takes, like `du`. Deletes nothing.
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    Run {
+        /// Run this package of the workspace instead of the root package.
+        #[structopt(short, long = "package", value_name = "name")]
+        package: Option<String>,
+
+        /// Run this function instead of `main`, from any module of the package, for example
+        /// `Tool.Cli.serve`.
+        #[structopt(long = "main", value_name = "Module.function")]
+        entrypoint: Option<Entrypoint>,
+    },
+    /// List all packages in the dependency graph.
+    List,
+    /// Show the dependency graph as a tree.
+    Tree,
+    /// Show the description, license, authors etc. of the root package, or of a dependency.
+    Info {
+        /// Package name, for example `CoolCollections`. The root package if not given.
+        package: Option<String>,
+    },
+    /// Show the license of every package in the dependency graph.
+    License {
+        /// Fail if any package can only be used under this license, for example `GPL-3.0`. Can
+        /// be given several times.
+        #[structopt(long, value_name = "license")]
+        deny: Vec<String>,
+    },
+    /// Write a software bill of materials of the dependency graph: versions, commits, checksums,
+    /// licenses and who depends on whom.
+    Sbom {
+        /// cyclonedx or spdx, both as JSON.
+        #[structopt(long, default_value = "cyclonedx")]
+        format: sbom::Format,
+
+        /// Where to write it. Default: stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Print the packages, versions, manifest paths and dependencies of the whole project, the
+    /// build directory and the toolchain as JSON, for editor plugins and other tools.
+    Metadata {
+        /// Pick conditional dependencies for this code generator: chez, racket, node or refc.
+        #[structopt(long, default_value = "chez")]
+        backend: Backend,
+    },
+    /// About Lair itself. Works outside of packages, too.
+    #[structopt(name = "self")]
+    SelfInfo {
+        /// Print what a bug report needs: the commit Lair was built from, the compiler, the
+        /// enabled features, and which idris2 it finds.
+        #[structopt(long)]
+        version_info: bool,
+    },
+    /// Create a new package in a new directory. Works outside of packages, too.
+    New {
+        /// Package name, for example `AmazingTool`, which is also the directory's name.
+        name: String,
+
+        /// Start from this git repository instead of a minimal package, replacing `{{name}}` and
+        /// `{{author}}` in its files.
+        #[structopt(long, value_name = "git-url")]
+        template: Option<String>,
+    },
+    /// Write an `Egg.toml` for a project built from an `.ipkg` file or a Makefile so far, and
+    /// report what couldn't be translated.
+    Migrate {
+        /// Print the `Egg.toml` instead of writing it.
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// Replace an existing `Egg.toml`.
+        #[structopt(long)]
+        force: bool,
+
+        /// Look dependencies up in this registry, whose index is a git repository.
+        #[structopt(long, env = "LAIR_REGISTRY", value_name = "url")]
+        registry: Option<String>,
+    },
+    /// Build the job on stdin for another machine's `--remote-exec`, and write the result to
+    /// stdout. Works outside of packages, too.
+    Worker,
+    /// Search a registry for packages by name or keyword. Works outside of packages, too.
+    Search {
+        /// Part of a package name or keyword, case doesn't matter.
+        query: String,
+
+        /// Index of the registry, a git repository.
+        #[structopt(long, env = "LAIR_REGISTRY", value_name = "url")]
+        registry: Option<String>,
+
+        /// Show at most this many packages.
+        #[structopt(long, default_value = "10")]
+        limit: usize,
+
+        /// Print the results as JSON, for scripts.
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Remember a token for a registry's API, for `lair owner`. Reads it from stdin unless given.
+    /// Works outside of packages, too.
+    Login {
+        /// As the registry's website shows it.
+        token: Option<String>,
+
+        /// Index of the registry, a git repository.
+        #[structopt(long, env = "LAIR_REGISTRY", value_name = "url")]
+        registry: Option<String>,
+    },
+    /// Forget the token for a registry.
+    Logout {
+        /// Index of the registry, a git repository.
+        #[structopt(long, env = "LAIR_REGISTRY", value_name = "url")]
+        registry: Option<String>,
+    },
+    /// Read or change settings in the global or the project's config file. Works outside of
+    /// packages, too.
+    Config {
+        #[structopt(subcommand)]
+        cmd: ConfigCmd,
+    },
+    /// See and change who may publish a package to a registry, or reserve a name. Needs `lair
+    /// login` first.
+    Owner {
+        /// Index of the registry, a git repository.
+        #[structopt(long, env = "LAIR_REGISTRY", value_name = "url")]
+        registry: Option<String>,
+
+        #[structopt(subcommand)]
+        cmd: OwnerCmd,
+    },
+    /// Show why a package is part of the dependency graph.
+    Why {
+        /// Package name, for example `CoolCollections`.
+        package: String,
+    },
+    /// Resolve all dependencies and write Egg.lock.
+    Lock {
+        /// Move this package, and whatever it depends on, to the newest commit of its branch.
+        /// Everything else stays pinned. Can be given several times.
+        #[structopt(long, value_name = "package")]
+        update: Vec<String>,
+    },
+    /// Compile the root package into a program, and put it in a directory along with everything
+    /// it needs to run somewhere else.
+    Bundle {
+        /// Code generator: chez, racket, node or refc.
+        #[structopt(long, default_value = "chez")]
+        backend: Backend,
+
+        /// Where to put the bundle. Default: build/bundle/<package>.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Write the root package's sources, with a generated ipkg, as an archive anybody can install
+    /// with `idris2 --install`, without Lair.
+    Package {
+        /// Where to put the archive. Default: build/package/<package>-<version>.tar.gz.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Compile the benchmarks from `[bench]` with optimizations, run them and show how long they
+    /// took.
+    Bench {
+        /// Only these benchmarks. Default: all of them.
+        names: Vec<String>,
+
+        /// Run each benchmark this many times.
+        #[structopt(long, default_value = "1", value_name = "n")]
+        runs: usize,
+
+        /// Code generator: chez, racket, node or refc.
+        #[structopt(long, default_value = "chez")]
+        backend: Backend,
+
+        /// Print the results as JSON, to compare with earlier runs.
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Generate the HTML documentation of the root package into build/docs, with `idris2 --mkdoc`.
+    Doc {
+        /// Document all dependencies too, with links to them from the root package's docs.
+        #[structopt(long)]
+        deps: bool,
+
+        /// Open the docs in the browser afterwards.
+        #[structopt(long)]
+        open: bool,
+    },
+    /// Check, without changing anything, that Egg.lock, the downloaded dependencies and the build
+    /// outputs all still match the manifests.
+    Verify,
+    /// Show dependencies which no module imports, and imports from packages which aren't
+    /// dependencies.
+    Udeps,
+    /// Explain the code of an error, for example `lair explain L0203`, or list all codes. Works
+    /// outside of packages, too.
+    Explain {
+        code: Option<String>,
+    },
+    /// Build, then build again whenever a file of the root package, a workspace member or a `path`
+    /// dependency changes, resolving the dependencies again first if a manifest changed.
+    Watch,
+    /// Show how long commands took and how many packages they got to reuse, as recorded with
+    /// `[stats] record = true` in a config file. Works outside of packages, too.
+    Stats,
+    /// Show which locked dependencies have newer commits, tags or versions, without changing
+    /// anything.
+    Outdated,
+    /// Show whether each package is downloaded, which commit is locked and checked out, and
+    /// whether its TTC files are up to date, without building anything.
+    Status,
+    /// Manage downloaded dependencies in build/deps.
+    Cache(CacheCmd),
+    /// Build the dependencies, then start an idris2 REPL with the main module loaded.
+    Repl,
+    /// Build the dependencies, then print what an Idris2 LSP server started by an editor needs to
+    /// see them (IDRIS2_PATH, the source directory, the main module) as JSON.
+    LspConfig {
+        /// Instead, write it to build/lsp.json and build/lsp.env, and generate an .ipkg for
+        /// idris2-lsp unless the package has its own.
+        #[structopt(long)]
+        write: bool,
+    },
+    /// Build the package, then run one of the scripts from its `[scripts]`, passing on any further
+    /// arguments.
+    #[structopt(setting = structopt::clap::AppSettings::TrailingVarArg)]
+    Script {
+        /// For example `bench`.
+        name: String,
+
+        #[structopt(parse(from_os_str))]
+        args: Vec<OsString>,
+    },
+    /// Build the dependencies, then run any command with IDRIS2_PATH set up for this project.
+    #[structopt(setting = structopt::clap::AppSettings::TrailingVarArg)]
+    Exec {
+        /// For example `idris2 --repl`.
+        #[structopt(required = true, parse(from_os_str))]
+        command: Vec<OsString>,
+    },
+}
+
+impl Opt {
+    /// Make the paths given on the command line absolute, relative to `dir`, where Lair was
+    /// invoked from, since it runs next to the root manifest, see `--manifest-path`.
+    fn rebase_paths(&mut self, dir: &Path) {
+        let rebase = |path: &mut PathBuf| *path = dir.join(&*path);
+        self.profile_lair.iter_mut().for_each(rebase);
+        match &mut self.cmd {
+            Cmd::Build { emit: Some(path), .. } if path != Path::new("-") => rebase(path),
+            Cmd::Sbom { output, .. } | Cmd::Bundle { output, .. } | Cmd::Package { output } => output.iter_mut().for_each(rebase),
+            _ => {},
+        }
+    }
+}
+
+impl Cmd {
+    /// Does the command print a document for other programs to read, like JSON, to stdout? Then
+    /// nothing else may go there.
+    fn prints_document(&self) -> bool {
+        match self {
+            Cmd::Metadata { .. } => true,
+            Cmd::Worker => true,
+            Cmd::Build { emit: Some(path), .. } => path == Path::new("-"),
+            Cmd::Migrate { dry_run, .. } => *dry_run,
+            Cmd::Sbom { output, .. } => output.is_none(),
+            Cmd::Search { json, .. } => *json,
+            Cmd::Bench { json, .. } => *json,
+            Cmd::LspConfig { write } => !write,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
+enum OwnerCmd {
+    /// Show who owns a package.
+    List {
+        package: PackageName,
+    },
+    /// Let other users publish a package, too.
+    Add {
+        package: PackageName,
+        #[structopt(required = true)]
+        users: Vec<String>,
+    },
+    /// Stop users from publishing a package.
+    Remove {
+        package: PackageName,
+        #[structopt(required = true)]
+        users: Vec<String>,
+    },
+    /// Claim a package name before publishing anything under it.
+    Reserve {
+        package: PackageName,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCmd {
+    /// Print the value of a key, one line per value for lists, and fail if it isn't set. Without
+    /// `--global` or `--project`, the one which counts.
+    Get {
+        #[structopt(parse(try_from_str = config::Key::find))]
+        key: &'static config::Key,
+        #[structopt(flatten)]
+        scope: ConfigScope,
+    },
+    /// Set a key, in the global config file unless `--project`. Lists take several values.
+    Set {
+        #[structopt(parse(try_from_str = config::Key::find))]
+        key: &'static config::Key,
+        #[structopt(required = true)]
+        values: Vec<String>,
+        #[structopt(flatten)]
+        scope: ConfigScope,
+    },
+    /// Remove a key, from the global config file unless `--project`.
+    Unset {
+        #[structopt(parse(try_from_str = config::Key::find))]
+        key: &'static config::Key,
+        #[structopt(flatten)]
+        scope: ConfigScope,
+    },
+    /// Print every key which is set, and which file sets it. Without `--global` or `--project`,
+    /// the values which count.
+    List {
+        #[structopt(flatten)]
+        scope: ConfigScope,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct ConfigScope {
+    /// The global config file, usually `~/.config/lair/config.toml`.
+    #[structopt(long, conflicts_with = "project")]
+    global: bool,
+
+    /// The project's config file, `.lair/config.toml` next to `Egg.toml`.
+    #[structopt(long)]
+    project: bool,
+}
+
+impl ConfigScope {
+    fn get(&self) -> Option<config::Scope> {
+        match (self.global, self.project) {
+            (true, _) => Some(config::Scope::Global),
+            (_, true) => Some(config::Scope::Project),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum CacheCmd {
+    /// Delete downloaded dependencies the dependency graph doesn't use anymore. Without options,
+    /// all of them.
+    Gc {
+        /// Only delete those which weren't used for this long, for example `30d`, `12h` or `2w`.
+        #[structopt(long, value_name = "age", parse(try_from_str = cache::parse_duration))]
+        max_age: Option<std::time::Duration>,
+
+        /// Delete the least recently used ones until all of them together take at most this much
+        /// space, for example `500M` or `2G`.
+        #[structopt(long, value_name = "size", parse(try_from_str = cache::parse_size))]
+        max_size: Option<u64>,
+    },
+}
+
+/// `lair search`.
+async fn search(query: &str, registry: Option<&str>, limit: usize, json: bool) -> anyhow::Result<ExitCode> {
+    let url = registry.context("No registry to search, pass one with `--registry <url>` or set LAIR_REGISTRY.")?.to_owned();
+    let query = query.to_owned();
+    let (found, total) = tokio::task::spawn_blocking(move || {
+        let found = registry::Index::update(&url)?.search(&query)?;
+        let total = found.len();
+        Ok::<_, anyhow::Error>((found.into_iter().take(limit).collect::<Vec<_>>(), total))
+    }).await.unwrap()?;
+
+    if json {
+        let found: Vec<_> = found.iter().map(|package| serde_json::json!({
+            "name": package.name,
+            "version": package.latest().map(|latest| &latest.version),
+            "description": package.description,
+            "url": package.url(),
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&found)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let versions: Vec<String> = found.iter()
+        .map(|package| package.latest().map(|latest| format!("v{}", latest.version)).unwrap_or_default())
+        .collect();
+    let width = found.iter().map(|package| package.name.len()).max().unwrap_or(0);
+    let version_width = versions.iter().map(String::len).max().unwrap_or(0);
+    for (package, version) in found.iter().zip(&versions) {
+        println!("{:width$}  {:version_width$}  {}", package.name, version, package.description.as_deref().unwrap_or_default(),
+            width = width, version_width = version_width);
+        if let Some(url) = package.url() {
+            println!("{:width$}  {:version_width$}  {}", "", "", url, width = width, version_width = version_width);
+        }
+    }
+    if total > found.len() {
+        println!("... and {} more, see `--limit`.", total - found.len());
+    } else if found.is_empty() {
+        println!("Nothing found.");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn login(registry: Option<&str>, token: Option<String>, verbosity: Verbosity) -> anyhow::Result<ExitCode> {
+    let registry = registry.context("No registry to log in to, pass one with `--registry <url>` or set LAIR_REGISTRY.")?.to_owned();
+    let token = match token {
+        Some(token) => token,
+        None => {
+            if std::io::stdin().is_terminal() {
+                eprint!("Token for {}: ", registry);
+            }
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            line
+        },
+    };
+    let token = token.trim().to_owned();
+    anyhow::ensure!(!token.is_empty(), "No token given.");
+    let storage = tokio::task::spawn_blocking({
+        let registry = registry.clone();
+        move || credentials::store(&registry, &token)
+    }).await.unwrap()?;
+    if verbosity >= Verbosity::Normal {
+        match storage {
+            credentials::Storage::Keychain => eprintln!("Stored the token for {} in the keychain.", registry),
+            credentials::Storage::File(path) => eprintln!("Stored the token for {} in {}.", registry, path.display()),
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn logout(registry: Option<&str>, verbosity: Verbosity) -> anyhow::Result<ExitCode> {
+    let registry = registry.context("No registry to log out of, pass one with `--registry <url>` or set LAIR_REGISTRY.")?.to_owned();
+    let removed = tokio::task::spawn_blocking({
+        let registry = registry.clone();
+        move || credentials::remove(&registry)
+    }).await.unwrap()?;
+    if verbosity >= Verbosity::Normal {
+        if removed {
+            eprintln!("Forgot the token for {}.", registry);
+        } else {
+            eprintln!("There was no token for {}.", registry);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn owner(registry: Option<&str>, cmd: &OwnerCmd, verbosity: Verbosity) -> anyhow::Result<ExitCode> {
+    let url = registry.context("No registry, pass one with `--registry <url>` or set LAIR_REGISTRY.")?.to_owned();
+    let cmd = cmd.clone();
+    tokio::task::spawn_blocking(move || {
+        let token = credentials::load(&url)?
+            .with_context(|| format!("Not logged in to {}, run `lair login --registry {}` first.", url, url))?;
+        let client = registry_api::Client::new(&registry::Index::update(&url)?, token)?;
+        match cmd {
+            OwnerCmd::List { package } => {
+                for owner in client.owners(&package)? {
+                    println!("{}", owner);
+                }
+            },
+            OwnerCmd::Add { package, users } => {
+                client.add_owners(&package, &users)?;
+                if verbosity >= Verbosity::Normal {
+                    eprintln!("`{}` is now also owned by {}.", package, users.join(", "));
+                }
+            },
+            OwnerCmd::Remove { package, users } => {
+                client.remove_owners(&package, &users)?;
+                if verbosity >= Verbosity::Normal {
+                    eprintln!("`{}` is no longer owned by {}.", package, users.join(", "));
+                }
+            },
+            OwnerCmd::Reserve { package } => {
+                client.reserve(&package)?;
+                if verbosity >= Verbosity::Normal {
+                    eprintln!("Reserved `{}`.", package);
+                }
+            },
+        }
+        Ok(ExitCode::SUCCESS)
+    }).await.unwrap()
+}
+
+/// `lair migrate`, in `dir` (the current directory if empty).
+async fn migrate(dir: &Path, dry_run: bool, force: bool, registry: Option<String>, offline: bool, verbosity: Verbosity) -> anyhow::Result<ExitCode> {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir }.to_owned();
+    let egg = dir.join("Egg.toml");
+    if egg.exists() && !dry_run && !force {
+        anyhow::bail!("`{}` already exists; pass `--force` to replace it, or `--dry-run` to see what it would become.", egg.display());
+    }
+    if registry.is_some() && offline {
+        anyhow::bail!("`--registry` needs to download the registry's index, which `--offline` doesn't allow.");
+    }
+    let migration = tokio::task::spawn_blocking(move || {
+        let index = registry.as_deref().map(registry::Index::update).transpose()?;
+        migrate::migrate(&dir, registry.as_deref().zip(index.as_ref()))
+    }).await.unwrap()?;
+
+    if dry_run {
+        print!("{}", migration.manifest);
+    } else {
+        std::fs::write(&egg, &migration.manifest).with_context(|| format!("Failed to write `{}`", egg.display()))?;
+    }
+    if verbosity >= Verbosity::Normal {
+        let sources = migration.sources.iter().map(|source| format!("`{}`", source.display())).join(" and ");
+        if !dry_run {
+            eprintln!("{:>12} `{}` from {}", "Created", egg.display(), sources);
+        }
+        for note in &migration.notes {
+            eprintln!("{:>12} {}", "Note", note);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `lair config`.
+fn config_command(project_dir: &Path, cmd: &ConfigCmd, verbosity: Verbosity) -> anyhow::Result<ExitCode> {
+    // The project's file first, since it wins.
+    let files = |scope: Option<config::Scope>| -> anyhow::Result<Vec<config::File>> {
+        match scope {
+            Some(scope) => Ok(vec![config::File::open(scope, project_dir)?]),
+            None => Ok(vec![config::File::open(config::Scope::Project, project_dir)?, config::File::open(config::Scope::Global, project_dir)?]),
+        }
+    };
+    match cmd {
+        ConfigCmd::Get { key, scope } => {
+            match files(scope.get())?.iter().find_map(|file| file.get(key)) {
+                Some(values) => {
+                    for value in values {
+                        println!("{}", value);
+                    }
+                    Ok(ExitCode::SUCCESS)
+                },
+                None => Ok(ExitCode::FAILURE),
+            }
+        },
+        ConfigCmd::Set { key, values, scope } => {
+            let mut file = config::File::open(scope.get().unwrap_or(config::Scope::Global), project_dir)?;
+            file.set(key, values.clone())?;
+            file.save()?;
+            if verbosity >= Verbosity::Normal {
+                eprintln!("Set `{}` in `{}`.", key.name, file.path.display());
+            }
+            Ok(ExitCode::SUCCESS)
+        },
+        ConfigCmd::Unset { key, scope } => {
+            let mut file = config::File::open(scope.get().unwrap_or(config::Scope::Global), project_dir)?;
+            if file.unset(key) {
+                file.save()?;
+                if verbosity >= Verbosity::Normal {
+                    eprintln!("Removed `{}` from `{}`.", key.name, file.path.display());
+                }
+            } else if verbosity >= Verbosity::Normal {
+                eprintln!("`{}` doesn't set `{}`.", file.path.display(), key.name);
+            }
+            Ok(ExitCode::SUCCESS)
+        },
+        ConfigCmd::List { scope } => {
+            let files = files(scope.get())?;
+            for key in config::KEYS {
+                if let Some((file, value)) = files.iter().find_map(|file| Some((file, file.value(key)?))) {
+                    println!("{} = {}  # {}", key.name, value, file.path.display());
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        },
+    }
+}
+
+/// `lair self --version-info`, one `key: value` line each.
+fn version_info_text() -> String {
+    let features = match env!("LAIR_FEATURES") {
+        "" => "none",
+        features => features,
+    };
+    let idris2 = match Toolchain::detect() {
+        Some(toolchain) => format!("{} ({})", toolchain.full, paths::idris2().display()),
+        None => format!("not found ({})", paths::idris2().display()),
+    };
+    [
+        ("lair", updates::VERSION),
+        ("commit", env!("LAIR_COMMIT")),
+        ("rustc", env!("LAIR_RUSTC_VERSION")),
+        ("target", env!("LAIR_TARGET")),
+        ("profile", env!("LAIR_PROFILE")),
+        ("features", features),
+        ("os", std::env::consts::OS),
+        ("idris2", &idris2),
+    ].iter().map(|(key, value)| format!("{}: {}\n", key, value)).collect()
+}
+
+/// Pass on a child process' exit code as our own.
+fn exit_code(status: ExitStatus) -> ExitCode {
+    match status.code() {
+        Some(0) => ExitCode::SUCCESS,
+        Some(code) => ExitCode::from(u8::try_from(code).unwrap_or(1)),
+        None => ExitCode::FAILURE, // killed by a signal
+    }
+}
+
+async fn real_main() -> anyhow::Result<ExitCode> {
+    // Read in command line options
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
+    opt.rebase_paths(&std::env::current_dir()?);
+    let usage = Arc::new(stats::Usage::new(matches.subcommand_name().unwrap_or_default()));
+    let started = Instant::now();
+    let result = profiled(opt, usage.clone()).await;
+    // Only statistics, not worth failing the command over.
+    let _ = usage.save(started.elapsed(), matches!(result, Ok(code) if code == ExitCode::SUCCESS));
+    result
+}
+
+async fn profiled(opt: Opt, usage: Arc<stats::Usage>) -> anyhow::Result<ExitCode> {
+    let Some(path) = opt.profile_lair.clone() else {
+        return run(opt, None, usage).await;
+    };
+    // Also when the command fails, that may be what's to be looked into.
+    let profiler = Arc::new(Profiler::new());
+    let (hook, hook_path) = (profiler.clone(), path.clone());
+    interrupt::on_force_exit(move || drop(hook.save(&hook_path)));
+    let result = run(opt, Some(profiler.clone()), usage).await;
+    profiler.save(&path)?;
+    result
+}
+
+async fn run(opt: Opt, profiler: Option<Arc<Profiler>>, usage: Arc<stats::Usage>) -> anyhow::Result<ExitCode> {
+    let verbosity = Verbosity::from_flags(opt.quiet, opt.verbose);
+    // Documents for other programs go to stdout, everything for humans to stderr then.
+    let stream = if opt.cmd.prints_document() { Stream::Stderr } else { Stream::Stdout };
+    let mode = if opt.ci { OutputMode::Ci } else { OutputMode::detect(stream) };
+    let tracer = SimpleTracer::with_mode(verbosity, mode).with_stream(stream);
+
+    // Before anything goes over the network, even without a package. The project's config is
+    // next to the root manifest.
+    let project_dir = opt.manifest_path.as_deref().and_then(|path| manifest::package_dir_of(path).ok()).unwrap_or_default();
+    // Even if the config files are broken, to fix them.
+    if let Cmd::Config { cmd } = &opt.cmd {
+        return config_command(&project_dir, cmd, verbosity);
+    }
+    let config = config::Config::load(&project_dir)?;
+    if let Some(proxy) = &config.network.proxy {
+        proxy::configure(proxy);
+    }
+    interpolate::configure(config.manifest.env.as_deref().unwrap_or_default(), opt.frozen);
+    if config.updates.check == Some(true) && !opt.offline && !matches!(opt.cmd, Cmd::Worker) {
+        for update in updates::available() {
+            tracer.update_available(&update);
+        }
+        updates::check_in_background();
+    }
+
+    // Don't need a package.
+    if let Cmd::Search { query, registry, limit, json } = &opt.cmd {
+        return search(query, registry.as_deref(), *limit, *json).await;
+    }
+    if let Cmd::Login { token, registry } = &opt.cmd {
+        return login(registry.as_deref(), token.clone(), verbosity).await;
+    }
+    if let Cmd::Logout { registry } = &opt.cmd {
+        return logout(registry.as_deref(), verbosity).await;
+    }
+    if let Cmd::Owner { registry, cmd } = &opt.cmd {
+        if opt.offline {
+            anyhow::bail!("`lair owner` needs to talk to the registry, which `--offline` doesn't allow.");
+        }
+        return owner(registry.as_deref(), cmd, verbosity).await;
+    }
+    if let Cmd::SelfInfo { version_info } = &opt.cmd {
+        match version_info {
+            true => print!("{}", version_info_text()),
+            false => println!("lair {}", updates::VERSION),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Cmd::Explain { code } = &opt.cmd {
+        match code {
+            Some(code) => match explain::lookup(code) {
+                Some(explanation) => println!("{}: {}\n\n{}", explanation.code, explanation.title, explanation.text),
+                None => anyhow::bail!("`{}` isn't a Lair error code. `lair explain` lists them all.", code),
+            },
+            None => for explanation in explain::CODES {
+                println!("{}  {}", explanation.code, explanation.title);
+            },
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if config.stats.record == Some(true) && !matches!(opt.cmd, Cmd::Stats) {
+        usage.enable();
+    }
+    if let Cmd::Stats = &opt.cmd {
+        match stats::Summary::new(stats::Stats::load()) {
+            Some(summary) => print!("{}", summary),
+            None if config.stats.record == Some(true) => println!("Nothing recorded in {} yet.", stats::path().display()),
+            None => println!("Nothing recorded yet. `lair config set stats.record true` starts recording, on this machine only."),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Cmd::Worker = &opt.cmd {
+        let mut job = Vec::new();
+        std::io::stdin().read_to_end(&mut job).context("Failed to read the job")?;
+        let result = remote_exec::work(job).await?;
+        std::io::stdout().write_all(&result)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Cmd::New { name, template } = &opt.cmd {
+        if template.is_some() && opt.offline {
+            anyhow::bail!("`--template` needs to download the template, which `--offline` doesn't allow.");
+        }
+        let (dir, template) = (name.clone(), template.clone());
+        tokio::task::spawn_blocking(move || scaffold::create(Path::new(&dir), &dir, template.as_deref())).await.unwrap()?;
+        if verbosity >= Verbosity::Normal {
+            println!("{:>12} package `{}`", "Created", name);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Cmd::Migrate { dry_run, force, registry } = &opt.cmd {
+        return migrate(&project_dir, *dry_run, *force, registry.clone(), opt.offline, verbosity).await;
+    }
+
+    // Only the cache shared by all projects, which needs no package.
+    if let Cmd::Clean { deps: false, ttc: false, logs: false, cache: true, dry_run } = &opt.cmd {
+        clean_areas(&[Area::Cache], *dry_run)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Everything (`build/`, `Egg.lock`, ...) lives next to the root manifest.
+    if let Some(path) = &opt.manifest_path {
+        let dir = manifest::package_dir_of(path)
+            .map_err(|error| ManifestFetchError::Invalid { path: path.clone(), error: Arc::new(error) })?;
+        if !dir.as_os_str().is_empty() {
+            std::env::set_current_dir(&dir).with_context(|| format!("Failed to change into `{}`", dir.display()))?;
+        }
+    }
+
+    // Parsed once, and shared with every `Lair` we create.
+    let manifest = Arc::new(manifest::Manifest::from_path("Egg.toml")?);
+    let remote_cache = opt.remote_cache.as_deref()
+        .map(|url| RemoteCache::new(url, opt.remote_cache_upload))
+        .transpose()?;
+    let remote_exec = opt.remote_exec.as_deref().map(Worker::new).transpose()?;
+    // Only once Lair is about to fetch or build, so that Ctrl-C still simply exits before.
+    interrupt::install();
+    let new_lair_with = |manifest: Arc<Manifest>| Lair::with_tracer(manifest, "", tracer.clone())
+        .with_build_timeout(opt.build_timeout)
+        .with_prompt(Prompt::from_flags(opt.yes))
+        .with_remote_cache(remote_cache.clone())
+        .with_remote_exec(remote_exec.clone())
+        .with_offline(opt.offline)
+        .with_dirty_deps(DirtyDeps::from_flags(opt.frozen, opt.allow_dirty_deps))
+        .with_trust(Trust { keys: config.trust.keys.clone().unwrap_or_default(), require: opt.require_signatures })
+        .with_sandbox(opt.sandbox.or(config.build.sandbox).unwrap_or_default())
+        .with_link_local(config.build.link_local.unwrap_or(false))
+        .with_jobs(opt.jobs)
+        .with_keep_going(opt.keep_going)
+        .with_refresh(opt.refresh)
+        .with_profiler(profiler.clone())
+        .with_usage(&usage)
+        .with_lockfile(LOCKFILE_NAME);
+    let new_lair = || new_lair_with(manifest.clone());
+
+    match opt.cmd {
+        Cmd::Build { dry_run: true, .. } => {
+            let lair = new_lair()?;
+            let steps = lair.plan().await?;
+            let width = steps.iter().map(|step| step.descriptor.name().len()).max().unwrap_or(0);
+            for step in &steps {
+                let what = match step.action {
+                    plan::Action::Fetch(_) => "fetch",
+                    plan::Action::Build(_) => "build",
+                    plan::Action::Fresh => "skip",
+                };
+                println!("{}  {:width$}  {}", what, step.descriptor.name(), step.action, width = width);
+            }
+            if steps.iter().any(|step| matches!(step.action, plan::Action::Fetch(plan::FetchReason::NotDownloaded))) {
+                println!("Dependencies of packages which aren't downloaded yet aren't listed.");
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Build { emit: Some(path), .. } => {
+            let lair = new_lair()?;
+            let document = serde_json::to_string_pretty(&lair.plan_document().await?)? + "\n";
+            if path == Path::new("-") {
+                print!("{}", document);
+            } else {
+                std::fs::write(&path, document).with_context(|| format!("Failed to write `{}`", path.display()))?;
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Build { reproducible_check: true, .. } => {
+            let lair = new_lair()?;
+            let (differences, files) = lair.reproducible_check().await?;
+            let [first, second] = reproducible::RUNS.map(|run| reproducible::dir().join(run));
+            println!("Built `{}` twice, into {} and {}: {} files, {} of them differ.",
+                lair.root().descriptor.name(), first.display(), second.display(), files, differences.len());
+            let width = differences.iter().map(|difference| difference.path.display().to_string().len()).max().unwrap_or(0);
+            for difference in &differences {
+                let what = match &difference.kind {
+                    reproducible::DifferenceKind::OnlyInFirst => "only in the first build".to_owned(),
+                    reproducible::DifferenceKind::OnlyInSecond => "only in the second build".to_owned(),
+                    reproducible::DifferenceKind::Content(causes) => causes.iter().map(ToString::to_string).join(", "),
+                };
+                println!("  {:width$}  {}", difference.path.display().to_string(), what, width = width);
+            }
+            Ok(if differences.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+        },
+        Cmd::Build { dry_run: false, package, all, .. } if package.is_empty() && !all => {
+            let lair = new_lair()?;
+            lair.build().await?;
+            lair.post_build().await?;
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Build { dry_run: false, package, all, .. } => {
+            let lair = new_lair()?;
+            let packages = lair.workspace_packages(&package, all).await?;
+            lair.build_packages(&packages).await?;
+            if packages.contains(&lair.root().descriptor) {
+                lair.post_build().await?;
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Watch => {
+            let mut manifest = manifest.clone();
+            let mut paths = watch::Paths::root();
+            let mut resolve = false;
+            loop {
+                let built = async {
+                    let lair = new_lair_with(manifest.clone())?;
+                    if resolve {
+                        lair.resolve().await?;
+                    }
+                    paths = lair.watched().await?;
+                    lair.build().await?;
+                    lair.post_build().await
+                }.await;
+                if let Err(e) = built {
+                    if interrupt::requested() {
+                        return Err(e);
+                    }
+                    eprintln!("{}", error::render(&e));
+                }
+
+                // Until there's a change we can build.
+                loop {
+                    if verbosity >= Verbosity::Normal {
+                        eprintln!("{:>12} for changes, press Ctrl-C to stop.", "Waiting");
+                    }
+                    let watched = paths.clone();
+                    let Some(change) = tokio::task::spawn_blocking(move || watch::wait(&watched)).await.unwrap() else {
+                        return Ok(ExitCode::from(error::exit_codes::INTERRUPTED));
+                    };
+                    if !change.manifest {
+                        if verbosity >= Verbosity::Normal {
+                            eprintln!("{:>12} {}", "Changed", change.path.display());
+                        }
+                        resolve = false;
+                        break;
+                    }
+                    if verbosity >= Verbosity::Normal {
+                        eprintln!("{:>12} {}, resolving the dependencies again", "Changed", change.path.display());
+                    }
+                    match manifest::Manifest::from_path("Egg.toml") {
+                        Ok(changed) => {
+                            manifest = Arc::new(changed);
+                            resolve = true;
+                            break;
+                        },
+                        Err(e) => eprintln!("{}", error::render(&e.into())),
+                    }
+                }
+            }
+        },
+        Cmd::Fetch { backend } => {
+            let lair = new_lair()?.with_target(Target::host(backend));
+            lair.fetch().await?;
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Run { package: None, entrypoint } => {
+            let lair = new_lair()?;
+            lair.build().await?;
+            let status = lair.run(entrypoint.as_ref()).await?;
+
+            Ok(exit_code(status))
+        },
+        Cmd::Run { package: Some(package), entrypoint } => {
+            let lair = new_lair()?;
+            let packages = lair.workspace_packages(&[package], false).await?;
+            lair.build_packages(&packages).await?;
+            let status = lair.run_package(&packages[0], entrypoint.as_ref()).await?;
+
+            Ok(exit_code(status))
+        },
+        Cmd::Repl => {
+            let lair = new_lair()?;
+            let status = lair.repl().await?;
+
+            Ok(exit_code(status))
+        },
+        Cmd::LspConfig { write } => {
+            let lair = new_lair()?;
+            let config = lair.lsp_config().await?;
+            if write {
+                let root = std::env::current_dir()?;
+                for path in config.write(&root, &manifest.name, &manifest.version)? {
+                    if verbosity >= Verbosity::Normal {
+                        println!("{:>12} {}", "Wrote", path.strip_prefix(&root).unwrap_or(&path).display());
+                    }
+                }
+            } else {
+                println!("{}", serde_json::to_string_pretty(&config)?);
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Script { name, args } => {
+            let lair = new_lair()?;
+            let status = lair.script(&name, &args).await?;
+
+            Ok(exit_code(status))
+        },
+        Cmd::Exec { command } => {
+            let lair = new_lair()?;
+            let (program, args) = command.split_first().expect("structopt requires at least one argument");
+            let status = lair.exec(program, args).await?;
+
+            Ok(exit_code(status))
+        },
+        Cmd::List => {
+            let lair = new_lair()?;
+            let graph = lair.graph().await?;
+            for package in graph.packages.iter().sorted_by_key(|package| &package.descriptor) {
+                println!("{}", graph.label(package));
+            }
+            warn_conflicts(&graph);
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Tree => {
+            let lair = new_lair()?;
+            let graph = lair.graph().await?;
+            print!("{}", graph.tree());
+            warn_conflicts(&graph);
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Why { package } => {
+            let lair = new_lair()?;
+            let graph = lair.graph().await?;
+            let targets: Vec<_> = graph.packages_named(&package).collect();
+            if targets.is_empty() {
+                anyhow::bail!("`{}` is not part of the dependency graph.", package);
+            }
+            for target in targets {
+                for chain in graph.why(&target.descriptor) {
+                    println!("{}", chain.iter().map(Descriptor::name).join(" -> "));
+                    if let [.., dependent, dependency] = chain.as_slice() {
+                        if let Some(origin) = graph.origin(dependent, dependency) {
+                            println!("    {}", origin);
+                        }
+                    }
+                }
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Info { package: None } => {
+            print_info(&Descriptor::Root { name: manifest.name.clone() }, &manifest);
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Info { package: Some(package) } => {
+            let lair = new_lair()?;
+            let graph = lair.graph().await?;
+            let targets: Vec<_> = graph.packages_named(&package).collect();
+            if targets.is_empty() {
+                anyhow::bail!("`{}` is not part of the dependency graph.", package);
+            }
+            for (i, target) in targets.into_iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                let manifest = lair.node(&target.descriptor).manifest().await?;
+                print_info(&target.descriptor, &manifest);
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::License { deny } => {
+            let lair = new_lair()?;
+            let licenses = lair.licenses().await?;
+
+            let width = licenses.iter().map(|package| package.descriptor.name().len()).max().unwrap_or(0);
+            let mut denied = Vec::new();
+            for package in &licenses {
+                let name = package.descriptor.name();
+                match (&package.license, &package.origin) {
+                    (Some(license), Some(LicenseOrigin::File(path))) =>
+                        println!("{:width$}  {} (guessed from {})", name, license, path.display(), width = width),
+                    (Some(license), _) => println!("{:width$}  {}", name, license, width = width),
+                    (None, _) => println!("{:width$}  unknown", name, width = width),
+                }
+                if package.license.as_ref().is_some_and(|license| license::is_denied(license, &deny)) {
+                    denied.push(package);
+                }
+            }
+
+            for package in &denied {
+                eprintln!("error: `{}` is licensed under `{}`, which is denied.",
+                    package.descriptor.name(), package.license.as_deref().unwrap_or_default());
+            }
+            Ok(if denied.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+        },
+        Cmd::Metadata { backend } => {
+            let lair = new_lair()?.with_target(Target::host(backend));
+            println!("{}", serde_json::to_string_pretty(&lair.metadata().await?)?);
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Sbom { format, output } => {
+            let lair = new_lair()?;
+            let sbom = serde_json::to_string_pretty(&lair.sbom(format).await?)?;
+            match output {
+                Some(path) => std::fs::write(&path, sbom + "\n").with_context(|| format!("Failed to write `{}`", path.display()))?,
+                None => println!("{}", sbom),
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Lock { update } => {
+            let lair = new_lair()?;
+            if update.is_empty() {
+                lair.resolve().await?;
+            } else {
+                let changes = lair.update_lockfile(&update).await?;
+                print_lock_changes(&changes);
+
+                // Pick up and lock whatever the updated packages depend on now.
+                new_lair()?.resolve().await?;
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Bundle { backend, output } => {
+            let lair = new_lair()?.with_target(Target::host(backend));
+            let dest = output.unwrap_or_else(|| bundle::default_dest(&manifest.name));
+            let program = lair.bundle(backend, &dest).await?;
+            if verbosity >= Verbosity::Normal {
+                println!("{:>12} {} into {}, run it with {}", "Bundled", manifest.name, dest.display(), program.display());
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Package { output } => {
+            let lair = new_lair()?;
+            let dest = output.unwrap_or_else(|| package::default_dest(&manifest.name, &manifest.version));
+            let checksum = lair.package(&dest).await?;
+            if verbosity >= Verbosity::Normal {
+                println!("{:>12} {} {} into {}, sha256 {}", "Packaged", manifest.name, manifest.version, dest.display(), checksum);
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Verify => {
+            let lair = new_lair()?;
+            let drifts = lair.verify().await?;
+            for drift in &drifts {
+                println!("{}", drift);
+            }
+            match drifts.len() {
+                0 => println!("Everything is up to date."),
+                1 => eprintln!("error: 1 problem found."),
+                n => eprintln!("error: {} problems found.", n),
+            }
+
+            Ok(if drifts.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+        },
+        Cmd::Udeps => {
+            let lair = new_lair()?;
+            let findings = lair.udeps().await?;
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            match findings.len() {
+                0 => println!("Every dependency is imported, and every import is declared."),
+                1 => eprintln!("error: 1 problem found."),
+                n => eprintln!("error: {} problems found.", n),
+            }
+
+            Ok(if findings.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+        },
+        Cmd::Bench { names, runs, backend, json } => {
+            let lair = new_lair()?.with_target(Target::host(backend));
+            let measurements = lair.bench(&names, backend, runs.max(1)).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&measurements)?);
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let ms = |ms: f64| format!("{:.1}ms", ms);
+            let rows: Vec<[String; 5]> = measurements.iter()
+                .map(|m| [m.name.clone(), m.runs.to_string(), ms(m.mean_ms), ms(m.min_ms), ms(m.max_ms)])
+                .collect();
+            let header = ["Name", "Runs", "Mean", "Min", "Max"].map(str::to_owned);
+            let widths: Vec<usize> = (0..5)
+                .map(|i| rows.iter().chain([&header]).map(|row| row[i].len()).max().unwrap_or(0))
+                .collect();
+            for row in [&header].into_iter().chain(&rows) {
+                println!("{:w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {:>w4$}", row[0], row[1], row[2], row[3], row[4],
+                    w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4]);
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Doc { deps, open } => {
+            let lair = new_lair()?;
+            let index = lair.doc(deps).await?;
+            if verbosity >= Verbosity::Normal {
+                println!("{:>12} {}", "Documented", index.display());
+            }
+            if open {
+                doc::open(&index).context("Failed to open the docs in a browser")?;
+            }
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Outdated => {
+            let lair = new_lair()?;
+            let outdated = lair.outdated().await;
+            if outdated.is_empty() {
+                println!("Nothing is locked yet, see `lair lock`.");
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let rows: Vec<[String; 4]> = outdated.iter()
+                .map(|package| [
+                    package.descriptor.name().to_owned(),
+                    package.current.clone(),
+                    package.latest.clone().unwrap_or_else(|| "-".to_owned()),
+                    package.status.to_string(),
+                ])
+                .collect();
+            let header = ["Name", "Current", "Latest", "Status"].map(str::to_owned);
+            let widths: Vec<usize> = (0..3)
+                .map(|i| rows.iter().chain([&header]).map(|row| row[i].len()).max().unwrap_or(0))
+                .collect();
+            for row in [&header].into_iter().chain(&rows) {
+                println!("{:w0$}  {:w1$}  {:w2$}  {}", row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Status => {
+            let lair = new_lair()?;
+            let statuses = lair.status().await?;
+            let commit = |commit: &Option<String>| commit.as_deref().map_or("-", status::short).to_owned();
+            let rows: Vec<[String; 5]> = statuses.iter()
+                .map(|package| {
+                    let (locked, checked_out) = match &package.commit {
+                        status::Commit::None => ("-".to_owned(), "-".to_owned()),
+                        status::Commit::Unlocked { checked_out } => ("unlocked".to_owned(), commit(checked_out)),
+                        status::Commit::Locked { locked, checked_out } => (status::short(locked).to_owned(), commit(checked_out)),
+                    };
+                    [package.descriptor.name().to_owned(), package.source.to_string(), locked, checked_out, package.ttc.to_string()]
+                })
+                .collect();
+            let header = ["Name", "Source", "Locked", "Checked out", "TTC"].map(str::to_owned);
+            let widths: Vec<usize> = (0..4)
+                .map(|i| rows.iter().chain([&header]).map(|row| row[i].len()).max().unwrap_or(0))
+                .collect();
+            for row in [&header].into_iter().chain(&rows) {
+                println!("{:w0$}  {:w1$}  {:w2$}  {:w3$}  {}", row[0], row[1], row[2], row[3], row[4],
+                    w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3]);
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Cache(CacheCmd::Gc { max_age, max_size }) => {
+            let lair = new_lair()?;
+            let removed = lair.gc(GcPolicy { max_age, max_size }).await?;
+            let now = std::time::SystemTime::now();
+            for removed in &removed {
+                let last_used = match removed.last_used {
+                    Some(time) => format!("last used {} days ago", now.duration_since(time).unwrap_or_default().as_secs() / (24 * 60 * 60)),
+                    None => "never used".to_owned(),
+                };
+                println!("{:>12} {} ({}, {})", "Removing", removed.path.display(), cache::format_size(removed.size), last_used);
+            }
+            let reclaimed: u64 = removed.iter().map(|removed| removed.size).sum();
+            println!("{:>12} {} checkouts, {} reclaimed", "Removed", removed.len(), cache::format_size(reclaimed));
+
+            Ok(ExitCode::SUCCESS)
+        },
+        Cmd::Search { .. } | Cmd::New { .. } | Cmd::Migrate { .. } | Cmd::Login { .. } | Cmd::Logout { .. } | Cmd::Config { .. } | Cmd::Owner { .. } | Cmd::Worker | Cmd::SelfInfo { .. } | Cmd::Explain { .. } | Cmd::Stats =>
+            unreachable!("Handled before reading the manifest."),
+        Cmd::Clean { deps, ttc, logs, cache, dry_run } => {
+            let selected = [(Area::Deps, deps), (Area::Ttc, ttc), (Area::Logs, logs), (Area::Cache, cache)];
+            let areas: Vec<Area> = selected.iter().filter(|(_, selected)| *selected).map(|(area, _)| *area).collect();
+            if !areas.is_empty() {
+                clean_areas(&areas, dry_run)?;
+            } else if dry_run {
+                let build_size = cache::dir_size(&layout::build_dir()).unwrap_or(0);
+                clean_areas(&Area::ALL, dry_run)?;
+                println!("{:>10}  {} (all of the above but the shared cache)", cache::format_size(build_size), layout::build_dir().display());
+            } else {
+                let build_size = cache::dir_size(&layout::build_dir()).unwrap_or(0);
+                clean(layout::build_dir())?;
+                println!("{:>12} {} ({})", "Removed", layout::build_dir().display(), cache::format_size(build_size));
+            }
+
+            Ok(ExitCode::SUCCESS)
+        },
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match real_main().await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", error::render(&e));
+            // Whatever else went wrong meanwhile, it's not why we stopped.
+            match interrupt::requested() {
+                true => ExitCode::from(error::exit_codes::INTERRUPTED),
+                false => ExitCode::from(error::exit_code(&e)),
+            }
+        },
+    }
 }
\ No newline at end of file
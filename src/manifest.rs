@@ -1,58 +1,949 @@
 //! Reading `Egg.toml`.
+//!
+//! Manifests say which version of the schema they're written against (`manifest-version = 1`,
+//! assumed if missing). Older manifests are migrated to the current schema before parsing, so
+//! that the schema can change without breaking existing packages.
+//!
+//! Packages in the same repository can share dependency declarations: a `[workspace.dependencies]`
+//! table in a manifest further up declares them once, and members then only write
+//! `CoolCollections = { workspace = true }`. These are filled in before anything else looks at
+//! the manifest, so the rest of Lair never sees `workspace = true`.
 use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
 use crate::Descriptor;
+use crate::compiler::Idris2Requirement;
+use crate::error::ManifestFetchError;
+use crate::interpolate;
 use crate::descriptor::GitVersion;
+use crate::ipkg::Ipkg;
+use crate::package_name::PackageName;
+use crate::target::{Cfg, Target};
+use crate::semver::{Version, VersionRequirement};
 
+/// The schema version this Lair understands.
+pub const MANIFEST_VERSION: i64 = 1;
+
+/// Rewrites a manifest from one schema version to the next, in place.
+type Migration = fn(&mut toml::value::Table) -> Result<(), anyhow::Error>;
+
+/// `MIGRATIONS[i]` turns a manifest of version `i + 1` into one of version `i + 2`.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Every key we know. Anything else is most likely a typo, and silently ignoring it would be
+/// confusing, for example a misspelled `[dependancies]` would just mean no dependencies.
+const TOP_KEYS: &[&str] = &["manifest-version", "package", "dependencies", "target", "patch", "workspace", "scripts", "bench"];
+const TARGET_KEYS: &[&str] = &["dependencies"];
+const WORKSPACE_KEYS: &[&str] = &["dependencies", "members"];
+const PACKAGE_KEYS: &[&str] = &["name", "version", "idris2", "source-dir", "description", "license", "authors", "repository", "keywords"];
+const DEPENDENCY_KEYS: &[&str] = &["git", "branch", "tag", "tag-prefix", "rev", "archive", "path", "sha256", "signature", "subdir", "manifest-path", "registry", "version", "package", "workspace", "idris-flags"];
+
+/// What lair passes to idris2 itself when building a package, so not allowed in `idris-flags`,
+/// neither on their own nor as `--build-dir=...`.
+const RESERVED_FLAGS: &[&str] = &["--build-dir", "--source-dir", "--output-dir", "--check", "-c", "--cg", "--codegen", "-o", "--output"];
+
+// No `deny_unknown_fields` here, serde doesn't support it together with `flatten`. `check_keys`
+// catches unknown keys anyway.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct TopDecl {
-    /// Name of the package, for example "CoolCollections".
+    /// Name of the package, for example "CoolCollections". See [`PackageName`].
     name: String,
 
-    /// SemVer like "0.1.0".
+    /// SemVer like "0.1.0", see [`crate::semver`].
     version: String,
+
+    /// Which idris2 versions the package works with.
+    #[serde(default)]
+    idris2: Option<Idris2Requirement>,
+
+    /// Where the modules are, relative to the package, for example `lib`.
+    #[serde(default, rename = "source-dir")]
+    source_dir: Option<PathBuf>,
+
+    #[serde(flatten)]
+    metadata: Metadata,
+}
+
+/// Optional information about a package, which doesn't matter for building it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// One sentence about what the package does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// SPDX license expression, for example `MIT OR Apache-2.0`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+
+    /// For example `["Kiiyya <kiiyya@example.com>"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+
+    /// Url to the source repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+}
+
+impl Metadata {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(license) = &self.license {
+            if let Err(e) = spdx::Expression::parse(license) {
+                anyhow::bail!("`license = \"{}\"` is not a valid SPDX license expression: {}", license, e.reason);
+            }
+        }
+        Ok(())
+    }
+
+    /// From the `brief`, `license`, `authors` and `sourceloc` fields of an ipkg, whatever of
+    /// those is there. Unlike in `Egg.toml`, the license isn't validated.
+    fn from_ipkg(ipkg: &Ipkg) -> Self {
+        let unquote = |s: &String| s.trim_matches('"').to_owned();
+        Self {
+            description: ipkg.fields.get("brief").map(unquote),
+            license: ipkg.fields.get("license").map(unquote),
+            authors: ipkg.fields.get("authors")
+                .map(|authors| authors.split(',').map(|author| author.trim().trim_matches('"').to_owned()).filter(|author| !author.is_empty()).collect())
+                .unwrap_or_default(),
+            repository: ipkg.fields.get("sourceloc").map(unquote),
+            keywords: Vec::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct Dep {
-    /// Url to git repository, for example `https://github.com/Kiiyya/CoolCollections`.
-    git: String,
+    /// Url to git repository, for example `https://github.com/Kiiyya/CoolCollections`. Like
+    /// `archive`, `signature`, `registry` and `path`, can use `${env:VAR}`, see
+    /// [`crate::interpolate`].
+    git: Option<String>,
+
+    /// Which branch of the `git` repository to follow. The repository's default branch if none of
+    /// `branch`, `tag` and `rev` is given.
+    branch: Option<String>,
+
+    /// A tag of the `git` repository, for example `v0.1.0`.
+    tag: Option<String>,
+
+    /// Follow the newest tag starting with this (and matching `version`, if given), for example
+    /// `collections-v` for tags like `collections-v1.2.0`.
+    #[serde(rename = "tag-prefix")]
+    tag_prefix: Option<String>,
+
+    /// A commit hash of the `git` repository, possibly abbreviated.
+    rev: Option<String>,
+
+    /// Url to a `.tar.gz` or `.zip` file, for example
+    /// `https://example.com/CoolCollections-0.1.0.tar.gz`.
+    archive: Option<String>,
+
+    /// Directory on this computer, for example `../NotJson`, relative to the package depending on
+    /// it.
+    path: Option<PathBuf>,
+
+    /// Expected hex-encoded sha256 of the `archive`.
+    sha256: Option<String>,
+
+    /// Url of a detached signature of the `archive`, for example
+    /// `https://example.com/CoolCollections-0.1.0.tar.gz.sig`.
+    signature: Option<String>,
+
+    /// Where the package lives inside the `git` repository, for example `packages/NotJson`.
+    subdir: Option<PathBuf>,
+
+    /// Where the package's `Egg.toml` is inside the repository, archive or `path`, for example
+    /// `packages/NotJson/Egg.toml`.
+    #[serde(rename = "manifest-path")]
+    manifest_path: Option<PathBuf>,
+
+    /// Url of a registry's index, see [`crate::registry`].
+    registry: Option<String>,
+
+    /// Which versions from the `registry` (or tags with the `tag-prefix`) will do, for example
+    /// `0.2` for any `0.2.x`. See [`crate::semver`].
+    version: Option<String>,
+
+    /// Actual package name, if it differs from the name we depend on it by. This allows two
+    /// forks of the same package to coexist under different names.
+    package: Option<String>,
+
+    /// Extra flags to build the package with, for example `["--total"]`. Only the root
+    /// manifest's are used, see [`Manifest::idris_flags`].
+    #[serde(rename = "idris-flags")]
+    idris_flags: Option<Vec<String>>,
+}
+
+impl Dep {
+    fn into_descriptor(mut self, name: &str) -> Result<Descriptor, anyhow::Error> {
+        let name: PackageName = name.parse().map_err(|e| anyhow::anyhow!("Dependency {}.", e))?;
+        // Urls are expanded when they're used, see `Descriptor::expanded`.
+        for (key, value) in [("git", &self.git), ("archive", &self.archive), ("signature", &self.signature), ("registry", &self.registry)] {
+            if let Some(value) = value {
+                interpolate::check(value).map_err(|e| anyhow::anyhow!("Dependency `{}`: `{}`: {}.", name, key, e))?;
+            }
+        }
+        if let Some(path) = &mut self.path {
+            if let Some(s) = path.to_str() {
+                let expanded = interpolate::expand(s).map_err(|e| anyhow::anyhow!("Dependency `{}`: `path`: {}.", name, e))?;
+                *path = PathBuf::from(expanded);
+            }
+        }
+        let package = self.package.as_deref()
+            .map(|package| package.parse::<PackageName>().map_err(|e| anyhow::anyhow!("Dependency `{}`: `package`: {}.", name, e)))
+            .transpose()?;
+        if self.sha256.is_some() && self.archive.is_none() {
+            anyhow::bail!("Dependency `{}`: `sha256` only makes sense together with `archive`.", name);
+        }
+        if self.signature.is_some() && self.archive.is_none() {
+            anyhow::bail!("Dependency `{}`: `signature` only makes sense together with `archive`, git dependencies are signed by their tags or commits.", name);
+        }
+        if self.subdir.is_some() && self.git.is_none() {
+            anyhow::bail!("Dependency `{}`: `subdir` only makes sense together with `git`.", name);
+        }
+        if self.git.is_none() && (self.branch.is_some() || self.tag.is_some() || self.tag_prefix.is_some() || self.rev.is_some()) {
+            anyhow::bail!("Dependency `{}`: `branch`, `tag`, `tag-prefix` and `rev` only make sense together with `git`.", name);
+        }
+        if self.registry.is_some() && self.version.is_none() {
+            anyhow::bail!("Dependency `{}`: `registry` needs a `version`.", name);
+        }
+        if self.version.is_some() && self.registry.is_none() && self.tag_prefix.is_none() {
+            anyhow::bail!("Dependency `{}`: `version` only makes sense together with `registry` or `tag-prefix`.", name);
+        }
+        if let Some(version) = &self.version {
+            version.parse::<VersionRequirement>().map_err(|e| anyhow::anyhow!("Dependency `{}`: {}.", name, e))?;
+        }
+        if self.registry.is_some() && self.manifest_path.is_some() {
+            anyhow::bail!("Dependency `{}`: `manifest-path` doesn't make sense together with `registry`.", name);
+        }
+        let subdir = match (self.subdir, &self.manifest_path) {
+            (subdir, None) => subdir,
+            (None, Some(manifest_path)) => {
+                let dir = package_dir_of(manifest_path).with_context(|| format!("Dependency `{}`", name))?;
+                (!dir.as_os_str().is_empty()).then_some(dir)
+            },
+            (Some(_), Some(_)) => anyhow::bail!("Dependency `{}` can only have one of `subdir` or `manifest-path`.", name),
+        };
+        let version = match (self.branch, self.tag, self.tag_prefix, self.rev) {
+            (None, None, None, None) => GitVersion::DefaultBranch,
+            (Some(branch), None, None, None) => GitVersion::Branch(branch),
+            (None, Some(tag), None, None) => GitVersion::Tag(tag),
+            (None, None, Some(prefix), None) => GitVersion::TagPrefix {
+                prefix,
+                requirement: self.version.clone().unwrap_or_else(|| "*".to_owned()),
+            },
+            // Never a commit, but git would take it for an option.
+            (None, None, None, Some(rev)) if rev.starts_with('-') => anyhow::bail!("Dependency `{}`: `rev` can't start with `-`.", name),
+            (None, None, None, Some(rev)) => GitVersion::Rev(rev),
+            _ => anyhow::bail!("Dependency `{}` can only have one of `branch`, `tag`, `tag-prefix` or `rev`.", name),
+        };
+
+        match (self.git, self.archive, self.path, self.registry) {
+            (Some(url), None, None, None) => Ok(Descriptor::Git {
+                name: name.clone(),
+                package: package.clone(),
+                url,
+                version,
+                subdir,
+            }),
+            (None, Some(url), None, None) => Ok(Descriptor::Archive {
+                name: name.clone(),
+                package: package.clone(),
+                url,
+                checksum: self.sha256,
+                signature: self.signature,
+                subdir,
+            }),
+            (None, None, Some(path), None) => Ok(Descriptor::Local {
+                name: name.clone(),
+                package: package.clone(),
+                path: match subdir {
+                    Some(subdir) => path.join(subdir),
+                    None => path,
+                },
+            }),
+            (None, None, None, Some(registry)) => Ok(Descriptor::Registry {
+                name: name.clone(),
+                package: package.clone(),
+                registry,
+                version: self.version.unwrap_or_default(),
+            }),
+            (None, None, None, None) =>
+                anyhow::bail!("Dependency `{}` needs one of `git`, `archive`, `path` or `registry`.", name),
+            _ =>
+                anyhow::bail!("Dependency `{}` can only have one of `git`, `archive`, `path` or `registry`.", name),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct RawManifest {
     package: TopDecl,
 
     /// Package name --> (where to find it, version, etc...).
     dependencies: BTreeMap<String, Dep>,
+
+    /// `cfg(...)` --> dependencies only needed when it holds, see [`crate::target`].
+    #[serde(default)]
+    target: BTreeMap<String, TargetDecl>,
+
+    /// Package name --> where to find it instead, anywhere in the dependency graph.
+    #[serde(default)]
+    patch: BTreeMap<String, Dep>,
+
+    /// Script name --> shell command, see [`crate::scripts`].
+    #[serde(default)]
+    scripts: BTreeMap<String, String>,
+
+    /// Benchmark name --> main module, see [`crate::bench`].
+    #[serde(default)]
+    bench: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetDecl {
+    #[serde(default)]
+    dependencies: BTreeMap<String, Dep>,
+}
+
+/// Dependencies which are only needed when `cfg` holds.
+/// Where a manifest declares a dependency or patch, see [`Manifest::declarations`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Declaration {
+    /// The manifest, for example `build/deps/NotJson-3f2a9c01/Egg.toml`. Empty for manifests which
+    /// aren't in a file.
+    pub manifest: PathBuf,
+    /// The key declaring it, for example `dependencies.CoolCollections`.
+    pub key: String,
+    /// Line of that key, counting from 1. `None` if we couldn't find it, say because the manifest
+    /// was migrated from an older format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+impl Declaration {
+    /// The declaration at `key` in the manifest `source`.
+    fn find(source: &str, key: &[&str]) -> Self {
+        Self {
+            manifest: PathBuf::new(),
+            key: key.iter().map(|part| quote_key(part)).collect::<Vec<_>>().join("."),
+            line: find_key(source, key),
+        }
+    }
+}
+
+/// For example "`dependencies.CoolCollections` in build/deps/NotJson-3f2a9c01/Egg.toml:12".
+impl std::fmt::Display for Declaration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let manifest = match self.manifest.as_os_str().is_empty() {
+            true => "Egg.toml".to_owned(),
+            false => self.manifest.display().to_string(),
+        };
+        write!(f, "`{}` in {}", self.key, manifest)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TargetDependencies {
+    pub cfg: Cfg,
+    pub dependencies: BTreeSet<Descriptor>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Manifest {
-    pub name: String,
+    pub name: PackageName,
     pub version: String,
+    pub metadata: Metadata,
+
+    /// Which idris2 versions the package works with, `None` for any.
+    pub idris2: Option<Idris2Requirement>,
 
+    /// Where the package's modules are, relative to it, for example `lib`. `None` if the manifest
+    /// doesn't say, see [`crate::node::Node::source_dir`].
+    pub source_dir: Option<PathBuf>,
+
+    /// Needed for every target.
     pub dependencies: BTreeSet<Descriptor>,
+
+    /// Only needed for some targets, see [`Manifest::dependencies_for`].
+    pub targets: Vec<TargetDependencies>,
+
+    /// Package name --> what to use instead, wherever it occurs in the dependency graph. Only
+    /// the root manifest's patches are used.
+    pub patches: BTreeMap<String, Descriptor>,
+
+    /// The manifest further up whose `[workspace.dependencies]` this one inherits, relative to the
+    /// package, for example `../Egg.toml`.
+    pub workspace: Option<PathBuf>,
+
+    /// Directories of the other packages in this package's workspace, from `[workspace] members`,
+    /// for example `packages/Core`. Only the root manifest's members are used.
+    pub members: Vec<PathBuf>,
+
+    /// Script name --> shell command, see [`crate::scripts`].
+    pub scripts: BTreeMap<String, String>,
+
+    /// Benchmark name --> main module relative to the package, for example
+    /// `src/Bench/Parse.idr`, see [`crate::bench`].
+    pub benches: BTreeMap<String, PathBuf>,
+
+    /// Package name --> extra flags for idris2 when building it, from the `idris-flags` of
+    /// dependencies and patches, wherever the package occurs in the dependency graph. Only the
+    /// root manifest's are used.
+    pub idris_flags: BTreeMap<String, Vec<String>>,
+
+    /// Dependency, of any target --> where this manifest declares it.
+    pub declarations: BTreeMap<Descriptor, Declaration>,
+
+    /// Package name --> where this manifest's `[patch]` replaces it.
+    pub patch_declarations: BTreeMap<String, Declaration>,
 }
 
 impl Manifest {
-    // ugly, but for now...
+    /// Read and parse the manifest at `path`, usually `Egg.toml`. Its workspace is looked for up
+    /// to the root of the git repository it's in.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Manifest, ManifestFetchError> {
+        Self::from_path_within(path, None)
+    }
+
+    /// Same as [`Manifest::from_path`], but looking for its workspace no further up than
+    /// `boundary`, for example the checkout a dependency lives in.
+    pub fn from_path_within(path: impl AsRef<Path>, boundary: Option<&Path>) -> Result<Manifest, ManifestFetchError> {
+        let path = path.as_ref();
+        let invalid = |error: anyhow::Error| ManifestFetchError::Invalid { path: path.to_owned(), error: Arc::new(error) };
+        let s = std::fs::read_to_string(path).map_err(|e| invalid(e.into()))?;
+        let mut manifest = Self::parse(s.as_ref(), || find_workspace(path, boundary)).map_err(invalid)?;
+        for declaration in manifest.declarations.values_mut().chain(manifest.patch_declarations.values_mut()) {
+            declaration.manifest = path.to_owned();
+        }
+        Ok(manifest)
+    }
+
+    /// Parse a manifest which isn't in any file, so whose `workspace = true` dependencies can
+    /// only come from its own `[workspace.dependencies]`.
     pub fn from_string(s: impl AsRef<str>) -> Result<Manifest, anyhow::Error> {
-        let egg: RawManifest = toml::from_str(s.as_ref())?;
+        Self::parse(s.as_ref(), || Ok(None))
+    }
+
+    /// `workspace` finds the workspace further up, only called if the manifest needs it.
+    fn parse(s: &str, workspace: impl FnOnce() -> Result<Option<Workspace>, anyhow::Error>) -> Result<Manifest, anyhow::Error> {
+        let mut table: toml::value::Table = toml::from_str(s)?;
+        migrate(&mut table)?;
+        check_keys(&table)?;
+        let (workspace, members) = match table.remove("workspace") {
+            Some(mut own) => {
+                let members = match own.as_table_mut().and_then(|own| own.remove("members")) {
+                    Some(members) => members.try_into::<Vec<PathBuf>>()
+                        .map_err(|_| anyhow::anyhow!("`members` in `[workspace]` has to be a list of directories."))?,
+                    None => Vec::new(),
+                };
+                let own = Workspace::from_value(own, PathBuf::from("Egg.toml"), PathBuf::new())?;
+                inherit(&mut table, || Ok(Some(own)))?;
+                (None, members)
+            },
+            None => (inherit(&mut table, workspace)?, Vec::new()),
+        };
+        let mut egg: RawManifest = toml::Value::Table(table).try_into()?;
+        egg.package.metadata.validate()?;
+        let name = egg.package.name.parse::<PackageName>().map_err(|e| anyhow::anyhow!("`name` in `[package]`: {}.", e))?;
+        egg.package.version = Version::parse_lenient(&egg.package.version)
+            .map_err(|e| anyhow::anyhow!("`version` in `[package]`: {}.", e))?
+            .to_string();
+        let idris_flags = idris_flags(&mut egg)?;
+        let source_dir = egg.package.source_dir.map(checked_source_dir).transpose()?;
+
+        let mut declarations = BTreeMap::new();
+        let mut dependencies = BTreeSet::new();
+        for (name, dep) in egg.dependencies {
+            let desc = dep.into_descriptor(&name)?;
+            declarations.entry(desc.clone()).or_insert_with(|| Declaration::find(s, &["dependencies", &name]));
+            dependencies.insert(desc);
+        }
+        let mut targets = Vec::new();
+        for (cfg, target) in egg.target {
+            let mut dependencies = BTreeSet::new();
+            for (name, dep) in target.dependencies {
+                let desc = dep.into_descriptor(&name)?;
+                declarations.entry(desc.clone()).or_insert_with(|| Declaration::find(s, &["target", &cfg, "dependencies", &name]));
+                dependencies.insert(desc);
+            }
+            targets.push(TargetDependencies {
+                cfg: cfg.parse().map_err(|e| anyhow::anyhow!("Invalid target `{}`: {}", cfg, e))?,
+                dependencies,
+            });
+        }
+        let mut patches = BTreeMap::new();
+        let mut patch_declarations = BTreeMap::new();
+        for (name, dep) in egg.patch {
+            patch_declarations.insert(name.clone(), Declaration::find(s, &["patch", &name]));
+            patches.insert(name.clone(), dep.into_descriptor(&name)?);
+        }
+
         let manifest = Self {
-            name: egg.package.name,
+            name,
             version: egg.package.version,
-            dependencies: egg.dependencies.iter().map(|(name, dep)|
-                Descriptor::Git {
-                    name: name.to_owned(),
-                    url: dep.git.to_owned(),
-                    version: GitVersion::Branch("main".to_string()),
-                }
-            ).collect(),
+            idris2: egg.package.idris2,
+            source_dir,
+            metadata: egg.package.metadata,
+            dependencies,
+            targets,
+            patches,
+            workspace,
+            members,
+            scripts: egg.scripts,
+            benches: egg.bench,
+            idris_flags,
+            declarations,
+            patch_declarations,
         };
 
         Ok(manifest)
     }
+
+    /// Make relative `path` dependencies (and patches, and workspace members) relative to the
+    /// current directory instead of to the package, which lives in `base_path`.
+    pub fn rebase_local_paths(&mut self, base_path: &Path) {
+        let rebase = |desc: &Descriptor| match desc {
+            Descriptor::Local { name, package, path } => Descriptor::Local {
+                name: name.clone(),
+                package: package.clone(),
+                path: normalize(&base_path.join(path)),
+            },
+            _ => desc.clone(),
+        };
+        self.dependencies = self.dependencies.iter().map(rebase).collect();
+        for target in &mut self.targets {
+            target.dependencies = target.dependencies.iter().map(rebase).collect();
+        }
+        for patch in self.patches.values_mut() {
+            *patch = rebase(patch);
+        }
+        self.declarations = std::mem::take(&mut self.declarations).into_iter()
+            .map(|(desc, declaration)| (rebase(&desc), declaration))
+            .collect();
+        for member in &mut self.members {
+            *member = normalize(&base_path.join(&*member));
+        }
+    }
+
+    /// The dependencies needed when building for `target`: those needed for every target, plus
+    /// those whose condition holds.
+    pub fn dependencies_for(&self, target: &Target) -> BTreeSet<Descriptor> {
+        let mut dependencies = self.dependencies.clone();
+        for conditional in self.targets.iter().filter(|conditional| conditional.cfg.matches(target)) {
+            dependencies.extend(conditional.dependencies.iter().cloned());
+        }
+        dependencies
+    }
+
+    /// The conditional dependencies not needed for `target`.
+    pub fn inactive_dependencies(&self, target: &Target) -> BTreeSet<Descriptor> {
+        let active = self.dependencies_for(target);
+        self.targets.iter()
+            .filter(|conditional| !conditional.cfg.matches(target))
+            .flat_map(|conditional| conditional.dependencies.iter())
+            .filter(|dep| !active.contains(dep))
+            .cloned()
+            .collect()
+    }
+
+    /// Best-effort manifest for packages which only have an `.ipkg` file. The caller has to make
+    /// sure the ipkg doesn't depend on anything but idris2's builtin packages. Fails if the
+    /// package's name isn't one Lair accepts, see [`PackageName`].
+    pub fn from_ipkg(ipkg: &Ipkg) -> Result<Manifest, anyhow::Error> {
+        Ok(Self {
+            name: ipkg.name.parse().map_err(|e| anyhow::anyhow!("{}.", e))?,
+            version: ipkg.version.clone().unwrap_or_else(|| "0.0.0".to_string()),
+            metadata: Metadata::from_ipkg(ipkg),
+            idris2: None,
+            source_dir: ipkg.fields.get("sourcedir").map(|dir| PathBuf::from(dir.trim_matches('"')))
+                .map(checked_source_dir).transpose()?,
+            dependencies: BTreeSet::new(),
+            targets: Vec::new(),
+            patches: BTreeMap::new(),
+            workspace: None,
+            members: Vec::new(),
+            scripts: BTreeMap::new(),
+            benches: BTreeMap::new(),
+            idris_flags: BTreeMap::new(),
+            declarations: BTreeMap::new(),
+            patch_declarations: BTreeMap::new(),
+        })
+    }
+}
+
+/// `source-dir`, which has to stay inside the package: fingerprints (see [`crate::fingerprint`])
+/// only look at the package's own files, so changes to modules elsewhere would go unnoticed.
+fn checked_source_dir(dir: PathBuf) -> Result<PathBuf, anyhow::Error> {
+    if dir.as_os_str().is_empty() || !dir.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        anyhow::bail!("`source-dir = \"{}\"` in `[package]` has to be a directory inside the package, for example `lib`.", dir.display());
+    }
+    Ok(dir)
+}
+
+/// Take the `idris-flags` out of all dependency declarations of `egg`, by package name.
+fn idris_flags(egg: &mut RawManifest) -> Result<BTreeMap<String, Vec<String>>, anyhow::Error> {
+    let mut all: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let declarations = egg.dependencies.iter_mut()
+        .chain(egg.target.values_mut().flat_map(|target| target.dependencies.iter_mut()))
+        .chain(egg.patch.iter_mut());
+    for (name, dep) in declarations {
+        let Some(flags) = dep.idris_flags.take() else { continue };
+        // Lair decides where things are and what to build.
+        if let Some(flag) = flags.iter().find(|flag| RESERVED_FLAGS.contains(&flag.split('=').next().unwrap_or_default())) {
+            anyhow::bail!("Dependency `{}`: `{}` can't be in its `idris-flags`, lair passes that itself.", name, flag);
+        }
+        all.entry(name.clone()).or_default().extend(flags);
+    }
+    Ok(all)
+}
+
+/// The `[workspace.dependencies]` of some manifest.
+struct Workspace {
+    /// Where the workspace's manifest is, for error messages.
+    manifest: PathBuf,
+    /// Name --> declaration, as in `[dependencies]`.
+    dependencies: toml::value::Table,
+    /// From the member to the workspace, for example `../..`, so that `path`s relative to the
+    /// workspace can be made relative to the member instead.
+    up: PathBuf,
+}
+
+impl Workspace {
+    fn from_value(value: toml::Value, manifest: PathBuf, up: PathBuf) -> Result<Self, anyhow::Error> {
+        let dependencies = match value {
+            toml::Value::Table(mut workspace) => match workspace.remove("dependencies") {
+                Some(toml::Value::Table(dependencies)) => dependencies,
+                Some(_) => anyhow::bail!("`[workspace.dependencies]` has to be a table."),
+                None => toml::value::Table::new(),
+            },
+            _ => anyhow::bail!("`workspace` has to be a table."),
+        };
+        for (name, dep) in &dependencies {
+            if dep.get("workspace").is_some() {
+                anyhow::bail!("Workspace dependency `{}` can't itself be `workspace = true`.", name);
+            }
+        }
+        Ok(Self { manifest, dependencies, up })
+    }
+}
+
+/// Walk up from the manifest at `path` to the nearest manifest with a `[workspace]` table, but no
+/// further than `boundary` or the root of a git repository.
+fn find_workspace(path: &Path, boundary: Option<&Path>) -> Result<Option<Workspace>, anyhow::Error> {
+    let member_dir = path.parent().unwrap_or(Path::new(""));
+    let path = std::path::absolute(path)?;
+    let boundary = boundary.map(std::path::absolute).transpose()?;
+    let mut dir = path.parent().map(Path::to_owned).unwrap_or_default();
+    let mut up = PathBuf::new();
+    loop {
+        if dir.join(".git").exists() || boundary.as_ref() == Some(&dir) || !dir.pop() {
+            return Ok(None);
+        }
+        up.push("..");
+        let candidate = dir.join("Egg.toml");
+        if !candidate.is_file() {
+            continue;
+        }
+        let mut table: toml::value::Table = std::fs::read_to_string(&candidate)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(toml::from_str(&s)?))
+            .with_context(|| format!("Failed to read the workspace manifest `{}`", candidate.display()))?;
+        if let Some(workspace) = table.remove("workspace") {
+            let shown = normalize(&member_dir.join(&up).join("Egg.toml"));
+            return Workspace::from_value(workspace, shown, up.clone())
+                .with_context(|| format!("In the workspace manifest `{}`", candidate.display()))
+                .map(Some);
+        }
+    }
+}
+
+/// The dependency tables of a manifest: `[dependencies]`, `[patch]`, and
+/// `[target.'cfg(...)'.dependencies]`.
+fn dependency_tables(table: &mut toml::value::Table) -> Vec<&mut toml::value::Table> {
+    let mut tables = Vec::new();
+    let mut targets = None;
+    for (key, value) in table.iter_mut() {
+        match (key.as_str(), value) {
+            ("dependencies" | "patch", toml::Value::Table(dependencies)) => tables.push(dependencies),
+            ("target", toml::Value::Table(target)) => targets = Some(target),
+            _ => {},
+        }
+    }
+    for target in targets.into_iter().flat_map(|targets| targets.iter_mut().map(|(_, target)| target)) {
+        if let Some(toml::Value::Table(dependencies)) = target.get_mut("dependencies") {
+            tables.push(dependencies);
+        }
+    }
+    tables
+}
+
+/// Replace every `{ workspace = true }` with the declaration from the workspace, which is only
+/// looked for if there is any. The workspace's manifest, if it was needed.
+fn inherit(table: &mut toml::value::Table, find: impl FnOnce() -> Result<Option<Workspace>, anyhow::Error>) -> Result<Option<PathBuf>, anyhow::Error> {
+    let mut find = Some(find);
+    let mut workspace = None;
+    for dependencies in dependency_tables(table) {
+        for (name, dep) in dependencies.iter_mut() {
+            let Some(flag) = dep.get("workspace") else { continue };
+            if flag != &toml::Value::Boolean(true) {
+                anyhow::bail!("Dependency `{}`: `workspace` can only be `true`, leave it out otherwise.", name);
+            }
+            if dep.as_table().is_some_and(|dep| dep.len() > 1) {
+                anyhow::bail!("Dependency `{}`: `workspace = true` can't be combined with other keys, those come from `[workspace.dependencies]`.", name);
+            }
+            if let Some(find) = find.take() {
+                workspace = find()?;
+            }
+            let Some(workspace) = &workspace else {
+                anyhow::bail!("Dependency `{}` has `workspace = true`, but there's no `[workspace.dependencies]` in this or any manifest further up.", name);
+            };
+            let Some(declared) = workspace.dependencies.get(name) else {
+                anyhow::bail!("Dependency `{}` has `workspace = true`, but `{}` doesn't declare it in `[workspace.dependencies]`.",
+                    name, workspace.manifest.display());
+            };
+            let mut declared = declared.clone();
+            if let Some(toml::Value::String(path)) = declared.get_mut("path") {
+                *path = workspace.up.join(&*path).to_string_lossy().into_owned();
+            }
+            *dep = declared;
+        }
+    }
+    Ok(workspace.map(|workspace| workspace.up.join("Egg.toml")))
+}
+
+/// The line (counting from 1) of `source` declaring `key`, either as a table header
+/// (`[dependencies.NotJson]`) or as a key in one (`NotJson = ...` below `[dependencies]`, or
+/// `dependencies.NotJson = ...` at the top). Only reads as much TOML as it takes to find keys.
+fn find_key(source: &str, key: &[&str]) -> Option<usize> {
+    let mut table: Vec<String> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim_start();
+        if let Some(header) = line.strip_prefix('[') {
+            // `[[...]]` isn't used in manifests, but shouldn't be mistaken for a key either.
+            let header = header.strip_prefix('[').unwrap_or(header);
+            if let Some((parts, rest)) = parse_key(header) {
+                if rest.starts_with(']') {
+                    if parts == key {
+                        return Some(i + 1);
+                    }
+                    table = parts;
+                }
+            }
+            continue;
+        }
+        if let Some((parts, rest)) = parse_key(line) {
+            if rest.starts_with('=') && table.iter().chain(&parts).map(String::as_str).eq(key.iter().copied()) {
+                return Some(i + 1);
+            }
+        }
+    }
+    None
+}
+
+/// A dotted TOML key at the start of `s`, such as `dependencies."Cool Collections"`, and what
+/// follows it, without the whitespace around.
+fn parse_key(s: &str) -> Option<(Vec<String>, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = s.trim_start();
+    loop {
+        let (part, after) = match rest.chars().next()? {
+            quote @ ('"' | '\'') => {
+                let end = rest[1..].find(quote)? + 1;
+                (rest[1..end].to_owned(), &rest[end + 1..])
+            },
+            _ => {
+                let end = rest.find(|c: char| !is_bare(c)).unwrap_or(rest.len());
+                if end == 0 {
+                    return None;
+                }
+                (rest[..end].to_owned(), &rest[end..])
+            },
+        };
+        parts.push(part);
+        rest = after.trim_start();
+        match rest.strip_prefix('.') {
+            Some(after) => rest = after.trim_start(),
+            None => return Some((parts, rest)),
+        }
+    }
+}
+
+/// `part` as it would be written in a dotted key: as it is if it's a bare key, otherwise quoted.
+fn quote_key(part: &str) -> String {
+    match (!part.is_empty() && part.chars().all(is_bare), part.contains('\'')) {
+        (true, _) => part.to_owned(),
+        (false, false) => format!("'{}'", part),
+        (false, true) => format!("{:?}", part),
+    }
+}
+
+fn is_bare(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Remove `.` and `a/..` from a path without looking at the filesystem, so that the same local
+/// package reached from different places ends up with the same descriptor.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The directory of the package whose manifest is `manifest_path`, that is, its parent. Empty
+/// for just `Egg.toml`. Manifests are always called `Egg.toml`, so anything else is an error.
+pub fn package_dir_of(manifest_path: &Path) -> Result<PathBuf, anyhow::Error> {
+    if manifest_path.file_name().is_none_or(|name| name != "Egg.toml") {
+        anyhow::bail!("`manifest-path` has to point to an `Egg.toml`, not `{}`.", manifest_path.display());
+    }
+    Ok(manifest_path.parent().map(Path::to_owned).unwrap_or_default())
+}
+
+/// Bring a manifest up to [`MANIFEST_VERSION`], and remove the `manifest-version` key.
+fn migrate(table: &mut toml::value::Table) -> Result<(), anyhow::Error> {
+    let version = match table.remove("manifest-version") {
+        None => 1,
+        Some(toml::Value::Integer(version)) if version >= 1 => version,
+        Some(other) => anyhow::bail!("`manifest-version` must be a positive integer, not `{}`.", other),
+    };
+    if version > MANIFEST_VERSION {
+        anyhow::bail!("This manifest needs `manifest-version = {}`, but this Lair only understands up to {}. Try updating Lair.",
+            version, MANIFEST_VERSION);
+    }
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(table)?;
+    }
+    Ok(())
+}
+
+/// Fail on keys we don't know, suggesting what was probably meant.
+fn check_keys(table: &toml::value::Table) -> Result<(), anyhow::Error> {
+    check_table_keys(table, TOP_KEYS, "Egg.toml")?;
+    if let Some(toml::Value::Table(package)) = table.get("package") {
+        check_table_keys(package, PACKAGE_KEYS, "[package]")?;
+    }
+    for (section, what) in [("dependencies", "dependency"), ("patch", "patch")] {
+        if let Some(toml::Value::Table(dependencies)) = table.get(section) {
+            check_dependency_keys(dependencies, what)?;
+        }
+    }
+    if let Some(toml::Value::Table(workspace)) = table.get("workspace") {
+        check_table_keys(workspace, WORKSPACE_KEYS, "[workspace]")?;
+        if let Some(toml::Value::Table(dependencies)) = workspace.get("dependencies") {
+            check_dependency_keys(dependencies, "workspace dependency")?;
+        }
+    }
+    if let Some(toml::Value::Table(targets)) = table.get("target") {
+        for (cfg, target) in targets {
+            if let toml::Value::Table(target) = target {
+                check_table_keys(target, TARGET_KEYS, &format!("[target.'{}']", cfg))?;
+                if let Some(toml::Value::Table(dependencies)) = target.get("dependencies") {
+                    check_dependency_keys(dependencies, "dependency")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_dependency_keys(dependencies: &toml::value::Table, what: &str) -> Result<(), anyhow::Error> {
+    for (name, dep) in dependencies {
+        if let toml::Value::Table(dep) = dep {
+            check_table_keys(dep, DEPENDENCY_KEYS, &format!("{} `{}`", what, name))?;
+        }
+    }
+    Ok(())
+}
+
+fn check_table_keys(table: &toml::value::Table, known: &[&str], location: &str) -> Result<(), anyhow::Error> {
+    for key in table.keys() {
+        if !known.contains(&key.as_str()) {
+            match suggest(key, known) {
+                Some(suggestion) => anyhow::bail!("Unknown key `{}` in {}. Did you mean `{}`?", key, location, suggestion),
+                None => anyhow::bail!("Unknown key `{}` in {}. Expected one of {}.",
+                    key, location, known.iter().map(|k| format!("`{}`", k)).collect::<Vec<_>>().join(", ")),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The known key closest to `key`, if it's close enough to be a plausible typo.
+fn suggest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (key.chars().count() / 3).max(1);
+    known.iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance, where inserting, removing, replacing, or swapping two adjacent characters
+/// count as one edit each (optimal string alignment distance).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // d[i][j]: distance between the first i characters of a and the first j characters of b.
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_flags(flags: &str) -> Result<Manifest, anyhow::Error> {
+        Manifest::from_string(format!("[package]\nname = \"P\"\nversion = \"0.1.0\"\n[dependencies]\nA = {{ path = \"A\", idris-flags = {} }}\n", flags))
+    }
+
+    #[test]
+    fn rejects_reserved_flags_with_values() {
+        assert!(with_flags(r#"["--total", "-X", "ElabReflection"]"#).is_ok());
+        assert!(with_flags(r#"["--build-dir"]"#).is_err());
+        assert!(with_flags(r#"["--build-dir=elsewhere"]"#).is_err());
+        assert!(with_flags(r#"["--cg=node"]"#).is_err());
+    }
+
+    #[test]
+    fn rejects_revs_like_options() {
+        let with_rev = |rev: &str| Manifest::from_string(format!("[package]\nname = \"P\"\nversion = \"0.1.0\"\n[dependencies]\nA = {{ git = \"https://example.com/A\", rev = \"{}\" }}\n", rev));
+        assert!(with_rev("4edaf5b").is_ok());
+        assert!(with_rev("--upload-pack=touch pwned").is_err());
+        assert!(with_rev("-h").is_err());
+    }
 }
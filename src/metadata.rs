@@ -0,0 +1,160 @@
+//! `lair metadata`: the project as one JSON document, for editor plugins and other tools which
+//! would rather not read `Egg.toml`s and resolve dependencies themselves.
+//!
+//! The document looks like this, with every path absolute:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "root": "AmazingTool-1f0c3b7e",
+//!   "workspace_root": "/home/me/AmazingTool",
+//!   "target_directory": "/home/me/AmazingTool/build",
+//!   "packages": [{
+//!     "id": "NotJson-3f2a9c01",
+//!     "name": "NotJson",
+//!     "package": "NotJson",
+//!     "version": "0.2.0",
+//!     "kind": "git",
+//!     "source": "https://github.com/Kiiyya/NotJson, branch main",
+//!     "commit": "5d0e7c...",
+//!     "manifest_path": "/home/me/AmazingTool/build/deps/NotJson-3f2a9c01/Egg.toml",
+//!     "root": "/home/me/AmazingTool/build/deps/NotJson-3f2a9c01",
+//!     "source_dir": "/home/me/AmazingTool/build/deps/NotJson-3f2a9c01/src",
+//!     "main": "/home/me/AmazingTool/build/deps/NotJson-3f2a9c01/src/NotJson.idr",
+//!     "idris2": ">=0.6",
+//!     "dependencies": [{
+//!       "name": "CoolCollections",
+//!       "id": "CoolCollections-8e41d2aa",
+//!       "declared": { "manifest": "/home/me/AmazingTool/build/deps/NotJson-3f2a9c01/Egg.toml", "key": "dependencies.CoolCollections", "line": 7 },
+//!       "patched": null
+//!     }]
+//!   }],
+//!   "inactive": [],
+//!   "target": { "backend": "chez", "os": "linux", "family": "unix" },
+//!   "toolchain": { "lair": "0.1.0", "idris2": "idris2", "idris2_version": "0.6.0" }
+//! }
+//! ```
+//!
+//! `id`s are unique within the document, and stay the same as long as the dependency is declared
+//! the same way. `declared` says where a package's manifest asks for a dependency, and `patched`
+//! where the root's `[patch]` replaces that, if it does; `line` is `null` if it couldn't be found.
+//! `inactive` names the conditional dependencies left out for this target (see
+//! [`crate::target`]). Fields are only ever added, anything else bumps `version`.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::compiler::Idris2Version;
+use crate::descriptor::Descriptor;
+use crate::graph::Graph;
+use crate::layout;
+use crate::lockfile::Lockfile;
+use crate::manifest::Declaration;
+use crate::paths;
+use crate::target::Target;
+
+/// Bumped whenever the document changes in a way which could break its readers.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Where one package of the graph lives on disk.
+#[derive(Clone, Debug)]
+pub struct PackageLocation {
+    pub descriptor: Descriptor,
+    /// The directory containing the manifest.
+    pub root: PathBuf,
+    /// `Egg.toml`, or the `.ipkg` for packages without one.
+    pub manifest_path: PathBuf,
+    /// Where the modules are, see [`crate::node::Node::source_dir`].
+    pub source_dir: PathBuf,
+    /// The main module, see [`crate::node::Node::main`].
+    pub main: PathBuf,
+}
+
+/// The document for `graph`, whose packages are found at `locations`.
+pub fn generate(
+    graph: &Graph,
+    lockfile: &Lockfile,
+    locations: &[PackageLocation],
+    target: &Target,
+    idris2_version: Option<Idris2Version>,
+) -> Value {
+    let packages: Vec<Value> = graph.packages.iter()
+        .map(|package| {
+            let desc = &package.descriptor;
+            let location = locations.iter().find(|location| &location.descriptor == desc);
+            let locked = lockfile.locked(desc);
+            json!({
+                "id": id(desc),
+                "name": desc.name(),
+                "package": desc.package_name(),
+                "version": locked.and_then(|locked| locked.version.clone()).unwrap_or_else(|| package.version.clone()),
+                "kind": kind(desc),
+                "source": desc.source(),
+                "commit": locked.map(|locked| &locked.commit),
+                "manifest_path": location.map(|location| absolute(&location.manifest_path)),
+                "root": location.map(|location| absolute(&location.root)),
+                "source_dir": location.map(|location| absolute(&location.source_dir)),
+                "main": location.map(|location| absolute(&location.main)),
+                "idris2": package.idris2.as_ref().map(ToString::to_string),
+                "dependencies": package.dependencies.iter()
+                    .map(|dep| {
+                        let origin = package.origins.iter().find(|origin| &origin.dependency == dep);
+                        json!({
+                            "name": dep.name(),
+                            "id": id(dep),
+                            "declared": origin.map(|origin| declaration(&origin.declared)),
+                            "patched": origin.and_then(|origin| origin.patched.as_ref()).map(declaration),
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    json!({
+        "version": FORMAT_VERSION,
+        "root": id(&graph.root),
+        "workspace_root": absolute(Path::new("")),
+        "target_directory": absolute(&layout::build_dir()),
+        "packages": packages,
+        "inactive": graph.inactive.iter()
+            .map(|dep| json!({ "name": dep.name(), "source": dep.source() }))
+            .collect::<Vec<_>>(),
+        "target": target,
+        "toolchain": {
+            "lair": env!("CARGO_PKG_VERSION"),
+            "idris2": paths::idris2(),
+            "idris2_version": idris2_version.map(|version| version.to_string()),
+        },
+    })
+}
+
+fn declaration(declaration: &Declaration) -> Value {
+    json!({ "manifest": absolute(&declaration.manifest), "key": declaration.key, "line": declaration.line })
+}
+
+/// For example `NotJson-3f2a9c01`.
+pub fn id(desc: &Descriptor) -> String {
+    format!("{}-{}", desc.name(), layout::short_hash(desc))
+}
+
+/// `root`, `git`, `archive`, `registry` or `local`.
+pub fn kind(desc: &Descriptor) -> &'static str {
+    match desc {
+        Descriptor::Root { .. } => "root",
+        Descriptor::Git { .. } => "git",
+        Descriptor::Archive { .. } => "archive",
+        Descriptor::Registry { .. } => "registry",
+        Descriptor::Local { .. } => "local",
+    }
+}
+
+/// Relative to the root package's directory, which is the current directory in `lair`.
+pub fn absolute(path: &Path) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(dir) if path.as_os_str().is_empty() => dir,
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_owned(),
+    }
+}
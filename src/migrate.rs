@@ -0,0 +1,374 @@
+//! `lair migrate`: writing an `Egg.toml` for a project which so far was built from an `.ipkg`
+//! file, or by a Makefile calling idris2.
+//!
+//! From the ipkg come the name, version, `brief`, `license`, `authors`, `sourceloc`, `sourcedir`
+//! and `depends`, and its hooks become [`crate::scripts`]. From a Makefile, what its idris2 command
+//! lines say: the ipkg they `--build` (read like one lying next to it), the packages they load
+//! with `-p`, and their `--source-dir`. Dependencies become registry dependencies if the registry
+//! has them, git dependencies if they are one of the [`KNOWN`] packages, and a commented-out
+//! line to fill in otherwise.
+//!
+//! Everything Lair can't express, or does differently, is reported in [`Migration::notes`], one
+//! sentence each, rather than silently dropped.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use itertools::Itertools;
+
+use crate::compiler::Idris2Requirement;
+use crate::ipkg::{self, Ipkg, BUILTIN_PACKAGES};
+use crate::manifest::Manifest;
+use crate::modules::DEFAULT_SOURCE_DIR;
+use crate::package_name::PackageName;
+use crate::registry::Index;
+use crate::scripts;
+use crate::semver::{Version, VersionRequirement};
+
+/// Well-known packages without a registry entry, by their ipkg name.
+pub const KNOWN: &[(&str, &str)] = &[
+    ("elab-util", "https://github.com/stefan-hoeck/idris2-elab-util"),
+    ("sop", "https://github.com/stefan-hoeck/idris2-sop"),
+    ("pretty-show", "https://github.com/stefan-hoeck/idris2-pretty-show"),
+    ("hedgehog", "https://github.com/stefan-hoeck/idris2-hedgehog"),
+    ("json", "https://github.com/stefan-hoeck/idris2-json"),
+    ("parser", "https://github.com/stefan-hoeck/idris2-parser"),
+];
+
+/// Builtin packages idris2 loads without being asked to.
+const LOADED_BUILTINS: &[&str] = &["prelude", "base"];
+
+/// ipkg fields which say where things go or how they are built, which Lair decides itself.
+const LAIR_DECIDES: &[&str] = &["builddir", "outputdir"];
+
+/// ipkg hooks, each becoming a script of the same name, except `postbuild`.
+const HOOKS: &[&str] = &["prebuild", "postbuild", "preinstall", "postinstall", "preclean", "postclean"];
+
+/// What [`migrate`] came up with.
+#[derive(Clone, Debug)]
+pub struct Migration {
+    /// The new `Egg.toml`.
+    pub manifest: String,
+    /// The files it is made from.
+    pub sources: Vec<PathBuf>,
+    /// What couldn't be translated, or needs a look.
+    pub notes: Vec<String>,
+}
+
+/// What the idris2 command lines of a Makefile say, all of them together.
+#[derive(Clone, Debug, Default)]
+struct Invocations {
+    ipkg: Option<PathBuf>,
+    packages: Vec<String>,
+    source_dir: Option<String>,
+    /// The `.idr` files given, if any.
+    main: Option<PathBuf>,
+    /// `-o`, the program's name.
+    output: Option<String>,
+    /// Arguments we don't translate, as written.
+    untranslated: Vec<String>,
+}
+
+/// Work out an `Egg.toml` for the project in `dir`, looking up dependencies in `registry` (its
+/// url and index) if given. Blocking.
+pub fn migrate(dir: &Path, registry: Option<(&str, &Index)>) -> Result<Migration, anyhow::Error> {
+    let mut notes = Vec::new();
+    let mut sources = Vec::new();
+
+    let makefile = find_makefile(dir);
+    let invocations = match &makefile {
+        Some(path) => {
+            let makefile = std::fs::read_to_string(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+            let invocations = parse_makefile(&makefile);
+            if invocations.is_some() {
+                sources.push(path.clone());
+            }
+            invocations
+        },
+        None => None,
+    };
+
+    let ipkg_path = match invocations.as_ref().and_then(|invocations| invocations.ipkg.clone()) {
+        Some(path) => Some(dir.join(path)),
+        None => ipkg::find(dir)?,
+    };
+    let ipkg = match &ipkg_path {
+        Some(path) => {
+            let s = std::fs::read_to_string(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+            sources.insert(0, path.clone());
+            Some(Ipkg::parse(&s).with_context(|| format!("`{}` has no `package` line", path.display()))?)
+        },
+        None => None,
+    };
+    if ipkg.is_none() && invocations.is_none() {
+        anyhow::bail!("Found neither an `.ipkg` file nor a Makefile calling idris2 in `{}`.", dir.display());
+    }
+    let invocations = invocations.unwrap_or_default();
+    let fields = ipkg.as_ref().map(|ipkg| ipkg.fields.clone()).unwrap_or_default();
+    let field = |key: &str| fields.get(key).map(|value| value.trim_matches('"').to_owned());
+
+    // The package.
+    let written_name = match &ipkg {
+        Some(ipkg) => ipkg.name.clone(),
+        None => std::path::absolute(dir)?.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+    };
+    let name = match written_name.parse::<PackageName>() {
+        Ok(name) => name,
+        Err(e) => {
+            let name = sanitized(&written_name);
+            notes.push(format!("{}, so the package is called `{}` instead.", e, name));
+            name
+        },
+    };
+    // Lair wants semver, ipkgs often have `0.1`.
+    let version = match ipkg.as_ref().and_then(|ipkg| ipkg.version.as_deref()) {
+        Some(version) => match Version::parse_lenient(version) {
+            Ok(parsed) => parsed.to_string(),
+            Err(e) => {
+                notes.push(format!("{}, so the version is `0.1.0` instead.", e));
+                "0.1.0".to_owned()
+            },
+        },
+        None => "0.1.0".to_owned(),
+    };
+    let source_dir = field("sourcedir").or(invocations.source_dir.clone()).unwrap_or_else(|| DEFAULT_SOURCE_DIR.to_owned());
+
+    // In the order `lair new` writes them.
+    let mut package = vec![("name", toml::Value::String(name.to_string())), ("version", toml::Value::String(version))];
+    if let Some(authors) = fields.get("authors") {
+        let authors = authors.split(',').map(|author| author.trim().trim_matches('"')).filter(|author| !author.is_empty());
+        package.push(("authors", toml::Value::Array(authors.map(|author| toml::Value::String(author.to_owned())).collect())));
+    }
+    for (ipkg_key, key) in [("brief", "description"), ("license", "license"), ("sourceloc", "repository")] {
+        if let Some(value) = field(ipkg_key) {
+            package.push((key, toml::Value::String(value)));
+        }
+    }
+    // `langversion >= 0.6.0` reads as the field `langversion >`.
+    if let Some((key, value)) = fields.iter().find(|(key, _)| key.starts_with("langversion")) {
+        let constraint = format!("{}={}", &key["langversion".len()..], value);
+        let requirement = converted(&constraint);
+        match requirement.parse::<Idris2Requirement>() {
+            Ok(_) => package.push(("idris2", toml::Value::String(requirement))),
+            Err(_) => notes.push(format!("`langversion {}` isn't an idris2 version requirement Lair understands, so it is left out.", constraint.trim())),
+        }
+    }
+    if source_dir != DEFAULT_SOURCE_DIR {
+        package.push(("source-dir", toml::Value::String(source_dir.clone())));
+    }
+
+    // Lair checks `{source_dir}/{name}.idr`, and whatever it imports.
+    let main_module = dir.join(&source_dir).join(format!("{}.idr", name));
+    if !main_module.exists() {
+        let modules: Vec<String> = match fields.get("modules") {
+            Some(modules) => modules.split(',').map(|module| module.trim().to_owned()).filter(|module| !module.is_empty()).collect(),
+            None => Vec::new(),
+        };
+        let imports = match modules.is_empty() {
+            true => String::new(),
+            false => format!(" importing {}", modules.iter().map(|module| format!("`{}`", module)).join(", ")),
+        };
+        notes.push(format!("Lair builds a package by checking its main module `{}`, which doesn't exist yet; add it{}.",
+            Path::new(&source_dir).join(format!("{}.idr", name)).display(), imports));
+    }
+    let program_main = field("main").or(invocations.main.as_ref().and_then(|main| Some(main.file_stem()?.to_string_lossy().into_owned())));
+    if let Some(main) = program_main.filter(|main| *main != name.to_string()) {
+        notes.push(format!("The program's `main` is in module `{}`, but `lair run` runs the one in `{}`, so move it there.", main, name));
+    }
+    if let Some(executable) = field("executable").or(invocations.output.clone()).filter(|executable| *executable != name.to_string()) {
+        notes.push(format!("The program is called `{}` after the package rather than `{}`.", name, executable));
+    }
+    if let Some(opts) = field("opts") {
+        notes.push(format!("Lair doesn't pass idris2 flags for the package itself, so `opts = \"{}\"` is left out.", opts));
+    }
+    for key in LAIR_DECIDES {
+        if fields.contains_key(*key) {
+            notes.push(format!("`{}` is left out, Lair decides where things go (`build/`).", key));
+        }
+    }
+
+    // Hooks.
+    let mut scripts = toml::value::Table::new();
+    for hook in HOOKS {
+        let Some(command) = field(hook) else { continue };
+        let script = match *hook {
+            "postbuild" => scripts::POST_BUILD.to_owned(),
+            hook => {
+                notes.push(format!("`{}` is now a script, which Lair doesn't run on its own: run it with `lair script {}`.", hook, hook));
+                hook.to_owned()
+            },
+        };
+        scripts.insert(script, toml::Value::String(command));
+    }
+    let known_fields = ["brief", "license", "sourceloc", "authors", "sourcedir", "modules", "main", "executable", "opts"];
+    for key in fields.keys() {
+        let translated = known_fields.contains(&key.as_str()) || LAIR_DECIDES.contains(&key.as_str()) || HOOKS.contains(&key.as_str());
+        if !translated && !key.starts_with("langversion") {
+            notes.push(format!("`{}` in the ipkg has no counterpart in `Egg.toml`, so it is left out.", key));
+        }
+    }
+    for argument in &invocations.untranslated {
+        notes.push(format!("`{}` from the Makefile's idris2 command line is left out.", argument));
+    }
+
+    // Dependencies.
+    let mut depends: BTreeSet<&str> = BTreeSet::new();
+    let ipkg_depends = ipkg.as_ref().map(|ipkg| ipkg.depends.as_slice()).unwrap_or_default();
+    depends.extend(ipkg_depends.iter().chain(&invocations.packages).map(String::as_str));
+    let constraints = ipkg.as_ref().map(|ipkg| ipkg.constraints.clone()).unwrap_or_default();
+    let mut dependencies = toml::value::Table::new();
+    let mut unresolved = Vec::new();
+    for dep in depends {
+        if LOADED_BUILTINS.contains(&dep) {
+            continue;
+        }
+        if BUILTIN_PACKAGES.contains(&dep) {
+            notes.push(format!("`{}` comes with idris2, but Lair only loads `prelude` and `base` of those, so its modules can't be imported.", dep));
+            continue;
+        }
+        let constraint = constraints.get(dep);
+        let in_registry = match registry {
+            Some((url, index)) => index.package(dep)?.map(|package| (url, package)),
+            None => None,
+        };
+        let mut declaration = toml::value::Table::new();
+        if let Some((url, package)) = in_registry {
+            let translated = constraint.and_then(|constraint| {
+                let requirement = Some(converted(constraint)).filter(|requirement| requirement.parse::<VersionRequirement>().is_ok());
+                if requirement.is_none() {
+                    notes.push(format!("`{} {}` isn't a version requirement Lair understands, so `{}` asks for the newest version instead.", dep, constraint, dep));
+                }
+                requirement
+            });
+            let requirement = translated
+                .or_else(|| package.latest().map(|version| version.version.clone()))
+                .unwrap_or_else(|| "*".to_owned());
+            declaration.insert("registry".to_owned(), toml::Value::String(url.to_owned()));
+            declaration.insert("version".to_owned(), toml::Value::String(requirement));
+        } else if let Some((_, url)) = KNOWN.iter().find(|(known, _)| *known == dep) {
+            declaration.insert("git".to_owned(), toml::Value::String((*url).to_owned()));
+            if let Some(constraint) = constraint {
+                notes.push(format!("`{}` follows its default branch, rather than `{}`; add a `tag` or `rev` to pin it.", dep, constraint));
+            }
+        } else {
+            notes.push(format!("Don't know where to get `{}`, fill in its line in `[dependencies]`.", dep));
+            unresolved.push(dep);
+            continue;
+        }
+        dependencies.insert(dep.to_owned(), toml::Value::Table(declaration));
+    }
+
+    let mut manifest = "[package]\n".to_owned();
+    for (key, value) in package {
+        manifest.push_str(&format!("{} = {}\n", key, value));
+    }
+    manifest.push_str("\n[dependencies]\n");
+    manifest.push_str(&inline_tables(&dependencies));
+    for dep in unresolved {
+        manifest.push_str(&format!("# {} = {{ git = \"...\" }}\n", dep));
+    }
+    if !scripts.is_empty() {
+        manifest.push_str(&format!("\n[scripts]\n{}", toml::to_string(&scripts).expect("Tables are always serializable.")));
+    }
+    // What we write has to be something Lair reads.
+    Manifest::from_string(&manifest).context("The generated `Egg.toml` is invalid")?;
+
+    Ok(Migration { manifest, sources, notes })
+}
+
+/// `name = { key = "value", ... }` lines, the way dependencies are usually written.
+fn inline_tables(tables: &toml::value::Table) -> String {
+    tables.iter()
+        .map(|(name, table)| {
+            let fields = match table {
+                toml::Value::Table(table) => table.iter().map(|(key, value)| format!("{} = {}", key, value)).join(", "),
+                _ => unreachable!("Only tables go in here."),
+            };
+            format!("{} = {{ {} }}\n", toml::Value::String(name.clone()).to_string().trim_matches('"'), fields)
+        })
+        .collect()
+}
+
+/// An ipkg version constraint (`>= 0.5 && < 0.7`, `== 1.0`) written like Lair's requirements
+/// (`>=0.5, <0.7`, `=1.0`), which may or may not understand it.
+fn converted(constraint: &str) -> String {
+    constraint.split("&&").map(|part| part.trim().replace("==", "=").replace(' ', "")).join(", ")
+}
+
+/// `written` made into a valid package name, as close as it gets.
+fn sanitized(written: &str) -> PackageName {
+    let mut name: String = written.chars().map(|c| if c.is_alphanumeric() || matches!(c, '_' | '\'' | '-') { c } else { '_' }).collect();
+    if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name.truncate(64);
+    name.parse().unwrap_or_else(|_| "Package".parse().expect("A valid name."))
+}
+
+fn find_makefile(dir: &Path) -> Option<PathBuf> {
+    ["GNUmakefile", "makefile", "Makefile"].iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
+
+/// The idris2 command lines in `makefile`: lines calling `idris2`, or a variable set to it (like
+/// `IDRIS2 ?= idris2` and `$(IDRIS2) --build foo.ipkg`). `None` if there are none.
+fn parse_makefile(makefile: &str) -> Option<Invocations> {
+    let joined = makefile.replace("\\\n", " ");
+    let mut programs: Vec<String> = vec!["idris2".to_owned()];
+    for line in joined.lines() {
+        let Some((variable, value)) = line.split_once('=') else { continue };
+        let variable = variable.trim_end_matches([':', '?', '+']).trim();
+        if value.split_whitespace().next().is_some_and(|program| program.ends_with("idris2")) && !variable.contains(char::is_whitespace) {
+            programs.push(format!("$({})", variable));
+            programs.push(format!("${{{}}}", variable));
+        }
+    }
+
+    let mut found = false;
+    let mut invocations = Invocations::default();
+    for line in joined.lines() {
+        let mut words = line.split_whitespace().skip_while(|word| !programs.iter().any(|program| word == program || word.ends_with("/idris2")));
+        if words.next().is_none() {
+            continue;
+        }
+        found = true;
+        while let Some(word) = words.next() {
+            // The rest belongs to the next command.
+            if matches!(word, "&&" | ";" | "||" | "|") {
+                break;
+            }
+            match word {
+                "--build" | "--install" | "--install-with-src" | "--typecheck" | "--clean" | "--mkdoc" => {
+                    if let Some(ipkg) = words.next() {
+                        invocations.ipkg.get_or_insert_with(|| PathBuf::from(ipkg));
+                    }
+                },
+                "-p" | "--package" => invocations.packages.extend(words.next().map(str::to_owned)),
+                "--source-dir" => {
+                    if let Some(dir) = words.next() {
+                        invocations.source_dir.get_or_insert_with(|| dir.to_owned());
+                    }
+                },
+                "-o" | "--output" => {
+                    if let Some(output) = words.next() {
+                        invocations.output.get_or_insert_with(|| output.to_owned());
+                    }
+                },
+                // Lair passes these itself.
+                "--build-dir" | "--output-dir" | "--cg" | "--codegen" => {
+                    words.next();
+                },
+                "-c" | "--check" => {},
+                word if word.ends_with(".idr") || word.ends_with(".lidr") => {
+                    invocations.main.get_or_insert_with(|| PathBuf::from(word));
+                },
+                word if word.ends_with(".ipkg") => {
+                    invocations.ipkg.get_or_insert_with(|| PathBuf::from(word));
+                },
+                word => invocations.untranslated.push(word.to_owned()),
+            }
+        }
+    }
+    invocations.untranslated = invocations.untranslated.into_iter().unique().collect();
+    found.then_some(invocations)
+}
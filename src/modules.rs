@@ -0,0 +1,343 @@
+//! Which idris2 modules each package provides, to catch two packages providing the same one.
+//!
+//! idris2 finds modules by name alone, searching the TTC directories of all dependencies. So if
+//! both `NotJson` and `FastJson` have a `src/Data/Json.idr`, whichever comes first wins, and the
+//! errors that follows don't mention the other package at all. Checking the sources beforehand
+//! can name both.
+//!
+//! Also which modules a module imports, see [`imports`].
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::descriptor::Descriptor;
+
+/// Source file extensions idris2 reads modules from: plain and literate.
+const EXTENSIONS: &[&str] = &["idr", "lidr"];
+
+/// Where a package's modules are, relative to the package, unless its manifest says otherwise
+/// with `source-dir`.
+pub const DEFAULT_SOURCE_DIR: &str = "src";
+
+/// Where else packages without a `src` tend to keep their modules, in the order they're tried.
+const GUESSED_SOURCE_DIRS: &[&str] = &["lib", "."];
+
+/// A module provided by more than one package.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Collision {
+    /// For example `Data.Json`.
+    pub module: String,
+    pub packages: Vec<Descriptor>,
+}
+
+/// The modules in `source_dir`, for example `Data.Json` for `{source_dir}/Data/Json.idr`, sorted.
+/// Nothing if there is no `source_dir`. Skips `build` and hidden directories, which aren't modules
+/// even when the source directory is the package's own.
+pub fn scan(source_dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut modules = Vec::new();
+    if source_dir.is_dir() {
+        scan_rec(source_dir, "", &mut modules)?;
+    }
+    modules.sort();
+    Ok(modules)
+}
+
+fn scan_rec(dir: &Path, prefix: &str, modules: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+        if entry.file_type()?.is_dir() {
+            if stem == "build" || stem.starts_with('.') {
+                continue;
+            }
+            scan_rec(&path, &format!("{}{}.", prefix, stem), modules)?;
+        } else if path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| EXTENSIONS.contains(&ext)) {
+            modules.push(format!("{}{}", prefix, stem));
+        }
+    }
+    Ok(())
+}
+
+/// The source file of `module` in `source_dir`, for example `{source_dir}/Data/Json.idr` for
+/// `Data.Json`. `None` if there is none.
+pub fn file_of(source_dir: &Path, module: &str) -> Option<PathBuf> {
+    let path = module.split('.').fold(source_dir.to_owned(), |path, part| path.join(part));
+    EXTENSIONS.iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|file| file.is_file())
+}
+
+/// Where the modules of package `name` in `base_path` are, relative to it, if its manifest doesn't
+/// say and there is no [`DEFAULT_SOURCE_DIR`]: the first of `lib` and the package's directory
+/// itself having the main module `{name}.idr`, or else any module at all. `None` if there is a
+/// `src` after all, or nothing looks like one. Blocking.
+pub fn guess_source_dir(base_path: &Path, name: &str) -> Option<PathBuf> {
+    if base_path.join(DEFAULT_SOURCE_DIR).is_dir() {
+        return None;
+    }
+    // Only looking at the directory itself, the package's directory has `build` and the like.
+    let has_modules = |dir: &Path| std::fs::read_dir(dir).into_iter().flatten().flatten()
+        .any(|entry| entry.path().extension().and_then(|ext| ext.to_str()).is_some_and(|ext| EXTENSIONS.contains(&ext)));
+    let candidates = || GUESSED_SOURCE_DIRS.iter().map(Path::new);
+    candidates().find(|dir| file_of(&base_path.join(dir), name).is_some())
+        .or_else(|| candidates().find(|dir| has_modules(&base_path.join(dir))))
+        .map(Path::to_owned)
+}
+
+/// A function to run instead of `main`, for example `Tool.Cli.serve`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entrypoint {
+    /// For example `Tool.Cli`.
+    pub module: String,
+    /// For example `serve`.
+    pub function: String,
+}
+
+impl FromStr for Entrypoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{}` isn't a function in a module, expected something like `Tool.Cli.serve`", s);
+        let (module, function) = s.trim().rsplit_once('.').ok_or_else(invalid)?;
+        let is_name = |part: &str| part.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && part.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '\'');
+        if !is_name(function) || !module.split('.').all(is_name) {
+            return Err(invalid());
+        }
+        Ok(Self { module: module.to_owned(), function: function.to_owned() })
+    }
+}
+
+impl Display for Entrypoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.module, self.function)
+    }
+}
+
+/// What happened to a module between two versions of a package.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// The module `file` (a path relative to the source directory) holds, for example `Data.Json` for
+/// `Data/Json.idr`. `None` if it isn't an idris2 source file.
+pub fn module_of(file: &Path) -> Option<String> {
+    let ext = file.extension()?.to_str()?;
+    if !EXTENSIONS.contains(&ext) {
+        return None;
+    }
+    let parts = file.with_extension("").iter().map(|part| part.to_str().map(str::to_owned)).collect::<Option<Vec<_>>>()?;
+    Some(parts.join("."))
+}
+
+/// An `import` line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Import {
+    /// For example `Data.Json`.
+    pub module: String,
+    /// Counting from 1.
+    pub line: usize,
+}
+
+/// The modules `source` imports, in order. `literate` for `.lidr` files, whose code is on lines
+/// starting with `>` or between `\begin{code}` and `\end{code}`.
+pub fn imports(source: &str, literate: bool) -> Vec<Import> {
+    let mut imports = Vec::new();
+    let mut comment_depth = 0usize;
+    let mut in_code_block = false;
+    for (i, line) in source.lines().enumerate() {
+        let code = if literate {
+            match line.trim_end() {
+                "\\begin{code}" => { in_code_block = true; continue },
+                "\\end{code}" => { in_code_block = false; continue },
+                _ if in_code_block => line,
+                _ => match line.strip_prefix('>') {
+                    Some(code) => code,
+                    None => continue,
+                },
+            }
+        } else {
+            line
+        };
+        let code = strip_comments(code, &mut comment_depth);
+        let mut words = code.split_whitespace();
+        if words.next() != Some("import") {
+            continue;
+        }
+        let module = match words.next() {
+            Some("public") => words.next(),
+            module => module,
+        };
+        if let Some(module) = module.filter(|module| is_module_name(module)) {
+            imports.push(Import { module: module.to_owned(), line: i + 1 });
+        }
+    }
+    imports
+}
+
+/// `line` without `--` comments and `{- -}` comments, which nest and may span lines, so
+/// `depth` carries over how deep in them the previous line ended. Neither starts within a string
+/// or character literal. Strings spanning lines (`"""`) are taken for several one-line ones.
+fn strip_comments(line: &str, depth: &mut usize) -> String {
+    let mut code = String::new();
+    let mut chars = line.chars().peekable();
+    // Whether a `'` starts a character literal, rather than ending a name like `x'`.
+    let mut after_name = false;
+    while let Some(c) = chars.next() {
+        if *depth == 0 && (c == '"' || c == '\'' && !after_name) {
+            code.push(c);
+            while let Some(inner) = chars.next() {
+                code.push(inner);
+                if inner == '\\' {
+                    code.extend(chars.next());
+                } else if inner == c {
+                    break;
+                }
+            }
+            after_name = false;
+            continue;
+        }
+        after_name = *depth == 0 && (c.is_alphanumeric() || c == '_' || c == '\'');
+        match (c, chars.peek()) {
+            ('{', Some('-')) => {
+                chars.next();
+                *depth += 1;
+            },
+            ('-', Some('}')) if *depth > 0 => {
+                chars.next();
+                *depth -= 1;
+            },
+            ('-', Some('-')) if *depth == 0 => break,
+            _ if *depth == 0 => code.push(c),
+            _ => {},
+        }
+    }
+    code
+}
+
+fn is_module_name(module: &str) -> bool {
+    module.split('.').all(|part| part.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && part.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '\''))
+}
+
+/// The imports of the module in `file`, see [`imports`].
+pub fn imports_of(file: &Path) -> std::io::Result<Vec<Import>> {
+    let literate = file.extension().is_some_and(|ext| ext == "lidr");
+    Ok(imports(&std::fs::read_to_string(file)?, literate))
+}
+
+/// `module` and the modules of `source_dir` it imports, directly or not, with their files. These
+/// are what `idris2 --check` on `module` checks of the package. Blocking.
+pub fn closure(source_dir: &Path, module: &str) -> std::io::Result<BTreeMap<String, PathBuf>> {
+    let mut closure = BTreeMap::new();
+    let mut stack = vec![module.to_owned()];
+    while let Some(module) = stack.pop() {
+        if closure.contains_key(&module) {
+            continue;
+        }
+        // Not in the package, so from a dependency or idris2 itself.
+        let Some(file) = file_of(source_dir, &module) else { continue };
+        stack.extend(imports_of(&file)?.into_iter().map(|import| import.module));
+        closure.insert(module, file);
+    }
+    Ok(closure)
+}
+
+/// Modules which more than one of `packages` provide, by name.
+pub fn collisions<'a>(packages: impl IntoIterator<Item = (&'a Descriptor, &'a [String])>) -> Vec<Collision> {
+    let mut providers: BTreeMap<&str, Vec<&Descriptor>> = BTreeMap::new();
+    for (desc, modules) in packages {
+        for module in modules {
+            providers.entry(module).or_default().push(desc);
+        }
+    }
+    providers.into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .map(|(module, packages)| Collision {
+            module: module.to_owned(),
+            packages: packages.into_iter().cloned().collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modules(imports: Vec<Import>) -> Vec<(String, usize)> {
+        imports.into_iter().map(|import| (import.module, import.line)).collect()
+    }
+
+    #[test]
+    fn finds_imports() {
+        let source = "\
+module Main
+
+import Data.List
+import public Data.Json -- the one we need
+-- import Commented.Out
+{- import In.Block
+   {- nested -} import Still.In.Block -}
+import Control.Monad.State {- trailing -}
+  import Indented
+import 1Bad
+importNot.This
+";
+        assert_eq!(modules(imports(source, false)), [
+            ("Data.List".to_owned(), 3),
+            ("Data.Json".to_owned(), 4),
+            ("Control.Monad.State".to_owned(), 8),
+            ("Indented".to_owned(), 9),
+        ]);
+    }
+
+    #[test]
+    fn comments_dont_start_in_literals() {
+        let strip = |line: &str| {
+            let mut depth = 0;
+            (strip_comments(line, &mut depth), depth)
+        };
+        assert_eq!(strip(r#"x = "{- not a comment" -- but this is"#), (r#"x = "{- not a comment" "#.to_owned(), 0));
+        assert_eq!(strip(r#"x = "a \" -- b" ++ "c""#), (r#"x = "a \" -- b" ++ "c""#.to_owned(), 0));
+        assert_eq!(strip("c = '\"' {- comment"), ("c = '\"' ".to_owned(), 1));
+        assert_eq!(strip("x' = '-' -- comment"), ("x' = '-' ".to_owned(), 0));
+    }
+
+    #[test]
+    fn finds_imports_in_literate_files() {
+        let source = "\
+import Prose.Only
+> import Bird.Style
+\\begin{code}
+import Code.Block
+\\end{code}
+import Prose.Again
+";
+        assert_eq!(modules(imports(source, true)), [("Bird.Style".to_owned(), 2), ("Code.Block".to_owned(), 4)]);
+    }
+
+    #[test]
+    fn closure_follows_imports_within_the_package() {
+        let dir = crate::files::temp_dir("lair-test-").unwrap();
+        let write = |file: &str, source: &str| {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        };
+        write("Main.idr", "module Main\nimport Data.Json\nimport Data.List\n");
+        write("Data/Json.idr", "module Data.Json\nimport Data.Json.Parser\nimport Main\n");
+        write("Data/Json/Parser.lidr", "> module Data.Json.Parser\n");
+        write("Unused.idr", "module Unused\n");
+
+        let closure = closure(&dir, "Main").unwrap();
+        assert_eq!(closure.keys().collect::<Vec<_>>(), ["Data.Json", "Data.Json.Parser", "Main"]);
+        assert_eq!(closure["Data.Json.Parser"], dir.join("Data/Json/Parser.lidr"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
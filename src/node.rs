@@ -1,28 +1,46 @@
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, OnceLock, Weak};
 
 use futures::future::try_join_all;
 
 use crate::LairInner;
 use crate::descriptor::Descriptor;
 use crate::error::{BuildTtcError, ManifestFetchError, SourceFetchError};
-use crate::lazy::Lazy;
+use crate::graph::Origin;
+use crate::lazy::{Lazy, Progress};
 use crate::manifest::Manifest;
+use crate::modules;
 use crate::tracing::Tracer;
 
 /// A node in the dependency tree.
 ///
-/// Contains weak references to Lair.
+/// Contains weak references to Lair. What it works out is kept behind `Arc`s, so that asking again
+/// doesn't copy it.
 #[derive(Debug)]
 pub struct Node<Tr: Tracer = ()> {
     pub descriptor: Descriptor,
 
-    manifest: Lazy<Result<Manifest, ManifestFetchError>>,
+    manifest: Lazy<Result<Arc<Manifest>, ManifestFetchError>>,
 
-    base_path: Lazy<Result<PathBuf, SourceFetchError>>,
+    base_path: Lazy<Result<Arc<Path>, SourceFetchError>>,
 
-    /// Compiled TTC files done? If yes, they can be found here (usually `{base_path}/build/ttc`).
-    ttc: Lazy<Result<PathBuf, BuildTtcError>>,
+    /// Compiled TTC files done? If yes, they can be found here (usually `{base_path}/build/idris2-0.6.0/ttc`).
+    ttc: Lazy<Result<Arc<Path>, BuildTtcError>>,
+
+    /// Set once the TTC files are built, see [`crate::fingerprint`].
+    fingerprint: OnceLock<String>,
+
+    /// Set before building, if the TTC files can be shared through a remote cache, see
+    /// [`Node::remote_key`].
+    remote_key: OnceLock<String>,
+
+    /// The package which first turned out to depend on this one, and where it declares that. For
+    /// telling where a package came from in its errors.
+    introduced_by: OnceLock<(Descriptor, Origin)>,
+
+    /// Relative to `base_path`, see [`Node::source_dir`]. Only worked out once, so that a guess is
+    /// only warned about once.
+    source_dir: OnceLock<PathBuf>,
 
     lair: Weak<LairInner<Tr>>,
 
@@ -34,15 +52,19 @@ impl<Tr: Tracer> Node<Tr> {
     pub(crate) fn new(
         lair: Weak<LairInner<Tr>>,
         descriptor: Descriptor,
-        manifest: Lazy<Result<Manifest, ManifestFetchError>>,
-        base_path: Lazy<Result<PathBuf, SourceFetchError>>,
-        ttc: Lazy<Result<PathBuf, BuildTtcError>>,
+        manifest: Lazy<Result<Arc<Manifest>, ManifestFetchError>>,
+        base_path: Lazy<Result<Arc<Path>, SourceFetchError>>,
+        ttc: Lazy<Result<Arc<Path>, BuildTtcError>>,
     ) -> Self {
         Self {
             descriptor,
             manifest,
             base_path,
             ttc,
+            fingerprint: OnceLock::new(),
+            remote_key: OnceLock::new(),
+            introduced_by: OnceLock::new(),
+            source_dir: OnceLock::new(),
             lair,
         }
     }
@@ -50,15 +72,19 @@ impl<Tr: Tracer> Node<Tr> {
     pub(crate) fn new_partial(
         lair: Weak<LairInner<Tr>>,
         descriptor: Descriptor,
-        manifest: Manifest,
+        manifest: Arc<Manifest>,
         base_path: impl AsRef<Path>,
-        ttc: Lazy<Result<PathBuf, BuildTtcError>>,
+        ttc: Lazy<Result<Arc<Path>, BuildTtcError>>,
     ) -> Self {
         Self {
             descriptor,
             manifest: Lazy::new_immediate(Ok(manifest)),
-            base_path: Lazy::new_immediate(Ok(base_path.as_ref().to_owned())),
+            base_path: Lazy::new_immediate(Ok(Arc::from(base_path.as_ref()))),
             ttc,
+            fingerprint: OnceLock::new(),
+            remote_key: OnceLock::new(),
+            introduced_by: OnceLock::new(),
+            source_dir: OnceLock::new(),
             lair,
         }
     }
@@ -68,42 +94,133 @@ impl<Tr: Tracer> Node<Tr> {
         self.lair.upgrade().expect("Failed to upgrade lair weak Arc.")
     }
 
-    /// Package name, for example `AmazingTool`.
+    /// Package name, for example `AmazingTool`. May be an alias, see [`Descriptor::name`].
     pub fn name(&self) -> &str {
         self.descriptor.name()
     }
 
     /// If the package name is `AmazingTool`, then this will usually be
-    /// `{base_path}/src/AmazingTool.idr`.
-    pub async fn main(&self) -> Result<PathBuf, SourceFetchError> {
-        Ok(self.base_path().await?.join("src").join(format!("{}.idr", self.name())))
+    /// `{base_path}/src/AmazingTool.idr`. Aliases don't matter here, it's always the actual
+    /// package name.
+    pub async fn main(&self) -> Result<PathBuf, ManifestFetchError> {
+        let src = self.source_dir().await?;
+        Ok(src.join(format!("{}.idr", self.descriptor.package_name())))
+    }
+
+    /// Where the package's modules are, usually `{base_path}/src`: the manifest's `source-dir`,
+    /// or if it doesn't say and there is no `src`, wherever they seem to be, with a warning. See
+    /// [`modules::guess_source_dir`].
+    pub async fn source_dir(&self) -> Result<PathBuf, ManifestFetchError> {
+        let base_path = self.base_path().await?;
+        let manifest = self.manifest().await?;
+        let dir = self.source_dir.get_or_init(|| match &manifest.source_dir {
+            Some(dir) => dir.clone(),
+            None => match modules::guess_source_dir(&base_path, self.descriptor.package_name()) {
+                Some(guessed) => {
+                    self.lair().tracer.guessed_source_dir(&self.descriptor, &guessed);
+                    guessed
+                },
+                None => PathBuf::from(modules::DEFAULT_SOURCE_DIR),
+            },
+        });
+        Ok(base_path.join(dir))
+    }
+
+    /// Parsed only once, later calls share the result.
+    pub async fn manifest(&self) -> Result<Arc<Manifest>, ManifestFetchError> {
+        self.manifest.get().await.clone()
     }
 
-    pub async fn manifest(&self) -> Result<Manifest, ManifestFetchError> {
-        self.manifest.get().await
+    /// The manifest, if it was already read successfully. Doesn't fetch anything.
+    pub fn peek_manifest(&self) -> Option<Arc<Manifest>> {
+        self.manifest.peek()?.as_ref().ok().cloned()
     }
 
     /// Base path, so that `{base_path}/Egg.toml`.
     /// Download sources if necessary.
-    pub async fn base_path(&self) -> Result<PathBuf, SourceFetchError> {
-        self.base_path.get().await
+    pub async fn base_path(&self) -> Result<Arc<Path>, SourceFetchError> {
+        self.base_path.get().await.clone()
+    }
+
+    /// How far along downloading the sources is, without starting it.
+    pub fn source_progress(&self) -> Progress {
+        self.base_path.probe_progress()
+    }
+
+    /// How far along building the TTC files is, without starting it.
+    pub fn ttc_progress(&self) -> Progress {
+        self.ttc.probe_progress()
+    }
+
+    /// TTC path, usually `{base_path}/build/idris2-0.6.0/ttc`.
+    pub async fn ttc(&self) -> Result<Arc<Path>, BuildTtcError> {
+        self.ttc.get().await.clone()
     }
 
-    /// TTC path, usually `{base_path}/build/ttc`.
-    pub async fn ttc(&self) -> Result<PathBuf, BuildTtcError> {
-        self.ttc.get().await
+    /// Combined fingerprint of the TTC files built by [`Node::ttc`], `None` until they are built.
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.get().map(String::as_str)
+    }
+
+    pub(crate) fn set_fingerprint(&self, fingerprint: String) {
+        let _ = self.fingerprint.set(fingerprint);
+    }
+
+    /// Key of the TTC files in the remote cache: a hash of the descriptor, what its sources are
+    /// pinned to (the locked commit, or the archive's checksum), how it's built (the idris2 and
+    /// `idris-flags`) and the keys of its direct dependencies, see [`crate::remote_cache::key`].
+    /// `None` before the build starts, without a remote cache, or if it or a dependency isn't
+    /// pinned to something every machine agrees on.
+    pub fn remote_key(&self) -> Option<&str> {
+        self.remote_key.get().map(String::as_str)
+    }
+
+    pub(crate) fn set_remote_key(&self, key: String) {
+        let _ = self.remote_key.set(key);
+    }
+
+    /// See [`Node::introduced_by`].
+    pub fn introduced_by(&self) -> Option<&(Descriptor, Origin)> {
+        self.introduced_by.get()
     }
 
     pub async fn dependencies(&self) -> Result<Vec<Arc<Node<Tr>>>, ManifestFetchError> {
         let lair = self.lair();
         let manifest = self.manifest().await?;
-        let ret = manifest.dependencies.iter()
-            .map(|dep| lair.node(dep))
+        let dependencies = manifest.dependencies_for(&lair.target());
+        for dep in &dependencies {
+            let dep = lair.patched(dep);
+            lair.correct_registry_name(&dep).await?;
+            lair.resolve_default_branch(&dep).await;
+        }
+        let ret = dependencies.iter()
+            .map(|dep| {
+                let node = lair.node(dep);
+                if let Some(origin) = lair.origin(&manifest, dep) {
+                    let _ = node.introduced_by.set((self.descriptor.clone(), origin));
+                }
+                node
+            })
             .collect();
         Ok(ret)
     }
 
-    pub async fn dependencies_ttc_paths(&self) -> Result<Vec<PathBuf>, BuildTtcError> {
+    /// Where the manifest declares each direct dependency, see [`Origin`].
+    pub async fn origins(&self) -> Result<Vec<Origin>, ManifestFetchError> {
+        let lair = self.lair();
+        let manifest = self.manifest().await?;
+        Ok(manifest.dependencies_for(&lair.target()).iter().filter_map(|dep| lair.origin(&manifest, dep)).collect())
+    }
+
+    /// Descriptors of the direct dependencies (after `[patch]`), if the manifest was already read
+    /// successfully. Doesn't fetch anything, and doesn't create nodes for them.
+    pub fn peek_dependencies(&self) -> Option<Vec<Descriptor>> {
+        let lair = self.lair();
+        let manifest = self.peek_manifest()?;
+        Some(manifest.dependencies_for(&lair.target()).iter().map(|dep| lair.known_as(&lair.patched(dep))).collect())
+    }
+
+    pub async fn dependencies_ttc_paths(&self) -> Result<Vec<Arc<Path>>, BuildTtcError> {
         let mut tmp = self.dependencies().await?;
         let futures = tmp.drain(..)
             .map(|dep| async move { dep.ttc().await });
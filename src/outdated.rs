@@ -0,0 +1,181 @@
+//! `lair outdated`: how far the locked dependencies are behind what their remotes and registries
+//! have by now.
+//!
+//! Like `lair verify`, this only looks: the lockfile and the checkouts stay as they are. Moving
+//! on is up to `lair lock --update <package>`.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::descriptor::{Descriptor, GitVersion};
+use crate::fetch;
+use crate::lockfile::{LockedPackage, Lockfile};
+use crate::registry::{self, Index};
+use crate::semver::Version;
+
+/// One locked dependency, compared to the newest thing it could use.
+#[derive(Clone, Debug)]
+pub struct Outdated {
+    pub descriptor: Descriptor,
+    /// The locked version, tag or (abbreviated) commit.
+    pub current: String,
+    /// The newest version or tag, or the (abbreviated) commit the branch is at now. `None` if
+    /// there is none, or we couldn't find out.
+    pub latest: Option<String>,
+    pub status: Status,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    UpToDate,
+    /// The branch moved on.
+    Behind,
+    /// There is a newer version, which semver says is compatible.
+    Compatible,
+    /// There is a newer version, which semver says may break things.
+    Breaking,
+    /// Locked to a `rev`, which isn't the newest tag. Whether that is older or newer, git can't
+    /// tell without downloading the history.
+    Pinned,
+    /// We couldn't find out, for example because the remote is unreachable.
+    Failed(String),
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::UpToDate => write!(f, "up to date"),
+            Status::Behind => write!(f, "behind"),
+            Status::Compatible => write!(f, "compatible"),
+            Status::Breaking => write!(f, "breaking"),
+            Status::Pinned => write!(f, "pinned"),
+            Status::Failed(error) => write!(f, "unknown ({})", error),
+        }
+    }
+}
+
+/// Compare every entry of `lockfile` to its remote or registry, sorted by name. Blocking.
+pub fn check(lockfile: &Lockfile) -> Vec<Outdated> {
+    let mut indexes = BTreeMap::new();
+    let mut outdated: Vec<Outdated> = lockfile.packages().iter()
+        .filter_map(|locked| Some((locked.descriptor()?, locked)))
+        .map(|(desc, locked)| {
+            let (current, latest, status) = match compare(&desc, locked, &mut indexes) {
+                Ok(compared) => compared,
+                Err(error) => (current(&desc, locked), None, Status::Failed(error)),
+            };
+            Outdated { descriptor: desc, current, latest, status }
+        })
+        .collect();
+    outdated.sort_by(|a, b| a.descriptor.name().cmp(b.descriptor.name()));
+    outdated
+}
+
+/// What the lockfile says, shortened for display.
+fn current(desc: &Descriptor, locked: &LockedPackage) -> String {
+    match (desc, &locked.version) {
+        (Descriptor::Git { version: GitVersion::Tag(tag), .. }, _) => tag.clone(),
+        (_, Some(version)) => version.clone(),
+        _ => short(&locked.commit).to_owned(),
+    }
+}
+
+fn compare(desc: &Descriptor, locked: &LockedPackage, indexes: &mut BTreeMap<String, Option<Index>>) -> Result<(String, Option<String>, Status), String> {
+    let current = current(desc, locked);
+    match &desc.expanded().map_err(|e| e.to_string())? {
+        Descriptor::Git { url, version, .. } => {
+            let refs = fetch::remote_refs(url).map_err(|e| e.to_string())?;
+            match version {
+                GitVersion::Branch(branch) => {
+                    let head = refs.iter()
+                        .find(|(name, _)| *name == format!("refs/heads/{}", branch))
+                        .map(|(_, commit)| commit)
+                        .ok_or_else(|| format!("the remote has no branch `{}` anymore", branch))?;
+                    let status = if *head == locked.commit { Status::UpToDate } else { Status::Behind };
+                    Ok((current, Some(short(head).to_owned()), status))
+                },
+                GitVersion::DefaultBranch => {
+                    let head = refs.iter()
+                        .find(|(name, _)| name == "HEAD")
+                        .map(|(_, commit)| commit)
+                        .ok_or_else(|| "the remote has no default branch".to_owned())?;
+                    let status = if *head == locked.commit { Status::UpToDate } else { Status::Behind };
+                    Ok((current, Some(short(head).to_owned()), status))
+                },
+                GitVersion::Tag(tag) => {
+                    let latest = newest_tag(&refs);
+                    let status = match &latest {
+                        Some((latest, _)) => version_status(strip_v(tag), strip_v(latest)),
+                        None => Status::UpToDate,
+                    };
+                    Ok((current, latest.map(|(tag, _)| tag.to_owned()), status))
+                },
+                GitVersion::TagPrefix { prefix, .. } => {
+                    let tags = refs.iter().filter_map(|(name, _)| name.strip_prefix("refs/tags/")).filter(|tag| !tag.ends_with("^{}"));
+                    let latest = fetch::pick_tag(tags, prefix, "*").map(|tag| &tag[prefix.len()..]);
+                    let status = match latest {
+                        Some(latest) => version_status(&current, latest),
+                        None => Status::UpToDate,
+                    };
+                    Ok((current, latest.map(str::to_owned), status))
+                },
+                GitVersion::Rev(_) => {
+                    let latest = newest_tag(&refs);
+                    let status = match &latest {
+                        Some((_, commit)) if *commit != locked.commit => Status::Pinned,
+                        _ => Status::UpToDate,
+                    };
+                    Ok((current, latest.map(|(tag, _)| tag.to_owned()), status))
+                },
+            }
+        },
+        Descriptor::Registry { registry, .. } => {
+            let index = indexes.entry(registry.clone())
+                .or_insert_with(|| Index::update(registry).ok())
+                .as_ref()
+                .ok_or_else(|| format!("couldn't update the index of `{}`", registry))?;
+            let latest = index.package(desc.package_name())
+                .map_err(|e| e.to_string())?
+                .and_then(|package| package.latest().map(|latest| latest.version.clone()));
+            let status = match &latest {
+                Some(latest) => version_status(&current, latest),
+                None => Status::UpToDate,
+            };
+            Ok((current, latest, status))
+        },
+        Descriptor::Root { .. } | Descriptor::Archive { .. } | Descriptor::Local { .. } =>
+            Ok((current, None, Status::UpToDate)),
+    }
+}
+
+fn version_status(current: &str, latest: &str) -> Status {
+    if registry::compare_versions(latest, current).is_le() {
+        Status::UpToDate
+    } else if registry::is_compatible(current, latest) {
+        Status::Compatible
+    } else {
+        Status::Breaking
+    }
+}
+
+/// The highest tag which looks like a version (`0.2.0` or `v0.2.0`), and isn't a pre-release
+/// (`0.3.0-beta.1`), with the commit it points to.
+pub fn newest_tag(refs: &[(String, String)]) -> Option<(&str, &str)> {
+    let tags: BTreeMap<&str, &str> = refs.iter()
+        .filter_map(|(name, commit)| Some((name.strip_prefix("refs/tags/")?, commit.as_str())))
+        .collect();
+    tags.iter()
+        .filter(|(tag, _)| !tag.ends_with("^{}") && strip_v(tag).starts_with(|c: char| c.is_ascii_digit()))
+        .filter(|(tag, _)| strip_v(tag).parse::<Version>().map_or(true, |version| !version.is_prerelease()))
+        // Annotated tags point to a tag object, the peeled entry to the commit.
+        .map(|(tag, commit)| (*tag, *tags.get(format!("{}^{{}}", tag).as_str()).unwrap_or(commit)))
+        .max_by(|(a, _), (b, _)| registry::compare_versions(strip_v(a), strip_v(b)))
+}
+
+pub fn strip_v(tag: &str) -> &str {
+    tag.strip_prefix('v').unwrap_or(tag)
+}
+
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
@@ -0,0 +1,182 @@
+//! `lair package`: the root package's sources as a tarball which can be installed with nothing
+//! but idris2, for distributions packaging Idris libraries and anybody else not using Lair.
+//!
+//! ```text
+//! build/package/
+//!     NotJson-0.3.0.tar.gz
+//!         NotJson-0.3.0/
+//!             NotJson.ipkg        -- Generated, lists every module and the dependencies.
+//!             src/                -- The package's modules, nothing else from its source
+//!                                    directory.
+//!             LICENSE             -- License files, if there are any.
+//!             lair-package.json   -- Metadata: where the dependencies came from, and which
+//!                                    versions of them and of idris2 the package was built with.
+//!             MANIFEST            -- Every file in the archive, one per line.
+//!             SHA256SUMS          -- Of every other file, for `sha256sum -c SHA256SUMS`.
+//!     NotJson-0.3.0.tar.gz.sha256 -- Of the archive, alike.
+//! ```
+//!
+//! Installing it is `idris2 --install NotJson.ipkg` in the unpacked directory, after installing
+//! the dependencies the same way. Files are added sorted, with the time from `SOURCE_DATE_EPOCH`
+//! if it is set, so that packaging the same sources twice gives the same archive.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use flate2::write::GzEncoder;
+use serde_json::json;
+
+use crate::archive;
+use crate::compiler::Idris2Requirement;
+use crate::layout;
+use crate::manifest::Metadata;
+use crate::modules;
+use crate::timestamp;
+
+/// Where the package's sources go in the archive, relative to its ipkg.
+const SOURCE_DIR: &str = "src";
+
+/// Everything that goes into the archive, gathered by [`crate::Lair::package`].
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub metadata: Metadata,
+    pub idris2: Option<Idris2Requirement>,
+    /// Version of idris2 the package was built with, if known.
+    pub built_with: Option<String>,
+    pub dependencies: Vec<Dependency>,
+    pub source_dir: PathBuf,
+    pub licenses: Vec<PathBuf>,
+}
+
+/// A direct dependency, by the name idris2 knows it as.
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    pub name: String,
+    pub version: String,
+    /// For example `https://github.com/Kiiyya/NotJson, v0.3.0`, see [`crate::descriptor::Descriptor::source`].
+    pub source: Option<String>,
+}
+
+/// `build/package/{name}-{version}.tar.gz`.
+pub fn default_dest(name: &str, version: &str) -> PathBuf {
+    layout::build_dir().join("package").join(format!("{}-{}.tar.gz", name, version))
+}
+
+/// An ipkg installing the `modules` of `package` from [`SOURCE_DIR`]. idris2 only understands
+/// versions made of numbers, so anything after a `-` or `+` is left out.
+pub fn ipkg(package: &Package, modules: &[String]) -> String {
+    let version = package.version.split(['-', '+']).next().unwrap_or_default();
+    let mut ipkg = format!("package {}\nversion = {}\n", package.name, version);
+    if let Some(description) = &package.metadata.description {
+        ipkg.push_str(&format!("brief = {:?}\n", description));
+    }
+    if !package.metadata.authors.is_empty() {
+        ipkg.push_str(&format!("authors = {:?}\n", package.metadata.authors.join(", ")));
+    }
+    if let Some(license) = &package.metadata.license {
+        ipkg.push_str(&format!("license = {:?}\n", license));
+    }
+    if let Some(repository) = &package.metadata.repository {
+        ipkg.push_str(&format!("sourceloc = {:?}\n", repository));
+    }
+    ipkg.push_str(&format!("sourcedir = {:?}\n", SOURCE_DIR));
+    if !modules.is_empty() {
+        ipkg.push_str(&format!("modules = {}\n", modules.join(", ")));
+    }
+    if !package.dependencies.is_empty() {
+        let depends: Vec<&str> = package.dependencies.iter().map(|dep| dep.name.as_str()).collect();
+        ipkg.push_str(&format!("depends = {}\n", depends.join(", ")));
+    }
+    ipkg
+}
+
+fn metadata(package: &Package) -> serde_json::Value {
+    json!({
+        "name": package.name,
+        "version": package.version,
+        "description": package.metadata.description,
+        "license": package.metadata.license,
+        "authors": package.metadata.authors,
+        "repository": package.metadata.repository,
+        "idris2": package.idris2.as_ref().map(|idris2| idris2.to_string()),
+        "built with idris2": package.built_with,
+        "dependencies": package.dependencies.iter()
+            .map(|dep| json!({ "name": dep.name, "version": dep.version, "source": dep.source }))
+            .collect::<Vec<_>>(),
+        "lair": crate::updates::VERSION,
+    })
+}
+
+/// Write the archive of `package` to `dest`, replacing whatever was there, and its checksum
+/// next to it. Returns the checksum. Blocking.
+pub fn assemble(package: &Package, dest: &Path) -> Result<String, anyhow::Error> {
+    let modules = modules::scan(&package.source_dir)?;
+    anyhow::ensure!(!modules.is_empty(), "`{}` has no modules in `{}` to package", package.name, package.source_dir.display());
+
+    // (path in the archive, contents), sorted before adding the MANIFEST and SHA256SUMS.
+    let mut files = vec![
+        (format!("{}.ipkg", package.name), ipkg(package, &modules).into_bytes()),
+        ("lair-package.json".to_owned(), (serde_json::to_string_pretty(&metadata(package))? + "\n").into_bytes()),
+    ];
+    for module in &modules {
+        let Some(file) = modules::file_of(&package.source_dir, module) else { continue };
+        let relative = file.strip_prefix(&package.source_dir).expect("found in `source_dir`");
+        let path = Path::new(SOURCE_DIR).join(relative).components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push((path, std::fs::read(&file)?));
+    }
+    for license in &package.licenses {
+        if let Some(file_name) = license.file_name() {
+            files.push((file_name.to_string_lossy().into_owned(), std::fs::read(license)?));
+        }
+    }
+    files.sort();
+
+    let sums: String = files.iter()
+        .map(|(path, bytes)| format!("{}  {}\n", archive::sha256(bytes), path))
+        .collect();
+    let mut listed: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).chain(["MANIFEST", "SHA256SUMS"]).collect();
+    listed.sort();
+    let manifest = listed.join("\n") + "\n";
+    files.push(("MANIFEST".to_owned(), manifest.into_bytes()));
+    files.push(("SHA256SUMS".to_owned(), sums.into_bytes()));
+
+    let top = format!("{}-{}", package.name, package.version);
+    let mtime = std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.trim().parse::<u64>().ok())
+        .unwrap_or_else(timestamp::now);
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), flate2::Compression::best()));
+    for (path, bytes) in &files {
+        append_bytes(&mut builder, &format!("{}/{}", top, path), bytes, mtime)?;
+    }
+    let tarball = builder.into_inner()?.finish()?;
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(dest, &tarball)?;
+    let checksum = archive::sha256(&tarball);
+    let file_name = dest.file_name().expect("written to just now").to_string_lossy();
+    std::fs::write(checksum_path(dest), format!("{}  {}\n", checksum, file_name))?;
+    Ok(checksum)
+}
+
+/// `{dest}.sha256`.
+pub fn checksum_path(dest: &Path) -> PathBuf {
+    let mut path = dest.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+fn append_bytes(builder: &mut tar::Builder<impl Write>, path: &str, bytes: &[u8], mtime: u64) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    builder.append_data(&mut header, path, bytes)
+}
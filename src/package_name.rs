@@ -0,0 +1,154 @@
+//! Package names, which Lair puts into paths (`build/deps/{name}-{hash}`, `build/logs/{name}.log`,
+//! `src/{name}.idr`, ...), so they have to be checked before a dependency's manifest gets to
+//! decide where those go.
+//!
+//! A name is an Idris identifier: a letter or `_`, followed by letters, digits, `_` and `'`. Since
+//! idris2's own `.ipkg` files are fine with names like `elab-util`, `-` is allowed too, after the
+//! first character. That leaves no way to spell `..`, `/` or `\`.
+//!
+//! Names are case-sensitive, like Idris modules: `Json` and `JSON` are different packages. But
+//! Windows ignores case in file names, so names it reserves for devices (`CON`, `nul`, `Com1`, ...)
+//! are rejected however they are spelled.
+
+use std::borrow::Borrow;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+/// At most this many characters, which is plenty, and keeps paths made from names short.
+const MAX_LENGTH: usize = 64;
+
+/// Names Windows won't let any file have, whatever the extension.
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A valid package name, see the module documentation. Derefs to `str`.
+///
+/// Shared rather than copied when cloned, and names [interned](crate::intern::Interner) by the
+/// same [`crate::Lair`] are compared by pointer before comparing their characters.
+#[derive(Clone, Debug, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PackageName(Arc<str>);
+
+impl PartialEq for PackageName {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+/// Like `str`'s, for looking names up by [`Borrow<str>`].
+impl Hash for PackageName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl FromStr for PackageName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| Err(format!("`{}` is not a valid package name, {}", s.escape_debug(), why));
+        let mut chars = s.chars();
+        match chars.next() {
+            None => return invalid("it is empty"),
+            Some(first) if !(first.is_alphabetic() || first == '_') => return invalid("it has to start with a letter or `_`"),
+            Some(_) => {},
+        }
+        if !chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '\'' | '-')) {
+            return invalid("it may only contain letters, digits, `_`, `'` and `-`");
+        }
+        if s.chars().count() > MAX_LENGTH {
+            return invalid(&format!("it is longer than {} characters", MAX_LENGTH));
+        }
+        if RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(s)) {
+            return invalid("Windows reserves it for a device");
+        }
+        Ok(Self(Arc::from(s)))
+    }
+}
+
+impl TryFrom<String> for PackageName {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PackageName> for String {
+    fn from(name: PackageName) -> Self {
+        name.0.as_ref().to_owned()
+    }
+}
+
+impl Deref for PackageName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for PackageName {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for PackageName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid(s: &str) -> bool {
+        s.parse::<PackageName>().is_ok()
+    }
+
+    #[test]
+    fn accepts_identifiers() {
+        for name in ["NotJson", "json", "_private", "elab-util", "x'", "Json2", "Ünïcode", "Console", "com10"] {
+            assert!(valid(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_anything_spelling_a_path() {
+        for name in ["", "..", ".", "../../etc", "a/b", "a\\b", "/etc", "C:", "a.b", "a b", "a\0b"] {
+            assert!(!valid(name), "{}", name.escape_debug());
+        }
+    }
+
+    #[test]
+    fn starts_with_a_letter_or_underscore() {
+        for name in ["1Json", "-json", "'json"] {
+            assert!(!valid(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn limits_the_length() {
+        assert!(valid(&"a".repeat(MAX_LENGTH)));
+        assert!(!valid(&"a".repeat(MAX_LENGTH + 1)));
+        // Characters, not bytes.
+        assert!(valid(&"ä".repeat(MAX_LENGTH)));
+    }
+
+    #[test]
+    fn rejects_reserved_names_in_any_case() {
+        for name in ["con", "CON", "Com1", "nUl", "lpt9", "aux"] {
+            assert!(!valid(name), "{}", name);
+        }
+    }
+}
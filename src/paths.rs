@@ -1,6 +1,10 @@
-use std::path::PathBuf;
+//! Where Lair has to care which platform it's running on: search path separators, executable
+//! names, and Windows' limit of 260 characters per path.
 
-use itertools::Itertools;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+use crate::error::BuildTtcError;
 
 #[cfg(target_os = "windows")]
 pub const PATH_SEP: &str = ";";
@@ -12,14 +16,89 @@ pub trait Idris2Paths {
     ///
     /// Resulting in, for example:
     ///
-    /// `build/deps/CoolCollections/build/ttc:build/deps/NotJson/build/ttc`
-    fn join_idris2(&self) -> String;
+    /// `build/deps/CoolCollections-d2c8e7a4/build/idris2-0.6.0/ttc:build/deps/NotJson-3f2a9c01/build/idris2-0.6.0/ttc`
+    ///
+    /// The paths don't have to be valid UTF-8, but can't contain the separator themselves, since
+    /// idris2 would split them apart.
+    fn join_idris2(&self) -> Result<OsString, BuildTtcError>;
+}
+
+impl Idris2Paths for [PathBuf] {
+    fn join_idris2(&self) -> Result<OsString, BuildTtcError> {
+        let mut joined = OsString::new();
+        for (i, path) in self.iter().enumerate() {
+            // The separator is ASCII, so it survives the lossy conversion wherever it occurs.
+            if path.to_string_lossy().contains(PATH_SEP) {
+                return Err(BuildTtcError::UnjoinablePath { path: path.clone() });
+            }
+            if i > 0 {
+                joined.push(PATH_SEP);
+            }
+            joined.push(path);
+        }
+        Ok(joined)
+    }
+}
+
+/// Suffixes an executable `foo` may have on disk, most likely first. idris2 itself and the
+/// programs it compiles are often batch files on Windows.
+#[cfg(target_os = "windows")]
+const EXECUTABLE_SUFFIXES: &[&str] = &[".exe", ".cmd", ".bat", ""];
+#[cfg(not(target_os = "windows"))]
+const EXECUTABLE_SUFFIXES: &[&str] = &[""];
+
+/// The idris2 to run. Usually just `idris2`, found on PATH by the OS. But on Windows only
+/// `idris2.exe` would be, so we look for `idris2.cmd` and `idris2.bat` on PATH ourselves.
+pub fn idris2() -> PathBuf {
+    if cfg!(target_os = "windows") {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        for dir in std::env::split_paths(&path) {
+            if let Some(program) = executable(&dir, "idris2") {
+                return program;
+            }
+        }
+    }
+    PathBuf::from("idris2")
 }
 
-impl Idris2Paths for Vec<PathBuf> {
-    fn join_idris2(&self) -> String {
-        self.iter()
-            .map(|path| path.to_str().unwrap()) // TODO: use OsStr instead.
-            .join(PATH_SEP)
+/// The executable called `name` in `dir`, with whatever suffix the platform gives it, for example
+/// `build/exec/AmazingTool.bat`. `None` if there is none.
+pub fn executable(dir: &Path, name: &str) -> Option<PathBuf> {
+    EXECUTABLE_SUFFIXES.iter()
+        .map(|suffix| dir.join(format!("{}{}", name, suffix)))
+        .find(|path| path.is_file())
+}
+
+/// `path` in a form which may be longer than 260 characters. On Windows that's the absolute path
+/// with a `\\?\` prefix, elsewhere `path` itself.
+///
+/// Checkouts in `build/deps` nest deeply (think `build/deps/X-3f2a9c01/build/idris2-0.6.0/ttc/Data/...`), so
+/// use this for anything walking or deleting them.
+pub fn long(path: &Path) -> PathBuf {
+    if !cfg!(target_os = "windows") {
+        return path.to_owned();
+    }
+    // Also gets rid of `.` and `..`, which the prefix would make literal.
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_owned();
+    };
+    let mut components = absolute.components();
+    let mut long = OsString::from(r"\\?\");
+    match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => long.push(prefix.as_os_str()),
+            // Network shares, `\\server\share`, are spelled `\\?\UNC\server\share`.
+            Prefix::UNC(server, share) => {
+                long.push(r"UNC\");
+                long.push(server);
+                long.push(r"\");
+                long.push(share);
+            },
+            // Already prefixed, or a device.
+            _ => return absolute,
+        },
+        _ => return absolute,
     }
+    long.push(components.as_path());
+    PathBuf::from(long)
 }
@@ -0,0 +1,237 @@
+//! `lair build --dry-run`: which packages a build would go through, in which order, and what it
+//! would do with each of them.
+//!
+//! Like `lair verify`, this works from what is on disk alone: nothing is downloaded, checked out,
+//! built or written. Dependencies of packages which aren't downloaded yet are unknown until they
+//! are, so the plan can't include them.
+//!
+//! `lair build --emit plan.json` writes the plan as JSON instead, for build farms and other tools
+//! which would rather run the build themselves (see [`export`]). It resolves the dependency graph
+//! first, downloading whatever that takes, so that every package is in it. Then it lists every
+//! task of the build (see [`crate::scheduler`]), with what each waits for:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "root": "AmazingTool-1f0c3b7e",
+//!   "packages": [{
+//!     "id": "NotJson-3f2a9c01",
+//!     "name": "NotJson",
+//!     "package": "NotJson",
+//!     "kind": "git",
+//!     "source": "https://github.com/Kiiyya/NotJson, branch main",
+//!     "root": "/home/me/AmazingTool/build/deps/NotJson-3f2a9c01",
+//!     "action": "build",
+//!     "reason": "changed since its last build"
+//!   }],
+//!   "tasks": [{
+//!     "id": 0,
+//!     "kind": "fetch",
+//!     "package": "NotJson-3f2a9c01",
+//!     "waits_for": [],
+//!     "cached": true,
+//!     "estimated_ms": 1,
+//!     "critical_path_ms": 5203
+//!   }]
+//! }
+//! ```
+//!
+//! Package `id`s are the same as in `lair metadata`. `action` is `fetch`, `build` or `fresh`, as
+//! with `--dry-run`, and `null` along with `reason` if it's unknown. A task is `cached` if there
+//! is nothing to do for it. `waits_for` are the `id`s of other tasks, `estimated_ms` is how long
+//! the task will probably take (builds as long as last time, see [`crate::timings`]), and
+//! `critical_path_ms` how long the longest chain of tasks waiting for it will, itself included.
+//! Fields are only ever added, anything else bumps `version`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::descriptor::Descriptor;
+use crate::fetch;
+use crate::graph::Graph;
+use crate::layout;
+use crate::lockfile::Lockfile;
+use crate::metadata;
+use crate::scheduler::{Dag, Task, TaskKind};
+use crate::verify::{self, StaleReason};
+
+/// Bumped whenever the document [`export`] writes changes in a way which could break its readers.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// What a build would do with one package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Download it, or check out another commit, and then build it.
+    Fetch(FetchReason),
+    /// Build it from what's already downloaded.
+    Build(BuildReason),
+    /// Reuse the TTC files of its last build.
+    Fresh,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FetchReason {
+    NotDownloaded,
+    /// No lockfile entry yet, so its branch (or registry version) is looked up afresh.
+    Unlocked,
+    /// The checkout is at another commit than the lockfile says.
+    WrongCommit { locked: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildReason {
+    Stale(StaleReason),
+    /// One of its dependencies is fetched first, so its TTC files can't be reused.
+    DependencyFetched,
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub descriptor: Descriptor,
+    pub action: Action,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Fetch(FetchReason::NotDownloaded) => write!(f, "not downloaded yet"),
+            Action::Fetch(FetchReason::Unlocked) => write!(f, "not in the lockfile yet"),
+            Action::Fetch(FetchReason::WrongCommit { locked }) => write!(f, "locked to {}, but another commit is checked out", locked),
+            Action::Build(BuildReason::Stale(StaleReason::NeverBuilt)) => write!(f, "never built with this idris2"),
+            Action::Build(BuildReason::Stale(StaleReason::SourcesChanged)) => write!(f, "changed since its last build"),
+            Action::Build(BuildReason::Stale(StaleReason::DependenciesChanged)) => write!(f, "dependencies changed since its last build"),
+            Action::Build(BuildReason::Stale(StaleReason::TtcMissing)) => write!(f, "TTC files are missing"),
+            Action::Build(BuildReason::DependencyFetched) => write!(f, "a dependency is fetched first"),
+            Action::Fresh => write!(f, "up to date"),
+        }
+    }
+}
+
+/// Every package of `packages` reachable from `root`, dependencies before their dependents, each
+/// with what a build would do with it. `lockfile`, `extra` and `toolchain` are as for
+/// [`verify::check`]. Blocking.
+pub fn plan(packages: &[verify::Package], root: &Descriptor, lockfile: &Lockfile, extra: &str, toolchain: &str) -> Vec<Step> {
+    let by_descriptor: BTreeMap<&Descriptor, &verify::Package> = packages.iter().map(|package| (&package.descriptor, package)).collect();
+    let mut order = Vec::new();
+    topological(root, &by_descriptor, &mut BTreeSet::new(), &mut order);
+
+    let mut fingerprints = BTreeMap::new();
+    let mut fetched = BTreeSet::new();
+    let mut steps = Vec::new();
+    for package in order {
+        let desc = &package.descriptor;
+        let action = if let Some(reason) = fetch_reason(package, lockfile) {
+            fetched.insert(desc);
+            Action::Fetch(reason)
+        } else if package.dependencies.iter().flatten().any(|dep| fetched.contains(dep)) {
+            Action::Build(BuildReason::DependencyFetched)
+        } else {
+            match verify::fingerprint(desc, &by_descriptor, extra, &mut fingerprints) {
+                Some(Ok(fingerprint)) => match verify::stale_reason(package, &fingerprint, toolchain) {
+                    Some(reason) => Action::Build(BuildReason::Stale(reason)),
+                    None => Action::Fresh,
+                },
+                // Can't tell, so assume the worst.
+                Some(Err(_)) | None => Action::Build(BuildReason::Stale(StaleReason::SourcesChanged)),
+            }
+        };
+        steps.push(Step { descriptor: desc.clone(), action });
+    }
+    steps
+}
+
+/// The document for `lair build --emit`, see the module docs: the packages of `graph` with their
+/// `steps`, and the tasks of `dag` building them, each expected to take `estimate`.
+pub fn export(graph: &Graph, dag: &Dag, steps: &[Step], estimate: impl Fn(&Task) -> Duration) -> Value {
+    let step = |desc: &Descriptor| steps.iter().find(|step| &step.descriptor == desc);
+    let packages: Vec<Value> = graph.packages.iter()
+        .map(|package| {
+            let desc = &package.descriptor;
+            let action = step(desc).map(|step| &step.action);
+            let root = layout::package_dir(desc).unwrap_or_default();
+            json!({
+                "id": metadata::id(desc),
+                "name": desc.name(),
+                "package": desc.package_name(),
+                "kind": metadata::kind(desc),
+                "source": desc.source(),
+                "root": metadata::absolute(&root),
+                "action": action.map(|action| match action {
+                    Action::Fetch(_) => "fetch",
+                    Action::Build(_) => "build",
+                    Action::Fresh => "fresh",
+                }),
+                "reason": action.map(ToString::to_string),
+            })
+        })
+        .collect();
+
+    let tasks: Vec<Value> = dag.tasks.iter().enumerate()
+        .map(|(i, task)| {
+            let action = step(&task.descriptor).map(|step| &step.action);
+            let cached = match task.kind {
+                TaskKind::Fetch | TaskKind::Manifest => action.is_some_and(|action| !matches!(action, Action::Fetch(_))),
+                TaskKind::Build => action == Some(&Action::Fresh),
+            };
+            json!({
+                "id": i,
+                "kind": match task.kind {
+                    TaskKind::Fetch => "fetch",
+                    TaskKind::Manifest => "manifest",
+                    TaskKind::Build => "build",
+                },
+                "package": metadata::id(&task.descriptor),
+                "waits_for": dag.waits_for(i),
+                "cached": cached,
+                "estimated_ms": estimate(task).as_millis() as u64,
+                "critical_path_ms": dag.priority(i).as_millis() as u64,
+            })
+        })
+        .collect();
+
+    json!({
+        "version": FORMAT_VERSION,
+        "root": metadata::id(&graph.root),
+        "packages": packages,
+        "tasks": tasks,
+    })
+}
+
+/// Depth-first, dependencies first and in order, so the plan is the same every time.
+fn topological<'a>(
+    desc: &Descriptor,
+    packages: &BTreeMap<&Descriptor, &'a verify::Package>,
+    visited: &mut BTreeSet<Descriptor>,
+    order: &mut Vec<&'a verify::Package>,
+) {
+    if !visited.insert(desc.clone()) {
+        return;
+    }
+    let Some(package) = packages.get(desc) else { return };
+    for dep in package.dependencies.iter().flatten() {
+        topological(dep, packages, visited, order);
+    }
+    order.push(package);
+}
+
+fn fetch_reason(package: &verify::Package, lockfile: &Lockfile) -> Option<FetchReason> {
+    let desc = &package.descriptor;
+    if package.dependencies.is_none() {
+        return Some(FetchReason::NotDownloaded);
+    }
+    match desc {
+        Descriptor::Git { .. } | Descriptor::Registry { .. } => {
+            let Some(locked) = lockfile.locked(desc) else {
+                return Some(FetchReason::Unlocked);
+            };
+            match fetch::head_commit(&layout::source_dir(desc)) {
+                Ok(head) if head == locked.commit => None,
+                _ => Some(FetchReason::WrongCommit { locked: locked.commit.clone() }),
+            }
+        },
+        Descriptor::Archive { .. } | Descriptor::Local { .. } | Descriptor::Root { .. } => None,
+    }
+}
@@ -0,0 +1,241 @@
+//! `--profile-lair trace.json`: where Lair itself spends its time, as opposed to idris2, for
+//! finding out why a big graph is slow.
+//!
+//! Recorded are the phases of a run (resolving the graph, checking it, scheduling, ...), how long
+//! anything had to wait for the locks of the node database (see [`crate::db`]), how many tasks
+//! the scheduler had ready and running, and when each task ran. Written in the chrome tracing
+//! format, which `chrome://tracing`, <https://ui.perfetto.dev> and <https://www.speedscope.app>
+//! show as a timeline.
+//!
+//! Phases are on a lane of their own, lock waits on the lane of the thread which waited, and tasks
+//! as async spans, since several run at once.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::scheduler::Task;
+
+/// Lane of the phases. Threads get the ones after it.
+const PHASE_LANE: u64 = 0;
+
+/// Waits shorter than this aren't recorded one by one, only counted, so that a graph of thousands
+/// of nodes doesn't make for a trace of millions of events.
+const MIN_WAIT: Duration = Duration::from_micros(20);
+
+static NEXT_LANE: AtomicU64 = AtomicU64::new(PHASE_LANE + 1);
+
+thread_local! {
+    static LANE: u64 = NEXT_LANE.fetch_add(1, Ordering::Relaxed);
+}
+
+/// One event of the chrome tracing format. Times are in microseconds since the profiler started.
+#[derive(Clone, Debug, Serialize)]
+struct Event {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: u32,
+    tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    args: serde_json::Value,
+}
+
+/// Collects events until [`Profiler::save`]. Shared by every [`crate::Lair`] of a command, see
+/// [`crate::Lair::with_profiler`].
+#[derive(Debug)]
+pub struct Profiler {
+    started: Instant,
+    events: Mutex<Vec<Event>>,
+    /// Tasks get an id each, tying together where they start and end.
+    next_task: AtomicU64,
+    /// Of the locks taken through [`Profiler::wait`].
+    locks: AtomicUsize,
+    contended: AtomicUsize,
+    /// In microseconds.
+    waited: AtomicU64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            events: Mutex::new(Vec::new()),
+            next_task: AtomicU64::new(0),
+            locks: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            waited: AtomicU64::new(0),
+        }
+    }
+
+    fn micros(&self, at: Instant) -> f64 {
+        at.duration_since(self.started).as_secs_f64() * 1e6
+    }
+
+    fn push(&self, event: Event) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// Record the phase `name` until the returned span is dropped.
+    pub fn phase(&self, name: impl Into<String>) -> Span<'_> {
+        Span { profiler: self, name: name.into(), started: Instant::now() }
+    }
+
+    /// Take a lock of any kind: `try_now` without waiting, if that fails `wait`, recording how long
+    /// that took. `name` says which lock it is.
+    pub fn wait<G>(&self, name: &'static str, try_now: impl FnOnce() -> Option<G>, wait: impl FnOnce() -> G) -> G {
+        self.locks.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = try_now() {
+            return guard;
+        }
+        let started = Instant::now();
+        let guard = wait();
+        let waited = started.elapsed();
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.waited.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+        if waited >= MIN_WAIT {
+            self.push(Event {
+                name: format!("wait for {}", name),
+                cat: "lock",
+                ph: "X",
+                ts: self.micros(started),
+                dur: Some(waited.as_secs_f64() * 1e6),
+                pid: std::process::id(),
+                tid: LANE.with(|lane| *lane),
+                id: None,
+                args: serde_json::Value::Null,
+            });
+        }
+        guard
+    }
+
+    /// How many tasks the scheduler has `ready` to start, but held back by its limits or by
+    /// tasks before them, and how many are `running`.
+    pub fn queue(&self, ready: usize, running: usize) {
+        self.push(Event {
+            name: "scheduler".to_owned(),
+            cat: "scheduler",
+            ph: "C",
+            ts: self.micros(Instant::now()),
+            dur: None,
+            pid: std::process::id(),
+            tid: PHASE_LANE,
+            id: None,
+            args: json!({ "ready": ready, "running": running }),
+        });
+    }
+
+    /// How far resolving the graph got: the `layer`-th layer, of `size` packages, after which
+    /// `known` packages are known.
+    pub fn resolved_layer(&self, layer: usize, size: usize, known: usize) {
+        self.push(Event {
+            name: "resolve".to_owned(),
+            cat: "graph",
+            ph: "C",
+            ts: self.micros(Instant::now()),
+            dur: None,
+            pid: std::process::id(),
+            tid: PHASE_LANE,
+            id: None,
+            args: json!({ "layer": layer, "layer size": size, "packages": known }),
+        });
+    }
+
+    /// The scheduler started `task`. Pass the returned id to [`Profiler::task_finished`].
+    pub fn task_started(&self, task: &Task) -> u64 {
+        let id = self.next_task.fetch_add(1, Ordering::Relaxed);
+        self.push(Event {
+            name: task.to_string(),
+            cat: "task",
+            ph: "b",
+            ts: self.micros(Instant::now()),
+            dur: None,
+            pid: std::process::id(),
+            tid: PHASE_LANE,
+            id: Some(id),
+            args: serde_json::Value::Null,
+        });
+        id
+    }
+
+    pub fn task_finished(&self, task: &Task, id: u64, success: bool) {
+        self.push(Event {
+            name: task.to_string(),
+            cat: "task",
+            ph: "e",
+            ts: self.micros(Instant::now()),
+            dur: None,
+            pid: std::process::id(),
+            tid: PHASE_LANE,
+            id: Some(id),
+            args: json!({ "success": success }),
+        });
+    }
+
+    /// Write everything recorded so far to `path`.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let lane = |tid: u64, name: &str| json!({
+            "name": "thread_name", "ph": "M", "pid": std::process::id(), "tid": tid, "args": { "name": name },
+        });
+        let events = self.events.lock().unwrap();
+        let mut lanes: Vec<u64> = events.iter().map(|event| event.tid).filter(|&tid| tid != PHASE_LANE).collect();
+        lanes.sort();
+        lanes.dedup();
+        let mut trace_events = vec![lane(PHASE_LANE, "phases")];
+        trace_events.extend(lanes.into_iter().map(|tid| lane(tid, &format!("thread {}", tid))));
+        trace_events.extend(events.iter().map(|event| serde_json::to_value(event).expect("Events are always serializable.")));
+
+        let document = json!({
+            "traceEvents": trace_events,
+            "displayTimeUnit": "ms",
+            "otherData": {
+                "lair": crate::updates::VERSION,
+                "locks": self.locks.load(Ordering::Relaxed),
+                "locks contended": self.contended.load(Ordering::Relaxed),
+                "lock wait (us)": self.waited.load(Ordering::Relaxed),
+            },
+        });
+        std::fs::write(path, serde_json::to_string(&document)? + "\n")
+            .with_context(|| format!("Failed to write `{}`", path.display()))
+    }
+}
+
+/// A phase being recorded, see [`Profiler::phase`].
+#[derive(Debug)]
+pub struct Span<'a> {
+    profiler: &'a Profiler,
+    name: String,
+    started: Instant,
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        self.profiler.push(Event {
+            name: std::mem::take(&mut self.name),
+            cat: "phase",
+            ph: "X",
+            ts: self.profiler.micros(self.started),
+            dur: Some(self.started.elapsed().as_secs_f64() * 1e6),
+            pid: std::process::id(),
+            tid: PHASE_LANE,
+            id: None,
+            args: serde_json::Value::Null,
+        });
+    }
+}
@@ -0,0 +1,132 @@
+//! Asking the user, for when Lair is about to do something destructive, or can't decide on its
+//! own: overwriting local changes in a `build/deps` checkout, trusting an SSH host it never saw
+//! before, or picking between registry packages with similar names.
+//!
+//! Questions go to stderr and answers come from stdin, one question at a time even if several
+//! packages are being fetched in parallel. Without a terminal to ask, every question is answered
+//! with no, unless `--yes` says otherwise. Except for unknown SSH hosts: then ssh itself decides,
+//! going by its config.
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::SourceFetchError;
+use crate::fetch;
+
+/// How questions get answered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Prompt {
+    /// Ask on the terminal.
+    Ask,
+    /// Answer yes without asking.
+    Yes,
+    /// Answer no without asking. What libraries want, so that nothing ever blocks on stdin.
+    #[default]
+    No,
+}
+
+/// Held while a question is on screen.
+static ASKING: Mutex<()> = Mutex::new(());
+
+impl Prompt {
+    /// [`Prompt::Yes`] if `yes`, otherwise [`Prompt::Ask`] if stdin and stderr are terminals, and
+    /// [`Prompt::No`] if they aren't.
+    pub fn from_flags(yes: bool) -> Self {
+        if yes {
+            Prompt::Yes
+        } else if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+            Prompt::Ask
+        } else {
+            Prompt::No
+        }
+    }
+
+    /// Ask a yes/no question, for example "Overwrite them?". Anything but `y` or `yes` is a no.
+    /// Blocking.
+    pub fn confirm(self, question: &str) -> bool {
+        match self {
+            Prompt::Yes => true,
+            Prompt::No => false,
+            Prompt::Ask => {
+                let _asking = ASKING.lock().unwrap_or_else(|e| e.into_inner());
+                let answer = ask(&format!("{} [y/N] ", question));
+                matches!(answer.to_lowercase().as_str(), "y" | "yes")
+            },
+        }
+    }
+
+    /// Let the user pick one of `options`, returning its index. `None` if they'd rather not.
+    /// Saying yes to everything only picks an option if it is the only one. Blocking.
+    pub fn choose(self, question: &str, options: &[String]) -> Option<usize> {
+        match self {
+            Prompt::Yes => (options.len() == 1).then_some(0),
+            Prompt::No => None,
+            Prompt::Ask => {
+                let _asking = ASKING.lock().unwrap_or_else(|e| e.into_inner());
+                let mut text = format!("{}\n", question);
+                for (i, option) in options.iter().enumerate() {
+                    text.push_str(&format!("  {}) {}\n", i + 1, option));
+                }
+                text.push_str(&format!("Pick one [1-{}], or press enter for none: ", options.len()));
+                let answer = ask(&text);
+                answer.parse::<usize>().ok()
+                    .filter(|&n| (1..=options.len()).contains(&n))
+                    .map(|n| n - 1)
+            },
+        }
+    }
+}
+
+/// Checking out `commit` in `path` throws away changes to the files git tracks there. If there
+/// are any, ask first. `commit` is `None` if we don't know yet which one it'll be. Blocking.
+pub fn allow_overwrite(prompt: Prompt, name: &str, path: &Path, commit: Option<&str>) -> Result<(), SourceFetchError> {
+    let unchanged = commit.is_some_and(|commit| fetch::head_commit(path).is_ok_and(|head| head == commit));
+    if unchanged || !fetch::is_dirty(path)? {
+        return Ok(());
+    }
+    let question = format!("`{}` has local changes in `{}`, which checking out {} would overwrite. Overwrite them?",
+        name, path.display(), commit.unwrap_or("another commit"));
+    if prompt.confirm(&question) {
+        Ok(())
+    } else {
+        Err(SourceFetchError::LocalChanges { name: name.to_owned(), path: path.to_owned() })
+    }
+}
+
+/// Before cloning `url` over SSH from a host we never connected to, show the host's key
+/// fingerprints and ask whether to trust it. Blocking.
+pub fn trust_ssh_host(prompt: Prompt, url: &str) -> Result<(), SourceFetchError> {
+    // One host at a time, so that several packages from the same host don't all ask.
+    static CHECKING: Mutex<()> = Mutex::new(());
+
+    let Some((host, port)) = fetch::ssh_host(url) else {
+        return Ok(());
+    };
+    let _checking = CHECKING.lock().unwrap_or_else(|e| e.into_inner());
+    if fetch::is_known_host(&host, port) {
+        return Ok(());
+    }
+    // Nobody to ask, so it's up to ssh and its config, as it was before Lair asked.
+    if prompt == Prompt::No {
+        return Ok(());
+    }
+    let (keys, fingerprints) = fetch::scan_host_keys(&host, port)?;
+    let question = format!("`{}` is not a known SSH host. Its keys are:\n{}\nTrust it, and add its keys to ~/.ssh/known_hosts?",
+        host, fingerprints.iter().map(|fingerprint| format!("  {}", fingerprint)).collect::<Vec<_>>().join("\n"));
+    if prompt.confirm(&question) {
+        fetch::trust_host_keys(&keys)
+    } else {
+        Err(SourceFetchError::UnknownHost { host, port })
+    }
+}
+
+/// Print `text`, and read one line. Empty if stdin is closed.
+fn ask(text: &str) -> String {
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(stderr, "{}", text);
+    let _ = stderr.flush();
+    let mut answer = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut answer);
+    answer.trim().to_owned()
+}
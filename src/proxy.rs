@@ -0,0 +1,94 @@
+//! HTTP(S) proxies, for networks which don't let anything out otherwise.
+//!
+//! Like curl and git, we follow `https_proxy` for `https` urls, `http_proxy` for `http` urls, and
+//! `all_proxy` for both, in lower or upper case. The `[network] proxy` config key (see
+//! [`crate::config`]) takes precedence over all of them. Either way, hosts listed in `no_proxy`
+//! are reached directly: it's a comma-separated list of host names, each also covering its
+//! subdomains, or `*` for all hosts. SSH and local urls never go through a proxy.
+//!
+//! This covers git (through libgit2, and through the `git` command line tool for sparse
+//! checkouts), downloading archives, and the remote cache.
+
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::error::SourceFetchError;
+
+/// The proxy from the config file, see [`configure`].
+static CONFIGURED: OnceLock<String> = OnceLock::new();
+
+/// Use `proxy` instead of what the environment variables say, from now on. Only the first call
+/// counts.
+pub fn configure(proxy: &str) {
+    let _ = CONFIGURED.set(proxy.to_owned());
+}
+
+/// The proxy to reach `url` through, if any.
+pub fn for_url(url: &str) -> Option<String> {
+    let (scheme, host) = scheme_and_host(url)?;
+    let var = |name: &str| std::env::var(name).ok()
+        .or_else(|| std::env::var(name.to_uppercase()).ok())
+        .filter(|value| !value.is_empty());
+    if var("no_proxy").is_some_and(|no_proxy| bypasses(&no_proxy, host)) {
+        return None;
+    }
+    match CONFIGURED.get() {
+        Some(proxy) => Some(proxy.clone()),
+        None => var(&format!("{}_proxy", scheme)).or_else(|| var("all_proxy")),
+    }
+}
+
+/// libgit2's proxy settings for `url`.
+pub fn git_options<'a>(url: &str) -> git2::ProxyOptions<'a> {
+    let mut options = git2::ProxyOptions::new();
+    if let Some(proxy) = for_url(url) {
+        options.url(&proxy);
+    }
+    options
+}
+
+/// The `git` command line tool, told about the configured proxy. It reads the environment
+/// variables itself.
+pub fn git_command() -> Command {
+    let mut command = Command::new("git");
+    if let Some(proxy) = CONFIGURED.get() {
+        command.arg("-c").arg(format!("http.proxy={}", proxy));
+    }
+    command
+}
+
+/// An HTTP client reaching `url` through its proxy, if any.
+pub fn agent(url: &str) -> Result<ureq::Agent, SourceFetchError> {
+    // Not `try_proxy_from_env`, which would ignore `no_proxy`.
+    let agent = ureq::AgentBuilder::new().try_proxy_from_env(false);
+    Ok(match for_url(url) {
+        Some(proxy) => agent.proxy(ureq::Proxy::new(proxy)?),
+        None => agent,
+    }.build())
+}
+
+/// `("https", "github.com")` for `https://user@github.com:443/Kiiyya/Lair`. `None` for anything but
+/// `http` and `https`.
+fn scheme_and_host(url: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = url.split_once("://")?;
+    if scheme != "http" && scheme != "https" {
+        return None;
+    }
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = match host.strip_prefix('[') {
+        // IPv6, `[::1]:8080`.
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    Some((scheme, host))
+}
+
+/// Does the `no_proxy` list cover `host`?
+fn bypasses(no_proxy: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy.split(',')
+        .map(|entry| entry.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
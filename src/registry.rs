@@ -0,0 +1,360 @@
+//! Package registries, for finding packages by name or keyword.
+//!
+//! A registry is just an *index*: a git repository with one file per package, saying what the
+//! package is and where each of its versions comes from. For example `packages/NotJson.toml`:
+//!
+//! ```toml
+//! name = "NotJson"
+//! description = "Parse and print JSON."
+//! repository = "https://github.com/Kiiyya/NotJson"
+//! keywords = ["json", "parser"]
+//!
+//! [[version]]
+//! version = "0.2.0"
+//! git = "https://github.com/Kiiyya/NotJson"
+//! rev = "3f2a9c01d5e8..."
+//! ```
+//!
+//! A version can be marked `yanked = true`, for example when it turned out to be broken. Yanked
+//! versions are never picked for new dependencies, but packages whose lockfile already pins one
+//! keep using it, with a warning.
+//!
+//! We keep a clone of the index in the user's cache directory (see [`layout::user_cache_dir`]),
+//! and follow its `main` branch.
+//!
+//! An index can also name an API next to it, for managing who owns which package, in
+//! `config.toml` at its top. See [`crate::registry_api`].
+
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::descriptor::{Descriptor, GitVersion};
+use crate::error::SourceFetchError;
+use crate::fetch;
+use crate::layout;
+use crate::package_name::PackageName;
+use crate::semver::{Version, VersionRequirement};
+
+/// One package in the index. Unknown keys are ignored, so that old Lairs can read newer indexes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistryPackage {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default, rename = "version")]
+    pub versions: Vec<RegistryVersion>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistryVersion {
+    /// For example `0.2.0`.
+    pub version: String,
+    pub git: String,
+    /// Full commit hash.
+    pub rev: String,
+    /// Withdrawn, so don't pick it for anything new.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub yanked: bool,
+}
+
+impl RegistryPackage {
+    /// The highest version which isn't yanked, see [`compare_versions`].
+    pub fn latest(&self) -> Option<&RegistryVersion> {
+        self.resolve("*")
+    }
+
+    /// The highest version matching `requirement` which isn't yanked, see [`matches`].
+    pub fn resolve(&self, requirement: &str) -> Option<&RegistryVersion> {
+        self.versions.iter()
+            .filter(|version| !version.yanked && matches(requirement, &version.version))
+            .max_by(|a, b| compare_versions(&a.version, &b.version))
+    }
+
+    /// Versions matching `requirement` which are yanked, for telling people why none would do.
+    pub fn yanked(&self, requirement: &str) -> Vec<&str> {
+        self.versions.iter()
+            .filter(|version| version.yanked && matches(requirement, &version.version))
+            .map(|version| version.version.as_str())
+            .collect()
+    }
+
+    /// Exactly `version`, yanked or not.
+    pub fn version(&self, version: &str) -> Option<&RegistryVersion> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    /// Where to find out more about the package: its `repository`, or else where its latest
+    /// version comes from.
+    pub fn url(&self) -> Option<&str> {
+        self.repository.as_deref().or_else(|| Some(self.latest()?.git.as_str()))
+    }
+}
+
+/// A local clone of a registry's index.
+#[derive(Clone, Debug)]
+pub struct Index {
+    url: String,
+    path: PathBuf,
+}
+
+impl Index {
+    /// Clone the index at `url`, or bring our clone of it up to date. Blocking.
+    pub fn update(url: &str) -> Result<Self, SourceFetchError> {
+        let path = layout::registry_dir(url);
+        if path.exists() {
+            fetch::fetch_origin(&path)?;
+        } else {
+            fetch::clone_git(url, &path, None, &|_, _, _| {})?;
+        }
+        fetch::checkout_version(&path, url, &GitVersion::DefaultBranch)?;
+        Ok(Self { url: url.to_owned(), path })
+    }
+
+    /// Our clone of the index at `url` as it is, without updating it. `None` if we never cloned
+    /// it.
+    pub fn open(url: &str) -> Option<Self> {
+        let path = layout::registry_dir(url);
+        path.is_dir().then(|| Self { url: url.to_owned(), path })
+    }
+
+    /// Url of the index.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Where the registry's API is, from `api` in the index's `config.toml`. `None` if it
+    /// doesn't have one.
+    pub fn api(&self) -> Result<Option<String>, anyhow::Error> {
+        #[derive(Deserialize)]
+        struct Config {
+            api: Option<String>,
+        }
+        let path = self.path.join("config.toml");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let config: Config = toml::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("Invalid registry config `{}`: {}", path.display(), e))?;
+        Ok(config.api)
+    }
+
+    /// The package called `name`, if the index has it.
+    pub fn package(&self, name: &str) -> Result<Option<RegistryPackage>, anyhow::Error> {
+        // Not a name any package could have, and maybe not a path inside the index either.
+        if name.parse::<PackageName>().is_err() {
+            return Ok(None);
+        }
+        let path = self.path.join("packages").join(format!("{}.toml", name));
+        if path.is_file() {
+            Ok(Some(read_package(&path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Every package in the index, sorted by name.
+    pub fn packages(&self) -> Result<Vec<RegistryPackage>, anyhow::Error> {
+        let dir = self.path.join("packages");
+        let mut packages = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                packages.push(read_package(&path)?);
+            }
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
+    }
+
+    /// The highest version of package `name` which matches `requirement` and isn't yanked.
+    pub fn pick(&self, name: &str, requirement: &str) -> Result<RegistryVersion, SourceFetchError> {
+        let package = self.package(name)
+            .map_err(|error| SourceFetchError::Registry { url: self.url.clone(), error: Arc::new(error) })?
+            .ok_or_else(|| SourceFetchError::NotInRegistry {
+                name: name.to_owned(),
+                registry: self.url.clone(),
+                similar: self.similar(name).unwrap_or_default(),
+            })?;
+        match package.resolve(requirement) {
+            Some(version) => Ok(version.clone()),
+            None => Err(SourceFetchError::NoMatchingVersion {
+                name: name.to_owned(),
+                registry: self.url.clone(),
+                requirement: requirement.to_owned(),
+                yanked: package.yanked(requirement).into_iter().map(str::to_owned).collect(),
+            }),
+        }
+    }
+
+    /// Names of packages which are spelled like `name` apart from case, `-` and `_`, for
+    /// example `NotJson` and `not_json`. Sorted.
+    pub fn similar(&self, name: &str) -> Result<Vec<String>, anyhow::Error> {
+        let normalize = |name: &str| name.chars().filter(|c| !matches!(c, '-' | '_')).collect::<String>().to_lowercase();
+        let normalized = normalize(name);
+        Ok(self.packages()?.into_iter()
+            .map(|package| package.name)
+            .filter(|other| other != name && normalize(other) == normalized)
+            .collect())
+    }
+
+    /// Has `version` of package `name` been yanked? `false` if the index doesn't know it at all.
+    pub fn is_yanked(&self, name: &str, version: &str) -> bool {
+        self.package(name).ok().flatten()
+            .and_then(|package| Some(package.version(version)?.yanked))
+            .unwrap_or(false)
+    }
+
+    /// Packages whose name or one of whose keywords contains `query`, ignoring case. Exact
+    /// name matches first, then other name matches, then keyword matches.
+    pub fn search(&self, query: &str) -> Result<Vec<RegistryPackage>, anyhow::Error> {
+        let query = query.to_lowercase();
+        let mut found: Vec<(u8, RegistryPackage)> = self.packages()?.into_iter()
+            .filter_map(|package| {
+                let name = package.name.to_lowercase();
+                let rank = if name == query {
+                    0
+                } else if name.contains(&query) {
+                    1
+                } else if package.keywords.iter().any(|keyword| keyword.to_lowercase().contains(&query)) {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, package))
+            })
+            .collect();
+        // Stable, so each group stays sorted by name.
+        found.sort_by_key(|(rank, _)| *rank);
+        Ok(found.into_iter().map(|(_, package)| package).collect())
+    }
+}
+
+/// If `descriptors` all ask for the same package from the same registry, the versions which
+/// would do for each of them and aren't yanked, lowest first. `None` if they don't, or the
+/// registry's index or the package isn't there. Doesn't update the index. Blocking.
+pub fn satisfying_all(descriptors: &[Descriptor]) -> Option<Vec<String>> {
+    let mut requirements = Vec::new();
+    let mut from = None;
+    for desc in descriptors {
+        let Descriptor::Registry { registry, version, .. } = desc else { return None };
+        let here = (registry.as_str(), desc.package_name());
+        if from.is_some_and(|from| from != here) {
+            return None;
+        }
+        from = Some(here);
+        requirements.push(version.as_str());
+    }
+    let (_, name) = from?;
+    let Descriptor::Registry { registry, .. } = descriptors.first()?.expanded().ok()? else { return None };
+    let package = Index::open(&registry)?.package(name).ok()??;
+    let mut versions: Vec<String> = package.versions.into_iter()
+        .filter(|version| !version.yanked && requirements.iter().all(|requirement| matches(requirement, &version.version)))
+        .map(|version| version.version)
+        .collect();
+    versions.sort_by(|a, b| compare_versions(a, b));
+    Some(versions)
+}
+
+fn read_package(path: &Path) -> Result<RegistryPackage, anyhow::Error> {
+    let s = std::fs::read_to_string(path)?;
+    toml::from_str(&s).map_err(|e| anyhow::anyhow!("Invalid registry entry `{}`: {}", path.display(), e))
+}
+
+/// Does `version` satisfy `requirement`, see [`crate::semver`]? `0.2` allows `0.2.0` and `0.2.7`,
+/// but neither `0.20.0` nor `0.2.8-beta.1`. Missing minor and patch numbers count as zero, so
+/// `0.2` is `0.2.0`; versions which aren't semver even then only satisfy `*`.
+pub fn matches(requirement: &str, version: &str) -> bool {
+    match (requirement.parse::<VersionRequirement>(), Version::parse_lenient(version)) {
+        (Ok(requirement), Ok(version)) => requirement.matches(&version),
+        _ => requirement.trim() == "*",
+    }
+}
+
+/// Compare versions like `0.10.1` and `0.9-beta.1` by semver precedence, missing numbers counting
+/// as zero. Versions which aren't semver even then, like `0.9.1.2`, are compared number by
+/// number, with parts which aren't numbers compared as text.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    if let (Ok(a), Ok(b)) = (Version::parse_lenient(a), Version::parse_lenient(b)) {
+        return a.cmp(&b);
+    }
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => x.cmp(y),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+        }
+    }
+}
+
+/// Can `new` replace `old` without breaking anything, going by semver? Everything up to the
+/// first number which isn't zero has to stay the same: `0.2.0` to `0.2.5` is fine, but `0.2.0` to
+/// `0.3.0` isn't, and neither is `1.4.0` to `2.0.0`.
+pub fn is_compatible(old: &str, new: &str) -> bool {
+    if let (Ok(old), Ok(new)) = (Version::parse_lenient(old), Version::parse_lenient(new)) {
+        return old.is_compatible(&new);
+    }
+    for (old, new) in old.split('.').zip(new.split('.')) {
+        if old != new {
+            return false;
+        }
+        if old.parse::<u64>().is_ok_and(|number| number != 0) {
+            return true;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_like_semver() {
+        assert!(matches("0.2", "0.2"));
+        assert!(matches("0.2", "0.2.7"));
+        assert!(!matches("0.2", "0.20.0"));
+        assert!(!matches("0.2", "0.2.8-beta.1"));
+        assert!(matches("^1.0", "1.4"));
+        assert!(matches("*", "0.9.1.2"));
+        assert!(!matches("0.9", "0.9.1.2"));
+        assert!(!matches("not a requirement", "1.0.0"));
+    }
+
+    #[test]
+    fn compares_versions() {
+        assert_eq!(compare_versions("0.10.1", "0.9.0"), Ordering::Greater);
+        assert_eq!(compare_versions("0.2", "0.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("0.9-beta.1", "0.9"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.0+a", "1.0.0+b"), Ordering::Equal);
+        assert_eq!(compare_versions("0.9.1.2", "0.9.1.10"), Ordering::Less);
+        assert_eq!(compare_versions("0.9.1", "0.9.1.2"), Ordering::Less);
+    }
+
+    #[test]
+    fn compatible_versions() {
+        assert!(is_compatible("0.2.0", "0.2.5"));
+        assert!(is_compatible("0.2", "0.2.5"));
+        assert!(!is_compatible("0.2.0", "0.3.0"));
+        assert!(is_compatible("1.4.0", "1.9.2"));
+        assert!(!is_compatible("1.4.0", "2.0.0"));
+        assert!(!is_compatible("0.0.1", "0.0.2"));
+    }
+}
@@ -0,0 +1,131 @@
+//! Talking to a registry's API, for what reading its index can't do: seeing and changing who owns
+//! a package, and reserving a name before anything is published under it.
+//!
+//! An index offering an API says where, in `config.toml` at its top (see [`Index::api`]):
+//! ```toml
+//! api = "https://registry.example.com"
+//! ```
+//!
+//! Requests and answers are JSON, and carry the token from `lair login` (see
+//! [`crate::credentials`]) as a bearer token:
+//!
+//! | Request                                      | Body                      | Answer                     |
+//! |----------------------------------------------|---------------------------|----------------------------|
+//! | `GET {api}/api/v1/packages/{name}/owners`    |                           | `{"owners": ["kiiyya"]}`   |
+//! | `PUT {api}/api/v1/packages/{name}/owners`    | `{"users": ["someone"]}`  | `{"ok": true}`             |
+//! | `DELETE {api}/api/v1/packages/{name}/owners` | `{"users": ["someone"]}`  | `{"ok": true}`             |
+//! | `PUT {api}/api/v1/packages/{name}/reserve`   |                           | `{"ok": true}`             |
+//!
+//! Failures answer with an error status, and say why as `{"errors": [{"detail": "..."}]}`, like
+//! cargo's registries.
+
+use std::io::Read;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::proxy;
+use crate::registry::Index;
+
+/// A registry's API, as a particular user.
+#[derive(Clone)]
+pub struct Client {
+    api: String,
+    token: String,
+}
+
+impl std::fmt::Debug for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client").field("api", &self.api).finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct Owners {
+    owners: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Errors {
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    detail: String,
+}
+
+impl Client {
+    /// The API of the registry `index`, with `token`. Fails if the index doesn't offer one.
+    pub fn new(index: &Index, token: String) -> Result<Self, anyhow::Error> {
+        let api = index.api()?.with_context(|| format!("Registry `{}` has no API, its index doesn't name one in `config.toml`", index.url()))?;
+        anyhow::ensure!(is_safe(&api), "Registry `{}` names `{}` as its API, but the token is only sent over `https://`, or to this computer", index.url(), api);
+        Ok(Self { api: api.trim_end_matches('/').to_owned(), token })
+    }
+
+    /// Who owns `package`. Blocking.
+    pub fn owners(&self, package: &str) -> Result<Vec<String>, anyhow::Error> {
+        let answer = self.request("GET", package, "owners", None)?;
+        let owners: Owners = serde_json::from_str(&answer)
+            .with_context(|| format!("`{}` answered something other than a list of owners", self.api))?;
+        Ok(owners.owners)
+    }
+
+    /// Let `users` publish `package`, too. Blocking.
+    pub fn add_owners(&self, package: &str, users: &[String]) -> Result<(), anyhow::Error> {
+        self.request("PUT", package, "owners", Some(serde_json::json!({ "users": users }))).map(drop)
+    }
+
+    /// Stop `users` from publishing `package`. Blocking.
+    pub fn remove_owners(&self, package: &str, users: &[String]) -> Result<(), anyhow::Error> {
+        self.request("DELETE", package, "owners", Some(serde_json::json!({ "users": users }))).map(drop)
+    }
+
+    /// Claim the name `package` for us, without publishing anything yet. Blocking.
+    pub fn reserve(&self, package: &str) -> Result<(), anyhow::Error> {
+        self.request("PUT", package, "reserve", None).map(drop)
+    }
+
+    /// `{api}/api/v1/packages/{package}/{what}`, returning the body of the answer.
+    fn request(&self, method: &str, package: &str, what: &str, body: Option<serde_json::Value>) -> Result<String, anyhow::Error> {
+        let url = format!("{}/api/v1/packages/{}/{}", self.api, package, what);
+        let request = proxy::agent(&url)?.request(method, &url)
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("Accept", "application/json");
+        let result = match body {
+            Some(body) => request.set("Content-Type", "application/json").send_string(&body.to_string()),
+            None => request.call(),
+        };
+        match result {
+            Ok(response) => Ok(response.into_string()?),
+            Err(ureq::Error::Status(status, response)) => {
+                let mut answer = String::new();
+                // Whatever we got is better than nothing.
+                let _ = response.into_reader().take(64 * 1024).read_to_string(&mut answer);
+                let why = serde_json::from_str::<Errors>(&answer).ok()
+                    .map(|errors| errors.errors.into_iter().map(|error| error.detail).collect::<Vec<_>>().join("; "))
+                    .filter(|why| !why.is_empty());
+                match (status, why) {
+                    (_, Some(why)) => anyhow::bail!("`{}` refused ({}): {}", url, status, why),
+                    (401 | 403, None) => anyhow::bail!("`{}` refused ({}), is the token from `lair login` still valid?", url, status),
+                    (_, None) => anyhow::bail!("`{}` refused ({})", url, status),
+                }
+            },
+            Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to reach `{}`", url))),
+        }
+    }
+}
+
+/// Whether the token can go to `api`: over https, or to this computer.
+fn is_safe(api: &str) -> bool {
+    if api.starts_with("https://") {
+        return true;
+    }
+    let Some(rest) = api.strip_prefix("http://") else { return false };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
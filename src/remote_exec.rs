@@ -0,0 +1,439 @@
+//! `--remote-exec <worker>`, experimental: building dependencies on another machine, say a big
+//! one, instead of on this one. The root package is still built here, where idris2 only rebuilds
+//! what changed.
+//!
+//! The worker is either
+//! - `ssh://[user@]host[:port][/path/to/lair]`: Lair runs `lair worker` there through the `ssh` on
+//!   PATH, so the machine needs lair (at that path, if given) as well as idris2.
+//! - `https://worker.example.com/build`: anything answering a `POST` of a job with its result, for
+//!   example `lair worker` behind a CGI script. `LAIR_REMOTE_EXEC_TOKEN`, if set, is sent as a
+//!   bearer token.
+//!
+//! A job is a `.tar.gz` of
+//! - `job.json`: the [`Job`], what to run.
+//! - `pkg/`: the package, without its `build` directory.
+//! - `deps/0/`, `deps/1/`, ...: the TTC files of its dependencies, for `IDRIS2_PATH`, or
+//!   `packages/NotJson-0.2.0/`, ...: all of those it loads, installed, for `IDRIS2_PACKAGE_PATH`.
+//!
+//! and the result is a `.tar.gz` of `result.json` (the [`Outcome`]), and if idris2 succeeded, of
+//! the package's TTC files in `ttc/`.
+//!
+//! Workers run nothing but `idris2 --check` on the job's main module, with the command line put
+//! together from the [`Job`]'s fields: its paths have to stay inside the job directory, and of
+//! `idris-flags` only those in [`ALLOWED_FLAGS`] are passed, so that a job can't have idris2 run
+//! code or write elsewhere. With `--sandbox`, workers build third-party packages in the same
+//! sandbox (see [`crate::sandbox`]), and can't do the job if they don't have it.
+//!
+//! Workers refuse jobs for any idris2 other than their own, down to the commit it was built from,
+//! since the TTC files couldn't be loaded here otherwise. Then, or if the worker can't be reached,
+//! Lair warns and builds the package here. idris2 failing on the worker fails the build as usual.
+
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+use crate::compiler::Toolchain;
+use crate::files;
+use crate::package_name::PackageName;
+use crate::paths::{self, Idris2Paths};
+use crate::proxy;
+use crate::sandbox::Sandbox;
+
+/// Bumped whenever [`Job`] or [`Outcome`] change in a way the other side wouldn't understand.
+pub const JOB_VERSION: u32 = 3;
+
+/// Where the package goes in a job.
+pub const PACKAGE: &str = "pkg";
+
+/// `idris-flags` a worker passes on: only those changing what idris2 checks, none making it run,
+/// load or write anything.
+pub const ALLOWED_FLAGS: &[&str] = &["--total", "--partial", "--covering", "--no-prelude", "--quiet", "-q", "--verbose", "--timing", "--no-color", "--no-colour"];
+
+/// Like [`ALLOWED_FLAGS`], but followed by a value, which has to be a plain word.
+pub const ALLOWED_FLAGS_WITH_VALUE: &[&str] = &["-X", "--extension", "--log"];
+
+/// What a worker is asked to do. Paths are relative to the directory the job is unpacked in,
+/// which is where idris2 runs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub version: u32,
+    /// The idris2 which has to build it, see [`Toolchain::full`].
+    pub toolchain: String,
+    /// For example `pkg/build/idris2-0.6.0`.
+    pub build_dir: PathBuf,
+    /// For example `pkg/src`.
+    pub source_dir: PathBuf,
+    /// The package's `idris-flags`, see [`ALLOWED_FLAGS`].
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Package names, for `-p`.
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// For example `pkg/src/NotJson.idr`.
+    pub main: PathBuf,
+    /// For example `deps/0`.
+    #[serde(default)]
+    pub idris2_path: Vec<PathBuf>,
+    /// For example `packages`.
+    #[serde(default)]
+    pub package_path: Vec<PathBuf>,
+    /// Where idris2 puts the TTC files, for example `pkg/build/idris2-0.6.0/ttc`.
+    pub ttc: PathBuf,
+    /// Kill idris2 after this long.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// How to confine idris2, as it would be here, see [`crate::sandbox`].
+    #[serde(default)]
+    pub sandbox: Sandbox,
+}
+
+impl Job {
+    /// What a worker passes to idris2.
+    pub fn args(&self) -> Vec<String> {
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let mut args = vec!["--build-dir".to_owned(), path(&self.build_dir), "--source-dir".to_owned(), path(&self.source_dir)];
+        args.extend(self.flags.iter().cloned());
+        args.push("--check".to_owned());
+        for package in &self.packages {
+            args.extend(["-p".to_owned(), package.clone()]);
+        }
+        args.push(path(&self.main));
+        args
+    }
+
+    /// Whether a worker can run it: paths inside the job directory, only allowed flags, and
+    /// package names which are just that.
+    pub fn check(&self) -> Result<(), anyhow::Error> {
+        let paths = [&self.build_dir, &self.source_dir, &self.main, &self.ttc].into_iter()
+            .chain(&self.idris2_path)
+            .chain(&self.package_path);
+        for path in paths {
+            anyhow::ensure!(path.components().all(|component| matches!(component, Component::Normal(_))),
+                "`{}` isn't a path inside the job", path.display());
+        }
+        let mut flags = self.flags.iter();
+        while let Some(flag) = flags.next() {
+            if ALLOWED_FLAGS.contains(&flag.as_str()) {
+                continue;
+            }
+            anyhow::ensure!(ALLOWED_FLAGS_WITH_VALUE.contains(&flag.as_str()), "`{}` isn't allowed in `idris-flags` of remote builds", flag);
+            let value = flags.next().filter(|value| !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':')));
+            anyhow::ensure!(value.is_some(), "`{}` needs a plain word after it in `idris-flags` of remote builds", flag);
+        }
+        for package in &self.packages {
+            package.parse::<PackageName>().map_err(anyhow::Error::msg)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a job went.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Outcome {
+    /// idris2's exit code, `None` if it was killed for taking too long.
+    pub code: Option<i32>,
+    /// Everything idris2 printed, line by line.
+    pub output: Vec<String>,
+}
+
+/// Where jobs are sent, see the module docs.
+#[derive(Clone, Debug)]
+pub enum Worker {
+    Ssh {
+        /// For example `me@builder`.
+        destination: String,
+        port: Option<u16>,
+        /// Of `lair` on the worker.
+        program: String,
+    },
+    Http {
+        url: String,
+        token: Option<String>,
+    },
+}
+
+impl Worker {
+    /// The worker at `url`, see the module docs. Reads the token from the environment.
+    pub fn new(url: &str) -> Result<Self, anyhow::Error> {
+        if let Some(rest) = url.strip_prefix("ssh://") {
+            let (authority, program) = match rest.find('/') {
+                Some(slash) => (&rest[..slash], &rest[slash..]),
+                None => (rest, ""),
+            };
+            let (destination, port) = match authority.rsplit_once(':') {
+                Some((destination, port)) => (destination, Some(port.parse::<u16>()
+                    .map_err(|_| anyhow::anyhow!("Worker `{}` has an invalid port `{}`", url, port))?)),
+                None => (authority, None),
+            };
+            anyhow::ensure!(!destination.is_empty(), "Worker `{}` doesn't name a host, expected `ssh://[user@]host`", url);
+            Ok(Worker::Ssh {
+                destination: destination.to_owned(),
+                port,
+                program: if program.len() > 1 { program.to_owned() } else { "lair".to_owned() },
+            })
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            let token = std::env::var("LAIR_REMOTE_EXEC_TOKEN").ok().filter(|token| !token.is_empty());
+            Ok(Worker::Http { url: url.to_owned(), token })
+        } else {
+            anyhow::bail!("Don't know how to reach worker `{}`, expected an `ssh://` or `https://` url", url);
+        }
+    }
+
+    /// Send the job archive `job`, returning the result archive. Blocking.
+    pub fn run(&self, job: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+        match self {
+            Worker::Ssh { destination, port, program } => {
+                let mut command = Command::new("ssh");
+                // Never ask for passwords or about unknown hosts, nobody would see the question.
+                command.args(["-o", "BatchMode=yes"]);
+                if let Some(port) = port {
+                    command.arg("-p").arg(port.to_string());
+                }
+                let mut child = command
+                    .arg(destination)
+                    .arg(format!("{} worker", program))
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .context("Failed to run `ssh`")?;
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                // Writing while reading, so that neither side waits for the other.
+                let writer = std::thread::spawn(move || stdin.write_all(&job));
+                let output = child.wait_with_output()?;
+                let written = writer.join().expect("Writing a job doesn't panic.");
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    anyhow::bail!("`ssh {} {} worker` failed ({}): {}", destination, program, output.status, stderr.trim());
+                }
+                written?;
+                Ok(output.stdout)
+            },
+            Worker::Http { url, token } => {
+                let mut request = proxy::agent(url)?.post(url).set("Content-Type", "application/gzip");
+                if let Some(token) = token {
+                    request = request.set("Authorization", &format!("Bearer {}", token));
+                }
+                let response = match request.send_bytes(&job) {
+                    Ok(response) => response,
+                    Err(ureq::Error::Status(status, response)) => {
+                        let mut why = String::new();
+                        // Whatever we got is better than nothing.
+                        let _ = response.into_reader().take(64 * 1024).read_to_string(&mut why);
+                        anyhow::bail!("`{}` refused the job ({}): {}", url, status, why.trim());
+                    },
+                    Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to reach `{}`", url))),
+                };
+                let mut bytes = Vec::new();
+                response.into_reader().read_to_end(&mut bytes)?;
+                Ok(bytes)
+            },
+        }
+    }
+}
+
+/// The url it was made from, more or less.
+impl std::fmt::Display for Worker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Worker::Ssh { destination, port: Some(port), .. } => write!(f, "ssh://{}:{}", destination, port),
+            Worker::Ssh { destination, port: None, .. } => write!(f, "ssh://{}", destination),
+            Worker::Http { url, .. } => write!(f, "{}", url),
+        }
+    }
+}
+
+/// The job archive of `job`, building the package in `package`, with `inputs` (where in the job,
+/// and where here) next to it. Blocking.
+pub fn pack(job: &Job, package: &Path, inputs: &[(PathBuf, PathBuf)]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), flate2::Compression::fast()));
+    append_bytes(&mut builder, "job.json", &serde_json::to_vec_pretty(job)?)?;
+    let root = if package.as_os_str().is_empty() { Path::new(".") } else { package };
+    for entry in std::fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        // Its own build output, and for the root package everything else's.
+        if name == "build" || name == ".git" {
+            continue;
+        }
+        let path = Path::new(PACKAGE).join(&name);
+        if entry.file_type()?.is_dir() {
+            builder.append_dir_all(path, paths::long(&entry.path()))?;
+        } else {
+            builder.append_path_with_name(entry.path(), path)?;
+        }
+    }
+    for (there, here) in inputs {
+        builder.append_dir_all(there, paths::long(here))?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+/// Unpack the result archive `result`, putting its TTC files (if any) into `ttc`. Blocking.
+pub fn unpack(result: &[u8], ttc: &Path) -> Result<Outcome, anyhow::Error> {
+    // Like `archive::extract`, only replace `ttc` once everything is unpacked.
+    let tmp = ttc.with_extension("remote");
+    if tmp.exists() {
+        std::fs::remove_dir_all(paths::long(&tmp))?;
+    }
+    std::fs::create_dir_all(&tmp)?;
+    tar::Archive::new(GzDecoder::new(result)).unpack(&tmp).context("The worker's answer isn't a result archive")?;
+    let outcome: Outcome = std::fs::read(tmp.join("result.json")).map_err(anyhow::Error::from)
+        .and_then(|json| Ok(serde_json::from_slice(&json)?))
+        .context("The worker's answer has no valid `result.json`")?;
+    if outcome.code == Some(0) {
+        if ttc.exists() {
+            std::fs::remove_dir_all(paths::long(ttc))?;
+        }
+        let built = tmp.join("ttc");
+        if built.is_dir() {
+            std::fs::rename(&built, ttc)?;
+        } else {
+            std::fs::create_dir_all(ttc)?;
+        }
+    }
+    std::fs::remove_dir_all(paths::long(&tmp))?;
+    Ok(outcome)
+}
+
+/// `lair worker`: do the job in the job archive `job`, returning the result archive. Fails if it
+/// can't be done here at all, not if idris2 fails.
+pub async fn work(job: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+    let dir = files::temp_dir(&format!("lair-job-{}-", std::process::id()))?;
+    let result = work_in(&dir, job).await;
+    // Best effort, it's in the temporary directory anyway.
+    let _ = std::fs::remove_dir_all(paths::long(&dir));
+    result
+}
+
+async fn work_in(dir: &Path, job: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+    tar::Archive::new(GzDecoder::new(job.as_slice())).unpack(dir).context("Not a job archive")?;
+    let job: Job = serde_json::from_slice(&std::fs::read(dir.join("job.json")).context("The job has no `job.json`")?)
+        .context("The job's `job.json` is invalid")?;
+    anyhow::ensure!(job.version == JOB_VERSION, "This worker only understands jobs of version {}, not {}, since it is another lair version", JOB_VERSION, job.version);
+    job.check().context("This worker doesn't run the job")?;
+    let own = tokio::task::spawn_blocking(Toolchain::detect).await.unwrap()
+        .context("This worker can't tell which idris2 it has")?;
+    anyhow::ensure!(own.full == job.toolchain, "This worker has idris2 {}, but the job needs {}", own.full, job.toolchain);
+
+    let mut command = Command::new(paths::idris2());
+    command.args(job.args()).current_dir(dir);
+    if !job.idris2_path.is_empty() {
+        command.env("IDRIS2_PATH", job.idris2_path.join_idris2()?);
+    }
+    if !job.package_path.is_empty() {
+        command.env("IDRIS2_PACKAGE_PATH", job.package_path.join_idris2()?);
+    }
+    // The job's paths are relative to `dir`, which the sandbox runs idris2 in, too.
+    let mut command = tokio::process::Command::from(job.sandbox.confine(command, dir).context("This worker can't sandbox the job")?);
+    command.stdin(Stdio::null()).kill_on_drop(true);
+    let output = command.output();
+    let outcome = match job.timeout_secs {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), output).await.ok(),
+        None => Some(output.await),
+    };
+    let outcome = match outcome {
+        Some(output) => {
+            let output = output.context("Failed to run idris2")?;
+            let text = String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr);
+            Outcome { code: Some(output.status.code().unwrap_or(-1)), output: text.lines().map(str::to_owned).collect() }
+        },
+        None => Outcome { code: None, output: Vec::new() },
+    };
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), flate2::Compression::fast()));
+    append_bytes(&mut builder, "result.json", &serde_json::to_vec_pretty(&outcome)?)?;
+    let ttc = dir.join(&job.ttc);
+    if outcome.code == Some(0) && ttc.is_dir() {
+        builder.append_dir_all("ttc", paths::long(&ttc))?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+fn append_bytes(builder: &mut tar::Builder<impl Write>, path: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> Job {
+        Job {
+            version: JOB_VERSION,
+            toolchain: "0.6.0-abc".to_owned(),
+            build_dir: "pkg/build/idris2-0.6.0".into(),
+            source_dir: "pkg/src".into(),
+            flags: vec!["--total".to_owned(), "-X".to_owned(), "ElabReflection".to_owned()],
+            packages: vec!["NotJson".to_owned(), "contrib".to_owned()],
+            main: "pkg/src/NotJson.idr".into(),
+            idris2_path: vec!["deps/0".into()],
+            package_path: vec!["packages".into()],
+            ttc: "pkg/build/idris2-0.6.0/ttc".into(),
+            timeout_secs: None,
+            sandbox: Sandbox::default(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_plain_job() {
+        assert!(job().check().is_ok());
+    }
+
+    #[test]
+    fn keeps_paths_inside_the_job() {
+        for path in ["../elsewhere", "pkg/../../elsewhere", "/etc", "./pkg"] {
+            let path = PathBuf::from(path);
+            let jobs = [
+                Job { build_dir: path.clone(), ..job() },
+                Job { source_dir: path.clone(), ..job() },
+                Job { main: path.clone(), ..job() },
+                Job { ttc: path.clone(), ..job() },
+                Job { idris2_path: vec![path.clone()], ..job() },
+                Job { package_path: vec![path.clone()], ..job() },
+            ];
+            for job in jobs {
+                assert!(job.check().is_err(), "{:?}", job);
+            }
+        }
+    }
+
+    #[test]
+    fn only_passes_allowed_flags() {
+        let with_flags = |flags: &[&str]| Job { flags: flags.iter().map(|&flag| flag.to_owned()).collect(), ..job() };
+        assert!(with_flags(&["--no-color", "--log", "2", "--extension", "Borrowing"]).check().is_ok());
+        for flags in [&["--cg", "racket"][..], &["--exec", "main"], &["--output-dir", "/tmp"], &["--total=yes"], &["-p", "base"], &["--codegen"]] {
+            assert!(with_flags(flags).check().is_err(), "{:?}", flags);
+        }
+    }
+
+    #[test]
+    fn flags_with_values_need_a_plain_word() {
+        let with_flags = |flags: &[&str]| Job { flags: flags.iter().map(|&flag| flag.to_owned()).collect(), ..job() };
+        assert!(with_flags(&["-X"]).check().is_err());
+        assert!(with_flags(&["-X", ""]).check().is_err());
+        for value in ["$(touch pwned)", "a b", "../x", "x;y"] {
+            assert!(with_flags(&["-X", value]).check().is_err(), "{}", value);
+        }
+        // The value isn't taken for a flag itself.
+        assert!(with_flags(&["-X", "--total"]).check().is_err());
+    }
+
+    #[test]
+    fn packages_are_just_names() {
+        for package in ["../../etc", "a/b", "-pwned", ""] {
+            let job = Job { packages: vec![package.to_owned()], ..job() };
+            assert!(job.check().is_err(), "{}", package);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! `lair build --reproducible-check`: building the root package twice and comparing the results,
+//! for packagers who need the same sources to always give the same files.
+//!
+//! Dependencies are built as usual, once. The root package itself is then built into
+//! `build/.lair/reproducible/a`, and again into `build/.lair/reproducible/b`: its TTC files, and
+//! its program if its main module has a `main`. Every file which differs between the two is
+//! reported, along with what seems to make it differ, see [`Cause`].
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::layout;
+
+/// The directories the two builds go into, below [`dir`].
+pub const RUNS: [&str; 2] = ["a", "b"];
+
+/// At most this many differing stretches of a file are looked at, for telling the causes.
+const MAX_STRETCHES: usize = 64;
+
+/// A file which isn't the same after both builds, relative to their directories.
+#[derive(Clone, Debug)]
+pub struct Difference {
+    pub path: PathBuf,
+    pub kind: DifferenceKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DifferenceKind {
+    OnlyInFirst,
+    OnlyInSecond,
+    /// Sorted, never empty.
+    Content(Vec<Cause>),
+}
+
+/// Why a file might differ between two builds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cause {
+    /// It contains the directory it was built in.
+    BuildDirectory,
+    /// It contains the time it was built, as seconds or milliseconds since 1970.
+    Timestamp,
+    /// Neither of the above.
+    Unknown,
+}
+
+impl Display for Cause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cause::BuildDirectory => write!(f, "embeds the directory it was built in"),
+            Cause::Timestamp => write!(f, "embeds the time it was built"),
+            Cause::Unknown => write!(f, "differs for an unknown reason"),
+        }
+    }
+}
+
+/// `build/.lair/reproducible`.
+pub fn dir() -> PathBuf {
+    layout::lair_dir().join("reproducible")
+}
+
+/// Does the module `main` (a `.idr` file) define a `main` function? Only looks for a line starting
+/// with `main :`, which is good enough to tell programs from libraries.
+pub fn has_main(main: &Path) -> std::io::Result<bool> {
+    let source = std::fs::read_to_string(main)?;
+    Ok(source.lines().any(|line| line.strip_prefix("main").is_some_and(|rest| rest.trim_start().starts_with(':'))))
+}
+
+/// Compare the files in `first` and `second`, which were built between the times `built` (in
+/// seconds since 1970), sorted by path. Also returns how many files there were. Blocking.
+pub fn compare(first: &Path, second: &Path, built: (u64, u64)) -> std::io::Result<(Vec<Difference>, usize)> {
+    let first_files = files(first)?;
+    let second_files = files(second)?;
+    let all: BTreeSet<&PathBuf> = first_files.iter().chain(&second_files).collect();
+    let mut differences = Vec::new();
+    for path in &all {
+        let kind = match (first_files.contains(*path), second_files.contains(*path)) {
+            (true, false) => DifferenceKind::OnlyInFirst,
+            (false, true) => DifferenceKind::OnlyInSecond,
+            _ => {
+                let a = std::fs::read(first.join(path))?;
+                let b = std::fs::read(second.join(path))?;
+                if a == b {
+                    continue;
+                }
+                DifferenceKind::Content(causes(&a, &b, first, second, built))
+            },
+        };
+        differences.push(Difference { path: (*path).clone(), kind });
+    }
+    Ok((differences, all.len()))
+}
+
+/// Every file below `dir`, relative to it.
+fn files(dir: &Path) -> std::io::Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative) = dirs.pop() {
+        for entry in std::fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                files.insert(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn causes(a: &[u8], b: &[u8], first: &Path, second: &Path, built: (u64, u64)) -> Vec<Cause> {
+    let mut causes = BTreeSet::new();
+    if mentions(a, first) && mentions(b, second) {
+        causes.insert(Cause::BuildDirectory);
+    }
+    // With the first build's directory spelled like the second's, what's left has to be explained
+    // some other way.
+    let a = without_dir(a, first, second);
+    if a != b {
+        if a.len() != b.len() {
+            causes.insert(Cause::Unknown);
+        }
+        for (start, end) in stretches(&a, b).into_iter().take(MAX_STRETCHES) {
+            let is_time = |bytes: &[u8]| digits_around(bytes, start, end).is_some_and(|number| is_timestamp(number, built));
+            causes.insert(if is_time(&a) && is_time(b) { Cause::Timestamp } else { Cause::Unknown });
+        }
+    }
+    causes.into_iter().collect()
+}
+
+/// Does `bytes` contain `dir`, either as it is or made absolute?
+fn mentions(bytes: &[u8], dir: &Path) -> bool {
+    spellings(dir).iter().any(|spelling| find(bytes, spelling.as_bytes()).is_some())
+}
+
+/// `bytes` with every mention of `dir` replaced by `replacement`.
+fn without_dir(bytes: &[u8], dir: &Path, replacement: &Path) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    for (spelling, replacement) in spellings(dir).iter().zip(spellings(replacement)) {
+        let mut replaced = Vec::with_capacity(bytes.len());
+        let mut rest = bytes.as_slice();
+        while let Some(at) = find(rest, spelling.as_bytes()) {
+            replaced.extend_from_slice(&rest[..at]);
+            replaced.extend_from_slice(replacement.as_bytes());
+            rest = &rest[at + spelling.len()..];
+        }
+        replaced.extend_from_slice(rest);
+        bytes = replaced;
+    }
+    bytes
+}
+
+/// Absolute first, since it contains the relative one.
+fn spellings(dir: &Path) -> Vec<String> {
+    let relative = dir.display().to_string();
+    match std::env::current_dir() {
+        Ok(cwd) if dir.is_relative() => vec![cwd.join(dir).display().to_string(), relative],
+        _ => vec![relative],
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// The stretches `start..end` where `a` and `b`, of the same length, differ.
+fn stretches(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    let mut stretches = Vec::new();
+    let mut start = None;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        match (x == y, start) {
+            (false, None) => start = Some(i),
+            (true, Some(from)) => {
+                stretches.push((from, i));
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(from) = start {
+        stretches.push((from, a.len().min(b.len())));
+    }
+    stretches
+}
+
+/// The number written in ASCII digits which `bytes[start..end]` is part of, if it is all digits.
+fn digits_around(bytes: &[u8], start: usize, end: usize) -> Option<u64> {
+    if !bytes.get(start..end)?.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let from = bytes[..start].iter().rposition(|b| !b.is_ascii_digit()).map_or(0, |i| i + 1);
+    let to = bytes[end..].iter().position(|b| !b.is_ascii_digit()).map_or(bytes.len(), |i| end + i);
+    std::str::from_utf8(&bytes[from..to]).ok()?.parse().ok()
+}
+
+/// Is `number` a time between `built`, give or take a minute, in seconds or milliseconds?
+fn is_timestamp(number: u64, (from, to): (u64, u64)) -> bool {
+    let within = |seconds: u64| seconds + 60 >= from && seconds <= to + 60;
+    within(number) || within(number / 1000)
+}
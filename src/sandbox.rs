@@ -0,0 +1,118 @@
+//! Building third-party packages (git, archive and registry dependencies) with less than the
+//! user's full privileges, see `--sandbox`. Local packages and the root are the user's own, and
+//! always build as usual.
+//!
+//! - `off`: idris2 runs like for any other package.
+//! - `env`: idris2 runs in the package's directory, and only sees the environment variables it
+//!   needs (see [`KEPT_VARS`]), so no tokens or cloud credentials.
+//! - `bwrap`: like `env`, and inside [bubblewrap](https://github.com/containers/bubblewrap) on
+//!   Linux: the whole file system is read-only except for the package's own directory, and there
+//!   is no network.
+
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::descriptor::Descriptor;
+
+/// Environment variables idris2 may see in the sandbox, besides those Lair sets for it and any
+/// starting with `IDRIS2_`.
+pub const KEPT_VARS: &[&str] = &[
+    "PATH", "HOME", "USER", "LANG", "LC_ALL", "LC_CTYPE", "TMPDIR", "TEMP", "TMP", "CHEZ",
+    // Windows won't even start programs without these.
+    "SYSTEMROOT", "SYSTEMDRIVE", "PATHEXT", "COMSPEC",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sandbox {
+    #[default]
+    Off,
+    Env,
+    #[serde(rename = "bwrap")]
+    Bubblewrap,
+}
+
+impl Sandbox {
+    /// Is `desc` built in the sandbox? Only third-party packages are.
+    pub fn applies_to(self, desc: &Descriptor) -> bool {
+        self != Sandbox::Off && matches!(desc, Descriptor::Git { .. } | Descriptor::Archive { .. } | Descriptor::Registry { .. })
+    }
+
+    /// `command`, an idris2 invocation building the package in `package_dir`, changed to run in
+    /// this sandbox. Paths in `command`'s arguments have to be absolute, since it runs in
+    /// `package_dir` rather than in the current directory.
+    pub fn confine(self, command: Command, package_dir: &Path) -> Result<Command, anyhow::Error> {
+        let package_dir = std::path::absolute(package_dir)?;
+        let mut confined = match self {
+            Sandbox::Off => return Ok(command),
+            Sandbox::Env => {
+                let mut confined = Command::new(command.get_program());
+                confined.args(command.get_args());
+                confined
+            },
+            Sandbox::Bubblewrap => {
+                if !cfg!(target_os = "linux") {
+                    anyhow::bail!("`--sandbox bwrap` needs bubblewrap, which only exists on Linux, use `--sandbox env` instead.");
+                }
+                let mut confined = Command::new("bwrap");
+                confined
+                    .args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"])
+                    .arg("--bind").arg(&package_dir).arg(&package_dir)
+                    .args(["--unshare-all", "--die-with-parent", "--new-session"])
+                    .arg("--chdir").arg(&package_dir)
+                    .arg("--")
+                    .arg(command.get_program())
+                    .args(command.get_args());
+                confined
+            },
+        };
+        // Removing rather than clearing everything, so that the command line in the build log
+        // only shows what Lair sets itself.
+        for (key, _) in std::env::vars_os() {
+            if !kept(&key) {
+                confined.env_remove(key);
+            }
+        }
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => confined.env(key, value),
+                None => confined.env_remove(key),
+            };
+        }
+        confined.current_dir(package_dir);
+        Ok(confined)
+    }
+}
+
+fn kept(key: &OsString) -> bool {
+    let key = key.to_string_lossy().to_uppercase();
+    KEPT_VARS.contains(&key.as_str()) || key.starts_with("IDRIS2_")
+}
+
+impl FromStr for Sandbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Sandbox::Off),
+            "env" => Ok(Sandbox::Env),
+            "bwrap" => Ok(Sandbox::Bubblewrap),
+            _ => Err(format!("Unknown sandbox `{}`, expected `off`, `env` or `bwrap`", s)),
+        }
+    }
+}
+
+impl Display for Sandbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sandbox::Off => write!(f, "off"),
+            Sandbox::Env => write!(f, "env"),
+            Sandbox::Bubblewrap => write!(f, "bwrap"),
+        }
+    }
+}
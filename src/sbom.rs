@@ -0,0 +1,246 @@
+//! `lair sbom`: a software bill of materials of the dependency graph, as CycloneDX 1.5 or SPDX
+//! 2.3 JSON.
+//!
+//! Each package gets its version, where it was downloaded from, the commit the lockfile pins it
+//! to, the sha256 of its archive (if the manifest gave one) and its license. Licenses which
+//! aren't valid SPDX expressions (ipkgs can say anything) are passed on as plain names in
+//! CycloneDX, and left out of SPDX.
+//!
+//! The creation time is taken from `SOURCE_DATE_EPOCH` if it is set, so that reproducible builds
+//! produce the same document.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::archive;
+use crate::descriptor::Descriptor;
+use crate::graph::{Graph, GraphPackage};
+use crate::layout;
+use crate::license::{LicenseOrigin, PackageLicense};
+use crate::lockfile::Lockfile;
+use crate::timestamp;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    CycloneDx,
+    Spdx,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cyclonedx" => Ok(Format::CycloneDx),
+            "spdx" => Ok(Format::Spdx),
+            _ => Err(format!("Unknown SBOM format `{}`, expected one of cyclonedx, spdx", s)),
+        }
+    }
+}
+
+/// Everything we know about one package.
+struct Component<'a> {
+    package: &'a GraphPackage,
+    /// Unique within the document, for example `NotJson-3f2a9c01`.
+    id: String,
+    /// For registry dependencies the version picked from the registry, otherwise what the
+    /// package's manifest says.
+    version: String,
+    download: Option<String>,
+    commit: Option<String>,
+    checksum: Option<String>,
+    license: Option<&'a PackageLicense>,
+}
+
+impl Component<'_> {
+    fn name(&self) -> &str {
+        self.package.descriptor.package_name()
+    }
+
+    /// The license, if it is a valid SPDX expression.
+    fn spdx_license(&self) -> Option<&str> {
+        let license = self.license?.license.as_deref()?;
+        spdx::Expression::parse(license).is_ok().then_some(license)
+    }
+}
+
+/// The bill of materials of `graph` in `format`. `licenses` is what [`crate::Lair::licenses`]
+/// found.
+pub fn generate(format: Format, graph: &Graph, lockfile: &Lockfile, licenses: &[PackageLicense]) -> Value {
+    let licenses: BTreeMap<&Descriptor, &PackageLicense> = licenses.iter().map(|license| (&license.descriptor, license)).collect();
+    let mut components: Vec<Component> = graph.packages.iter()
+        .map(|package| {
+            let desc = &package.descriptor;
+            let locked = lockfile.locked(desc);
+            let download = match desc {
+                Descriptor::Git { url, .. } | Descriptor::Archive { url, .. } => Some(url.clone()),
+                Descriptor::Registry { .. } => locked.map(|locked| locked.git.clone()),
+                Descriptor::Local { .. } | Descriptor::Root { .. } => None,
+            };
+            let checksum = match desc {
+                Descriptor::Archive { checksum, .. } => checksum.as_ref().map(|checksum| checksum.trim().to_lowercase()),
+                _ => None,
+            };
+            Component {
+                package,
+                id: format!("{}-{}", sanitize(desc.name()), layout::short_hash(desc)),
+                version: locked.and_then(|locked| locked.version.clone()).unwrap_or_else(|| package.version.clone()),
+                download,
+                commit: locked.map(|locked| locked.commit.clone()),
+                checksum,
+                license: licenses.get(desc).copied(),
+            }
+        })
+        .collect();
+    components.sort_by(|a, b| a.package.descriptor.cmp(&b.package.descriptor));
+
+    match format {
+        Format::CycloneDx => cyclonedx(graph, &components),
+        Format::Spdx => spdx(graph, &components),
+    }
+}
+
+fn cyclonedx(graph: &Graph, components: &[Component]) -> Value {
+    let ids: BTreeMap<&Descriptor, &str> = components.iter().map(|c| (&c.package.descriptor, c.id.as_str())).collect();
+    let component = |c: &Component| {
+        let mut value = json!({
+            "type": "library",
+            "bom-ref": c.id,
+            "name": c.name(),
+            "version": c.version,
+        });
+        if let Some(license) = c.license.and_then(|license| license.license.as_ref()) {
+            value["licenses"] = match c.spdx_license() {
+                Some(expression) => json!([{ "expression": expression }]),
+                None => json!([{ "license": { "name": license } }]),
+            };
+        }
+        if let Some(checksum) = &c.checksum {
+            value["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+        }
+        if let Some(download) = &c.download {
+            let kind = if matches!(c.package.descriptor, Descriptor::Archive { .. }) { "distribution" } else { "vcs" };
+            value["externalReferences"] = json!([{ "type": kind, "url": download }]);
+        }
+        if let Some(commit) = &c.commit {
+            value["properties"] = json!([{ "name": "lair:commit", "value": commit }]);
+        }
+        value
+    };
+
+    let root = components.iter().find(|c| c.package.descriptor == graph.root);
+    let mut root_component = root.map(component).unwrap_or(Value::Null);
+    if root.is_some() {
+        root_component["type"] = json!("application");
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": created(),
+            "tools": [{ "name": "lair", "version": env!("CARGO_PKG_VERSION") }],
+            "component": root_component,
+        },
+        "components": components.iter()
+            .filter(|c| c.package.descriptor != graph.root)
+            .map(component)
+            .collect::<Vec<_>>(),
+        "dependencies": components.iter()
+            .map(|c| json!({
+                "ref": c.id,
+                "dependsOn": c.package.dependencies.iter().filter_map(|dep| ids.get(dep)).collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn spdx(graph: &Graph, components: &[Component]) -> Value {
+    let ids: BTreeMap<&Descriptor, String> = components.iter()
+        .map(|c| (&c.package.descriptor, format!("SPDXRef-Package-{}", c.id)))
+        .collect();
+    let root = components.iter().find(|c| c.package.descriptor == graph.root);
+    let name = root.map_or(graph.root.name(), Component::name);
+    let version = root.map_or("", |root| root.version.as_str());
+
+    let packages: Vec<Value> = components.iter()
+        .map(|c| {
+            let download = match (&c.download, &c.commit) {
+                (Some(url), Some(commit)) if !matches!(c.package.descriptor, Descriptor::Archive { .. }) =>
+                    format!("git+{}@{}", url, commit),
+                (Some(url), _) => url.clone(),
+                (None, _) => "NOASSERTION".to_owned(),
+            };
+            let license = c.spdx_license().unwrap_or("NOASSERTION");
+            let guessed = matches!(c.license.and_then(|license| license.origin.as_ref()), Some(LicenseOrigin::File(_)));
+            let mut value = json!({
+                "SPDXID": ids[&c.package.descriptor],
+                "name": c.name(),
+                "versionInfo": c.version,
+                "downloadLocation": download,
+                // A license we recognized from a file is our conclusion, not the author's declaration.
+                "licenseConcluded": if guessed { license } else { "NOASSERTION" },
+                "licenseDeclared": if guessed { "NOASSERTION" } else { license },
+                "copyrightText": "NOASSERTION",
+                "filesAnalyzed": false,
+            });
+            if let Some(checksum) = &c.checksum {
+                value["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": checksum }]);
+            }
+            value
+        })
+        .collect();
+
+    let mut relationships = Vec::new();
+    if let Some(root) = root {
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": ids[&root.package.descriptor],
+        }));
+    }
+    for c in components {
+        for dep in &c.package.dependencies {
+            if let Some(dep) = ids.get(dep) {
+                relationships.push(json!({
+                    "spdxElementId": ids[&c.package.descriptor],
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": dep,
+                }));
+            }
+        }
+    }
+
+    // Has to be unique per document, so it depends on the contents and the time.
+    let created = created();
+    let namespace_hash = archive::sha256(format!("{}{}", serde_json::to_string(&packages).unwrap_or_default(), created).as_bytes());
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": name,
+        "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}-{}", sanitize(name), sanitize(version), &namespace_hash[..16]),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: lair-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// SPDX ids may only contain letters, digits, `.` and `-`.
+fn sanitize(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' }).collect()
+}
+
+/// Now, or `SOURCE_DATE_EPOCH`, for example `2021-12-24T18:30:00Z`.
+fn created() -> String {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.trim().parse::<u64>().ok())
+        .unwrap_or_else(timestamp::now);
+    timestamp::iso8601(seconds)
+}
@@ -0,0 +1,99 @@
+//! `lair new`: starting a package, either with a minimal layout or from a template.
+//!
+//! A template is any git repository, for example one with a web app or FFI binding already set
+//! up. It's cloned without its history, and `{{name}}` and `{{author}}` are replaced in the
+//! contents and names of all its files, by the new package's name and by who git says you are
+//! (`Kiiyya <kiiyya@example.com>`). Files which aren't UTF-8 are left as they are.
+//!
+//! Either way, the package starts out as a fresh git repository.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::fetch;
+use crate::paths;
+
+/// Create the package `name` in `dir`, which mustn't exist yet or be empty, from the template
+/// repository at `template` if given.
+pub fn create(dir: &Path, name: &str, template: Option<&str>) -> Result<(), anyhow::Error> {
+    if !name.starts_with(|c: char| c.is_ascii_uppercase()) || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        anyhow::bail!("`{}` can't be a package name, since it has to be a module name too: a capital letter, then letters, digits and `_`.", name);
+    }
+    if dir.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        anyhow::bail!("`{}` already exists and isn't empty.", dir.display());
+    }
+    let author = author();
+
+    match template {
+        Some(url) => {
+            let result = from_template(dir, url, name, author.as_deref().unwrap_or_default());
+            if result.is_err() && dir.exists() {
+                let _ = std::fs::remove_dir_all(paths::long(dir));
+            }
+            result?;
+        },
+        None => minimal(dir, name, author.as_deref())?,
+    }
+    git2::Repository::init(dir).with_context(|| format!("Failed to create a git repository in `{}`", dir.display()))?;
+    Ok(())
+}
+
+fn minimal(dir: &Path, name: &str, author: Option<&str>) -> Result<(), anyhow::Error> {
+    let authors = author.map(|author| format!("authors = [{:?}]\n", author)).unwrap_or_default();
+    std::fs::create_dir_all(dir.join("src"))?;
+    std::fs::write(dir.join("Egg.toml"), format!("[package]\nname = {:?}\nversion = \"0.1.0\"\n{}\n[dependencies]\n", name, authors))?;
+    std::fs::write(dir.join("src").join(format!("{}.idr", name)), format!("module {}\n\nmain : IO ()\nmain = putStrLn \"Hello from {}!\"\n", name, name))?;
+    std::fs::write(dir.join(".gitignore"), "/build\n")?;
+    Ok(())
+}
+
+fn from_template(dir: &Path, url: &str, name: &str, author: &str) -> Result<(), anyhow::Error> {
+    fetch::clone_git(url, dir, None, &|_, _, _| {}).with_context(|| format!("Failed to download the template `{}`", url))?;
+    std::fs::remove_dir_all(paths::long(&dir.join(".git")))?;
+    substitute(dir, &[("{{name}}", name), ("{{author}}", author)])?;
+    if !dir.join("Egg.toml").exists() {
+        anyhow::bail!("The template `{}` has no `Egg.toml` at its root.", url);
+    }
+    Ok(())
+}
+
+/// Replace the `placeholders` in the contents and names of everything in `dir`, recursively.
+/// Symlinks are renamed, but neither followed nor written through, since the template could point
+/// them anywhere.
+fn substitute(dir: &Path, placeholders: &[(&str, &str)]) -> Result<(), anyhow::Error> {
+    let replace = |s: &str| placeholders.iter().fold(s.to_owned(), |s, (placeholder, value)| s.replace(placeholder, value));
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            // Only renamed.
+        } else if file_type.is_dir() {
+            substitute(&path, placeholders)?;
+        } else if let Ok(contents) = std::fs::read_to_string(&path) {
+            let replaced = replace(&contents);
+            if replaced != contents {
+                std::fs::write(&path, replaced)?;
+            }
+        }
+        if let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) {
+            let replaced = replace(file_name);
+            if replaced != file_name {
+                std::fs::rename(&path, path.with_file_name(replaced))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `Name <email>` from git's `user.name` and `user.email`, or the login name.
+fn author() -> Option<String> {
+    let config = git2::Config::open_default().ok();
+    let get = |key: &str| config.as_ref()?.get_string(key).ok().filter(|value| !value.is_empty());
+    match (get("user.name"), get("user.email")) {
+        (Some(name), Some(email)) => Some(format!("{} <{}>", name, email)),
+        (Some(name), None) => Some(name),
+        (None, _) => std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok().filter(|user| !user.is_empty()),
+    }
+}
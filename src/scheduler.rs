@@ -0,0 +1,316 @@
+//! Running a build as a DAG of tasks, rather than having every package recursively await its
+//! dependencies.
+//!
+//! Every package of the resolved [`Graph`] has up to three [`Task`]s: fetching its source, reading
+//! its manifest, and building its TTC files. Reading a manifest waits for the source, and a build
+//! waits for the package's manifest and for the builds of its dependencies. [`run`] starts each
+//! task once everything it waits for is done, with at most [`Limits::builds`] builds and
+//! [`Limits::fetches`] downloads at a time. Among the tasks ready to start, those on the longest
+//! chain of tasks waiting behind them go first, since they hold up the most. How long a chain is
+//! depends on how long its tasks are expected to take, builds by how long they took last time
+//! (see [`crate::timings`]), so the critical path starts as early as possible. After the first
+//! failure, no new tasks are started, but those already running are waited for. When keeping
+//! going instead, everything not waiting for a failed task still runs, and all failures are
+//! reported together. After Ctrl-C, no new tasks are started either, see [`crate::interrupt`].
+//!
+//! The tasks themselves are still the nodes' recipes (see [`crate::node::Node`]), which remember
+//! their results. So anything the graph doesn't know about, say a manifest which changed since
+//! the graph was resolved, is still fetched and built on demand, just not scheduled.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::time::Duration;
+
+use futures::{Future, StreamExt};
+use futures::stream::FuturesUnordered;
+
+use crate::descriptor::Descriptor;
+use crate::graph::Graph;
+use crate::interrupt;
+use crate::profile::Profiler;
+use crate::tracing::Tracer;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskKind {
+    Fetch,
+    Manifest,
+    Build,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Task {
+    pub descriptor: Descriptor,
+    pub kind: TaskKind,
+}
+
+/// For example "build NotJson".
+impl Display for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            TaskKind::Fetch => "fetch",
+            TaskKind::Manifest => "read the manifest of",
+            TaskKind::Build => "build",
+        };
+        write!(f, "{} {}", kind, self.descriptor.name())
+    }
+}
+
+/// How many tasks of each kind may run at the same time. Reading manifests is cheap, so it isn't
+/// limited.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub builds: usize,
+    pub fetches: usize,
+}
+
+impl Default for Limits {
+    /// As many builds as there are CPUs, and a few downloads.
+    fn default() -> Self {
+        Self {
+            builds: std::thread::available_parallelism().map_or(1, usize::from),
+            fetches: 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Dag {
+    pub tasks: Vec<Task>,
+    /// Indices of the tasks each task waits for.
+    waits_for: Vec<Vec<usize>>,
+    /// The other way around: indices of the tasks waiting for each task.
+    waited_on_by: Vec<Vec<usize>>,
+    /// Expected time of the longest chain of tasks waiting for each task, itself included.
+    priority: Vec<Duration>,
+}
+
+impl Dag {
+    /// The tasks of building everything in `graph`, or only those `include` accepts. Tasks which
+    /// aren't included are treated as done already. `cost` is how long a task is expected to take.
+    pub fn new(graph: &Graph, include: impl Fn(&Task) -> bool, cost: impl Fn(&Task) -> Duration) -> Self {
+        let mut tasks = Vec::new();
+        let mut index = BTreeMap::new();
+        for package in &graph.packages {
+            for kind in [TaskKind::Fetch, TaskKind::Manifest, TaskKind::Build] {
+                // The root package is where we are, there's nothing to fetch.
+                if kind == TaskKind::Fetch && matches!(package.descriptor, Descriptor::Root { .. }) {
+                    continue;
+                }
+                let task = Task { descriptor: package.descriptor.clone(), kind };
+                if include(&task) {
+                    index.insert((package.descriptor.clone(), kind), tasks.len());
+                    tasks.push(task);
+                }
+            }
+        }
+
+        let waits_for: Vec<Vec<usize>> = tasks.iter()
+            .map(|task| {
+                let desc = &task.descriptor;
+                let wanted = match task.kind {
+                    TaskKind::Fetch => vec![],
+                    TaskKind::Manifest => vec![(desc.clone(), TaskKind::Fetch)],
+                    TaskKind::Build => {
+                        let dependencies = graph.package(desc).into_iter().flat_map(|package| &package.dependencies);
+                        std::iter::once((desc.clone(), TaskKind::Manifest))
+                            .chain(dependencies.map(|dep| (dep.clone(), TaskKind::Build)))
+                            .collect()
+                    },
+                };
+                wanted.iter().filter_map(|key| index.get(key).copied()).collect()
+            })
+            .collect();
+
+        let mut waited_on_by = vec![Vec::new(); tasks.len()];
+        for (task, waits_for) in waits_for.iter().enumerate() {
+            for &other in waits_for {
+                waited_on_by[other].push(task);
+            }
+        }
+        let costs: Vec<Duration> = tasks.iter().map(cost).collect();
+        let mut priority = vec![None; tasks.len()];
+        for task in 0..tasks.len() {
+            chain_length(task, &waited_on_by, &costs, &mut priority);
+        }
+
+        Self {
+            tasks,
+            waits_for,
+            waited_on_by,
+            priority: priority.into_iter().map(Option::unwrap_or_default).collect(),
+        }
+    }
+
+    /// Indices of the tasks `task` (an index into [`Dag::tasks`]) waits for.
+    pub fn waits_for(&self, task: usize) -> &[usize] {
+        &self.waits_for[task]
+    }
+
+    /// Expected time of the longest chain of tasks waiting for `task`, itself included.
+    pub fn priority(&self, task: usize) -> Duration {
+        self.priority[task]
+    }
+}
+
+/// Memoized in `done`, where `Some(ZERO)` marks tasks still being looked at, so that cycles end.
+fn chain_length(task: usize, waited_on_by: &[Vec<usize>], costs: &[Duration], done: &mut [Option<Duration>]) -> Duration {
+    if let Some(length) = done[task] {
+        return length;
+    }
+    done[task] = Some(Duration::ZERO);
+    let length = costs[task] + waited_on_by[task].iter()
+        .map(|&other| chain_length(other, waited_on_by, costs, done))
+        .max()
+        .unwrap_or_default();
+    done[task] = Some(length);
+    length
+}
+
+/// What went wrong in [`run`].
+#[derive(Debug)]
+pub struct Failed<E> {
+    /// In the order the tasks failed. Only the first one, unless keeping going.
+    pub errors: Vec<E>,
+    /// Tasks which didn't run, because they wait for a failed one, directly or not.
+    pub skipped: Vec<Task>,
+}
+
+/// Run every task of `dag` by calling `start` on it, see the module docs. Fails with the first
+/// failing task's error, or with those of all failing tasks if `keep_going`. Tasks which never
+/// become ready, because they wait for each other in a cycle, are left out. If `profiler` is
+/// given, every task and how many are waiting are recorded there.
+pub async fn run<Tr, E, F, Fut>(dag: &Dag, limits: Limits, keep_going: bool, tracer: &Tr, profiler: Option<&Profiler>, start: F) -> Result<(), Failed<E>>
+where
+    Tr: Tracer,
+    F: Fn(&Task) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+{
+    let mut waiting: Vec<usize> = dag.waits_for.iter().map(Vec::len).collect();
+    let mut ready: Vec<usize> = (0..dag.tasks.len()).filter(|&task| waiting[task] == 0).collect();
+    let mut running = FuturesUnordered::new();
+    let (mut builds, mut fetches) = (0, 0);
+    let mut failed = Vec::new();
+    let mut errors = Vec::new();
+
+    loop {
+        if (errors.is_empty() || keep_going) && !interrupt::requested() {
+            // Highest priority first, ties in graph order.
+            ready.sort_by_key(|&task| (std::cmp::Reverse(dag.priority[task]), task));
+            let mut i = 0;
+            while i < ready.len() {
+                let task = ready[i];
+                let kind = dag.tasks[task].kind;
+                let full = match kind {
+                    TaskKind::Build => builds >= limits.builds.max(1),
+                    TaskKind::Fetch => fetches >= limits.fetches.max(1),
+                    TaskKind::Manifest => false,
+                };
+                if full {
+                    i += 1;
+                    continue;
+                }
+                match kind {
+                    TaskKind::Build => builds += 1,
+                    TaskKind::Fetch => fetches += 1,
+                    TaskKind::Manifest => {},
+                }
+                ready.remove(i);
+                tracer.task_started(&dag.tasks[task]);
+                let id = profiler.map(|profiler| profiler.task_started(&dag.tasks[task]));
+                let future = start(&dag.tasks[task]);
+                running.push(async move { (task, id, future.await) });
+            }
+        }
+        if let Some(profiler) = profiler {
+            profiler.queue(ready.len(), running.len());
+        }
+
+        let Some((task, id, result)) = running.next().await else { break };
+        match dag.tasks[task].kind {
+            TaskKind::Build => builds -= 1,
+            TaskKind::Fetch => fetches -= 1,
+            TaskKind::Manifest => {},
+        }
+        tracer.task_finished(&dag.tasks[task], result.is_ok());
+        if let (Some(profiler), Some(id)) = (profiler, id) {
+            profiler.task_finished(&dag.tasks[task], id, result.is_ok());
+        }
+        match result {
+            Ok(()) => for &other in &dag.waited_on_by[task] {
+                waiting[other] -= 1;
+                if waiting[other] == 0 {
+                    ready.push(other);
+                }
+            },
+            Err(e) => {
+                failed.push(task);
+                errors.push(e);
+            },
+        }
+    }
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let mut skipped = BTreeSet::new();
+    while let Some(task) = failed.pop() {
+        for &other in &dag.waited_on_by[task] {
+            if skipped.insert(other) {
+                failed.push(other);
+            }
+        }
+    }
+    Err(Failed { errors, skipped: skipped.into_iter().map(|task| dag.tasks[task].clone()).collect() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::fixtures::{graph, local, root};
+
+    /// `Root` depends on `A` and `B`, `A` on `B`.
+    fn chain() -> Graph {
+        let (a, b) = (local("A", None), local("B", None));
+        graph(vec![(root(), vec![a.clone(), b.clone()]), (a, vec![b.clone()]), (b, Vec::new())])
+    }
+
+    fn index(dag: &Dag, name: &str, kind: TaskKind) -> usize {
+        dag.tasks.iter().position(|task| task.descriptor.name() == name && task.kind == kind).unwrap()
+    }
+
+    #[test]
+    fn builds_wait_for_manifests_and_dependencies() {
+        let dag = Dag::new(&chain(), |_| true, |_| Duration::from_secs(1));
+        // Nothing to fetch for the root.
+        assert_eq!(dag.tasks.len(), 8);
+        let waits_for = |name, kind| {
+            let mut tasks: Vec<String> = dag.waits_for(index(&dag, name, kind)).iter().map(|&task| dag.tasks[task].to_string()).collect();
+            tasks.sort();
+            tasks
+        };
+        assert_eq!(waits_for("Root", TaskKind::Build), ["build A", "build B", "read the manifest of Root"]);
+        assert_eq!(waits_for("A", TaskKind::Build), ["build B", "read the manifest of A"]);
+        assert_eq!(waits_for("B", TaskKind::Manifest), ["fetch B"]);
+        assert!(waits_for("B", TaskKind::Fetch).is_empty());
+        assert!(waits_for("Root", TaskKind::Manifest).is_empty());
+    }
+
+    #[test]
+    fn longest_chains_go_first() {
+        let cost = |task: &Task| Duration::from_secs(if task.kind == TaskKind::Build { 10 } else { 1 });
+        let dag = Dag::new(&chain(), |_| true, cost);
+        // fetch B, read its manifest, build B, A and Root.
+        assert_eq!(dag.priority(index(&dag, "B", TaskKind::Fetch)), Duration::from_secs(32));
+        assert_eq!(dag.priority(index(&dag, "A", TaskKind::Fetch)), Duration::from_secs(22));
+        assert_eq!(dag.priority(index(&dag, "Root", TaskKind::Manifest)), Duration::from_secs(11));
+        assert_eq!(dag.priority(index(&dag, "Root", TaskKind::Build)), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn left_out_tasks_count_as_done() {
+        let dag = Dag::new(&chain(), |task| task.kind != TaskKind::Fetch, |_| Duration::from_secs(1));
+        assert_eq!(dag.tasks.len(), 6);
+        assert!(dag.waits_for(index(&dag, "B", TaskKind::Manifest)).is_empty());
+        assert_eq!(dag.priority(index(&dag, "B", TaskKind::Manifest)), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,46 @@
+//! `[scripts]` in `Egg.toml`: named commands, run with `lair script <name>` once the package is
+//! built, so that projects don't each need a Makefile around Lair.
+//!
+//! ```toml
+//! [scripts]
+//! bench = "idris2 --exec bench src/Bench.idr"
+//! post-build = "cp build/exec/AmazingTool ~/bin"
+//! ```
+//!
+//! Scripts run in the package's directory through `sh -c` (`cmd /C` on Windows), with anything
+//! after the name passed on as arguments. Like for `lair exec`, `IDRIS2_PATH` makes the
+//! dependencies visible, and additionally
+//! - `LAIR_PACKAGE` is the package's name,
+//! - `LAIR_BUILD_DIR` is the absolute path of `build`,
+//! - `LAIR_EXE` is the absolute path the program is compiled to, `build/exec/{package}`. Scripts
+//!   mentioning it get the program compiled first, see [`needs_exe`].
+//!
+//! [`POST_BUILD`] also runs after every successful `lair build`. Only the root package's scripts
+//! are ever run, never those of dependencies.
+
+use std::ffi::OsString;
+use std::process::Command;
+
+/// Runs after every successful `lair build`.
+pub const POST_BUILD: &str = "post-build";
+
+/// Does `script` use the compiled program? Then it's compiled, for the backend of the target
+/// (chez unless told otherwise), before the script runs. Only looks for `LAIR_EXE` in the script
+/// itself, not in whatever it runs.
+pub fn needs_exe(script: &str) -> bool {
+    script.contains("LAIR_EXE")
+}
+
+/// The command running `script` with `args` through the shell.
+pub fn command(script: &str, args: &[OsString]) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(script).args(args);
+        command
+    } else {
+        // `sh -c '{script} "$@"' sh args...` hands the arguments over without any quoting.
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("{} \"$@\"", script)).arg("sh").args(args);
+        command
+    }
+}
@@ -0,0 +1,447 @@
+//! Package versions and requirements on them, following [semver](https://semver.org) and cargo.
+//!
+//! A version is `major.minor.patch`, optionally followed by a pre-release (`1.0.0-beta.2`) and
+//! build metadata (`1.0.0+20240101`). Pre-releases come before their release, and are ordered by
+//! their dot-separated parts: numbers by value, and below anything with letters. Build metadata
+//! is ignored, both for ordering and for requirements. Manifests and registries may leave out the
+//! minor and patch numbers, `0.1` is `0.1.0` (see [`Version::parse_lenient`]).
+//!
+//! A requirement is a comma-separated list of comparisons, all of which have to hold:
+//!
+//! | Requirement      | Allows                  |
+//! |------------------|-------------------------|
+//! | `1.2.3`, `^1.2.3` | `>=1.2.3, <2.0.0`      |
+//! | `0.2`, `^0.2`    | `>=0.2.0, <0.3.0`       |
+//! | `~1.2.3`         | `>=1.2.3, <1.3.0`       |
+//! | `=1.2.3`         | only `1.2.3`            |
+//! | `1.2.*`, `=1.2`  | `>=1.2.0, <1.3.0`       |
+//! | `*`              | everything              |
+//!
+//! plus `>`, `>=`, `<` and `<=`. Like with cargo, pre-releases only satisfy a requirement which
+//! asks for a pre-release of the same `major.minor.patch`: `^1.0.0-beta.1` allows `1.0.0-beta.2`
+//! and `1.0.5`, while `^1.0.0` and `*` allow neither `1.0.0-beta.2` nor `1.1.0-rc.1`.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Empty for releases.
+    pub pre: Vec<Identifier>,
+    /// As written, without the `+`. Not part of comparisons.
+    pub build: Option<String>,
+}
+
+/// One dot-separated part of a pre-release.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Identifier {
+    // Before `Alphanumeric`, so that numbers sort first.
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+/// See the module documentation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionRequirement {
+    /// None at all for `*`.
+    comparisons: Vec<Comparison>,
+}
+
+/// A comparison with a version whose minor and patch numbers can be left out (or be `*`), which
+/// then allows any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Comparison {
+    op: Op,
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<Identifier>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+impl Version {
+    /// Like parsing, but with the minor and patch numbers optional: `0.1` is `0.1.0`, and `2-rc.1`
+    /// is `2.0.0-rc.1`, the way versions were often written before they had to be semver.
+    pub fn parse_lenient(s: &str) -> Result<Self, String> {
+        let trimmed = s.trim();
+        let end = trimmed.find(['-', '+']).unwrap_or(trimmed.len());
+        let (core, rest) = trimmed.split_at(end);
+        let padded = match core.matches('.').count() {
+            0 => format!("{}.0.0{}", core, rest),
+            1 => format!("{}.0{}", core, rest),
+            _ => trimmed.to_owned(),
+        };
+        padded.parse().map_err(|_| format!("`{}` is not a valid version, expected something like `1.2.3` or `1.0.0-beta.2`", s))
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    /// Can `newer` replace `self` without breaking anything, going by semver? Everything up to
+    /// the first number which isn't zero has to stay the same: `0.2.0` to `0.2.5` is fine, but
+    /// `0.2.0` to `0.3.0` isn't, and neither is `1.4.0` to `2.0.0`.
+    pub fn is_compatible(&self, newer: &Version) -> bool {
+        self.major == newer.major
+            && (self.major != 0 || self.minor == newer.minor)
+            && (self.major != 0 || self.minor != 0 || self.patch == newer.patch)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| compare_pre(&self.pre, &other.pre))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Ignoring build metadata, like the ordering.
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+/// A release comes after all of its pre-releases.
+fn compare_pre(a: &[Identifier], b: &[Identifier]) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.cmp(b),
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{}` is not a valid version, expected something like `1.2.3` or `1.0.0-beta.2`", s);
+        let (rest, build) = match s.trim().split_once('+') {
+            Some((rest, build)) => (rest, Some(build)),
+            None => (s.trim(), None),
+        };
+        if let Some(build) = build {
+            if !build.split('.').all(|part| identifier_chars(part).is_some()) {
+                return Err(invalid());
+            }
+        }
+        let (core, pre) = match rest.split_once('-') {
+            Some((core, pre)) => (core, parse_pre(pre).ok_or_else(invalid)?),
+            None => (rest, Vec::new()),
+        };
+        let mut numbers = core.split('.').map(number);
+        match (numbers.next(), numbers.next(), numbers.next(), numbers.next()) {
+            (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) =>
+                Ok(Self { major, minor, patch, pre, build: build.map(str::to_owned) }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            write!(f, "-{}", display_pre(&self.pre))?;
+        }
+        if let Some(build) = &self.build {
+            write!(f, "+{}", build)?;
+        }
+        Ok(())
+    }
+}
+
+fn display_pre(pre: &[Identifier]) -> String {
+    pre.iter()
+        .map(|identifier| match identifier {
+            Identifier::Numeric(n) => n.to_string(),
+            Identifier::Alphanumeric(s) => s.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// A number without leading zeros.
+fn number(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) || (s.len() > 1 && s.starts_with('0')) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Non-empty, and only ASCII letters, digits and `-`.
+fn identifier_chars(s: &str) -> Option<&str> {
+    (!s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')).then_some(s)
+}
+
+fn parse_pre(pre: &str) -> Option<Vec<Identifier>> {
+    pre.split('.')
+        .map(|part| {
+            let part = identifier_chars(part)?;
+            if part.bytes().all(|b| b.is_ascii_digit()) {
+                number(part).map(Identifier::Numeric)
+            } else {
+                Some(Identifier::Alphanumeric(part.to_owned()))
+            }
+        })
+        .collect()
+}
+
+impl VersionRequirement {
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparisons.iter().all(|comparison| comparison.matches(version))
+            && (!version.is_prerelease() || self.comparisons.iter().any(|comparison| comparison.opts_into(version)))
+    }
+}
+
+impl Comparison {
+    fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Exact => self.matches_exact(version),
+            Op::Greater => self.matches_greater(version),
+            Op::GreaterEq => self.matches_exact(version) || self.matches_greater(version),
+            Op::Less => !self.matches_exact(version) && !self.matches_greater(version),
+            Op::LessEq => !self.matches_greater(version),
+            Op::Tilde => version.major == self.major
+                && self.minor.is_none_or(|minor| version.minor == minor)
+                && self.patch.is_none_or(|_| self.matches_exact(version) || self.matches_greater(version)),
+            Op::Caret => {
+                if version.major != self.major {
+                    return false;
+                }
+                let Some(minor) = self.minor else { return true };
+                let at_least = self.matches_exact(version) || self.matches_greater(version);
+                match self.patch {
+                    None if self.major > 0 => version.minor >= minor,
+                    None => version.minor == minor,
+                    Some(_) if self.major > 0 => at_least,
+                    Some(_) if minor > 0 => version.minor == minor && at_least,
+                    Some(patch) => version.minor == minor && version.patch == patch && at_least,
+                }
+            },
+        }
+    }
+
+    /// Equal in the numbers given, and in the pre-release if all numbers are.
+    fn matches_exact(&self, version: &Version) -> bool {
+        version.major == self.major
+            && self.minor.is_none_or(|minor| version.minor == minor)
+            && self.patch.is_none_or(|patch| version.patch == patch && version.pre == self.pre)
+    }
+
+    /// Greater in the numbers given, or in the pre-release if all numbers are equal.
+    fn matches_greater(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return version.major > self.major;
+        }
+        let Some(minor) = self.minor else { return false };
+        if version.minor != minor {
+            return version.minor > minor;
+        }
+        let Some(patch) = self.patch else { return false };
+        if version.patch != patch {
+            return version.patch > patch;
+        }
+        compare_pre(&version.pre, &self.pre) == Ordering::Greater
+    }
+
+    /// Does this comparison ask for a pre-release of `version`'s `major.minor.patch`?
+    fn opts_into(&self, version: &Version) -> bool {
+        !self.pre.is_empty()
+            && self.major == version.major
+            && self.minor == Some(version.minor)
+            && self.patch == Some(version.patch)
+    }
+}
+
+impl FromStr for VersionRequirement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{}` is not a valid version requirement, expected something like `1.2`, `^1.2.3`, `~0.4` or `>=1.0, <1.5`", s);
+        if s.trim() == "*" {
+            return Ok(Self { comparisons: Vec::new() });
+        }
+        let comparisons = s.split(',')
+            .map(|part| {
+                let part = part.trim();
+                // Two-character operators first, so that `>=` isn't read as `>`.
+                let (op, version) = [
+                    (">=", Op::GreaterEq),
+                    ("<=", Op::LessEq),
+                    (">", Op::Greater),
+                    ("<", Op::Less),
+                    ("=", Op::Exact),
+                    ("~", Op::Tilde),
+                    ("^", Op::Caret),
+                ].into_iter()
+                    .find_map(|(prefix, op)| Some((op, part.strip_prefix(prefix)?.trim_start())))
+                    .unwrap_or((Op::Caret, part));
+                parse_comparison(op, version).ok_or_else(invalid)
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { comparisons })
+    }
+}
+
+/// `version` is `1`, `1.2`, `1.2.3` or `1.2.3-beta.1`, with `*` (or `x`) for any minor or patch
+/// number. `1.2.*` means the same as `=1.2`, whatever `op` is.
+fn parse_comparison(op: Op, version: &str) -> Option<Comparison> {
+    let version = version.split_once('+').map_or(version, |(version, _)| version);
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, parse_pre(pre)?),
+        None => (version, Vec::new()),
+    };
+    let wildcard = |part: &str| matches!(part, "*" | "x" | "X");
+    let mut parts = core.split('.');
+    let major = number(parts.next()?)?;
+    let minor = parts.next();
+    let patch = parts.next();
+    if parts.next().is_some() {
+        return None;
+    }
+    let (op, minor, patch) = match (minor, patch) {
+        (None, None) => (op, None, None),
+        (Some(minor), _) if wildcard(minor) && patch.is_none_or(wildcard) => (Op::Exact, None, None),
+        (Some(minor), None) => (op, Some(number(minor)?), None),
+        (Some(minor), Some(patch)) if wildcard(patch) => (Op::Exact, Some(number(minor)?), None),
+        (Some(minor), Some(patch)) => (op, Some(number(minor)?), Some(number(patch)?)),
+        (None, Some(_)) => return None,
+    };
+    // A pre-release needs all three numbers to belong to.
+    if !pre.is_empty() && patch.is_none() {
+        return None;
+    }
+    Some(Comparison { op, major, minor, patch, pre })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    fn allows(requirement: &str, v: &str) -> bool {
+        requirement.parse::<VersionRequirement>().unwrap().matches(&version(v))
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        for s in ["0.0.0", "1.2.3", "1.0.0-beta.2", "1.0.0-x-y.7", "1.0.0+20240101", "1.0.0-rc.1+build.5"] {
+            assert_eq!(version(s).to_string(), s);
+        }
+        for s in ["", "1", "1.2", "1.2.3.4", "01.2.3", "1.2.3-", "1.2.3-01", "1.2.3-beta..1", "1.2.3+", "1.2.3+a_b", "v1.2.3"] {
+            assert!(s.parse::<Version>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_lenient_pads_missing_numbers() {
+        assert_eq!(Version::parse_lenient("0.1").unwrap().to_string(), "0.1.0");
+        assert_eq!(Version::parse_lenient("2").unwrap().to_string(), "2.0.0");
+        assert_eq!(Version::parse_lenient("2-rc.1").unwrap().to_string(), "2.0.0-rc.1");
+        assert_eq!(Version::parse_lenient("0.4+git").unwrap().to_string(), "0.4.0+git");
+        assert_eq!(Version::parse_lenient("1.2.3").unwrap().to_string(), "1.2.3");
+        assert!(Version::parse_lenient("0.1.x").is_err());
+        assert!(Version::parse_lenient("").is_err());
+    }
+
+    #[test]
+    fn orders_pre_releases_before_their_release() {
+        // The example from semver.org.
+        let ordered = ["1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2", "1.0.0-beta.11", "1.0.0-rc.1", "1.0.0", "1.0.1-alpha", "1.0.1"];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert!(version("1.10.0") > version("1.9.0"));
+    }
+
+    #[test]
+    fn ignores_build_metadata() {
+        assert_eq!(version("1.0.0+a"), version("1.0.0+b"));
+        assert_eq!(version("1.0.0+a").cmp(&version("1.0.0")), Ordering::Equal);
+        assert!(allows("=1.0.0+a", "1.0.0+b"));
+        assert!(allows("^1.0.0", "1.2.0+build"));
+    }
+
+    #[test]
+    fn caret_requirements() {
+        assert!(allows("1.2.3", "1.2.3"));
+        assert!(allows("^1.2.3", "1.9.0"));
+        assert!(!allows("^1.2.3", "1.2.2"));
+        assert!(!allows("^1.2.3", "2.0.0"));
+        assert!(allows("1.2", "1.10.0"));
+        assert!(allows("0.2", "0.2.7"));
+        assert!(!allows("0.2", "0.3.0"));
+        assert!(!allows("0.2", "0.20.0"));
+        assert!(allows("^0.2.3", "0.2.9"));
+        assert!(!allows("^0.2.3", "0.3.0"));
+        assert!(allows("^0.0.3", "0.0.3"));
+        assert!(!allows("^0.0.3", "0.0.4"));
+        assert!(allows("1", "1.99.0"));
+        assert!(!allows("1", "2.0.0"));
+    }
+
+    #[test]
+    fn tilde_and_other_requirements() {
+        assert!(allows("~1.2.3", "1.2.9"));
+        assert!(!allows("~1.2.3", "1.3.0"));
+        assert!(!allows("~1.2.3", "1.2.2"));
+        assert!(allows("~1.2", "1.2.0"));
+        assert!(!allows("~1.2", "1.3.0"));
+        assert!(allows("~1", "1.5.0"));
+        assert!(allows("=1.2.3", "1.2.3"));
+        assert!(!allows("=1.2.3", "1.2.4"));
+        assert!(allows("1.2.*", "1.2.7"));
+        assert!(!allows("1.2.*", "1.3.0"));
+        assert!(allows(">=1.0, <1.5", "1.4.9"));
+        assert!(!allows(">=1.0, <1.5", "1.5.0"));
+        assert!(allows(">1.2.3", "1.2.4"));
+        assert!(!allows(">1.2.3", "1.2.3"));
+        assert!(allows("<=1.2.3", "1.2.3"));
+        assert!(allows("*", "0.0.1"));
+        for s in ["", "1.2.3.4", "^x", ">=1.0,", "1.2-beta"] {
+            assert!(s.parse::<VersionRequirement>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn pre_releases_only_when_asked_for() {
+        assert!(!allows("*", "1.0.0-beta.1"));
+        assert!(!allows("^1.0.0", "1.1.0-rc.1"));
+        assert!(!allows("^1.0.0", "1.0.0-beta.2"));
+        assert!(allows("^1.0.0-beta.1", "1.0.0-beta.2"));
+        assert!(allows("^1.0.0-beta.1", "1.0.5"));
+        assert!(!allows("^1.0.0-beta.1", "1.0.0-alpha"));
+        assert!(!allows("^1.0.0-beta.1", "1.1.0-rc.1"));
+        assert!(allows("~1.2.3-beta.2", "1.2.3-beta.3"));
+        assert!(!allows("~1.2.3-beta.2", "1.2.3-beta.1"));
+        assert!(allows("~1.2.3-beta.2", "1.2.4"));
+        assert!(!allows("~1.2.3-beta.2", "1.2.4-alpha"));
+        assert!(allows(">=1.0.0-rc.1, <2.0.0", "1.0.0-rc.2"));
+        assert!(allows("=1.0.0-rc.1", "1.0.0-rc.1"));
+    }
+}
@@ -0,0 +1,278 @@
+//! Checking that dependencies are signed by someone we trust.
+//!
+//! Trusted keys are listed in the config files (see [`crate::config`]), GPG keys by fingerprint
+//! (or long key id), SSH keys as the public key itself:
+//! ```toml
+//! [trust]
+//! keys = [
+//!     "3AA5C34371567BD2",
+//!     "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIDd2... kiiyya@example.com",
+//! ]
+//! ```
+//!
+//! Once there are any, every git dependency is checked when it is checked out: the tag of `tag`
+//! dependencies, the commit of all others. Archives are checked against the detached signature
+//! their `signature` url points to when they are downloaded, and what they unpacked to is
+//! recorded (see [`Verified`]), so that changes to it since are caught. A signature which doesn't
+//! match, or which was made by a key not listed, is an error. Unsigned dependencies are fine,
+//! unless [`Trust::require`] says otherwise.
+//!
+//! GPG signatures are checked by `gpg`, so the keys have to be in its keyring, and SSH
+//! signatures by `ssh-keygen`. For git dependencies, `git` calls them.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::descriptor::Descriptor;
+use crate::files;
+use crate::layout;
+
+/// Whose signatures we accept, and whether unsigned dependencies are accepted too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trust {
+    /// GPG fingerprints or long key ids, and SSH public keys.
+    pub keys: Vec<String>,
+    /// Refuse every dependency which isn't signed by one of `keys`, like `--require-signatures`.
+    /// Local dependencies are exempt, there's nothing to sign.
+    pub require: bool,
+}
+
+impl Trust {
+    /// Is there anything to check at all?
+    pub fn checks(&self) -> bool {
+        self.require || !self.keys.is_empty()
+    }
+}
+
+/// What of a git checkout carries the signature.
+#[derive(Clone, Debug)]
+pub enum Signed {
+    Tag(String),
+    Commit(String),
+}
+
+/// Used in allowed signers files, which need one, but we don't care who is who.
+const PRINCIPAL: &str = "lair";
+
+/// Is `object` in the checkout `path` signed by one of `keys`? `Ok(false)` if it isn't signed at
+/// all, `Err` saying what's wrong with the signature otherwise. Blocking.
+pub fn verify_git(path: &Path, object: &Signed, keys: &[String]) -> Result<bool, String> {
+    let (kind, name) = match object {
+        Signed::Tag(tag) => ("tag", tag.as_str()),
+        Signed::Commit(commit) => ("commit", commit.as_str()),
+    };
+    // Lightweight tags aren't tag objects, so they can't be signed.
+    let raw = match run(Command::new("git").arg("-C").arg(path).args(["cat-file", kind, name]), None) {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+        _ => return Ok(false),
+    };
+    let ssh = raw.contains("-----BEGIN SSH SIGNATURE-----");
+    if !ssh && !raw.contains("-----BEGIN PGP SIGNATURE-----") {
+        return Ok(false);
+    }
+
+    let signers = TempFile::new("allowed-signers", allowed_signers(keys).as_bytes())?;
+    let output = run(Command::new("git").arg("-C").arg(path)
+        .arg("-c").arg(format!("gpg.ssh.allowedSignersFile={}", signers.path.display()))
+        .arg(format!("verify-{}", kind)).arg("--raw").arg(name), None)?;
+    // `--raw` puts gpg's status lines on stderr.
+    let status = String::from_utf8_lossy(&output.stderr);
+    if ssh {
+        match output.status.success() {
+            true => Ok(true),
+            false => Err(format!("its SSH signature isn't made by a trusted key, or doesn't match ({})", last_line(&status))),
+        }
+    } else {
+        check_gpg_status(&status, keys).map(|()| true)
+    }
+}
+
+/// Is `signature` a signature of `data` by one of `keys`? Blocking.
+pub fn verify_detached(data: &[u8], signature: &[u8], keys: &[String]) -> Result<(), String> {
+    let data_file = TempFile::new("data", data)?;
+    let signature_file = TempFile::new("signature", signature)?;
+    if String::from_utf8_lossy(signature).contains("-----BEGIN SSH SIGNATURE-----") {
+        let signers = TempFile::new("allowed-signers", allowed_signers(keys).as_bytes())?;
+        let output = run(Command::new("ssh-keygen")
+            .args(["-Y", "verify", "-n", "file", "-I", PRINCIPAL])
+            .arg("-f").arg(&signers.path)
+            .arg("-s").arg(&signature_file.path), Some(data))?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(format!("its SSH signature isn't made by a trusted key, or doesn't match ({})",
+                // `ssh-keygen -Y verify` complains on stdout.
+                last_line(&String::from_utf8_lossy(&[output.stdout, output.stderr].concat())))),
+        }
+    } else {
+        let output = run(Command::new("gpg")
+            .args(["--batch", "--status-fd", "1", "--verify"])
+            .arg(&signature_file.path)
+            .arg(&data_file.path), None)?;
+        check_gpg_status(&String::from_utf8_lossy(&output.stdout), keys)
+    }
+}
+
+/// Look through gpg's `--status-fd` output for a good signature by one of `keys`.
+fn check_gpg_status(status: &str, keys: &[String]) -> Result<(), String> {
+    let fields = |keyword: &str| status.lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] ")?.strip_prefix(keyword)?.strip_prefix(' '))
+        .map(|rest| rest.split(' ').map(str::to_owned).collect::<Vec<_>>());
+
+    if let Some(missing) = fields("NO_PUBKEY") {
+        return Err(format!("gpg doesn't have the key {} it's signed with, `gpg --recv-keys {}` fetches it", missing[0], missing[0]));
+    }
+    if fields("BADSIG").is_some() {
+        return Err("its GPG signature doesn't match".to_owned());
+    }
+    // `VALIDSIG <fingerprint> <date> ... <primary key fingerprint>`, the primary key being
+    // the one people usually know.
+    let Some(valid) = fields("VALIDSIG") else {
+        return Err(format!("its GPG signature couldn't be checked ({})", last_line(status)));
+    };
+    let fingerprints = [valid.first(), valid.last()];
+    let trusted = keys.iter()
+        .filter(|key| !is_ssh_key(key))
+        .map(|key| key.replace(' ', "").trim_start_matches("0x").to_uppercase())
+        .any(|key| key.len() >= 16 && fingerprints.iter().flatten().any(|fingerprint| fingerprint.to_uppercase().ends_with(&key)));
+    match trusted {
+        true => Ok(()),
+        false => Err(format!("it's signed by the GPG key {}, which isn't trusted", valid.last().map_or("", String::as_str))),
+    }
+}
+
+/// The gist of what `ssh-keygen` or `gpg` complained about, for example `No principal matched.`
+fn last_line(output: &str) -> &str {
+    output.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or_default().trim_end_matches('.')
+}
+
+/// Is `key` something [`Trust::keys`] can hold? Says what's wrong with it otherwise.
+pub fn check_key(key: &str) -> Result<(), String> {
+    if is_ssh_key(key) {
+        return match key.split_whitespace().count() {
+            1 => Err(format!("`{}` is only the type of an SSH key, the key itself has to follow it", key)),
+            _ => Ok(()),
+        };
+    }
+    let hex = key.replace(' ', "");
+    let hex = hex.trim_start_matches("0x");
+    match hex.len() >= 16 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => Ok(()),
+        false => Err(format!("`{}` isn't a GPG fingerprint, long key id (16 or more hex digits) or SSH public key", key)),
+    }
+}
+
+fn is_ssh_key(key: &str) -> bool {
+    key.starts_with("ssh-") || key.starts_with("ecdsa-") || key.starts_with("sk-")
+}
+
+/// An allowed signers file (see `ssh-keygen(1)`) of the SSH keys among `keys`.
+fn allowed_signers(keys: &[String]) -> String {
+    keys.iter()
+        .filter(|key| is_ssh_key(key))
+        .map(|key| format!("{} {}\n", PRINCIPAL, key))
+        .collect()
+}
+
+/// Run `command`, feeding it `stdin`, and wait for it.
+fn run(command: &mut Command, stdin: Option<&[u8]>) -> Result<std::process::Output, String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("`{}` is needed to check its signature, but couldn't be run: {}", program, e))?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // Whatever went wrong shows up in the exit status.
+        let _ = pipe.write_all(input);
+    }
+    child.wait_with_output().map_err(|e| format!("`{}` failed: {}", program, e))
+}
+
+/// A file in a directory of its own in the temp directory, see [`files::temp_dir`], deleted
+/// once dropped.
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn new(name: &str, contents: &[u8]) -> Result<Self, String> {
+        let dir = files::temp_dir(&format!("lair-{}-", std::process::id()))
+            .map_err(|e| format!("Failed to create a temporary directory: {}", e))?;
+        let path = dir.join(name);
+        std::fs::OpenOptions::new().write(true).create_new(true).open(&path)
+            .and_then(|mut file| file.write_all(contents))
+            .map_err(|e| format!("Failed to write `{}`: {}", path.display(), e))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(dir) = self.path.parent() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// What a signed archive unpacked to, recorded once its signature checked out, so that it can
+/// be trusted on later runs without downloading it again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Verified {
+    /// See [`tree_digest`].
+    pub digest: String,
+    /// The keys it was checked against.
+    pub keys: Vec<String>,
+}
+
+impl Verified {
+    /// For example `build/.lair/verified/NotJson-3f2a9c01.json`.
+    fn path(desc: &Descriptor) -> PathBuf {
+        layout::lair_dir().join("verified").join(format!("{}-{}.json", desc.name(), layout::short_hash(desc)))
+    }
+
+    pub fn load(desc: &Descriptor) -> Option<Self> {
+        serde_json::from_slice(&std::fs::read(Self::path(desc)).ok()?).ok()
+    }
+
+    pub fn save(&self, desc: &Descriptor) -> std::io::Result<()> {
+        let path = Self::path(desc);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec(self).expect("Records are always serializable."))
+    }
+}
+
+/// Hash of the paths and contents of every file below `dir`, except in its `build` directory,
+/// which is Lair's. Symlinks count by where they point. Blocking.
+pub fn tree_digest(dir: &Path) -> std::io::Result<String> {
+    fn walk(hasher: &mut Sha256, root: &Path, dir: &Path) -> std::io::Result<()> {
+        let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            if dir == root && entry.file_name() == "build" {
+                continue;
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                hasher.update(format!("link {}\0{}\n", relative, std::fs::read_link(&path)?.display()).as_bytes());
+            } else if file_type.is_dir() {
+                walk(hasher, root, &path)?;
+            } else {
+                hasher.update(format!("file {}\0", relative).as_bytes());
+                hasher.update(Sha256::digest(std::fs::read(&path)?));
+            }
+        }
+        Ok(())
+    }
+    let mut hasher = Sha256::new();
+    walk(&mut hasher, dir, dir)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
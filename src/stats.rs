@@ -0,0 +1,228 @@
+//! `lair stats`: how long commands took and how much of the build Lair could skip, recorded on
+//! this machine only, and only with `[stats] record = true` in a config file (see
+//! [`crate::config`]).
+//!
+//! Each run of a command adds how long it took, whether it succeeded and how many packages were
+//! built, up to date, from the remote cache or downloaded to `stats.json` in
+//! [`layout::user_data_dir`], keeping the newest [`MAX_RUNS`]. Nothing ever leaves the file.
+//! That's not in the cache directory, so `lair clean --cache` doesn't lose the history.
+//! Two runs finishing at the same moment may lose one of them, which doesn't matter for trends.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::layout;
+use crate::timestamp;
+
+/// Older runs are dropped.
+pub const MAX_RUNS: usize = 1000;
+
+/// How many of the newest runs of a command `lair stats` compares with all of them.
+const RECENT: usize = 10;
+
+/// Packages of one or more [`crate::Lair`]s, as in [`crate::tracing::RunOutcome`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counts {
+    pub fetched: u64,
+    pub built: u64,
+    pub cached: u64,
+    pub remote: u64,
+    pub failed: u64,
+    /// How long idris2 took for the `built` packages, in milliseconds.
+    pub build_millis: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.fetched += other.fetched;
+        self.built += other.built;
+        self.cached += other.cached;
+        self.remote += other.remote;
+        self.failed += other.failed;
+        self.build_millis += other.build_millis;
+    }
+
+    /// Packages built or reused.
+    fn needed(&self) -> u64 {
+        self.built + self.cached + self.remote
+    }
+}
+
+/// One run of a command.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Run {
+    /// For example `build`.
+    pub command: String,
+    /// Seconds since 1970-01-01, when it finished.
+    pub at: u64,
+    pub millis: u64,
+    pub success: bool,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub runs: Vec<Run>,
+}
+
+impl Stats {
+    /// What was recorded so far, or nothing if the file is missing or unreadable.
+    pub fn load() -> Self {
+        std::fs::read(path()).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let path = path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self).expect("Stats are always serializable."))
+    }
+}
+
+/// Collects the counts of a command's [`crate::Lair`]s, see [`crate::Lair::with_usage`], to
+/// record them once the command is done.
+pub struct Usage {
+    command: String,
+    enabled: AtomicBool,
+    sources: Mutex<Vec<Box<dyn Fn() -> Counts + Send + Sync>>>,
+}
+
+impl std::fmt::Debug for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Usage").field("command", &self.command).field("enabled", &self.enabled).finish_non_exhaustive()
+    }
+}
+
+impl Usage {
+    pub fn new(command: &str) -> Self {
+        Self { command: command.to_owned(), enabled: AtomicBool::new(false), sources: Mutex::new(Vec::new()) }
+    }
+
+    /// Record this run in [`Usage::save`]. Off until called, since the config files have to be
+    /// read first.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Add what `counts` says at the end.
+    pub fn watch(&self, counts: impl Fn() -> Counts + Send + Sync + 'static) {
+        self.sources.lock().unwrap().push(Box::new(counts));
+    }
+
+    /// Append this run to `stats.json`, if [enabled](Usage::enable). Blocking.
+    pub fn save(&self, took: Duration, success: bool) -> std::io::Result<()> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut counts = Counts::default();
+        for source in self.sources.lock().unwrap().iter() {
+            counts.add(source());
+        }
+        let mut stats = Stats::load();
+        stats.runs.push(Run {
+            command: self.command.clone(),
+            at: timestamp::now(),
+            millis: took.as_millis().try_into().unwrap_or(u64::MAX),
+            success,
+            counts,
+        });
+        let excess = stats.runs.len().saturating_sub(MAX_RUNS);
+        stats.runs.drain(..excess);
+        stats.save()
+    }
+}
+
+/// `stats.json` in [`layout::user_data_dir`], usually `~/.lair/stats.json`.
+pub fn path() -> PathBuf {
+    layout::user_data_dir().join("stats.json")
+}
+
+/// What `lair stats` shows: per command how long it takes, on average and recently, and how many
+/// packages it got to reuse.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    since: u64,
+    commands: BTreeMap<String, Vec<Run>>,
+    total: Counts,
+}
+
+impl Summary {
+    pub fn new(stats: Stats) -> Option<Self> {
+        let since = stats.runs.iter().map(|run| run.at).min()?;
+        let mut commands: BTreeMap<String, Vec<Run>> = BTreeMap::new();
+        let mut total = Counts::default();
+        for run in stats.runs {
+            total.add(run.counts);
+            commands.entry(run.command.clone()).or_default().push(run);
+        }
+        Some(Self { since, commands, total })
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let runs: usize = self.commands.values().map(Vec::len).sum();
+        writeln!(f, "{} runs since {}:\n", runs, &timestamp::iso8601(self.since)[..10])?;
+        let width = self.commands.keys().map(String::len).max().unwrap_or(0).max("Command".len());
+        writeln!(f, "{:<width$}  {:>5}  {:>9}  {:>9}  {:>7}  {:>7}", "Command", "Runs", "Average", "Last 10", "Reused", "Last 10")?;
+        for (command, runs) in &self.commands {
+            let recent = &runs[runs.len().saturating_sub(RECENT)..];
+            writeln!(f, "{:<width$}  {:>5}  {:>9}  {:>9}  {:>7}  {:>7}", command, runs.len(),
+                format_duration(average(runs)), format_duration(average(recent)), reused(runs), reused(recent))?;
+        }
+
+        let total = &self.total;
+        if total.needed() == 0 {
+            return Ok(());
+        }
+        let percent = |count: u64| count * 100 / total.needed();
+        write!(f, "\nOf {} packages needed, {} were up to date ({}%)", total.needed(), total.cached, percent(total.cached))?;
+        if total.remote > 0 {
+            write!(f, ", {} came from the remote cache ({}%)", total.remote, percent(total.remote))?;
+        }
+        write!(f, " and {} were built.", total.built)?;
+        if let Some(per_build) = total.build_millis.checked_div(total.built) {
+            let saved = per_build * (total.cached + total.remote);
+            write!(f, " Building one took {} on average, so reusing the others saved about {}.",
+                format_duration(Duration::from_millis(per_build)), format_duration(Duration::from_millis(saved)))?;
+        }
+        writeln!(f)
+    }
+}
+
+fn average(runs: &[Run]) -> Duration {
+    Duration::from_millis(runs.iter().map(|run| run.millis).sum::<u64>() / runs.len().max(1) as u64)
+}
+
+/// Share of the packages needed which didn't have to be built, for example `87%`, or `-` if
+/// none were needed.
+fn reused(runs: &[Run]) -> String {
+    let mut counts = Counts::default();
+    for run in runs {
+        counts.add(run.counts);
+    }
+    match counts.needed() {
+        0 => "-".to_owned(),
+        needed => format!("{}%", (counts.cached + counts.remote) * 100 / needed),
+    }
+}
+
+/// For example `0.35s`, `4m 12s` or `2h 5m`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{:.2}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
@@ -0,0 +1,128 @@
+//! `lair status`: where each package stands, without building anything.
+//!
+//! For every package reachable from the root: whether its sources are there, which commit the
+//! lockfile wants and which one is checked out, and whether its TTC files are up to date. Like
+//! `lair verify`, this only reads what is on disk, and never goes over the network. Packages this
+//! process is already fetching or building, as far as the nodes' [`Lazy`](crate::lazy::Lazy)s
+//! tell, are reported as such instead.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use crate::descriptor::Descriptor;
+use crate::fetch;
+use crate::layout;
+use crate::lazy::Progress;
+use crate::lockfile::Lockfile;
+use crate::verify::{self, StaleReason};
+
+#[derive(Clone, Debug)]
+pub struct Status {
+    pub descriptor: Descriptor,
+    pub source: Source,
+    pub commit: Commit,
+    pub ttc: Ttc,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Present(PathBuf),
+    Missing(PathBuf),
+    /// Being downloaded by this process right now.
+    Fetching,
+}
+
+/// For git and registry dependencies, the locked commit and the checked out one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Commit {
+    /// Not a git or registry dependency.
+    None,
+    /// The lockfile has no entry yet.
+    Unlocked { checked_out: Option<String> },
+    Locked { locked: String, checked_out: Option<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ttc {
+    Fresh,
+    Stale(StaleReason),
+    /// Being built by this process right now.
+    Building,
+    /// Can't tell, since it or one of its dependencies isn't downloaded.
+    Unknown,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Present(_) => write!(f, "present"),
+            Source::Missing(_) => write!(f, "missing"),
+            Source::Fetching => write!(f, "fetching"),
+        }
+    }
+}
+
+impl Display for Ttc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ttc::Fresh => write!(f, "fresh"),
+            Ttc::Stale(StaleReason::NeverBuilt) => write!(f, "never built"),
+            Ttc::Stale(StaleReason::SourcesChanged) => write!(f, "stale, sources changed"),
+            Ttc::Stale(StaleReason::DependenciesChanged) => write!(f, "stale, dependencies changed"),
+            Ttc::Stale(StaleReason::TtcMissing) => write!(f, "missing"),
+            Ttc::Building => write!(f, "building"),
+            Ttc::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The status of each of `packages` (see [`verify::check`] for the other arguments), sorted by
+/// descriptor. `progress` has how far fetching and building got in this process, for the
+/// packages it already has nodes for. Blocking.
+pub fn status(
+    packages: &[verify::Package],
+    progress: &BTreeMap<Descriptor, (Progress, Progress)>,
+    lockfile: &Lockfile,
+    extra: &str,
+    toolchain: &str,
+) -> Vec<Status> {
+    let by_descriptor: BTreeMap<&Descriptor, &verify::Package> = packages.iter().map(|package| (&package.descriptor, package)).collect();
+    let mut fingerprints = BTreeMap::new();
+    packages.iter()
+        .map(|package| {
+            let desc = &package.descriptor;
+            let (fetching, building) = progress.get(desc)
+                .map_or((false, false), |(source, ttc)| (*source == Progress::Working, *ttc == Progress::Working));
+
+            let source = match package.dependencies {
+                _ if fetching => Source::Fetching,
+                Some(_) => Source::Present(package.base_path.clone()),
+                None => Source::Missing(package.base_path.clone()),
+            };
+            let checked_out = || package.dependencies.as_ref()
+                .and_then(|_| fetch::head_commit(&layout::source_dir(desc)).ok());
+            let commit = match desc {
+                Descriptor::Git { .. } | Descriptor::Registry { .. } => match lockfile.locked(desc) {
+                    Some(locked) => Commit::Locked { locked: locked.commit.clone(), checked_out: checked_out() },
+                    None => Commit::Unlocked { checked_out: checked_out() },
+                },
+                Descriptor::Archive { .. } | Descriptor::Local { .. } | Descriptor::Root { .. } => Commit::None,
+            };
+            let ttc = match verify::fingerprint(desc, &by_descriptor, extra, &mut fingerprints) {
+                _ if building => Ttc::Building,
+                Some(Ok(fingerprint)) => match verify::stale_reason(package, &fingerprint, toolchain) {
+                    Some(reason) => Ttc::Stale(reason),
+                    None => Ttc::Fresh,
+                },
+                Some(Err(_)) | None => Ttc::Unknown,
+            };
+            Status { descriptor: desc.clone(), source, commit, ttc }
+        })
+        .collect()
+}
+
+/// The first few characters of `commit`, enough to tell commits apart.
+pub fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
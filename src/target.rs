@@ -0,0 +1,193 @@
+//! Dependencies which only apply to some targets, for example JavaScript shims only the node
+//! backend needs, or FFI wrappers for one operating system:
+//!
+//! ```toml
+//! [target.'cfg(backend = "node")'.dependencies]
+//! NodeShims = { git = "https://github.com/Kiiyya/NodeShims" }
+//!
+//! [target.'cfg(all(windows, not(backend = "node")))'.dependencies]
+//! Win32 = { git = "https://github.com/Kiiyya/Win32" }
+//! ```
+//!
+//! Conditions are written like Cargo's, with these keys:
+//! - `backend`: the code generator the package is built for, one of chez, racket, node or refc.
+//!   Chez, unless `lair bundle --backend` says otherwise.
+//! - `os`: the operating system Lair runs on, for example `linux`, `macos` or `windows`.
+//! - `family`: `unix` or `windows`. Plain `unix` and `windows` are short for this.
+//!
+//! Dependencies whose condition doesn't hold are neither downloaded nor built.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compiler::Backend;
+
+/// What conditions are evaluated against.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    pub backend: Backend,
+    /// See [`std::env::consts::OS`].
+    pub os: String,
+    /// See [`std::env::consts::FAMILY`].
+    pub family: String,
+}
+
+impl Target {
+    /// Building for `backend`, on this computer.
+    pub fn host(backend: Backend) -> Self {
+        Self {
+            backend,
+            os: std::env::consts::OS.to_owned(),
+            family: std::env::consts::FAMILY.to_owned(),
+        }
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::host(Backend::Chez)
+    }
+}
+
+/// A condition, for example `all(unix, backend = "node")`, without the surrounding `cfg(...)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cfg {
+    /// `key = "value"`, or a bare `unix` or `windows`.
+    Equals(String, String),
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>),
+}
+
+const KEYS: &[&str] = &["backend", "os", "family"];
+
+impl Cfg {
+    pub fn matches(&self, target: &Target) -> bool {
+        match self {
+            Cfg::Equals(key, value) => match key.as_str() {
+                "backend" => target.backend.cg() == value,
+                "os" => &target.os == value,
+                "family" => &target.family == value,
+                _ => false,
+            },
+            Cfg::All(cfgs) => cfgs.iter().all(|cfg| cfg.matches(target)),
+            Cfg::Any(cfgs) => cfgs.iter().any(|cfg| cfg.matches(target)),
+            Cfg::Not(cfg) => !cfg.matches(target),
+        }
+    }
+}
+
+/// Parses `cfg(...)`.
+impl FromStr for Cfg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { rest: s };
+        let cfg = match parser.ident()? {
+            "cfg" => {
+                parser.expect('(')?;
+                let cfg = parser.cfg()?;
+                parser.expect(')')?;
+                cfg
+            },
+            other => return Err(format!("Expected `cfg(...)`, not `{}`", other)),
+        };
+        if !parser.rest.trim().is_empty() {
+            return Err(format!("Unexpected `{}` after the condition", parser.rest.trim()));
+        }
+        Ok(cfg)
+    }
+}
+
+impl Display for Cfg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |cfgs: &[Cfg]| cfgs.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+        match self {
+            Cfg::Equals(key, value) => write!(f, "{} = \"{}\"", key, value),
+            Cfg::All(cfgs) => write!(f, "all({})", list(cfgs)),
+            Cfg::Any(cfgs) => write!(f, "any({})", list(cfgs)),
+            Cfg::Not(cfg) => write!(f, "not({})", cfg),
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn cfg(&mut self) -> Result<Cfg, String> {
+        let ident = self.ident()?;
+        match ident {
+            "all" | "any" | "not" => {
+                self.expect('(')?;
+                let mut cfgs = Vec::new();
+                while !self.peek(')') {
+                    cfgs.push(self.cfg()?);
+                    if !self.peek(')') {
+                        self.expect(',')?;
+                    }
+                }
+                self.expect(')')?;
+                match ident {
+                    "all" => Ok(Cfg::All(cfgs)),
+                    "any" => Ok(Cfg::Any(cfgs)),
+                    _ => match <[Cfg; 1]>::try_from(cfgs) {
+                        Ok([cfg]) => Ok(Cfg::Not(Box::new(cfg))),
+                        Err(_) => Err("`not(...)` takes exactly one condition".to_owned()),
+                    },
+                }
+            },
+            "unix" | "windows" if !self.peek('=') => Ok(Cfg::Equals("family".to_owned(), ident.to_owned())),
+            key if KEYS.contains(&key) => {
+                self.expect('=')?;
+                let value = self.string()?;
+                if key == "backend" {
+                    Backend::from_str(&value)?;
+                }
+                Ok(Cfg::Equals(key.to_owned(), value))
+            },
+            other => Err(format!("Unknown condition `{}`, expected one of {}, `unix`, `windows`, `all`, `any` or `not`",
+                other, KEYS.iter().map(|key| format!("`{}`", key)).collect::<Vec<_>>().join(", "))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn peek(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        self.rest.starts_with(c)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek(c) {
+            self.rest = &self.rest[c.len_utf8()..];
+            Ok(())
+        } else {
+            Err(format!("Expected `{}` at `{}`", c, self.rest))
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, String> {
+        self.skip_whitespace();
+        let end = self.rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(format!("Expected a name at `{}`", self.rest));
+        }
+        let (ident, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(ident)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let end = self.rest.find('"').ok_or("Missing closing `\"`")?;
+        let (value, rest) = self.rest.split_at(end);
+        self.rest = &rest[1..];
+        Ok(value.to_owned())
+    }
+}
@@ -0,0 +1,74 @@
+//! How long each package took to build last time, so that the [scheduler](crate::scheduler) can
+//! start the longest chains of builds first.
+//!
+//! Kept in `build/.lair/timings.json`, by package name and [`layout::short_hash`] like the
+//! fingerprints. Up-to-date packages and those coming from the remote cache aren't timed, only
+//! actual builds.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::descriptor::Descriptor;
+use crate::layout;
+
+/// What a build is guessed to take when no build has been timed at all.
+const UNKNOWN: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timings {
+    /// Milliseconds of the last successful build.
+    builds: BTreeMap<String, u64>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Timings {
+    /// What was recorded so far, or nothing if the file is missing or unreadable, since it's only a
+    /// hint.
+    pub fn load() -> Self {
+        std::fs::read(path()).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the timings back, if anything was recorded.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self).expect("Timings are always serializable."))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn record(&mut self, desc: &Descriptor, took: Duration) {
+        self.builds.insert(key(desc), took.as_millis().try_into().unwrap_or(u64::MAX));
+        self.dirty = true;
+    }
+
+    /// How long building `desc` probably takes: as long as last time, or if it was never timed, as
+    /// long as the average package.
+    pub fn estimate(&self, desc: &Descriptor) -> Duration {
+        match self.builds.get(&key(desc)) {
+            Some(&millis) => Duration::from_millis(millis),
+            None if self.builds.is_empty() => UNKNOWN,
+            None => Duration::from_millis(self.builds.values().sum::<u64>() / self.builds.len() as u64),
+        }
+    }
+}
+
+fn key(desc: &Descriptor) -> String {
+    format!("{}-{}", desc.name(), layout::short_hash(desc))
+}
+
+/// `build/.lair/timings.json`.
+fn path() -> PathBuf {
+    layout::lair_dir().join("timings.json")
+}
@@ -1,9 +1,13 @@
 //! Tracking the fetch and build progress, for pretty output, progress bars, analytics, anything.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::descriptor::Descriptor;
+use crate::graph::Graph;
 use crate::manifest::Manifest;
+use crate::scheduler::Task;
+use crate::updates::Update;
 
 pub trait ManifestProgress: Send + Sync + 'static {
     type Tr: Tracer;
@@ -16,6 +20,9 @@ pub trait ManifestProgress: Send + Sync + 'static {
 pub enum SourceProgressMethod<'a> {
     AlreadyDownloaded,
     Git { url: &'a str },
+    Archive { url: &'a str },
+    /// Nothing to fetch, the package is a directory on this computer.
+    Local { path: &'a Path },
 }
 
 pub trait SourceProgress: Send + Sync + 'static {
@@ -23,6 +30,10 @@ pub trait SourceProgress: Send + Sync + 'static {
 
     fn start<'a>(tr: &Self::Tr, desc: &Descriptor, method: SourceProgressMethod<'a>) -> Self;
 
+    /// Download progress of a git clone: `received` of `total` objects, `bytes` so far.
+    /// Called often, so don't do anything expensive here.
+    fn progress(&self, _received: usize, _total: usize, _bytes: u64) { }
+
     fn success(self, _source_path: &Path) where Self: Sized { }
 }
 
@@ -33,9 +44,31 @@ pub trait BuildProgress: Send + Sync + 'static {
 
     fn command(&self, _command: &str) { }
 
+    /// A line the build command printed, on stdout or stderr, as soon as it did.
+    fn output_line(&self, _line: &str) { }
+
     fn success(self, _ttc_path: &Path) where Self: Sized { }
 }
 
+/// How a whole run went, see [`Tracer::run_finished`].
+#[derive(Clone, Debug)]
+pub struct RunOutcome {
+    /// Packages downloaded during this run.
+    pub fetched: usize,
+    /// Packages idris2 was run on successfully.
+    pub built: usize,
+    /// Packages whose TTC files were reused, since nothing changed since their last build.
+    pub cached: usize,
+    /// Packages whose TTC files were downloaded from the remote cache.
+    pub remote: usize,
+    /// Packages which failed to download or build. Doesn't count their dependents.
+    pub failed: usize,
+    pub duration: Duration,
+    pub success: bool,
+    /// Where the output of each build is.
+    pub logs_dir: PathBuf,
+}
+
 pub trait Tracer: Send + Sync + 'static {
     type Manifest: ManifestProgress<Tr = Self>;
     type Source: SourceProgress<Tr = Self>;
@@ -44,6 +77,10 @@ pub trait Tracer: Send + Sync + 'static {
     /// Exploring the dependency tree, we have found a new dependency.
     fn new_descriptor(&self, _desc: &Descriptor) {}
 
+    /// We know every package of the run now, either from reading their manifests or from the
+    /// snapshot. Downloads may already have started, builds haven't.
+    fn graph_resolved(&self, _graph: &Graph) {}
+
     fn fetching_manifest(&self, desc: &Descriptor) -> Self::Manifest {
         Self::Manifest::start(self, desc)
     }
@@ -55,6 +92,56 @@ pub trait Tracer: Send + Sync + 'static {
     fn building(&self, desc: &Descriptor) -> Self::Build {
         Self::Build::start(self, desc)
     }
+
+    /// Nothing changed since `desc` was last built, so we're reusing the TTC files in `ttc_path`.
+    fn build_fresh(&self, _desc: &Descriptor, _ttc_path: &Path) {}
+
+    /// Instead of building `desc`, we downloaded its TTC files from the remote cache into
+    /// `ttc_path`.
+    fn remote_cache_hit(&self, _desc: &Descriptor, _ttc_path: &Path) {}
+
+    /// The remote cache couldn't be reached while downloading or uploading `desc`'s TTC files.
+    /// Merely slows things down.
+    fn remote_cache_failed(&self, _desc: &Descriptor, _error: &str) {}
+
+    /// `desc` couldn't be built on `worker` (see [`crate::remote_exec`]), not because idris2
+    /// failed there, so it is built here instead.
+    fn remote_exec_failed(&self, _desc: &Descriptor, _worker: &str, _error: &str) {}
+
+    /// Downloading `desc` failed in a way which might not happen again (see
+    /// [`crate::error::SourceFetchError::is_transient`]), so it is downloaded again.
+    fn retrying_fetch(&self, _desc: &Descriptor, _error: &str) {}
+
+    /// There's a newer Lair or idris2 than the one installed, see [`crate::updates`].
+    fn update_available(&self, _update: &Update) {}
+
+    /// The lockfile pins registry dependency `desc` to `version`, which has been yanked from the
+    /// registry since. We use it anyway, only new resolutions skip yanked versions.
+    fn yanked(&self, _desc: &Descriptor, _version: &str) {}
+
+    /// `desc`'s checkout in `path` has local changes to `files`, which are built as they are. See
+    /// [`crate::fetch::DirtyDeps`].
+    fn dirty_dependency(&self, _desc: &Descriptor, _path: &Path, _files: &[PathBuf]) {}
+
+    /// `desc`'s manifest has no `source-dir`, and there is no `src` directory, so we guessed that
+    /// its modules are in `source_dir` instead, relative to the package. See
+    /// [`crate::modules::guess_source_dir`].
+    fn guessed_source_dir(&self, _desc: &Descriptor, _source_dir: &Path) {}
+
+    /// The scheduler started `task`, everything it waits for being done. See [`crate::scheduler`].
+    fn task_started(&self, _task: &Task) {}
+
+    /// `task` is done, successfully or not.
+    fn task_finished(&self, _task: &Task, _success: bool) {}
+
+    /// About to run the root package's script `name`, see [`crate::scripts`].
+    fn script_started(&self, _name: &str, _script: &str) {}
+
+    /// We're about to build `root` and everything it depends on.
+    fn run_started(&self, _root: &Descriptor) {}
+
+    /// Done building, successfully or not.
+    fn run_finished(&self, _outcome: &RunOutcome) {}
 }
 
 pub mod no_tracing {
@@ -99,53 +186,993 @@ pub mod no_tracing {
     }
 }
 
+/// How much the human-facing tracers should print. Set via `-q`, `-v` and `-vv`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Verbosity {
+    /// Only errors.
+    Quiet,
+    /// Downloads and builds.
+    #[default]
+    Normal,
+    /// Also the commands we run, and packages which were already downloaded.
+    Verbose,
+    /// Everything we know about, including manifest reads and newly discovered dependencies.
+    VeryVerbose,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::VeryVerbose,
+        }
+    }
+}
+
+/// Keeping apart what packages fetched or built at the same time print, so that their output
+/// doesn't end up interleaved line by line.
+///
+/// Each package gets a [`Scope`], which either holds back everything until the package is done
+/// and then prints it in one go, or prints it right away, but marked with the package's name.
+/// Which one is up to the tracer, see [`Grouping`].
+pub mod scope {
+    use std::io::{IsTerminal, Write};
+    use std::sync::Mutex;
+
+    /// Where human-facing output goes. Stdout, unless stdout is taken by something meant for
+    /// other programs, like `lair metadata`'s JSON.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum Stream {
+        #[default]
+        Stdout,
+        Stderr,
+    }
+
+    impl Stream {
+        /// Locked, so that nothing else gets in between until it's dropped.
+        pub fn lock(self) -> Box<dyn Write> {
+            match self {
+                Stream::Stdout => Box::new(std::io::stdout().lock()),
+                Stream::Stderr => Box::new(std::io::stderr().lock()),
+            }
+        }
+
+        pub fn println(self, line: impl AsRef<str>) {
+            let _ = writeln!(self.lock(), "{}", line.as_ref());
+        }
+
+        pub fn is_terminal(self) -> bool {
+            match self {
+                Stream::Stdout => std::io::stdout().is_terminal(),
+                Stream::Stderr => std::io::stderr().is_terminal(),
+            }
+        }
+    }
+
+    /// Names longer than this are shortened in prefixes, so that one long name doesn't push
+    /// everyone's output to the right.
+    pub const MAX_NAME_WIDTH: usize = 24;
+
+    /// `name`, shortened to `width` characters with a `…` at the end if it's longer. `width`
+    /// should be at most [`MAX_NAME_WIDTH`].
+    pub fn shorten(name: &str, width: usize) -> String {
+        if width == 0 || name.chars().count() <= width {
+            return name.to_owned();
+        }
+        name.chars().take(width - 1).chain(std::iter::once('…')).collect()
+    }
+
+    /// How a [`Scope`] gets its lines onto its [`Stream`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Grouping {
+        /// Print every line as it comes, as is. Only fine if one thing happens at a time.
+        Immediate,
+        /// Print output lines as they come, each prefixed with the package's name, so that every
+        /// package's output forms its own region even when interleaved. The names are
+        /// right-aligned to the [width](Scope::with_width) of the longest one.
+        Prefixed,
+        /// Print nothing until the package is done, then everything at once. If `collapsible`,
+        /// as a collapsible group in GitHub Actions' `::group::` syntax.
+        Buffered { collapsible: bool },
+    }
+
+    /// Everything one package prints, see [`Grouping`]. Buffered lines are printed when it is
+    /// dropped, so also when the package failed.
+    #[derive(Debug)]
+    pub struct Scope {
+        grouping: Grouping,
+        stream: Stream,
+        name: String,
+        /// What [`Grouping::Prefixed`] aligns the name to, 0 for not at all.
+        width: usize,
+        /// Headline of the collapsible group, if any.
+        title: String,
+        buffer: Mutex<Vec<String>>,
+    }
+
+    impl Scope {
+        pub fn new(grouping: Grouping, stream: Stream, name: impl Into<String>, title: impl Into<String>) -> Self {
+            Self {
+                grouping,
+                stream,
+                name: name.into(),
+                width: 0,
+                title: title.into(),
+                buffer: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// Align prefixes to `width` characters, shortening longer names, see [`shorten`]. Usually
+        /// the longest name of all packages of the run, at most [`MAX_NAME_WIDTH`].
+        pub fn with_width(mut self, width: usize) -> Self {
+            self.width = width;
+            self
+        }
+
+        /// A line of our own about the package, which already says which package it is about.
+        pub fn status(&self, line: impl Into<String>) {
+            match self.grouping {
+                Grouping::Immediate | Grouping::Prefixed => self.stream.println(line.into()),
+                Grouping::Buffered { .. } => self.buffer.lock().unwrap().push(line.into()),
+            }
+        }
+
+        /// A line some command printed about the package, for example idris2.
+        pub fn output(&self, line: impl Into<String>) {
+            match self.grouping {
+                Grouping::Immediate => self.stream.println(line.into()),
+                Grouping::Prefixed => self.stream.println(format!("{:>width$} | {}",
+                    shorten(&self.name, self.width), line.into(), width = self.width)),
+                Grouping::Buffered { .. } => self.buffer.lock().unwrap().push(line.into()),
+            }
+        }
+    }
+
+    impl Drop for Scope {
+        fn drop(&mut self) {
+            let Grouping::Buffered { collapsible } = self.grouping else {
+                return;
+            };
+            let buffer = self.buffer.get_mut().unwrap();
+            if buffer.is_empty() && !collapsible {
+                return;
+            }
+            // One lock for all of it, so nothing else gets in between.
+            let mut out = self.stream.lock();
+            if collapsible {
+                let _ = writeln!(out, "::group::{}", self.title);
+            }
+            for line in buffer.iter() {
+                let _ = writeln!(out, "{}", line);
+            }
+            if collapsible {
+                let _ = writeln!(out, "::endgroup::");
+            }
+        }
+    }
+}
+
 pub mod simple {
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
     use crate::descriptor::Descriptor;
+    use crate::graph::Graph;
+    use crate::manifest::Manifest;
+    use crate::scheduler::Task;
+    use crate::updates::Update;
+
+    use super::scope::{self, Grouping, Scope, Stream};
+    use super::{Tracer, BuildProgress, SourceProgressMethod, SourceProgress, ManifestProgress, RunOutcome, Verbosity};
+
+    /// Width of the right-aligned status column, like cargo's `   Compiling`.
+    const STATUS_WIDTH: usize = 12;
+
+    #[derive(Clone, Copy, Debug)]
+    enum Color {
+        Green,
+        Yellow,
+        Red,
+        Cyan,
+        Dimmed,
+    }
+
+    impl Color {
+        fn ansi(self) -> &'static str {
+            match self {
+                Color::Green => "\x1b[1;32m",
+                Color::Yellow => "\x1b[1;33m",
+                Color::Red => "\x1b[1;31m",
+                Color::Cyan => "\x1b[1;36m",
+                Color::Dimmed => "\x1b[2m",
+            }
+        }
+    }
+
+    /// How output is laid out.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum OutputMode {
+        /// For humans at a terminal: colors, and progress redrawn in place.
+        Interactive,
+        /// One line per event, each prefixed with the time since we started, for logs. Everything
+        /// about building one package is printed in one go, so that packages built in parallel
+        /// don't end up interleaved.
+        Plain,
+        /// Like [`OutputMode::Plain`], but everything about building one package is printed in
+        /// a collapsible group (GitHub Actions' `::group::` syntax).
+        Ci,
+    }
 
-    use super::{Tracer, BuildProgress, SourceProgressMethod, SourceProgress};
-    use super::no_tracing::Ignore;
+    impl OutputMode {
+        /// [`OutputMode::Interactive`] if `stream` is a terminal, otherwise [`OutputMode::Plain`].
+        pub fn detect(stream: Stream) -> Self {
+            if stream.is_terminal() {
+                OutputMode::Interactive
+            } else {
+                OutputMode::Plain
+            }
+        }
+
+        /// How to keep packages built in parallel apart, unless told otherwise: marking the
+        /// lines with the package name for humans watching, whole packages at a time for logs.
+        pub fn grouping(self) -> Grouping {
+            match self {
+                OutputMode::Interactive => Grouping::Prefixed,
+                OutputMode::Plain => Grouping::Buffered { collapsible: false },
+                OutputMode::Ci => Grouping::Buffered { collapsible: true },
+            }
+        }
+    }
+
+    /// The configuration bits every progress guard needs, small enough to copy around.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Printer {
+        verbosity: Verbosity,
+        mode: OutputMode,
+        stream: Stream,
+        color: bool,
+        started: Instant,
+    }
+
+    impl Printer {
+        /// Print `{status} {message}`, with the status right-aligned, if we're at least at
+        /// `level` verbosity.
+        fn status(&self, level: Verbosity, color: Color, status: &str, message: impl AsRef<str>) {
+            if let Some(line) = self.line(level, color, status, message) {
+                self.stream.println(line);
+            }
+        }
+
+        /// What [`Printer::status`] would print, without the newline. `None` if nothing.
+        fn line(&self, level: Verbosity, color: Color, status: &str, message: impl AsRef<str>) -> Option<String> {
+            if self.verbosity < level {
+                return None;
+            }
+
+            let line = if self.color {
+                format!("{}{:>width$}\x1b[0m {}", color.ansi(), status, message.as_ref(), width = STATUS_WIDTH)
+            } else {
+                format!("{:>width$} {}", status, message.as_ref(), width = STATUS_WIDTH)
+            };
+            Some(match self.mode {
+                OutputMode::Interactive => line,
+                OutputMode::Plain | OutputMode::Ci =>
+                    format!("[{:>7.2}s] {}", self.started.elapsed().as_secs_f64(), line),
+            })
+        }
+
+        /// Like [`Printer::status`], but overwrite the current line instead of starting a new one.
+        /// Only in interactive mode, and never when quiet.
+        fn redraw(&self, status: &str, message: impl AsRef<str>) {
+            if self.mode != OutputMode::Interactive || self.verbosity < Verbosity::Normal {
+                return;
+            }
+
+            let mut out = self.stream.lock();
+            let _ = write!(out, "\r\x1b[2K{:>width$} {}", status, message.as_ref(), width = STATUS_WIDTH);
+            let _ = out.flush();
+        }
+
+        /// Clear what [`Printer::redraw`] left behind.
+        fn clear_line(&self) {
+            if self.mode == OutputMode::Interactive && self.verbosity >= Verbosity::Normal {
+                let mut out = self.stream.lock();
+                let _ = write!(out, "\r\x1b[2K");
+                let _ = out.flush();
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct SimpleManifestProgress {
+        printer: Printer,
+        name: String,
+    }
 
     #[derive(Debug)]
-    pub struct SimpleSourceProgress;
-    pub struct SimpleBuildProgress;
+    pub struct SimpleSourceProgress {
+        printer: Printer,
+        name: String,
+        /// Of the name column, see [`SimpleTracer::name_width`].
+        width: usize,
+        /// Last percentage shown, so we only redraw when it changes. `usize::MAX` if none yet.
+        shown_percent: AtomicUsize,
+    }
+
+    #[derive(Debug)]
+    pub struct SimpleBuildProgress {
+        printer: Printer,
+        name: String,
+        scope: Scope,
+    }
+
+    impl SimpleBuildProgress {
+        fn status(&self, level: Verbosity, color: Color, status: &str, message: impl AsRef<str>) {
+            if let Some(line) = self.printer.line(level, color, status, message) {
+                self.scope.status(line);
+            }
+        }
+    }
+
+    impl ManifestProgress for SimpleManifestProgress {
+        type Tr = SimpleTracer;
+
+        fn start(tr: &Self::Tr, desc: &Descriptor) -> Self {
+            Self {
+                printer: tr.printer,
+                name: desc.name().to_owned(),
+            }
+        }
+
+        fn success(self, manifest: &Manifest) {
+            self.printer.status(Verbosity::VeryVerbose, Color::Dimmed, "Manifest",
+                format!("{} v{} ({} dependencies)", self.name, manifest.version, manifest.dependencies.len()));
+        }
+    }
 
     impl SourceProgress for SimpleSourceProgress {
         type Tr = SimpleTracer;
 
-        fn start<'a>(_tr: &Self::Tr, desc: &Descriptor, method: SourceProgressMethod<'a>) -> Self {
+        fn start<'a>(tr: &Self::Tr, desc: &Descriptor, method: SourceProgressMethod<'a>) -> Self {
+            let printer = tr.printer;
             match method {
-                SourceProgressMethod::AlreadyDownloaded => (),
-                SourceProgressMethod::Git { url } => {
-                    println!("Downloading {} from {}", desc.name(), url);
+                SourceProgressMethod::AlreadyDownloaded => {
+                    printer.status(Verbosity::Verbose, Color::Dimmed, "Fresh", desc.name());
+                },
+                SourceProgressMethod::Git { url } | SourceProgressMethod::Archive { url } => {
+                    printer.status(Verbosity::Normal, Color::Green, "Downloading", format!("{} from {}", desc.name(), url));
                 },
+                SourceProgressMethod::Local { path } => {
+                    printer.status(Verbosity::Verbose, Color::Dimmed, "Using", format!("{} from {}", desc.name(), path.display()));
+                },
+            }
+            Self {
+                printer,
+                name: desc.name().to_owned(),
+                width: tr.name_width(),
+                shown_percent: AtomicUsize::new(usize::MAX),
+            }
+        }
+
+        fn progress(&self, received: usize, total: usize, bytes: u64) {
+            let percent = (received * 100).checked_div(total).unwrap_or(0);
+            if self.shown_percent.swap(percent, Ordering::Relaxed) != percent {
+                self.printer.redraw("Receiving", format!("{:<width$} {:>3}% ({}/{} objects, {:.1} MiB)",
+                    scope::shorten(&self.name, self.width), percent, received, total, bytes as f64 / (1024.0 * 1024.0), width = self.width));
             }
-            Self
+        }
+
+        fn success(self, source_path: &Path) {
+            if self.shown_percent.load(Ordering::Relaxed) != usize::MAX {
+                self.printer.clear_line();
+            }
+            self.printer.status(Verbosity::VeryVerbose, Color::Dimmed, "Fetched",
+                format!("{} ({})", self.name, source_path.display()));
         }
     }
 
     impl BuildProgress for SimpleBuildProgress {
         type Tr = SimpleTracer;
 
-        fn start(_tr: &Self::Tr, desc: &Descriptor) -> Self {
-            println!("Building {}", desc.name());
-            Self
+        fn start(tr: &Self::Tr, desc: &Descriptor) -> Self {
+            let progress = Self {
+                printer: tr.printer,
+                name: desc.name().to_owned(),
+                scope: Scope::new(tr.grouping, tr.printer.stream, desc.name(), format!("Building {}", desc.name()))
+                    .with_width(tr.name_width()),
+            };
+            progress.status(Verbosity::Normal, Color::Green, "Building", desc.name());
+            progress
         }
 
         fn command(&self, command: &str) {
-            println!("Running command: `{}`", command);
+            self.status(Verbosity::Verbose, Color::Cyan, "Running", format!("`{}`", command));
+        }
+
+        fn output_line(&self, line: &str) {
+            self.scope.output(line);
         }
+
+        fn success(self, ttc_path: &Path) {
+            self.status(Verbosity::VeryVerbose, Color::Dimmed, "Built",
+                format!("{} ({})", self.name, ttc_path.display()));
+        }
+    }
+
+    /// Human-readable output on stdout (or stderr, see [`Stream`]), filtered by [`Verbosity`], laid out according to
+    /// [`OutputMode`].
+    ///
+    /// Colored in interactive mode, unless the `NO_COLOR` environment variable is set (see
+    /// <https://no-color.org>).
+    #[derive(Clone, Debug)]
+    pub struct SimpleTracer {
+        printer: Printer,
+        grouping: Grouping,
+        /// Length of the longest package name we know of, in characters. Grows while the graph
+        /// is resolved, and is exact once it is.
+        widest_name: Arc<AtomicUsize>,
     }
 
-    pub struct SimpleTracer;
+    impl SimpleTracer {
+        /// With the [`OutputMode`] picked by [`OutputMode::detect`].
+        pub fn new(verbosity: Verbosity) -> Self {
+            Self::with_mode(verbosity, OutputMode::detect(Stream::Stdout))
+        }
+
+        pub fn with_mode(verbosity: Verbosity, mode: OutputMode) -> Self {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+            Self {
+                printer: Printer {
+                    verbosity,
+                    mode,
+                    stream: Stream::Stdout,
+                    color: mode == OutputMode::Interactive && !no_color,
+                    started: Instant::now(),
+                },
+                grouping: mode.grouping(),
+                widest_name: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        /// Keep the output of packages built in parallel apart differently than the
+        /// [`OutputMode`] would.
+        pub fn with_grouping(self, grouping: Grouping) -> Self {
+            Self { grouping, ..self }
+        }
+
+        /// Print to `stream` instead of stdout.
+        pub fn with_stream(mut self, stream: Stream) -> Self {
+            self.printer.stream = stream;
+            self
+        }
+
+        /// What to align package names in front of output lines to, so that packages built in
+        /// parallel line up. Longer names are shortened, see [`scope::shorten`].
+        fn name_width(&self) -> usize {
+            self.widest_name.load(Ordering::Relaxed).min(scope::MAX_NAME_WIDTH)
+        }
+    }
 
     impl Default for SimpleTracer {
-        fn default() -> Self { Self }
+        fn default() -> Self {
+            Self::new(Verbosity::default())
+        }
     }
 
     impl Tracer for SimpleTracer {
-        type Manifest = Ignore<Self>;
+        type Manifest = SimpleManifestProgress;
         type Source = SimpleSourceProgress;
         type Build = SimpleBuildProgress;
+
+        fn new_descriptor(&self, desc: &Descriptor) {
+            self.widest_name.fetch_max(desc.name().chars().count(), Ordering::Relaxed);
+            self.printer.status(Verbosity::VeryVerbose, Color::Dimmed, "Found", desc.name());
+        }
+
+        fn graph_resolved(&self, graph: &Graph) {
+            let widest = graph.packages.iter().map(|package| package.descriptor.name().chars().count()).max();
+            self.widest_name.store(widest.unwrap_or(0), Ordering::Relaxed);
+        }
+
+        fn build_fresh(&self, desc: &Descriptor, _ttc_path: &Path) {
+            self.printer.status(Verbosity::Verbose, Color::Dimmed, "Up-to-date", desc.name());
+        }
+
+        fn task_started(&self, task: &Task) {
+            self.printer.status(Verbosity::VeryVerbose, Color::Dimmed, "Starting", format!("to {}", task));
+        }
+
+        fn script_started(&self, name: &str, script: &str) {
+            self.printer.status(Verbosity::Normal, Color::Green, "Running", format!("{}: {}", name, script));
+        }
+
+        fn remote_cache_hit(&self, desc: &Descriptor, _ttc_path: &Path) {
+            self.printer.status(Verbosity::Normal, Color::Green, "Downloaded", format!("{} from the remote cache", desc.name()));
+        }
+
+        fn remote_cache_failed(&self, desc: &Descriptor, error: &str) {
+            self.printer.status(Verbosity::Normal, Color::Yellow, "Warning",
+                format!("Remote cache failed for {}, so it is built locally instead: {}", desc.name(), error));
+        }
+
+        fn remote_exec_failed(&self, desc: &Descriptor, worker: &str, error: &str) {
+            self.printer.status(Verbosity::Normal, Color::Yellow, "Warning",
+                format!("Couldn't build {} on {}, so it is built locally instead: {}", desc.name(), worker, error));
+        }
+
+        fn retrying_fetch(&self, desc: &Descriptor, error: &str) {
+            self.printer.status(Verbosity::Normal, Color::Yellow, "Warning",
+                format!("Failed to download {}, trying again: {}", desc.name(), error));
+        }
+
+        fn update_available(&self, update: &Update) {
+            self.printer.status(Verbosity::Normal, Color::Cyan, "Update",
+                format!("{} {} is out, this is {}: {}/releases", update.program, update.latest, update.current, update.url));
+        }
+
+        fn dirty_dependency(&self, desc: &Descriptor, path: &Path, files: &[PathBuf]) {
+            self.printer.status(Verbosity::Normal, Color::Yellow, "Warning",
+                format!("{} has local changes in {}, which are built as they are: {}. `--frozen` fails on this instead, `--allow-dirty-deps` silences it.",
+                    desc.name(), path.display(), files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>().join(", ")));
+        }
+
+        fn guessed_source_dir(&self, desc: &Descriptor, source_dir: &Path) {
+            self.printer.status(Verbosity::Normal, Color::Yellow, "Warning",
+                format!("{} has no `src` directory, using `{}` for its modules. `source-dir = \"{}\"` in the `[package]` of its manifest says so and silences this.",
+                    desc.name(), source_dir.display(), source_dir.display()));
+        }
+
+        fn yanked(&self, desc: &Descriptor, version: &str) {
+            self.printer.status(Verbosity::Normal, Color::Yellow, "Warning",
+                format!("{} v{} is yanked from its registry, but still pinned by the lockfile. `lair lock --update {}` moves away from it.",
+                    desc.name(), version, desc.name()));
+        }
+
+        fn run_finished(&self, outcome: &RunOutcome) {
+            let counts = [
+                (outcome.failed, "failed"),
+                (outcome.built, "built"),
+                (outcome.cached, "up-to-date"),
+                (outcome.remote, "from remote cache"),
+                (outcome.fetched, "downloaded"),
+            ];
+            let counts = counts.iter()
+                .filter(|(count, _)| *count > 0)
+                .map(|(count, what)| format!("{} {}", count, what))
+                .collect::<Vec<_>>();
+            let counts = if counts.is_empty() { String::new() } else { format!(" ({})", counts.join(", ")) };
+            let seconds = outcome.duration.as_secs_f64();
+            let logs_dir = outcome.logs_dir.display();
+
+            if outcome.success {
+                self.printer.status(Verbosity::Normal, Color::Green, "Finished",
+                    format!("in {:.2}s{}, build output is in {}", seconds, counts, logs_dir));
+            } else {
+                self.printer.status(Verbosity::Normal, Color::Red, "Failed",
+                    format!("after {:.2}s{}, build output is in {}", seconds, counts, logs_dir));
+            }
+        }
+    }
+}
+
+/// Progress as plain values sent over a channel, for GUIs, web dashboards and anything else
+/// embedding Lair which would rather render [`LairEvent`]s its own way than implement [`Tracer`]
+/// and its progress traits.
+///
+/// ```ignore
+/// let (tracer, mut events) = ChannelTracer::new();
+/// let lair = Lair::with_tracer(manifest, "", tracer);
+/// tokio::spawn(async move {
+///     while let Some(event) = events.recv().await {
+///         // Update the progress bars.
+///     }
+/// });
+/// lair.build().await?;
+/// ```
+pub mod channel {
+    use std::path::{Path, PathBuf};
+
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    use crate::descriptor::Descriptor;
+    use crate::graph::Graph;
+    use crate::manifest::Manifest;
+    use crate::scheduler::Task;
+    use crate::updates::Update;
+
+    use super::{Tracer, BuildProgress, SourceProgressMethod, SourceProgress, ManifestProgress, RunOutcome};
+
+    /// Where a package's source comes from, see [`SourceProgressMethod`].
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum SourceMethod {
+        AlreadyDownloaded,
+        Git { url: String },
+        Archive { url: String },
+        Local { path: PathBuf },
+    }
+
+    impl From<SourceProgressMethod<'_>> for SourceMethod {
+        fn from(method: SourceProgressMethod<'_>) -> Self {
+            match method {
+                SourceProgressMethod::AlreadyDownloaded => SourceMethod::AlreadyDownloaded,
+                SourceProgressMethod::Git { url } => SourceMethod::Git { url: url.to_owned() },
+                SourceProgressMethod::Archive { url } => SourceMethod::Archive { url: url.to_owned() },
+                SourceProgressMethod::Local { path } => SourceMethod::Local { path: path.to_owned() },
+            }
+        }
+    }
+
+    /// One call of a [`Tracer`] method, or of one of its progress guards, with everything it was
+    /// told. See there for what each means.
+    #[derive(Clone, Debug)]
+    pub enum LairEvent {
+        NewDescriptor { descriptor: Descriptor },
+        GraphResolved { graph: Box<Graph> },
+        ManifestStarted { descriptor: Descriptor },
+        ManifestRead { descriptor: Descriptor, manifest: Box<Manifest> },
+        SourceStarted { descriptor: Descriptor, method: SourceMethod },
+        SourceProgress { descriptor: Descriptor, received: usize, total: usize, bytes: u64 },
+        SourceFetched { descriptor: Descriptor, source_path: PathBuf },
+        BuildStarted { descriptor: Descriptor },
+        BuildCommand { descriptor: Descriptor, command: String },
+        BuildOutput { descriptor: Descriptor, line: String },
+        Built { descriptor: Descriptor, ttc_path: PathBuf },
+        BuildFresh { descriptor: Descriptor, ttc_path: PathBuf },
+        RemoteCacheHit { descriptor: Descriptor, ttc_path: PathBuf },
+        RemoteCacheFailed { descriptor: Descriptor, error: String },
+        RemoteExecFailed { descriptor: Descriptor, worker: String, error: String },
+        RetryingFetch { descriptor: Descriptor, error: String },
+        UpdateAvailable { update: Update },
+        Yanked { descriptor: Descriptor, version: String },
+        DirtyDependency { descriptor: Descriptor, path: PathBuf, files: Vec<PathBuf> },
+        GuessedSourceDir { descriptor: Descriptor, source_dir: PathBuf },
+        TaskStarted { task: Task },
+        TaskFinished { task: Task, success: bool },
+        ScriptStarted { name: String, script: String },
+        RunStarted { root: Descriptor },
+        RunFinished { outcome: RunOutcome },
+    }
+
+    /// Sends a [`LairEvent`] for everything it is told. The channel is unbounded, since tracer
+    /// methods can't wait for the receiver to catch up. Once the receiver is dropped, events are
+    /// dropped too, without affecting the build.
+    #[derive(Clone, Debug)]
+    pub struct ChannelTracer {
+        sender: UnboundedSender<LairEvent>,
+    }
+
+    impl ChannelTracer {
+        /// A tracer, and the receiving end of its events.
+        pub fn new() -> (Self, UnboundedReceiver<LairEvent>) {
+            let (sender, receiver) = unbounded_channel();
+            (Self::with_sender(sender), receiver)
+        }
+
+        /// Send to an existing channel, for example one shared with other parts of the GUI.
+        pub fn with_sender(sender: UnboundedSender<LairEvent>) -> Self {
+            Self { sender }
+        }
+
+        fn send(&self, event: LairEvent) {
+            let _ = self.sender.send(event);
+        }
+    }
+
+    /// Progress guard for a single package, reporting under its descriptor.
+    #[derive(Debug)]
+    pub struct ChannelProgress {
+        tracer: ChannelTracer,
+        descriptor: Descriptor,
+    }
+
+    impl ChannelProgress {
+        fn new(tracer: &ChannelTracer, descriptor: &Descriptor) -> Self {
+            Self { tracer: tracer.clone(), descriptor: descriptor.clone() }
+        }
+
+        fn send(&self, event: impl FnOnce(Descriptor) -> LairEvent) {
+            self.tracer.send(event(self.descriptor.clone()));
+        }
+    }
+
+    impl ManifestProgress for ChannelProgress {
+        type Tr = ChannelTracer;
+
+        fn start(tr: &Self::Tr, desc: &Descriptor) -> Self {
+            tr.send(LairEvent::ManifestStarted { descriptor: desc.clone() });
+            Self::new(tr, desc)
+        }
+
+        fn success(self, manifest: &Manifest) {
+            self.send(|descriptor| LairEvent::ManifestRead { descriptor, manifest: Box::new(manifest.clone()) });
+        }
+    }
+
+    impl SourceProgress for ChannelProgress {
+        type Tr = ChannelTracer;
+
+        fn start<'a>(tr: &Self::Tr, desc: &Descriptor, method: SourceProgressMethod<'a>) -> Self {
+            tr.send(LairEvent::SourceStarted { descriptor: desc.clone(), method: method.into() });
+            Self::new(tr, desc)
+        }
+
+        fn progress(&self, received: usize, total: usize, bytes: u64) {
+            self.send(|descriptor| LairEvent::SourceProgress { descriptor, received, total, bytes });
+        }
+
+        fn success(self, source_path: &Path) {
+            self.send(|descriptor| LairEvent::SourceFetched { descriptor, source_path: source_path.to_owned() });
+        }
+    }
+
+    impl BuildProgress for ChannelProgress {
+        type Tr = ChannelTracer;
+
+        fn start(tr: &Self::Tr, desc: &Descriptor) -> Self {
+            tr.send(LairEvent::BuildStarted { descriptor: desc.clone() });
+            Self::new(tr, desc)
+        }
+
+        fn command(&self, command: &str) {
+            self.send(|descriptor| LairEvent::BuildCommand { descriptor, command: command.to_owned() });
+        }
+
+        fn output_line(&self, line: &str) {
+            self.send(|descriptor| LairEvent::BuildOutput { descriptor, line: line.to_owned() });
+        }
+
+        fn success(self, ttc_path: &Path) {
+            self.send(|descriptor| LairEvent::Built { descriptor, ttc_path: ttc_path.to_owned() });
+        }
+    }
+
+    impl Tracer for ChannelTracer {
+        type Manifest = ChannelProgress;
+        type Source = ChannelProgress;
+        type Build = ChannelProgress;
+
+        fn new_descriptor(&self, desc: &Descriptor) {
+            self.send(LairEvent::NewDescriptor { descriptor: desc.clone() });
+        }
+
+        fn graph_resolved(&self, graph: &Graph) {
+            self.send(LairEvent::GraphResolved { graph: Box::new(graph.clone()) });
+        }
+
+        fn build_fresh(&self, desc: &Descriptor, ttc_path: &Path) {
+            self.send(LairEvent::BuildFresh { descriptor: desc.clone(), ttc_path: ttc_path.to_owned() });
+        }
+
+        fn remote_cache_hit(&self, desc: &Descriptor, ttc_path: &Path) {
+            self.send(LairEvent::RemoteCacheHit { descriptor: desc.clone(), ttc_path: ttc_path.to_owned() });
+        }
+
+        fn remote_cache_failed(&self, desc: &Descriptor, error: &str) {
+            self.send(LairEvent::RemoteCacheFailed { descriptor: desc.clone(), error: error.to_owned() });
+        }
+
+        fn remote_exec_failed(&self, desc: &Descriptor, worker: &str, error: &str) {
+            self.send(LairEvent::RemoteExecFailed { descriptor: desc.clone(), worker: worker.to_owned(), error: error.to_owned() });
+        }
+
+        fn retrying_fetch(&self, desc: &Descriptor, error: &str) {
+            self.send(LairEvent::RetryingFetch { descriptor: desc.clone(), error: error.to_owned() });
+        }
+
+        fn update_available(&self, update: &Update) {
+            self.send(LairEvent::UpdateAvailable { update: update.clone() });
+        }
+
+        fn yanked(&self, desc: &Descriptor, version: &str) {
+            self.send(LairEvent::Yanked { descriptor: desc.clone(), version: version.to_owned() });
+        }
+
+        fn dirty_dependency(&self, desc: &Descriptor, path: &Path, files: &[PathBuf]) {
+            self.send(LairEvent::DirtyDependency { descriptor: desc.clone(), path: path.to_owned(), files: files.to_vec() });
+        }
+
+        fn guessed_source_dir(&self, desc: &Descriptor, source_dir: &Path) {
+            self.send(LairEvent::GuessedSourceDir { descriptor: desc.clone(), source_dir: source_dir.to_owned() });
+        }
+
+        fn task_started(&self, task: &Task) {
+            self.send(LairEvent::TaskStarted { task: task.clone() });
+        }
+
+        fn task_finished(&self, task: &Task, success: bool) {
+            self.send(LairEvent::TaskFinished { task: task.clone(), success });
+        }
+
+        fn script_started(&self, name: &str, script: &str) {
+            self.send(LairEvent::ScriptStarted { name: name.to_owned(), script: script.to_owned() });
+        }
+
+        fn run_started(&self, root: &Descriptor) {
+            self.send(LairEvent::RunStarted { root: root.clone() });
+        }
+
+        fn run_finished(&self, outcome: &RunOutcome) {
+            self.send(LairEvent::RunFinished { outcome: outcome.clone() });
+        }
     }
 }
 
+/// Forwards everything to the [`tracing`](https://docs.rs/tracing) crate, for applications
+/// embedding Lair which already collect their logs with it. Each manifest read, download and build
+/// is a span, and whatever happens during it an event inside; everything else is an event of its
+/// own. All of them have the target `lair`, and those about a package its name in `package`.
+///
+/// Without a `tracing` subscriber, they're emitted as [`log`](https://docs.rs/log) records
+/// instead, so `env_logger` and friends work too.
+pub mod ecosystem {
+    use std::path::{Path, PathBuf};
+
+    use ::tracing::{debug, error, info, trace, warn, Span};
+
+    use crate::descriptor::Descriptor;
+    use crate::graph::Graph;
+    use crate::manifest::Manifest;
+    use crate::scheduler::Task;
+    use crate::updates::Update;
+
+    use super::{Tracer, BuildProgress, SourceProgressMethod, SourceProgress, ManifestProgress, RunOutcome};
+
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct TracingTracer;
+
+    /// Progress guard for a single package. Its span ends when it is dropped; if that's before
+    /// `success`, the package failed, which the error returned by Lair tells more about.
+    #[derive(Debug)]
+    pub struct TracingProgress {
+        span: Span,
+        /// Also on each event, since `log` records don't know their span.
+        package: String,
+    }
+
+    impl TracingProgress {
+        fn new(span: Span, desc: &Descriptor) -> Self {
+            Self { span, package: desc.name().to_owned() }
+        }
+    }
+
+    impl ManifestProgress for TracingProgress {
+        type Tr = TracingTracer;
+
+        fn start(_tr: &Self::Tr, desc: &Descriptor) -> Self {
+            let span = ::tracing::debug_span!(target: "lair", "manifest", package = desc.name());
+            debug!(target: "lair", parent: &span, package = desc.name(), descriptor = %desc, "reading manifest");
+            Self::new(span, desc)
+        }
+
+        fn success(self, manifest: &Manifest) {
+            debug!(target: "lair", parent: &self.span, package = self.package.as_str(), version = %manifest.version, "read manifest");
+        }
+    }
+
+    impl SourceProgress for TracingProgress {
+        type Tr = TracingTracer;
+
+        fn start<'a>(_tr: &Self::Tr, desc: &Descriptor, method: SourceProgressMethod<'a>) -> Self {
+            let span = ::tracing::info_span!(target: "lair", "fetch", package = desc.name());
+            match method {
+                SourceProgressMethod::AlreadyDownloaded => debug!(target: "lair", parent: &span, package = desc.name(), "already downloaded"),
+                SourceProgressMethod::Git { url } => info!(target: "lair", parent: &span, package = desc.name(), url, "cloning"),
+                SourceProgressMethod::Archive { url } => info!(target: "lair", parent: &span, package = desc.name(), url, "downloading"),
+                SourceProgressMethod::Local { path } => debug!(target: "lair", parent: &span, package = desc.name(), path = %path.display(), "on this computer"),
+            }
+            Self::new(span, desc)
+        }
+
+        fn progress(&self, received: usize, total: usize, bytes: u64) {
+            trace!(target: "lair", parent: &self.span, package = self.package.as_str(), received, total, bytes, "receiving objects");
+        }
+
+        fn success(self, source_path: &Path) {
+            info!(target: "lair", parent: &self.span, package = self.package.as_str(), path = %source_path.display(), "fetched");
+        }
+    }
+
+    impl BuildProgress for TracingProgress {
+        type Tr = TracingTracer;
+
+        fn start(_tr: &Self::Tr, desc: &Descriptor) -> Self {
+            let span = ::tracing::info_span!(target: "lair", "build", package = desc.name());
+            info!(target: "lair", parent: &span, package = desc.name(), descriptor = %desc, "building");
+            Self::new(span, desc)
+        }
+
+        fn command(&self, command: &str) {
+            debug!(target: "lair", parent: &self.span, package = self.package.as_str(), command, "running");
+        }
+
+        fn output_line(&self, line: &str) {
+            debug!(target: "lair", parent: &self.span, package = self.package.as_str(), line, "idris2 output");
+        }
+
+        fn success(self, ttc_path: &Path) {
+            info!(target: "lair", parent: &self.span, package = self.package.as_str(), ttc = %ttc_path.display(), "built");
+        }
+    }
+
+    impl Tracer for TracingTracer {
+        type Manifest = TracingProgress;
+        type Source = TracingProgress;
+        type Build = TracingProgress;
+
+        fn new_descriptor(&self, desc: &Descriptor) {
+            trace!(target: "lair", package = desc.name(), descriptor = %desc, "new dependency");
+        }
+
+        fn graph_resolved(&self, graph: &Graph) {
+            debug!(target: "lair", root = graph.root.name(), packages = graph.packages.len(), "resolved dependency graph");
+        }
+
+        fn build_fresh(&self, desc: &Descriptor, ttc_path: &Path) {
+            debug!(target: "lair", package = desc.name(), ttc = %ttc_path.display(), "up to date");
+        }
+
+        fn remote_cache_hit(&self, desc: &Descriptor, ttc_path: &Path) {
+            info!(target: "lair", package = desc.name(), ttc = %ttc_path.display(), "downloaded from the remote cache");
+        }
+
+        fn remote_cache_failed(&self, desc: &Descriptor, error: &str) {
+            warn!(target: "lair", package = desc.name(), error, "remote cache failed");
+        }
+
+        fn remote_exec_failed(&self, desc: &Descriptor, worker: &str, error: &str) {
+            warn!(target: "lair", package = desc.name(), worker, error, "remote build failed, building here instead");
+        }
+
+        fn retrying_fetch(&self, desc: &Descriptor, error: &str) {
+            warn!(target: "lair", package = desc.name(), error, "download failed, retrying");
+        }
+
+        fn update_available(&self, update: &Update) {
+            info!(target: "lair", program = update.program, current = %update.current, latest = %update.latest, url = update.url, "update available");
+        }
+
+        fn yanked(&self, desc: &Descriptor, version: &str) {
+            warn!(target: "lair", package = desc.name(), version, "locked version was yanked");
+        }
+
+        fn dirty_dependency(&self, desc: &Descriptor, path: &Path, files: &[PathBuf]) {
+            let files: Vec<String> = files.iter().map(|file| file.display().to_string()).collect();
+            warn!(target: "lair", package = desc.name(), path = %path.display(), files = ?files, "checkout has local changes");
+        }
+
+        fn guessed_source_dir(&self, desc: &Descriptor, source_dir: &Path) {
+            warn!(target: "lair", package = desc.name(), source_dir = %source_dir.display(), "no `src` directory, guessed the source directory");
+        }
+
+        fn task_started(&self, task: &Task) {
+            trace!(target: "lair", package = task.descriptor.name(), task = %task, "task started");
+        }
+
+        fn task_finished(&self, task: &Task, success: bool) {
+            trace!(target: "lair", package = task.descriptor.name(), task = %task, success, "task finished");
+        }
+
+        fn script_started(&self, name: &str, script: &str) {
+            info!(target: "lair", name, script, "running script");
+        }
+
+        fn run_started(&self, root: &Descriptor) {
+            info!(target: "lair", root = root.name(), "starting");
+        }
+
+        fn run_finished(&self, outcome: &RunOutcome) {
+            let RunOutcome { fetched, built, cached, remote, failed, duration, success, logs_dir } = outcome;
+            let millis: u64 = duration.as_millis().try_into().unwrap_or(u64::MAX);
+            let logs_dir = logs_dir.display();
+            match success {
+                true => info!(target: "lair", fetched, built, cached, remote, failed, millis, logs = %logs_dir, "finished"),
+                false => error!(target: "lair", fetched, built, cached, remote, failed, millis, logs = %logs_dir, "failed"),
+            }
+        }
+    }
+}
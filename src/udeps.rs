@@ -0,0 +1,101 @@
+//! `lair udeps`: dependencies nothing imports, and imports from packages which aren't dependencies.
+//!
+//! Compares the `import` lines of a package's modules with what every package of the graph
+//! provides (see [`modules::scan`] and [`modules::imports`]). A dependency none of whose modules
+//! are imported is unused. A module imported from a package which is only in the graph because
+//! something else depends on it still builds, since idris2 sees all of a build's TTC files, but
+//! breaks once that something else stops depending on it.
+//!
+//! Only looks at the sources, so a dependency only used for, say, its scripts counts as unused.
+//! Imports nothing in the graph provides are left alone: they are the package's own, from
+//! idris2's `prelude` and `base`, or idris2 will complain about them anyway.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::descriptor::Descriptor;
+use crate::graph::{Graph, Origin};
+use crate::modules::{self, Import};
+
+/// What's wrong with one package's dependencies.
+#[derive(Clone, Debug)]
+pub enum Finding {
+    /// None of `package`'s modules import anything from `dependency`.
+    Unused { package: Descriptor, dependency: Descriptor, origin: Option<Box<Origin>> },
+    /// `package` imports `module` in `file`, but only (indirect) dependencies, `provided_by`,
+    /// have it.
+    Undeclared { package: Descriptor, module: String, file: PathBuf, line: usize, provided_by: Vec<Descriptor> },
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::Unused { package, dependency, origin } => {
+                write!(f, "`{}` doesn't import anything from `{}`", package.name(), dependency.name())?;
+                if let Some(origin) = origin {
+                    write!(f, ", declared as {}", origin)?;
+                }
+                Ok(())
+            },
+            Finding::Undeclared { package, module, file, line, provided_by } => write!(
+                f, "`{}` imports `{}` in {}:{}, which comes from {}, but doesn't depend on it",
+                package.name(), module, file.display(), line,
+                provided_by.iter().map(|desc| format!("`{}`", desc.name())).collect::<Vec<_>>().join(" or "),
+            ),
+        }
+    }
+}
+
+/// Check the direct dependencies of each of `packages` against what their modules import.
+/// `source_dirs` are where the modules of every package of `graph` are. Blocking.
+pub fn check(graph: &Graph, source_dirs: &BTreeMap<Descriptor, PathBuf>, packages: &[Descriptor]) -> std::io::Result<Vec<Finding>> {
+    let mut providers: BTreeMap<String, Vec<&Descriptor>> = BTreeMap::new();
+    for (desc, source_dir) in source_dirs {
+        for module in modules::scan(source_dir)? {
+            providers.entry(module).or_default().push(desc);
+        }
+    }
+
+    let mut findings = Vec::new();
+    for package in packages {
+        let (Some(graph_package), Some(source_dir)) = (graph.package(package), source_dirs.get(package)) else { continue };
+        let dependencies = &graph_package.dependencies;
+        let mut used = BTreeSet::new();
+        for (file, import) in imports_in(source_dir)? {
+            let Some(provided_by) = providers.get(&import.module) else { continue };
+            if provided_by.contains(&package) {
+                continue;
+            }
+            let declared: Vec<&Descriptor> = provided_by.iter().copied().filter(|desc| dependencies.contains(desc)).collect();
+            if declared.is_empty() {
+                findings.push(Finding::Undeclared {
+                    package: package.clone(),
+                    module: import.module,
+                    file,
+                    line: import.line,
+                    provided_by: provided_by.iter().map(|&desc| desc.clone()).collect(),
+                });
+            }
+            used.extend(declared);
+        }
+        for dependency in dependencies.iter().filter(|dep| !used.contains(dep)) {
+            findings.push(Finding::Unused {
+                package: package.clone(),
+                dependency: dependency.clone(),
+                origin: graph.origin(package, dependency).cloned().map(Box::new),
+            });
+        }
+    }
+    Ok(findings)
+}
+
+/// The imports of every module in `source_dir`, with the file they're in.
+fn imports_in(source_dir: &Path) -> std::io::Result<Vec<(PathBuf, Import)>> {
+    let mut imports = Vec::new();
+    for module in modules::scan(source_dir)? {
+        let Some(file) = modules::file_of(source_dir, &module) else { continue };
+        imports.extend(modules::imports_of(&file)?.into_iter().map(|import| (file.clone(), import)));
+    }
+    Ok(imports)
+}
@@ -0,0 +1,112 @@
+//! Telling users when there's a newer Lair or idris2 out, if they asked for it with
+//! `[updates] check = true` in a config file (see [`crate::config`]).
+//!
+//! At most once a day, the release tags of both repositories are looked up in the background,
+//! along with which idris2 is installed, and kept in `updates.json` in
+//! [`layout::user_data_dir`]. Runs only ever read that file, so they don't wait for the network;
+//! what a check finds therefore shows from the run after it on. It's not in the cache directory,
+//! so that `lair clean --cache` doesn't make the next run check again.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compiler::Toolchain;
+use crate::fetch;
+use crate::layout;
+use crate::outdated;
+use crate::registry;
+use crate::timestamp;
+
+pub const LAIR_REPOSITORY: &str = "https://github.com/Kiiyya/Lair";
+pub const IDRIS2_REPOSITORY: &str = "https://github.com/idris-lang/Idris2";
+
+/// How long what a check found is good for.
+pub const INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The version of this Lair.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Checked {
+    /// Seconds since 1970-01-01. Also set when the check failed, so that the next one waits as long.
+    at: u64,
+    /// The newest release, without the `v`. `None` if we couldn't find out.
+    lair: Option<String>,
+    idris2: Option<String>,
+    /// The `major.minor.patch` of the idris2 on PATH back then.
+    installed_idris2: Option<String>,
+}
+
+/// Something newer than what's installed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Update {
+    /// `Lair` or `idris2`.
+    pub program: &'static str,
+    pub current: String,
+    pub latest: String,
+    /// Where to get it.
+    pub url: &'static str,
+}
+
+/// What the last check found to be newer. Blocking.
+pub fn available() -> Vec<Update> {
+    let checked = load().unwrap_or_default();
+    let newer = |latest: Option<String>, current: Option<String>| match (latest, current) {
+        (Some(latest), Some(current)) => registry::compare_versions(&latest, &current).is_gt().then_some((current, latest)),
+        _ => None,
+    };
+    let mut updates = Vec::new();
+    if let Some((current, latest)) = newer(checked.lair, Some(VERSION.to_owned())) {
+        updates.push(Update { program: "Lair", current, latest, url: LAIR_REPOSITORY });
+    }
+    if let Some((current, latest)) = newer(checked.idris2, checked.installed_idris2) {
+        updates.push(Update { program: "idris2", current, latest, url: IDRIS2_REPOSITORY });
+    }
+    updates
+}
+
+/// Check again in a background thread, unless the last check was less than [`INTERVAL`] ago.
+/// Doesn't wait for it: if Lair is done first, the next run tries again.
+pub fn check_in_background() {
+    let last = load();
+    if last.as_ref().is_some_and(|last| timestamp::now().saturating_sub(last.at) < INTERVAL.as_secs()) {
+        return;
+    }
+    std::thread::spawn(move || {
+        let last = last.unwrap_or_default();
+        let newest = |url: &str| fetch::remote_refs(url).ok()
+            .and_then(|refs| outdated::newest_tag(&refs).map(|(tag, _)| outdated::strip_v(tag).to_owned()));
+        let installed = Toolchain::detect().map(|toolchain| toolchain.version);
+        let checked = Checked {
+            at: timestamp::now(),
+            // Keep what an earlier check found, if this one couldn't reach a repository.
+            lair: newest(LAIR_REPOSITORY).or(last.lair),
+            idris2: newest(IDRIS2_REPOSITORY).or(last.idris2),
+            installed_idris2: installed.map(|version| version.to_string()),
+        };
+        // Only a nicety, so not being able to write it is no reason to complain.
+        let _ = save(&checked);
+    });
+}
+
+fn load() -> Option<Checked> {
+    serde_json::from_slice(&std::fs::read(path()).ok()?).ok()
+}
+
+fn save(checked: &Checked) -> std::io::Result<()> {
+    let path = path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Another run may be reading it.
+    let tmp = path.with_extension(format!("json.{}", std::process::id()));
+    std::fs::write(&tmp, serde_json::to_vec_pretty(checked).expect("Checks are always serializable."))?;
+    std::fs::rename(tmp, path)
+}
+
+/// `updates.json` in [`layout::user_data_dir`].
+fn path() -> PathBuf {
+    layout::user_data_dir().join("updates.json")
+}
@@ -0,0 +1,227 @@
+//! `lair verify`: do the lockfile, the downloaded dependencies and the build outputs still agree
+//! with the manifests?
+//!
+//! Unlike every other command, this never downloads into `build/deps`, checks anything out,
+//! builds, or writes the lockfile. It only reports what `lair build` would change.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use crate::archive;
+use crate::descriptor::Descriptor;
+use crate::fetch;
+use crate::fingerprint::Fingerprint;
+use crate::layout;
+use crate::lockfile::Lockfile;
+use crate::package_name::PackageName;
+
+/// A package as far as we could find it on disk.
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub descriptor: Descriptor,
+    pub base_path: PathBuf,
+    /// Direct dependencies, after `[patch]`. `None` if the package isn't on disk, so we couldn't
+    /// read its manifest.
+    pub dependencies: Option<BTreeSet<Descriptor>>,
+    /// What it's built with besides the usual, see [`Fingerprint::extra_with_flags`].
+    pub idris_flags: Vec<String>,
+    /// Other descriptors sharing its checkout, which are locked to the same commit.
+    pub aliases: BTreeSet<Descriptor>,
+}
+
+/// Why a package would be rebuilt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaleReason {
+    NeverBuilt,
+    SourcesChanged,
+    DependenciesChanged,
+    TtcMissing,
+}
+
+/// Something which doesn't match.
+#[derive(Clone, Debug)]
+pub enum Drift {
+    /// A git or registry dependency without a lockfile entry.
+    Unlocked { descriptor: Descriptor },
+    /// A lockfile entry no manifest asks for anymore.
+    StaleLock { name: PackageName, commit: String },
+    /// Not downloaded yet, or the local path doesn't exist.
+    Missing { descriptor: Descriptor, path: PathBuf },
+    /// The checkout is at a different commit than the lockfile says.
+    WrongCommit { descriptor: Descriptor, locked: String, checked_out: String },
+    /// The locked commit isn't on any branch or tag of the remote anymore, for example after a
+    /// force push.
+    CommitGone { descriptor: Descriptor, commit: String },
+    /// The archive at the dependency's url isn't the one its checksum says.
+    ChecksumMismatch { descriptor: Descriptor, expected: String, actual: String },
+    /// The TTC files are out of date.
+    Stale { descriptor: Descriptor, reason: StaleReason },
+    /// We couldn't check, for example because the remote is unreachable.
+    Failed { descriptor: Descriptor, error: String },
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::Unlocked { descriptor } =>
+                write!(f, "`{}` is not in the lockfile", descriptor.name()),
+            Drift::StaleLock { name, commit } =>
+                write!(f, "`{}` is locked (to {}), but nothing depends on it anymore", name, commit),
+            Drift::Missing { descriptor, path } =>
+                write!(f, "`{}` is not downloaded, `{}` doesn't exist", descriptor.name(), path.display()),
+            Drift::WrongCommit { descriptor, locked, checked_out } =>
+                write!(f, "`{}` is locked to {}, but {} is checked out", descriptor.name(), locked, checked_out),
+            Drift::CommitGone { descriptor, commit } =>
+                write!(f, "`{}` is locked to {}, which is not on any branch or tag of the remote anymore", descriptor.name(), commit),
+            Drift::ChecksumMismatch { descriptor, expected, actual } =>
+                write!(f, "`{}` should have sha256 {}, but the archive now has {}", descriptor.name(), expected, actual),
+            Drift::Stale { descriptor, reason } => {
+                let reason = match reason {
+                    StaleReason::NeverBuilt => "was never built",
+                    StaleReason::SourcesChanged => "changed since it was last built",
+                    StaleReason::DependenciesChanged => "has dependencies which changed since it was last built",
+                    StaleReason::TtcMissing => "has no TTC files",
+                };
+                write!(f, "`{}` {}", descriptor.name(), reason)
+            },
+            Drift::Failed { descriptor, error } =>
+                write!(f, "Couldn't check `{}`: {}", descriptor.name(), error),
+        }
+    }
+}
+
+/// Check `packages` (every package reachable from the root) against `lockfile`, their remotes,
+/// and the fingerprints of their last builds with `toolchain` (see [`layout::ttc_dir`]). `extra` is
+/// what builds put into fingerprints besides the sources, see [`Fingerprint::compute`]. Blocking.
+pub fn check(packages: &[Package], lockfile: &Lockfile, extra: &str, toolchain: &str) -> Vec<Drift> {
+    let mut drifts = Vec::new();
+
+    for package in packages {
+        let desc = &package.descriptor;
+        if package.dependencies.is_none() {
+            drifts.push(Drift::Missing { descriptor: desc.clone(), path: package.base_path.clone() });
+        }
+        let failed = |error: &dyn Display| Drift::Failed { descriptor: desc.clone(), error: error.to_string() };
+        // What to download from, with `${env:VAR}` expanded.
+        let expanded = match desc.expanded() {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                drifts.push(failed(&e));
+                continue;
+            },
+        };
+
+        match (desc, &expanded) {
+            (Descriptor::Git { .. } | Descriptor::Registry { .. }, _) => {
+                let Some(locked) = lockfile.locked(desc) else {
+                    drifts.push(Drift::Unlocked { descriptor: desc.clone() });
+                    continue;
+                };
+                // For registry dependencies, the repository of the locked version.
+                let url = match &expanded {
+                    Descriptor::Git { url, .. } => url,
+                    _ => &locked.git,
+                };
+                let locked = &locked.commit;
+                if package.dependencies.is_some() {
+                    match fetch::head_commit(&layout::source_dir(desc)) {
+                        Ok(head) if head != *locked =>
+                            drifts.push(Drift::WrongCommit { descriptor: desc.clone(), locked: locked.to_owned(), checked_out: head }),
+                        Ok(_) => {},
+                        Err(e) => drifts.push(failed(&e)),
+                    }
+                }
+                match fetch::is_reachable(url, locked) {
+                    Ok(true) => {},
+                    Ok(false) => drifts.push(Drift::CommitGone { descriptor: desc.clone(), commit: locked.to_owned() }),
+                    Err(e) => drifts.push(failed(&e)),
+                }
+            },
+            (Descriptor::Archive { checksum: Some(expected), .. }, Descriptor::Archive { url, .. }) => {
+                match archive::download(url) {
+                    Ok(bytes) => {
+                        let actual = archive::sha256(&bytes);
+                        if !actual.eq_ignore_ascii_case(expected.trim()) {
+                            drifts.push(Drift::ChecksumMismatch { descriptor: desc.clone(), expected: expected.clone(), actual });
+                        }
+                    },
+                    Err(e) => drifts.push(failed(&e)),
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // With packages missing, we don't know everything the lockfile should contain.
+    if packages.iter().all(|package| package.dependencies.is_some()) {
+        let known: BTreeSet<&Descriptor> = packages.iter()
+            .flat_map(|package| std::iter::once(&package.descriptor).chain(&package.aliases))
+            .collect();
+        for locked in lockfile.packages() {
+            if locked.descriptor().is_none_or(|desc| !known.contains(&desc)) {
+                drifts.push(Drift::StaleLock { name: locked.name.clone(), commit: locked.commit.clone() });
+            }
+        }
+    }
+
+    let by_descriptor: BTreeMap<&Descriptor, &Package> = packages.iter().map(|package| (&package.descriptor, package)).collect();
+    let mut fingerprints = BTreeMap::new();
+    for package in packages {
+        let desc = &package.descriptor;
+        let fingerprint = match fingerprint(desc, &by_descriptor, extra, &mut fingerprints) {
+            Some(Ok(fingerprint)) => fingerprint,
+            Some(Err(e)) => {
+                drifts.push(Drift::Failed { descriptor: desc.clone(), error: e });
+                continue;
+            },
+            // Not on disk, already reported.
+            None => continue,
+        };
+        if let Some(reason) = stale_reason(package, &fingerprint, toolchain) {
+            drifts.push(Drift::Stale { descriptor: desc.clone(), reason });
+        }
+    }
+
+    drifts
+}
+
+/// Why `package`, whose sources and dependencies now add up to `fingerprint`, would be rebuilt
+/// with `toolchain`. `None` if it wouldn't.
+pub fn stale_reason(package: &Package, fingerprint: &Fingerprint, toolchain: &str) -> Option<StaleReason> {
+    match Fingerprint::load(&package.descriptor, toolchain) {
+        None => Some(StaleReason::NeverBuilt),
+        Some(previous) if previous.own != fingerprint.own => Some(StaleReason::SourcesChanged),
+        Some(previous) if previous.deps != fingerprint.deps => Some(StaleReason::DependenciesChanged),
+        Some(_) if !layout::ttc_dir(&package.base_path, toolchain).is_dir() => Some(StaleReason::TtcMissing),
+        Some(_) => None,
+    }
+}
+
+/// The fingerprint a build of `desc` would compute right now, dependencies first. `None` if it or
+/// one of its dependencies isn't on disk.
+pub fn fingerprint(
+    desc: &Descriptor,
+    packages: &BTreeMap<&Descriptor, &Package>,
+    extra: &str,
+    done: &mut BTreeMap<Descriptor, Option<Result<Fingerprint, String>>>,
+) -> Option<Result<Fingerprint, String>> {
+    if let Some(fingerprint) = done.get(desc) {
+        return fingerprint.clone();
+    }
+    // Guards against cycles.
+    done.insert(desc.clone(), None);
+
+    let package = packages.get(desc)?;
+    let mut dep_fingerprints = Vec::new();
+    for dep in package.dependencies.as_ref()? {
+        match fingerprint(dep, packages, extra, done)? {
+            Ok(fingerprint) => dep_fingerprints.push(fingerprint.combined()),
+            Err(_) => return None,
+        }
+    }
+    let extra = Fingerprint::extra_with_flags(extra, &package.idris_flags);
+    let fingerprint = Fingerprint::compute(desc, &package.base_path, &extra, &dep_fingerprints).map_err(|e| e.to_string());
+    done.insert(desc.clone(), Some(fingerprint.clone()));
+    Some(fingerprint)
+}
@@ -0,0 +1,106 @@
+//! `lair watch`: build, then build again whenever a file of the packages on this computer (the
+//! root, workspace members and `path` dependencies) changes.
+//!
+//! Sources changing only means recompiling: the next build starts from the graph as it was
+//! resolved. A manifest changing, `Egg.lock` included, means resolving the graph again first,
+//! since dependencies may have come or gone, or point elsewhere now.
+//!
+//! Files are polled every [`INTERVAL`], which needs nothing from the operating system and is
+//! cheap enough for the few packages being worked on.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::interrupt;
+use crate::lockfile::LOCKFILE_NAME;
+
+pub const INTERVAL: Duration = Duration::from_millis(300);
+
+/// What to watch, see [`crate::Lair::watched`].
+#[derive(Clone, Debug, Default)]
+pub struct Paths {
+    pub manifests: Vec<PathBuf>,
+    /// Every file in them counts, except in `build` and hidden directories.
+    pub source_dirs: Vec<PathBuf>,
+}
+
+impl Paths {
+    /// The root's manifest and lockfile, for when we can't tell anything else.
+    pub fn root() -> Self {
+        Self { manifests: vec![PathBuf::from("Egg.toml"), PathBuf::from(LOCKFILE_NAME)], source_dirs: Vec::new() }
+    }
+}
+
+/// The first file [`wait`] saw change.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub path: PathBuf,
+    /// Whether it is one of the [`Paths::manifests`].
+    pub manifest: bool,
+}
+
+/// When a file was last modified and how long it is. `None` if it doesn't exist.
+type Stamp = Option<(Option<SystemTime>, u64)>;
+
+/// Wait until one of `paths` changes, is created or removed. `None` if Ctrl-C was pressed
+/// meanwhile. Blocking.
+pub fn wait(paths: &Paths) -> Option<Change> {
+    let before = stamps(paths);
+    loop {
+        std::thread::sleep(INTERVAL);
+        if interrupt::requested() {
+            return None;
+        }
+        let now = stamps(paths);
+        // Manifests sort first, so that they win when sources changed, too.
+        let changed = before.keys().chain(now.keys())
+            .filter(|key| before.get(key) != now.get(key))
+            .min();
+        if let Some((kind, path)) = changed {
+            return Some(Change { path: path.clone(), manifest: *kind == Kind::Manifest });
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Manifest,
+    Source,
+}
+
+/// Sources which didn't exist yet aren't there, manifests are, with `None`.
+fn stamps(paths: &Paths) -> BTreeMap<(Kind, PathBuf), Stamp> {
+    let mut stamps = BTreeMap::new();
+    for manifest in &paths.manifests {
+        stamps.insert((Kind::Manifest, manifest.clone()), stamp(manifest));
+    }
+    for dir in &paths.source_dirs {
+        files(dir, &mut |path| {
+            stamps.insert((Kind::Source, path.to_owned()), stamp(path));
+        });
+    }
+    stamps
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+fn files(dir: &Path, found: &mut impl FnMut(&Path)) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name == "build" {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => files(&path, found),
+            Ok(_) => found(&path),
+            Err(_) => {},
+        }
+    }
+}